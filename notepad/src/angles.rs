@@ -0,0 +1,421 @@
+//! Turns the gravity vector (or a full orientation) into the angles of the pen's long axis, for
+//! guiding how the pen is held.
+//!
+//! The pen body has two axes we care about: the *tip* axis, running along the pen towards its tip,
+//! and the *top* axis, perpendicular to it and marking which way is "up" when rolling the pen. How
+//! those line up with the BNO055's axes depends on how it's mounted, see [`AxisMapping`].
+use pensel_types::mint::{Quaternion, Vector3};
+use std::{fmt, str::FromStr};
+
+use crate::{
+    orientation,
+    vector::{cross, dot, norm, normalized, scale, sub},
+};
+
+/// One of the sensor's axes, possibly flipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignedAxis {
+    /// which axis: 0 for x, 1 for y, 2 for z
+    index: usize,
+    negative: bool,
+}
+
+impl SignedAxis {
+    /// The sensor's x axis
+    pub const X: Self = Self::new(0, false);
+    /// The sensor's y axis
+    pub const Y: Self = Self::new(1, false);
+    /// The sensor's z axis
+    pub const Z: Self = Self::new(2, false);
+
+    const NAMES: [char; 3] = ['x', 'y', 'z'];
+
+    const fn new(index: usize, negative: bool) -> Self {
+        Self { index, negative }
+    }
+
+    /// The same axis, pointing the other way
+    #[must_use]
+    pub const fn flipped(self) -> Self {
+        Self::new(self.index, !self.negative)
+    }
+
+    /// The unit vector along this axis, in the sensor's frame
+    #[must_use]
+    pub fn unit(self) -> Vector3<f32> {
+        let mut unit = [0.; 3];
+        unit[self.index] = if self.negative { -1. } else { 1. };
+        unit.into()
+    }
+}
+
+impl FromStr for SignedAxis {
+    type Err = String;
+
+    /// Parses `x`, `+x`, `-x` and so on
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, name) = match s.trim() {
+            s if s.starts_with('-') => (true, &s[1..]),
+            s => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Self::NAMES
+                .iter()
+                .position(|name| *name == c.to_ascii_lowercase())
+                .map(|index| Self::new(index, negative))
+                .ok_or_else(|| format!("unknown axis '{}'", s)),
+            _ => Err(format!("unknown axis '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for SignedAxis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.negative { '-' } else { '+' };
+        write!(f, "{}{}", sign, Self::NAMES[self.index])
+    }
+}
+
+/// Which of the sensor's axes the pen's tip and top axes run along
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AxisMapping {
+    /// sensor axis pointing towards the pen's tip
+    pub tip: SignedAxis,
+    /// sensor axis pointing out of the pen's top
+    pub top: SignedAxis,
+}
+
+impl AxisMapping {
+    /// The BNO055 lying flat in the pen, with its x axis towards the tip and its chip facing the
+    /// top
+    pub const DEFAULT: Self = Self {
+        tip: SignedAxis::X,
+        top: SignedAxis::Z,
+    };
+}
+
+impl Default for AxisMapping {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl FromStr for AxisMapping {
+    type Err = String;
+
+    /// Parses `<tip>,<top>`, like `+x,+z`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tip, top) = s
+            .split_once(',')
+            .ok_or_else(|| format!("expected '<tip axis>,<top axis>', got '{}'", s))?;
+        let mapping = Self {
+            tip: tip.parse()?,
+            top: top.parse()?,
+        };
+        if mapping.tip.index == mapping.top.index {
+            return Err(format!("tip and top must be different axes, got '{}'", s));
+        }
+        Ok(mapping)
+    }
+}
+
+impl fmt::Display for AxisMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.tip, self.top)
+    }
+}
+
+/// The angles of the pen's long axis, in degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PenAngles {
+    /// How far the tip is from pointing straight down: 0° is vertical, 90° horizontal
+    pub tilt: f32,
+    /// Which way the tip points, counter clockwise from world x seen from above, in `[0, 360)`.
+    /// Only known with a full orientation, and `None` while the pen is vertical.
+    pub azimuth: Option<f32>,
+    /// How far the pen is rolled around its long axis: 0° when its top faces up, positive when
+    /// rolled clockwise looking towards the tip. `None` while the pen is vertical, since then
+    /// nothing is up.
+    pub roll: Option<f32>,
+}
+
+/// Below this, a vector's horizontal part is too short to have a meaningful direction
+const MIN_HORIZONTAL: f32 = 1e-3;
+
+impl PenAngles {
+    /// Computes tilt and roll from the gravity vector, in the sensor's frame. Gravity alone
+    /// can't tell which way the pen points, so there's no azimuth.
+    ///
+    /// # Returns
+    /// `None` if `gravity` is zero
+    #[must_use]
+    pub fn from_gravity(gravity: Vector3<f32>, mapping: AxisMapping) -> Option<Self> {
+        let up = normalized(gravity)?;
+
+        let tip = mapping.tip.unit();
+        let top = mapping.top.unit();
+        let tilt = (-dot(tip, up)).clamp(-1., 1.).acos().to_degrees();
+
+        // up, as seen looking down the pen towards its tip
+        let up_across = sub(up, scale(tip, dot(up, tip)));
+        let roll = (norm(up_across) > MIN_HORIZONTAL).then(|| {
+            // the angle from up to the top axis, clockwise looking towards the tip
+            dot(cross(top, up_across), tip)
+                .atan2(dot(top, up_across))
+                .to_degrees()
+        });
+
+        Some(Self {
+            tilt,
+            azimuth: None,
+            roll,
+        })
+    }
+
+    /// Computes all angles from a full orientation (see [`orientation`]). The azimuth is only
+    /// as good as the orientation's heading, which needs a gyro or magnetometer to mean anything.
+    #[must_use]
+    pub fn from_orientation(orientation: Quaternion<f32>, mapping: AxisMapping) -> Self {
+        let world_up = Vector3 {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        };
+        let up = orientation::rotate(orientation::inverse(orientation), world_up);
+        // a unit quaternion always rotates world up to a unit vector
+        let mut angles = Self::from_gravity(up, mapping).unwrap_or(Self {
+            tilt: 0.,
+            azimuth: None,
+            roll: None,
+        });
+
+        let tip = orientation::rotate(orientation, mapping.tip.unit());
+        angles.azimuth = (tip.x.hypot(tip.y) > MIN_HORIZONTAL)
+            .then(|| tip.y.atan2(tip.x).to_degrees().rem_euclid(360.));
+        angles
+    }
+}
+
+impl fmt::Display for PenAngles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tilt {:6.1}°", self.tilt)?;
+        match self.roll {
+            Some(roll) => write!(f, "  roll {:6.1}°", roll)?,
+            None => write!(f, "  roll      -")?,
+        }
+        if let Some(azimuth) = self.azimuth {
+            write!(f, "  azimuth {:5.1}°", azimuth)?;
+        }
+        Ok(())
+    }
+}
+
+/// A range of angles in degrees, inclusive on both ends
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AngleRange {
+    /// smallest angle in range
+    pub min: f32,
+    /// largest angle in range
+    pub max: f32,
+}
+
+impl AngleRange {
+    /// Whether `angle` falls within the range
+    #[must_use]
+    pub fn contains(&self, angle: f32) -> bool {
+        (self.min..=self.max).contains(&angle)
+    }
+
+    /// Whether the heading `angle` falls within the range, going counter clockwise from `min` to
+    /// `max`. Unlike [`AngleRange::contains`] this wraps around, so `350..10` covers north.
+    #[must_use]
+    pub fn contains_heading(&self, angle: f32) -> bool {
+        (angle - self.min).rem_euclid(360.) <= (self.max - self.min).rem_euclid(360.)
+    }
+}
+
+impl FromStr for AngleRange {
+    type Err = String;
+
+    /// Parses `<min>..<max>`, like `30..45`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad_range = || format!("expected '<min>..<max>' in degrees, got '{}'", s);
+        let (min, max) = s.split_once("..").ok_or_else(bad_range)?;
+        let min = f32::from_str(min.trim()).map_err(|_| bad_range())?;
+        let max = f32::from_str(max.trim()).map_err(|_| bad_range())?;
+        Ok(Self { min, max })
+    }
+}
+
+impl fmt::Display for AngleRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.min, self.max)
+    }
+}
+
+/// The angles the pen should be held at. Angles without a range aren't checked.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AngleWindow {
+    /// allowed tilt
+    pub tilt: Option<AngleRange>,
+    /// allowed roll
+    pub roll: Option<AngleRange>,
+    /// allowed azimuth
+    pub azimuth: Option<AngleRange>,
+}
+
+impl AngleWindow {
+    /// Whether any angle is checked at all
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.tilt.is_none() && self.roll.is_none() && self.azimuth.is_none()
+    }
+
+    /// Whether `angles` are inside the window. An angle that has a range but is unknown, like
+    /// roll while the pen is vertical, counts as outside.
+    #[must_use]
+    pub fn contains(&self, angles: &PenAngles) -> bool {
+        let tilt_ok = self.tilt.is_none_or(|range| range.contains(angles.tilt));
+        let roll_ok = self
+            .roll
+            .is_none_or(|range| angles.roll.is_some_and(|roll| range.contains(roll)));
+        let azimuth_ok = self.azimuth.is_none_or(|range| {
+            angles
+                .azimuth
+                .is_some_and(|azimuth| range.contains_heading(azimuth))
+        });
+        tilt_ok && roll_ok && azimuth_ok
+    }
+}
+
+#[cfg(test)]
+mod test_angles {
+    use super::*;
+    use crate::test_util::v;
+
+    const EPSILON: f32 = 1e-3;
+
+    fn assert_angle(actual: Option<f32>, expected: f32) {
+        let actual = actual.unwrap();
+        assert!(
+            (actual - expected).abs() < EPSILON,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn parse_mapping() {
+        assert_eq!("+x,+z".parse(), Ok(AxisMapping::DEFAULT));
+        assert_eq!(
+            "-Y, z".parse(),
+            Ok(AxisMapping {
+                tip: SignedAxis::Y.flipped(),
+                top: SignedAxis::Z,
+            })
+        );
+        assert!("x,x".parse::<AxisMapping>().is_err());
+        assert!("x,w".parse::<AxisMapping>().is_err());
+        assert!("xz".parse::<AxisMapping>().is_err());
+        assert_eq!(AxisMapping::DEFAULT.to_string(), "+x,+z");
+    }
+
+    #[test]
+    fn vertical() {
+        // tip (+x) straight down, so up is -x
+        let angles = PenAngles::from_gravity(v(-9.81, 0., 0.), AxisMapping::DEFAULT).unwrap();
+        assert_angle(Some(angles.tilt), 0.);
+        assert_eq!(angles.roll, None);
+        assert_eq!(angles.azimuth, None);
+    }
+
+    #[test]
+    fn tilted() {
+        // lying flat, chip up
+        let angles = PenAngles::from_gravity(v(0., 0., 9.81), AxisMapping::DEFAULT).unwrap();
+        assert_angle(Some(angles.tilt), 90.);
+        assert_angle(angles.roll, 0.);
+
+        // tip 30° from straight down, chip facing up
+        let (sin, cos) = 30_f32.to_radians().sin_cos();
+        let angles = PenAngles::from_gravity(v(-cos, 0., sin), AxisMapping::DEFAULT).unwrap();
+        assert_angle(Some(angles.tilt), 30.);
+        assert_angle(angles.roll, 0.);
+
+        // lying flat, rolled so the chip faces sideways
+        let angles = PenAngles::from_gravity(v(0., 1., 0.), AxisMapping::DEFAULT).unwrap();
+        assert_angle(Some(angles.tilt), 90.);
+        assert_angle(angles.roll.map(f32::abs), 90.);
+
+        // upside down, chip towards the floor
+        let angles = PenAngles::from_gravity(v(0., 0., -1.), AxisMapping::DEFAULT).unwrap();
+        assert_angle(angles.roll.map(f32::abs), 180.);
+
+        assert_eq!(
+            PenAngles::from_gravity(v(0., 0., 0.), AxisMapping::DEFAULT),
+            None
+        );
+    }
+
+    #[test]
+    fn mapping_changes_axes() {
+        let mapping = AxisMapping {
+            tip: SignedAxis::Z.flipped(),
+            top: SignedAxis::Y,
+        };
+        // tip (-z) straight down
+        let angles = PenAngles::from_gravity(v(0., 0., 9.81), mapping).unwrap();
+        assert_angle(Some(angles.tilt), 0.);
+    }
+
+    #[test]
+    fn from_orientation() {
+        // identity: lying flat with the tip along world x
+        let identity = Quaternion {
+            v: v(0., 0., 0.),
+            s: 1.,
+        };
+        let angles = PenAngles::from_orientation(identity, AxisMapping::DEFAULT);
+        assert_angle(Some(angles.tilt), 90.);
+        assert_angle(angles.azimuth, 0.);
+        assert_angle(angles.roll, 0.);
+
+        // turned a quarter around world z, so the tip points along world y
+        let (sin, cos) = 45_f32.to_radians().sin_cos();
+        let turned = Quaternion {
+            v: v(0., 0., sin),
+            s: cos,
+        };
+        let angles = PenAngles::from_orientation(turned, AxisMapping::DEFAULT);
+        assert_angle(angles.azimuth, 90.);
+    }
+
+    #[test]
+    fn window() {
+        let window = AngleWindow {
+            tilt: Some("30..45".parse().unwrap()),
+            roll: Some("-10..10".parse().unwrap()),
+            azimuth: None,
+        };
+        let mut angles = PenAngles {
+            tilt: 35.,
+            azimuth: None,
+            roll: Some(0.),
+        };
+        assert!(window.contains(&angles));
+        angles.tilt = 50.;
+        assert!(!window.contains(&angles));
+        angles.tilt = 35.;
+        angles.roll = None;
+        assert!(!window.contains(&angles));
+        assert!(AngleWindow::default().contains(&angles));
+
+        let north: AngleRange = "350..10".parse().unwrap();
+        assert!(north.contains_heading(355.));
+        assert!(north.contains_heading(5.));
+        assert!(!north.contains_heading(180.));
+        assert!("30-45".parse::<AngleRange>().is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! Owns the serial connection to pensel and shares it over the network, so several processes can
+//! use one pen at once
+use clap::{Arg, ArgAction, Command};
+use heapless::spsc::Queue;
+use std::{
+    net::{SocketAddr, TcpListener},
+    ptr::addr_of_mut,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use notepad::{
+    bridge::{self, Hub, Message, Transport},
+    comms::{self, SampleSource},
+    recording::{Stream, TimedSample},
+    registration::{self, Registration},
+    replay::{Replay, Speed},
+    shell,
+    types::{self, imu},
+};
+use pensel_types::cli;
+
+static mut A_QUEUE: Queue<imu::AccelerationVector, { types::ACC_QUEUE_SIZE }> = Queue::new();
+static mut G_QUEUE: Queue<imu::GravityVector, { types::GRAV_QUEUE_SIZE }> = Queue::new();
+
+/// How often the bridge logs how it's doing
+const STATUS_PERIOD: Duration = Duration::from_secs(10);
+
+fn main() {
+    let matches = Command::new("bridge")
+        .about("Shares pensel's samples over TCP, WebSocket and UDP multicast, and takes commands back")
+        .arg(
+            Arg::new("port")
+                .short('p')
+                .long("port")
+                .value_name("PORT")
+                .help("Serial port pensel is on. Defaults to the first one named PENSEL"),
+        )
+        .arg(
+            Arg::new("axis-map")
+                .long("axis-map")
+                .value_name("MAP")
+                .value_parser(clap::value_parser!(imu::AxisMap))
+                .allow_hyphen_values(true)
+                .conflicts_with("replay")
+                .help("Sets the BNO055's axis map on connecting: P0-P7, or the axes x,y,z take, like -y,+x,+z. Pensel forgets it on reset"),
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .value_name("FILE")
+                .help("Shares a recording instead of a pen"),
+        )
+        .arg(
+            Arg::new("speed")
+                .long("speed")
+                .value_name("SPEED")
+                .value_parser(clap::value_parser!(Speed))
+                .default_value("realtime")
+                .help("How fast --replay plays back: 'realtime', 'max' or a factor like '4x'"),
+        )
+        .arg(
+            Arg::new("tcp")
+                .long("tcp")
+                .value_name("ADDRESS")
+                .value_parser(clap::value_parser!(SocketAddr))
+                .default_value("127.0.0.1:7878")
+                .help("Where to accept clients of length prefixed binary messages. Use 0.0.0.0:7878 to take them from the network too"),
+        )
+        .arg(
+            Arg::new("websocket")
+                .long("websocket")
+                .value_name("ADDRESS")
+                .value_parser(clap::value_parser!(SocketAddr))
+                .default_value("127.0.0.1:7879")
+                .help("Where to accept WebSocket clients of JSON messages. Use 0.0.0.0:7879 to take them from the network too"),
+        )
+        .arg(
+            Arg::new("multicast")
+                .long("multicast")
+                .value_name("GROUP:PORT")
+                .value_parser(clap::value_parser!(SocketAddr))
+                .help("Also sends every message as a UDP datagram to GROUP:PORT, e.g. 239.255.42.1:7880"),
+        )
+        .arg(
+            Arg::new("token")
+                .long("token")
+                .value_name("TOKEN")
+                .help("Lets clients that send TOKEN send commands to pensel. Without it, clients can only listen"),
+        )
+        .arg(
+            Arg::new("streams")
+                .long("streams")
+                .value_name("STREAMS")
+                .value_parser(clap::value_parser!(Stream))
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .default_value("accel,gravity")
+                .help("Comma separated streams to enable on the pen, out of 'accel' and 'gravity'"),
+        )
+        .arg(
+            Arg::new("registration")
+                .long("registration")
+                .value_name("FILE")
+                .value_parser(|path: &str| Registration::load(path))
+                .help("Shares samples in the tool frame of the registration saved by `register`, instead of the BNO055's axes"),
+        )
+        .arg(
+            Arg::new("v")
+                .short('v')
+                .action(ArgAction::Count)
+                .help("Sets the level of verbosity"),
+        )
+        .get_matches();
+
+    let level = match matches.get_count("v") {
+        0 => log::Level::Info,
+        1 => log::Level::Debug,
+        _ => log::Level::Trace,
+    };
+    simple_logger::init_with_level(level).unwrap();
+
+    let config = bridge::Config {
+        token: matches.get_one::<String>("token").cloned(),
+        multicast: matches.get_one::<SocketAddr>("multicast").copied(),
+        ..bridge::Config::default()
+    };
+    let (hub, commands) = Hub::new(config).unwrap_or_else(|error| {
+        eprintln!("failed to set up multicast: {}", error);
+        std::process::exit(1);
+    });
+    for (arg, transport) in [("tcp", Transport::Tcp), ("websocket", Transport::WebSocket)] {
+        let address = matches.get_one::<SocketAddr>(arg).unwrap();
+        let listener = TcpListener::bind(address).unwrap_or_else(|error| {
+            eprintln!("failed to listen on {}: {}", address, error);
+            std::process::exit(1);
+        });
+        log::info!("accepting {} clients on {}", transport, address);
+        hub.serve(listener, transport);
+    }
+    if let Some(group) = matches.get_one::<SocketAddr>("multicast") {
+        log::info!("multicasting to {}", group);
+    }
+
+    let should_run = Arc::new(AtomicBool::new(true));
+    let should_run_ctrl_c = should_run.clone();
+    ctrlc::set_handler(move || {
+        should_run_ctrl_c.as_ref().store(false, Ordering::Release);
+    })
+    .unwrap();
+    let started = Instant::now();
+    let registration = matches.get_one::<Registration>("registration").copied();
+
+    if let Some(path) = matches.get_one::<String>("replay") {
+        let speed = *matches.get_one::<Speed>("speed").unwrap();
+        let mut replay = Replay::open(path, speed).expect("failed to open recording");
+        let (a_producer, mut a_consumer) = unsafe { (*addr_of_mut!(A_QUEUE)).split() };
+        let (g_producer, mut g_consumer) = unsafe { (*addr_of_mut!(G_QUEUE)).split() };
+        let should_run_thread_ref = should_run.clone();
+        let sender = thread::spawn(move || {
+            replay.stream_until(a_producer, g_producer, &should_run_thread_ref);
+        });
+        log::info!("sharing {}", path);
+
+        let mut last_status = Instant::now();
+        while should_run.as_ref().load(Ordering::Acquire) {
+            let a = a_consumer.dequeue().map(types::Sample::Accel);
+            let g = g_consumer.dequeue().map(types::Sample::Grav);
+            if a.is_none() && g.is_none() {
+                if sender.is_finished() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+            let registered = a
+                .into_iter()
+                .chain(g)
+                .filter_map(|sample| registration::register(registration.as_ref(), sample));
+            for sample in registered {
+                hub.broadcast(&Message::Sample(TimedSample {
+                    timestamp: started.elapsed(),
+                    sample,
+                }));
+            }
+            for command in commands.try_iter() {
+                log::warn!(
+                    "ignoring command '{}', there's no pen to send it to",
+                    command
+                );
+            }
+            log_status(&hub, &mut last_status);
+        }
+        log::info!("replay finished");
+        return;
+    }
+
+    let mut serial = match matches.get_one::<String>("port") {
+        Some(name) => comms::PenselSerial::new_from_name(name),
+        None => comms::PenselSerial::new_first_matching(),
+    };
+    // pensel forgets the axis map on reset, so it's sent every time
+    if let Some(map) = matches.get_one::<imu::AxisMap>("axis-map") {
+        serial.set_axis_map(*map).unwrap();
+    }
+    let mut writer = serial.try_clone().expect("failed to clone serial port");
+    log::info!(
+        "sharing pensel on {}",
+        serial
+            .port_name()
+            .unwrap_or_else(|| "unknown port".to_owned())
+    );
+
+    let streams: Vec<Stream> = matches
+        .get_many::<Stream>("streams")
+        .unwrap()
+        .copied()
+        .collect();
+    let enable_streaming_cmd = streams.iter().fold(cli::CMD_IMU.to_owned(), |cmd, stream| {
+        format!("{} --{}", cmd, stream.name())
+    });
+    writer.send_command(&enable_streaming_cmd).unwrap();
+
+    let should_run_thread_ref = should_run.clone();
+    let reader_hub = hub.clone();
+    let reader = thread::spawn(move || {
+        serial.read_lines_until(
+            |raw| {
+                let line = shell::clean_line(raw);
+                let message =
+                    match types::Sample::from_parsed(comms::PenselSerial::parse_line(&line)) {
+                        Some(sample) => {
+                            let Some(sample) =
+                                registration::register(registration.as_ref(), sample)
+                            else {
+                                return;
+                            };
+                            Message::Sample(TimedSample {
+                                timestamp: started.elapsed(),
+                                sample,
+                            })
+                        }
+                        None if line.is_empty() => return,
+                        None => Message::Line(line),
+                    };
+                reader_hub.broadcast(&message);
+            },
+            &should_run_thread_ref,
+        );
+    });
+
+    let mut last_status = Instant::now();
+    while should_run.as_ref().load(Ordering::Acquire) && !reader.is_finished() {
+        match commands.recv_timeout(Duration::from_millis(100)) {
+            Ok(command) => {
+                log::info!("sending '{}'", command);
+                if let Err(error) = writer.send_command(&command) {
+                    log::warn!("failed to send '{}': {}", command, error);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        log_status(&hub, &mut last_status);
+    }
+    should_run.as_ref().store(false, Ordering::Release);
+    reader.join().ok();
+}
+
+/// Logs how many clients there are and what they've missed, every [`STATUS_PERIOD`]
+fn log_status(hub: &Hub, last_status: &mut Instant) {
+    if last_status.elapsed() >= STATUS_PERIOD {
+        *last_status = Instant::now();
+        log::info!(
+            "{} clients, {} messages dropped",
+            hub.clients(),
+            hub.dropped()
+        );
+    }
+}
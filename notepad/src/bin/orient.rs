@@ -0,0 +1,500 @@
+//! Shows which way pensel is pointing: a wireframe pen rotating along with the real one
+use clap::{Arg, ArgAction, Command};
+use heapless::spsc::Queue;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style},
+    symbols::Marker,
+    text::Line,
+    widgets::{
+        canvas::{self, Canvas},
+        Block, Paragraph,
+    },
+    DefaultTerminal, Frame,
+};
+use std::{
+    ptr::addr_of_mut,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use notepad::{
+    angles::{AxisMapping, PenAngles},
+    comms::{self, SampleSource},
+    orientation::{self, Estimator, Madgwick},
+    recording::TimedSample,
+    registration::{self, Registration},
+    replay::{Replay, Speed},
+    types::{self, imu},
+    wireframe::{self, Camera, Model, Part, Segment},
+};
+use pensel_types::{
+    cli,
+    mint::{Quaternion, Vector3},
+};
+
+static mut A_QUEUE: Queue<imu::AccelerationVector, { types::ACC_QUEUE_SIZE }> = Queue::new();
+static mut G_QUEUE: Queue<imu::GravityVector, { types::GRAV_QUEUE_SIZE }> = Queue::new();
+
+/// How often the screen is redrawn
+const FRAME_PERIOD: Duration = Duration::from_millis(33);
+/// How far the arrow keys turn the camera, in degrees
+const CAMERA_STEP: f32 = 10.;
+/// Width of the readouts next to the view
+const READOUT_WIDTH: u16 = 30;
+/// How much of the world the view shows vertically, either side of the origin
+const VIEW_HALF_HEIGHT: f64 = 1.6;
+
+/// Where the pen's orientation comes from
+enum Pose {
+    /// straight from each gravity sample. Quick, but jittery.
+    Gravity(Quaternion<f32>),
+    /// fused over time, which smooths out shaking
+    Fusion(Estimator<Madgwick>),
+}
+
+impl Pose {
+    fn feed(&mut self, timed: &TimedSample) {
+        match self {
+            Self::Gravity(orientation) => {
+                if let types::Sample::Grav(gravity) = timed.sample {
+                    *orientation = orientation::from_up(gravity.m_s2());
+                }
+            }
+            Self::Fusion(estimator) => {
+                estimator.feed(timed);
+            }
+        }
+    }
+
+    fn orientation(&self) -> Quaternion<f32> {
+        match self {
+            Self::Gravity(orientation) => *orientation,
+            Self::Fusion(estimator) => estimator.orientation(),
+        }
+    }
+}
+
+struct App {
+    source: String,
+    /// set once the source has stopped sending anything
+    finished: bool,
+    pose: Pose,
+    mapping: AxisMapping,
+    model: Model,
+    camera: Camera,
+    marker: Marker,
+    /// orientation shown while paused
+    frozen: Option<Quaternion<f32>>,
+    samples: usize,
+    started: Instant,
+}
+
+impl App {
+    fn on_sample(&mut self, sample: types::Sample) {
+        self.samples += 1;
+        self.pose.feed(&TimedSample {
+            timestamp: self.started.elapsed(),
+            sample,
+        });
+    }
+
+    /// Handles a key press
+    ///
+    /// # Returns
+    /// `false` if it's time to quit
+    fn on_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Left => self.camera = self.camera.orbited(-CAMERA_STEP, 0.),
+            KeyCode::Right => self.camera = self.camera.orbited(CAMERA_STEP, 0.),
+            KeyCode::Up => self.camera = self.camera.orbited(0., CAMERA_STEP),
+            KeyCode::Down => self.camera = self.camera.orbited(0., -CAMERA_STEP),
+            KeyCode::Char('r') => self.camera = Camera::DEFAULT,
+            KeyCode::Char('m') => {
+                self.model = match self.model {
+                    Model::Pen => Model::Axes,
+                    Model::Axes => Model::Pen,
+                };
+            }
+            KeyCode::Char(' ') => {
+                self.frozen = match self.frozen {
+                    Some(_) => None,
+                    None => Some(self.pose.orientation()),
+                };
+            }
+            _ => (),
+        }
+        true
+    }
+
+    fn orientation(&self) -> Quaternion<f32> {
+        self.frozen.unwrap_or_else(|| self.pose.orientation())
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main_area, keys_area] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [view_area, readout_area] =
+            Layout::horizontal([Constraint::Min(0), Constraint::Length(READOUT_WIDTH)])
+                .areas(main_area);
+
+        let orientation = self.orientation();
+        let mut angles = PenAngles::from_orientation(orientation, self.mapping);
+        // heading takes a gyro or magnetometer, and pensel streams neither, so either pose's
+        // azimuth is only wherever tilting left it
+        angles.azimuth = None;
+        self.draw_view(frame, view_area, orientation, &angles);
+        self.draw_readout(frame, readout_area, &angles);
+        frame.render_widget(
+            Paragraph::new("q quit  arrows turn view  r reset view  m model  space pause")
+                .style(Style::new().fg(Color::DarkGray)),
+            keys_area,
+        );
+    }
+
+    fn draw_view(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        orientation: Quaternion<f32>,
+        angles: &PenAngles,
+    ) {
+        let camera = self.camera;
+        let floor = wireframe::PEN_LENGTH / 2.;
+        let ground = wireframe::ground(wireframe::PEN_LENGTH * 1.5, floor, 7);
+        let model: Vec<Segment> = self
+            .model
+            .segments(self.mapping)
+            .into_iter()
+            .map(|segment| segment.rotated(orientation))
+            .collect();
+        let tip = orientation::rotate(
+            orientation,
+            Vector3 {
+                x: self.mapping.tip.unit().x * floor,
+                y: self.mapping.tip.unit().y * floor,
+                z: self.mapping.tip.unit().z * floor,
+            },
+        );
+
+        // keep the view's pixels square: braille packs 2x4 dots into cells about twice as tall
+        // as they are wide
+        let inner_width = f64::from(area.width.saturating_sub(2).max(1));
+        let inner_height = f64::from(area.height.saturating_sub(2).max(1));
+        let half_width = VIEW_HALF_HEIGHT * inner_width / (inner_height * 2.);
+
+        let title = match angles.azimuth {
+            Some(azimuth) => format!(
+                " tilt {:.1}°  azimuth {:.1}° ({}) ",
+                angles.tilt, azimuth, self.source
+            ),
+            None => format!(" tilt {:.1}° ({}) ", angles.tilt, self.source),
+        };
+        let view = Canvas::default()
+            .block(Block::bordered().title(title))
+            .marker(self.marker)
+            .x_bounds([-half_width, half_width])
+            .y_bounds([-VIEW_HALF_HEIGHT, VIEW_HALF_HEIGHT])
+            .paint(|ctx| {
+                let line = |segment: &Segment| {
+                    let (x1, y1) = camera.project(segment.from);
+                    let (x2, y2) = camera.project(segment.to);
+                    canvas::Line::new(
+                        f64::from(x1),
+                        f64::from(y1),
+                        f64::from(x2),
+                        f64::from(y2),
+                        part_color(segment.part),
+                    )
+                };
+                for segment in &ground {
+                    ctx.draw(&line(segment));
+                }
+
+                // where the tip is over the floor, and which way it's heading
+                let shadow = Vector3 {
+                    x: tip.x,
+                    y: tip.y,
+                    z: -floor,
+                };
+                let centre = Vector3 {
+                    x: 0.,
+                    y: 0.,
+                    z: -floor,
+                };
+                ctx.draw(&line(&Segment {
+                    from: centre,
+                    to: shadow,
+                    part: Part::Tip,
+                }));
+                // straight down, to measure tilt against
+                ctx.draw(&line(&Segment {
+                    from: Vector3 {
+                        x: 0.,
+                        y: 0.,
+                        z: 0.,
+                    },
+                    to: centre,
+                    part: Part::Ground,
+                }));
+                ctx.layer();
+
+                for segment in &model {
+                    ctx.draw(&line(segment));
+                }
+                let (x, y) = camera.project(tip);
+                ctx.print(
+                    f64::from(x),
+                    f64::from(y),
+                    Line::styled("tip", Style::new().fg(part_color(Part::Tip))),
+                );
+                let (x, y) = camera.project(centre);
+                ctx.print(
+                    f64::from(x),
+                    f64::from(y),
+                    Line::styled(
+                        format!("{:.0}°", angles.tilt),
+                        Style::new().fg(Color::DarkGray),
+                    ),
+                );
+            });
+        frame.render_widget(view, area);
+    }
+
+    fn draw_readout(&self, frame: &mut Frame, area: Rect, angles: &PenAngles) {
+        let angle = |angle: Option<f32>| {
+            angle.map_or_else(|| "      -".to_owned(), |angle| format!("{:6.1}°", angle))
+        };
+        let status = if self.finished {
+            "finished"
+        } else if self.frozen.is_some() {
+            "paused"
+        } else {
+            "live"
+        };
+        let lines = vec![
+            Line::raw(format!("tilt     {}", angle(Some(angles.tilt)))),
+            Line::raw(format!("azimuth  {}", angle(angles.azimuth))),
+            Line::raw(format!("roll     {}", angle(angles.roll))),
+            Line::raw(""),
+            Line::raw(format!("axes     {}", self.mapping)),
+            Line::raw(format!("model    {}", self.model)),
+            Line::raw(format!(
+                "view     {:.0}° / {:.0}°",
+                self.camera.azimuth, self.camera.elevation
+            )),
+            Line::raw(""),
+            Line::raw(format!("status   {}", status)),
+            Line::raw(format!("samples  {}", self.samples)),
+        ];
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" pen ")),
+            area,
+        );
+    }
+}
+
+const fn part_color(part: Part) -> Color {
+    match part {
+        Part::Body => Color::White,
+        Part::Tip => Color::Yellow,
+        Part::Top => Color::Cyan,
+        Part::Axis(0) => Color::Rgb(0xFF, 0x00, 0x00),
+        Part::Axis(1) => Color::Rgb(0x00, 0xFF, 0x00),
+        Part::Axis(_) => Color::Rgb(0xE0, 0x80, 0xFF),
+        Part::Ground => Color::DarkGray,
+    }
+}
+
+fn main() {
+    let matches = Command::new("orient")
+        .about("Shows which way pensel is pointing, as a wireframe pen rotating in the terminal")
+        .arg(
+            Arg::new("port")
+                .short('p')
+                .long("port")
+                .value_name("PORT")
+                .help("Serial port pensel is on. Defaults to the first one named PENSEL"),
+        )
+        .arg(
+            Arg::new("axis-map")
+                .long("axis-map")
+                .value_name("MAP")
+                .value_parser(clap::value_parser!(imu::AxisMap))
+                .allow_hyphen_values(true)
+                .conflicts_with("replay")
+                .help("Sets the BNO055's axis map on connecting: P0-P7, or the axes x,y,z take, like -y,+x,+z. Pensel forgets it on reset"),
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .value_name("FILE")
+                .help("Plays back a recording instead of streaming from a pen"),
+        )
+        .arg(
+            Arg::new("speed")
+                .long("speed")
+                .value_name("SPEED")
+                .value_parser(clap::value_parser!(Speed))
+                .default_value("realtime")
+                .help("How fast --replay plays back: 'realtime', 'max' or a factor like '4x'"),
+        )
+        .arg(
+            Arg::new("axes")
+                .long("axes")
+                .value_name("TIP,TOP")
+                .value_parser(clap::value_parser!(AxisMapping))
+                .default_value("+x,+z")
+                .help("Sensor axes pointing towards the pen's tip and out of its top"),
+        )
+        .arg(
+            Arg::new("registration")
+                .long("registration")
+                .value_name("FILE")
+                .value_parser(|path: &str| Registration::load(path))
+                .conflicts_with("axes")
+                .help("Shows the pen in the work frame saved by `register`, instead of along --axes"),
+        )
+        .arg(
+            Arg::new("model")
+                .short('m')
+                .long("model")
+                .value_name("MODEL")
+                .value_parser(clap::value_parser!(Model))
+                .default_value("pen")
+                .help("'pen' for a pen, or 'axes' for the sensor's axes. Toggle it with 'm'"),
+        )
+        .arg(
+            Arg::new("fusion")
+                .long("fusion")
+                .help("Smooths the orientation with sensor fusion, instead of following each gravity sample")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("blocks")
+                .long("blocks")
+                .help("Draws with half blocks, for terminals whose fonts lack braille")
+                .action(ArgAction::SetTrue),
+        )
+        .get_matches();
+
+    let should_run = Arc::new(AtomicBool::new(true));
+    let should_run_thread_ref = should_run.clone();
+    let (mut source, name): (Box<dyn SampleSource>, _) =
+        if let Some(path) = matches.get_one::<String>("replay") {
+            let speed = *matches.get_one::<Speed>("speed").unwrap();
+            let replay = Replay::open(path, speed).expect("failed to open recording");
+            (Box::new(replay), path.clone())
+        } else {
+            let mut serial = match matches.get_one::<String>("port") {
+                Some(name) => comms::PenselSerial::new_from_name(name),
+                None => comms::PenselSerial::new_first_matching(),
+            };
+            // pensel forgets the axis map on reset, so it's sent every time
+            if let Some(map) = matches.get_one::<imu::AxisMap>("axis-map") {
+                serial.set_axis_map(*map).unwrap();
+            }
+            let port = serial
+                .port_name()
+                .unwrap_or_else(|| "unknown port".to_owned());
+
+            // only gravity says anything about orientation
+            let enable_streaming_cmd = format!("{} --{}", cli::CMD_IMU, cli::ARG_GRAVITY);
+            serial.send_command(&enable_streaming_cmd).unwrap();
+            (Box::new(serial), port)
+        };
+
+    let (a_producer, mut a_consumer) = unsafe { (*addr_of_mut!(A_QUEUE)).split() };
+    let (g_producer, mut g_consumer) = unsafe { (*addr_of_mut!(G_QUEUE)).split() };
+    let sender = thread::spawn(move || {
+        source.stream_until(a_producer, g_producer, &should_run_thread_ref);
+    });
+
+    let registration = matches.get_one::<Registration>("registration").copied();
+
+    let mut app = App {
+        source: name,
+        finished: false,
+        pose: if matches.get_flag("fusion") {
+            Pose::Fusion(Estimator::new(Madgwick::default()))
+        } else {
+            Pose::Gravity(orientation::from_up(Vector3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            }))
+        },
+        mapping: registration
+            .as_ref()
+            .map_or(*matches.get_one::<AxisMapping>("axes").unwrap(), |_| {
+                Registration::MAPPING
+            }),
+        model: *matches.get_one::<Model>("model").unwrap(),
+        camera: Camera::DEFAULT,
+        marker: if matches.get_flag("blocks") {
+            Marker::HalfBlock
+        } else {
+            Marker::Braille
+        },
+        frozen: None,
+        samples: 0,
+        started: Instant::now(),
+    };
+
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut app, || {
+        // linear acceleration has gravity removed, so says nothing about orientation
+        while a_consumer.dequeue().is_some() {}
+        let mut samples = Vec::new();
+        while let Some(g) = g_consumer.dequeue() {
+            let sample = types::Sample::Grav(g);
+            // registered samples are in the tool frame
+            samples.extend(registration::register(registration.as_ref(), sample));
+        }
+        (samples, sender.is_finished())
+    });
+    ratatui::restore();
+
+    should_run.as_ref().store(false, Ordering::Release);
+    if let Err(error) = result {
+        eprintln!("orientation view failed: {}", error);
+        std::process::exit(1);
+    }
+}
+
+/// Runs the view until the user quits. `poll` hands over the samples that arrived since it was
+/// last called, and whether the source has finished.
+fn run(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    mut poll: impl FnMut() -> (Vec<types::Sample>, bool),
+) -> std::io::Result<()> {
+    let mut last_frame: Option<Instant> = None;
+    loop {
+        let (samples, finished) = poll();
+        for sample in samples {
+            app.on_sample(sample);
+        }
+        app.finished = finished;
+
+        if last_frame.is_none_or(|drawn| drawn.elapsed() >= FRAME_PERIOD) {
+            last_frame = Some(Instant::now());
+            terminal.draw(|frame| app.draw(frame))?;
+        }
+
+        if event::poll(Duration::from_millis(5))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !app.on_key(key.code, key.modifiers) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
@@ -1,127 +1,928 @@
-//! Example that just prints all packets
-use console::Term;
+//! Full-screen terminal dashboard for pensel's gravity and acceleration streams
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use heapless::spsc::Queue;
-use rgb::RGB8;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    symbols::Marker,
+    text::{Line, Span},
+    widgets::{Axis, Block, Chart, Dataset, GraphType, Paragraph},
+    DefaultTerminal, Frame,
+};
 use std::{
+    collections::VecDeque,
+    fs::File,
+    io::BufWriter,
+    path::PathBuf,
+    ptr::addr_of_mut,
+    str::FromStr,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc,
     },
     thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use textplots::{Chart, ColorPlot, Shape};
 
 use notepad::{
-    comms,
+    comms::{self, SampleSource},
+    filters::{FilterSpec, SampleFilter},
+    recording::{self, RecordingWriter, Stream, TimedSample},
+    registration::{self, Registration},
+    replay::{Replay, Speed},
+    shell::{self, Response},
+    spectrum::{self, Spectrogram},
     types::{self, imu},
 };
-use pensel_types::cli;
+use pensel_types::{cli, mint::Vector3};
 
 static mut A_QUEUE: Queue<imu::AccelerationVector, { types::ACC_QUEUE_SIZE }> = Queue::new();
 static mut G_QUEUE: Queue<imu::GravityVector, { types::GRAV_QUEUE_SIZE }> = Queue::new();
 
-const PURPLE: RGB8 = RGB8::new(0xE0, 0x80, 0xFF);
-const RED: RGB8 = RGB8::new(0xFF, 0x00, 0x00);
-const GREEN: RGB8 = RGB8::new(0x00, 0xFF, 0x00);
+const X_COLOR: Color = Color::Rgb(0xFF, 0x00, 0x00);
+const Y_COLOR: Color = Color::Rgb(0x00, 0xFF, 0x00);
+const Z_COLOR: Color = Color::Rgb(0xE0, 0x80, 0xFF);
+
+/// How much time the charts show to begin with, and how far in they can zoom
+const DEFAULT_WINDOW: u64 = 10;
+const MIN_WINDOW: Duration = Duration::from_secs(1);
+/// How many seconds of samples are kept, which is how far out the charts can zoom
+const DEFAULT_HISTORY: u64 = 120;
+/// How often the screen is redrawn
+const FRAME_PERIOD: Duration = Duration::from_millis(50);
+/// How many firmware log lines are kept around
+const LOG_LINES: usize = 200;
+/// Height of the firmware log pane, borders included
+const LOG_HEIGHT: u16 = 8;
+/// Width of the numeric readouts next to each chart
+const READOUT_WIDTH: u16 = 24;
+/// Samples per spectrogram column, how many samples apart columns are, and how many are kept
+const SPECTROGRAM_WINDOW: usize = 128;
+const SPECTROGRAM_HOP: usize = 10;
+const SPECTROGRAM_COLUMNS: usize = 200;
+
+/// Where samples are coming from
+enum Connection {
+    /// a live pen, on this port
+    Serial(String),
+    /// a recording, from this file
+    Replay(String),
+}
+
+/// What the charts and readouts are in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Units {
+    /// m/s²
+    Si,
+    /// BNO055 counts, as pensel streams them
+    Raw,
+}
+
+impl Units {
+    const fn label(self) -> &'static str {
+        match self {
+            Self::Si => "m/s²",
+            Self::Raw => "LSB",
+        }
+    }
+
+    /// Converts `value`, in m/s², to these units
+    fn convert(self, value: Vector3<f32>) -> Vector3<f32> {
+        match self {
+            Self::Si => value,
+            Self::Raw => Vector3 {
+                x: value.x * imu::scale::ACCEL_LSB_PER_M_S2,
+                y: value.y * imu::scale::ACCEL_LSB_PER_M_S2,
+                z: value.z * imu::scale::ACCEL_LSB_PER_M_S2,
+            },
+        }
+    }
+}
+
+impl FromStr for Units {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "si" => Ok(Self::Si),
+            "raw" => Ok(Self::Raw),
+            _ => Err(format!("unknown units '{}', expected 'si' or 'raw'", s)),
+        }
+    }
+}
+
+/// Fixed bounds for the charts' y axes, given as "MIN..MAX"
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct YRange {
+    low: f64,
+    high: f64,
+}
+
+impl FromStr for YRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (low, high) = s
+            .split_once("..")
+            .ok_or_else(|| format!("expected MIN..MAX, got '{}'", s))?;
+        let parse = |bound: &str| {
+            bound
+                .trim()
+                .parse::<f64>()
+                .map_err(|error| format!("bad bound '{}': {}", bound, error))
+        };
+        let (low, high) = (parse(low)?, parse(high)?);
+        if low >= high {
+            return Err(format!("{} isn't below {}", low, high));
+        }
+        Ok(Self { low, high })
+    }
+}
+
+/// Everything the dashboard shows about one stream
+struct StreamView {
+    title: &'static str,
+    visible: bool,
+    /// `(seconds since start, value)`, going back [`App::history`]
+    history: VecDeque<(f64, Vector3<f32>)>,
+    latest: Option<Vector3<f32>>,
+    received: usize,
+    /// samples the reader had to throw away because we didn't keep up
+    dropped: Arc<AtomicUsize>,
+    /// when the samples of the last second arrived
+    arrivals: VecDeque<Instant>,
+}
+
+impl StreamView {
+    fn new(title: &'static str) -> Self {
+        Self {
+            title,
+            visible: true,
+            history: VecDeque::new(),
+            latest: None,
+            received: 0,
+            dropped: Arc::new(AtomicUsize::new(0)),
+            arrivals: VecDeque::new(),
+        }
+    }
+
+    /// Adds `value`, which arrived `now` seconds since start, keeping `history` seconds. The
+    /// chart stays as it is while `paused`, but the readouts keep going.
+    fn push(&mut self, now: f64, value: Vector3<f32>, history: f64, paused: bool) {
+        self.latest = Some(value);
+        self.received += 1;
+        self.arrivals.push_back(Instant::now());
+        if !paused {
+            self.history.push_back((now, value));
+            while self.history.front().is_some_and(|(t, _)| now - t > history) {
+                self.history.pop_front();
+            }
+        }
+    }
+
+    /// Samples per second, over the last second
+    #[allow(clippy::cast_precision_loss)]
+    fn rate(&mut self) -> f32 {
+        while self
+            .arrivals
+            .front()
+            .is_some_and(|arrived| arrived.elapsed() > Duration::from_secs(1))
+        {
+            self.arrivals.pop_front();
+        }
+        self.arrivals.len() as f32
+    }
+}
+
+/// A recording started from the dashboard
+struct ActiveRecording {
+    path: PathBuf,
+    writer: RecordingWriter<BufWriter<File>>,
+    samples: usize,
+}
+
+struct App {
+    connection: Connection,
+    /// set once the source has stopped sending anything
+    finished: bool,
+    device: types::DeviceInfo,
+    accel: StreamView,
+    gravity: StreamView,
+    filter: SampleFilter,
+    /// turns shown samples into the tool frame. Recordings stay as the pen sent them.
+    registration: Option<Registration>,
+    spectrogram: Spectrogram,
+    /// when the last spectrogram window's worth of acceleration arrived, to estimate the rate from
+    accel_arrivals: VecDeque<Duration>,
+    show_spectrogram: bool,
+    logs: VecDeque<String>,
+    window: Duration,
+    /// how much of the streams is kept, and how far out the charts can zoom
+    history: Duration,
+    /// `None` to fit the y axes to what's shown
+    y_range: Option<YRange>,
+    units: Units,
+    /// the streams pensel was asked for
+    streams: Vec<Stream>,
+    /// seconds since start the charts were frozen at
+    paused_at: Option<f64>,
+    recording: Option<ActiveRecording>,
+    record_dir: PathBuf,
+    /// the outcome of the last thing the user asked for
+    status: String,
+    started: Instant,
+}
+
+impl App {
+    fn seconds(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+
+    fn on_sample(&mut self, sample: types::Sample) {
+        if let Some(recording) = self.recording.as_mut() {
+            match recording.writer.write_sample(sample) {
+                Ok(()) => recording.samples += 1,
+                Err(error) => self.status = format!("recording failed: {}", error),
+            }
+        }
+
+        let Some(sample) = registration::register(self.registration.as_ref(), sample) else {
+            return;
+        };
+        let timed = self.filter.apply(TimedSample {
+            timestamp: self.started.elapsed(),
+            sample,
+        });
+        let now = self.seconds();
+        let history = self.history.as_secs_f64();
+        let paused = self.paused_at.is_some();
+        match timed.sample {
+            types::Sample::Accel(a) => {
+                if self.accel_arrivals.len() == SPECTROGRAM_WINDOW {
+                    self.accel_arrivals.pop_front();
+                }
+                self.accel_arrivals.push_back(timed.timestamp);
+                if let Some(rate) =
+                    spectrum::estimate_sample_rate(self.accel_arrivals.make_contiguous())
+                {
+                    self.spectrogram.set_sample_rate(rate);
+                }
+                let a = a.m_s2();
+                self.spectrogram.push(a);
+                self.accel.push(now, self.units.convert(a), history, paused);
+            }
+            types::Sample::Grav(g) => {
+                self.gravity
+                    .push(now, self.units.convert(g.m_s2()), history, paused);
+            }
+        }
+    }
+
+    fn on_log(&mut self, line: String) {
+        if self.logs.len() == LOG_LINES {
+            self.logs.pop_front();
+        }
+        self.logs.push_back(line);
+    }
+
+    /// Handles a key press
+    ///
+    /// # Returns
+    /// `false` if it's time to quit
+    fn on_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char(' ') => {
+                self.paused_at = match self.paused_at {
+                    Some(_) => None,
+                    None => Some(self.seconds()),
+                };
+            }
+            KeyCode::Char('+' | '=') => self.window = (self.window / 2).max(MIN_WINDOW),
+            KeyCode::Char('-' | '_') => self.window = (self.window * 2).min(self.history),
+            KeyCode::Char('a') => self.accel.visible = !self.accel.visible,
+            KeyCode::Char('g') => self.gravity.visible = !self.gravity.visible,
+            KeyCode::Char('s') => self.show_spectrogram = !self.show_spectrogram,
+            KeyCode::Char('r') => self.toggle_recording(),
+            _ => (),
+        }
+        true
+    }
+
+    fn toggle_recording(&mut self) {
+        if let Some(mut recording) = self.recording.take() {
+            self.status = match recording.writer.flush() {
+                Ok(()) => format!(
+                    "saved {} samples to {}",
+                    recording.samples,
+                    recording.path.display()
+                ),
+                Err(error) => format!("failed to save {}: {}", recording.path.display(), error),
+            };
+            return;
+        }
+
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = self.record_dir.join(format!("pensel-{}.txt", secs));
+        let header = recording::Header::new(self.device.clone(), self.streams.clone());
+        match RecordingWriter::create(&path, &header) {
+            Ok(writer) => {
+                self.status = format!("recording to {}", path.display());
+                self.recording = Some(ActiveRecording {
+                    path,
+                    writer,
+                    samples: 0,
+                });
+            }
+            Err(error) => {
+                self.status = format!("failed to start recording {}: {}", path.display(), error);
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [status_area, body_area, log_area, keys_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(LOG_HEIGHT),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(Paragraph::new(self.status_line()), status_area);
+        self.draw_body(frame, body_area);
+        self.draw_logs(frame, log_area);
+        frame.render_widget(
+            Paragraph::new(
+                "q quit  space pause  +/- zoom  a accel  g gravity  s spectrogram  r record",
+            )
+            .style(Style::new().fg(Color::DarkGray)),
+            keys_area,
+        );
+    }
+
+    fn status_line(&self) -> Line<'static> {
+        let (connection, color) = match (&self.connection, self.finished) {
+            (Connection::Serial(port), false) => (format!("● connected {}", port), Color::Green),
+            (Connection::Serial(port), true) => (format!("● disconnected {}", port), Color::Red),
+            (Connection::Replay(path), false) => (format!("▶ replaying {}", path), Color::Green),
+            (Connection::Replay(path), true) => {
+                (format!("■ replay finished {}", path), Color::Yellow)
+            }
+        };
+        let mode = match self.paused_at {
+            Some(_) => Span::styled(" PAUSED ", Style::new().fg(Color::Black).bg(Color::Yellow)),
+            None => Span::styled(" LIVE ", Style::new().fg(Color::Black).bg(Color::Green)),
+        };
+        let mut spans = vec![
+            Span::styled(connection, Style::new().fg(color)),
+            Span::raw(format!(
+                "  pen {} fw {}  ",
+                self.device.pen_id, self.device.firmware
+            )),
+            mode,
+            Span::raw(format!("  window {}s  ", self.window.as_secs())),
+        ];
+        if self.registration.is_some() {
+            spans.push(Span::raw("tool frame  "));
+        }
+        if self.recording.is_some() {
+            spans.push(Span::styled(
+                "● REC ",
+                Style::new().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ));
+        }
+        spans.push(Span::raw(self.status.clone()));
+        Line::from(spans)
+    }
+
+    fn draw_body(&mut self, frame: &mut Frame, area: Rect) {
+        let now = self.paused_at.unwrap_or_else(|| self.seconds());
+        let window = self.window.as_secs_f64();
+        let (y_range, units) = (self.y_range, self.units);
+        let streams: Vec<&mut StreamView> = [&mut self.accel, &mut self.gravity]
+            .into_iter()
+            .filter(|stream| stream.visible)
+            .collect();
+        let panes = streams.len() + usize::from(self.show_spectrogram);
+        if panes == 0 {
+            return;
+        }
+        let areas = Layout::vertical(vec![Constraint::Fill(1); panes]).split(area);
+
+        for (stream, area) in streams.into_iter().zip(areas.iter()) {
+            let [chart_area, readout_area] =
+                Layout::horizontal([Constraint::Min(0), Constraint::Length(READOUT_WIDTH)])
+                    .areas(*area);
+            draw_chart(frame, chart_area, stream, now, window, y_range, units);
+            draw_readout(frame, readout_area, stream, units);
+        }
+        if self.show_spectrogram {
+            draw_spectrogram(frame, areas[panes - 1], &self.spectrogram);
+        }
+    }
+
+    fn draw_logs(&self, frame: &mut Frame, area: Rect) {
+        let shown = usize::from(area.height.saturating_sub(2));
+        let lines: Vec<Line> = self
+            .logs
+            .iter()
+            .skip(self.logs.len().saturating_sub(shown))
+            .map(|line| {
+                let color = match Response::classify(line) {
+                    Response::Log { level, .. } => match level {
+                        log::Level::Error => Color::Red,
+                        log::Level::Warn => Color::Yellow,
+                        log::Level::Info => Color::Green,
+                        log::Level::Debug | log::Level::Trace => Color::DarkGray,
+                    },
+                    Response::Error(_) => Color::Red,
+                    Response::KeyValue { .. } => Color::Cyan,
+                    Response::Text(_) => Color::Reset,
+                };
+                Line::styled(line.clone(), Style::new().fg(color))
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("pensel log")),
+            area,
+        );
+    }
+}
+
+/// Charts the last `window` seconds of `stream` up to `now`, within `y_range` if given
+fn draw_chart(
+    frame: &mut Frame,
+    area: Rect,
+    stream: &StreamView,
+    now: f64,
+    window: f64,
+    y_range: Option<YRange>,
+    units: Units,
+) {
+    let start = now - window;
+    let visible: Vec<&(f64, Vector3<f32>)> =
+        stream.history.iter().filter(|(t, _)| *t >= start).collect();
+    let axis = |pick: fn(&Vector3<f32>) -> f32| -> Vec<(f64, f64)> {
+        visible
+            .iter()
+            .map(|(t, v)| (*t, f64::from(pick(v))))
+            .collect()
+    };
+    let (x, y, z) = (axis(|v| v.x), axis(|v| v.y), axis(|v| v.z));
+
+    let (low, high) = y_range.map_or_else(
+        || fit_y_range(x.iter().chain(&y).chain(&z).map(|(_, v)| *v), units),
+        |range| (range.low, range.high),
+    );
+    let middle = (low + high) / 2.;
+
+    let dataset = |name: &'static str, data, color| {
+        Dataset::default()
+            .name(name)
+            .marker(Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::new().fg(color))
+            .data(data)
+    };
+    let chart = Chart::new(vec![
+        dataset("x", &x, X_COLOR),
+        dataset("y", &y, Y_COLOR),
+        dataset("z", &z, Z_COLOR),
+    ])
+    .block(Block::bordered().title(format!("{} ({})", stream.title, units.label())))
+    .x_axis(
+        Axis::default()
+            .bounds([start, now])
+            .labels([
+                format!("-{}s", window),
+                format!("-{}s", window / 2.),
+                "0s".to_owned(),
+            ])
+            .style(Style::new().fg(Color::DarkGray)),
+    )
+    .y_axis(
+        Axis::default()
+            .bounds([low, high])
+            .labels([
+                format!("{:.2}", low),
+                format!("{:.2}", middle),
+                format!("{:.2}", high),
+            ])
+            .style(Style::new().fg(Color::DarkGray)),
+    );
+    frame.render_widget(chart, area);
+}
+
+/// Y axis bounds that fit `values`, with some room to spare
+fn fit_y_range(values: impl Iterator<Item = f64>, units: Units) -> (f64, f64) {
+    let (mut low, mut high) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), v| {
+        (low.min(v), high.max(v))
+    });
+    if !low.is_finite() {
+        (low, high) = (-1., 1.);
+    }
+    // don't blow noise up to fill the screen
+    let min_half = f64::from(
+        units
+            .convert(Vector3 {
+                x: 0.5,
+                y: 0.,
+                z: 0.,
+            })
+            .x,
+    );
+    let middle = (low + high) / 2.;
+    let half = ((high - low) / 2. * 1.1).max(min_half);
+    (middle - half, middle + half)
+}
 
-const PRINT_LEN: usize = 1000;
+/// Latest values and counters of `stream`
+fn draw_readout(frame: &mut Frame, area: Rect, stream: &mut StreamView, units: Units) {
+    let value = |name: &'static str, value: Option<f32>, color: Color| {
+        Line::from(vec![
+            Span::styled(format!("{:>4} ", name), Style::new().fg(color)),
+            Span::raw(value.map_or_else(|| "-".to_owned(), |v| format!("{:9.3}", v))),
+        ])
+    };
+    let latest = stream.latest;
+    let dropped = stream.dropped.load(Ordering::Relaxed);
+    let lines = vec![
+        value("x", latest.map(|v| v.x), X_COLOR),
+        value("y", latest.map(|v| v.y), Y_COLOR),
+        value("z", latest.map(|v| v.z), Z_COLOR),
+        value(
+            "|v|",
+            latest.map(|v| (v.x * v.x + v.y * v.y + v.z * v.z).sqrt()),
+            Color::Reset,
+        ),
+        Line::raw(""),
+        Line::raw(format!("rate {:7.1} Hz", stream.rate())),
+        Line::raw(format!("rx   {:>9}", stream.received)),
+        Line::styled(
+            format!("drop {:>9}", dropped),
+            Style::new().fg(if dropped > 0 {
+                Color::Red
+            } else {
+                Color::Reset
+            }),
+        ),
+    ];
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(units.label())),
+        area,
+    );
+}
+
+/// The acceleration spectrogram, newest on the right, with its dominant frequency and tremor
+fn draw_spectrogram(frame: &mut Frame, area: Rect, spectrogram: &Spectrogram) {
+    let width = usize::from(area.width.saturating_sub(2));
+    let rows = spectrogram.render(usize::from(area.height.saturating_sub(2)));
+    let lines: Vec<Line> = rows
+        .into_iter()
+        .map(|row| {
+            let skip = row.chars().count().saturating_sub(width);
+            Line::raw(row.chars().skip(skip).collect::<String>())
+        })
+        .collect();
+
+    let latest = spectrogram.latest();
+    let dominant = latest
+        .and_then(|spectrum| spectrum.dominant(1).first().copied())
+        .map_or_else(
+            || "-".to_owned(),
+            |peak| format!("{:.2} Hz", peak.frequency),
+        );
+    let tremor = latest
+        .and_then(spectrum::Spectrum::tremor)
+        .map_or_else(|| "tremor -".to_owned(), |tremor| tremor.to_string());
+    let title = format!(
+        "Acceleration spectrogram (0 - {:.0} Hz)  dominant {}  {}",
+        spectrogram.sample_rate() / 2.,
+        dominant,
+        tremor
+    );
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        area,
+    );
+}
 
 fn main() {
+    let matches = Command::new("plot")
+        .about("Full-screen dashboard of pensel's gravity and acceleration streams")
+        .arg(
+            Arg::new("port")
+                .short('p')
+                .long("port")
+                .value_name("PORT")
+                .help("Serial port pensel is on. Defaults to the first one named PENSEL"),
+        )
+        .arg(
+            Arg::new("axis-map")
+                .long("axis-map")
+                .value_name("MAP")
+                .value_parser(clap::value_parser!(imu::AxisMap))
+                .allow_hyphen_values(true)
+                .conflicts_with("replay")
+                .help("Sets the BNO055's axis map on connecting: P0-P7, or the axes x,y,z take, like -y,+x,+z. Pensel forgets it on reset"),
+        )
+        .arg(
+            Arg::new("streams")
+                .long("streams")
+                .value_name("STREAMS")
+                .value_parser(clap::value_parser!(Stream))
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .default_value("accel,gravity")
+                .help("Comma separated streams to show, out of 'accel' and 'gravity'"),
+        )
+        .arg(
+            Arg::new("window")
+                .short('w')
+                .long("window")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help(format!(
+                    "How many seconds the charts show to begin with [default: {}]. Zoom with +/-",
+                    DEFAULT_WINDOW
+                )),
+        )
+        .arg(
+            Arg::new("history")
+                .long("history")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help(format!(
+                    "How many seconds of samples are kept to zoom out to [default: {}]",
+                    DEFAULT_HISTORY
+                )),
+        )
+        .arg(
+            Arg::new("y-range")
+                .short('y')
+                .long("y-range")
+                .value_name("MIN..MAX")
+                .value_parser(clap::value_parser!(YRange))
+                .allow_hyphen_values(true)
+                .help("Fixes the charts' y axes, e.g. '-20..20'. By default they fit what's shown"),
+        )
+        .arg(
+            Arg::new("units")
+                .short('u')
+                .long("units")
+                .value_name("UNITS")
+                .value_parser(clap::value_parser!(Units))
+                .default_value("si")
+                .help("'si' for m/s², or 'raw' for the BNO055 counts pensel streams"),
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .value_name("FILE")
+                .help("Plays back a recording instead of streaming from a pen"),
+        )
+        .arg(
+            Arg::new("speed")
+                .long("speed")
+                .value_name("SPEED")
+                .value_parser(clap::value_parser!(Speed))
+                .default_value("realtime")
+                .help("How fast --replay plays back: 'realtime', 'max' or a factor like '4x'"),
+        )
+        .arg(
+            Arg::new("registration")
+                .long("registration")
+                .value_name("FILE")
+                .value_parser(|path: &str| Registration::load(path))
+                .help("Charts the streams in the tool frame of the registration saved by `register`, instead of the BNO055's axes"),
+        )
+        .arg(filter_arg("accel-filter", "acceleration"))
+        .arg(filter_arg("gravity-filter", "gravity"))
+        .arg(
+            Arg::new("spectrogram")
+                .long("spectrogram")
+                .help("Starts with the acceleration spectrogram shown. Toggle it with 's'")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("record-dir")
+                .long("record-dir")
+                .value_name("DIR")
+                .default_value(".")
+                .help("Where recordings started with 'r' are saved"),
+        )
+        .get_matches();
+
     let should_run = Arc::new(AtomicBool::new(true));
     let should_run_thread_ref = should_run.clone();
-    let should_ctrlc_ref = should_run.clone();
-    let mut serial = comms::PenselSerial::new_first_matching();
-
-    // enable streaming, if it isn't already
-    let enable_streaming_cmd = format!(
-        "{} --{} --{}",
-        cli::CMD_IMU,
-        cli::ARG_ACCEL,
-        cli::ARG_GRAVITY
+    let (a_producer, mut a_consumer) = unsafe { (*addr_of_mut!(A_QUEUE)).split() };
+    let (g_producer, mut g_consumer) = unsafe { (*addr_of_mut!(G_QUEUE)).split() };
+    let (log_sender, log_receiver) = mpsc::channel();
+    let streams: Vec<Stream> = matches
+        .get_many::<Stream>("streams")
+        .unwrap()
+        .copied()
+        .collect();
+    let mut accel = StreamView::new("Linear Acceleration");
+    let mut gravity = StreamView::new("Gravity Vector");
+    accel.visible = streams.contains(&Stream::Accel);
+    gravity.visible = streams.contains(&Stream::Gravity);
+    let history = Duration::from_secs(
+        matches
+            .get_one::<u64>("history")
+            .copied()
+            .unwrap_or(DEFAULT_HISTORY),
     );
-    serial.send_command(&enable_streaming_cmd).unwrap();
+    let window = Duration::from_secs(
+        matches
+            .get_one::<u64>("window")
+            .copied()
+            .unwrap_or(DEFAULT_WINDOW),
+    )
+    .min(history);
 
-    let (a_producer, mut a_consumer) = unsafe { A_QUEUE.split() };
-    let (g_producer, mut g_consumer) = unsafe { G_QUEUE.split() };
-
-    let _sender = thread::spawn(move || {
-        serial.parse_data_until(a_producer, g_producer, &should_run_thread_ref);
-    });
+    let (connection, device, sender) = if let Some(path) = matches.get_one::<String>("replay") {
+        let speed = *matches.get_one::<Speed>("speed").unwrap();
+        let mut replay = Replay::open(path, speed).expect("failed to open recording");
+        let device = replay.header().device.clone();
+        let sender = thread::spawn(move || {
+            replay.stream_until(a_producer, g_producer, &should_run_thread_ref);
+        });
+        (Connection::Replay(path.clone()), device, sender)
+    } else {
+        let mut serial = match matches.get_one::<String>("port") {
+            Some(name) => comms::PenselSerial::new_from_name(name),
+            None => comms::PenselSerial::new_first_matching(),
+        };
+        // pensel forgets the axis map on reset, so it's sent every time
+        if let Some(map) = matches.get_one::<imu::AxisMap>("axis-map") {
+            serial.set_axis_map(*map).unwrap();
+        }
+        let port = serial
+            .port_name()
+            .unwrap_or_else(|| "unknown port".to_owned());
+        let device = serial
+            .device_info(Duration::from_secs(1))
+            .unwrap_or_else(|error| {
+                log::warn!("couldn't get device info: {}", error);
+                types::DeviceInfo::unknown()
+            });
 
-    should_run.as_ref().store(true, Ordering::Release);
+        // stream just what's shown
+        let enable_streaming_cmd = streams.iter().fold(cli::CMD_IMU.to_owned(), |cmd, stream| {
+            format!("{} --{}", cmd, stream.name())
+        });
+        serial.send_command(&enable_streaming_cmd).unwrap();
 
-    let mut acc_x: [(f32, f32); PRINT_LEN] = [(0., 0.); PRINT_LEN];
-    let mut acc_y: [(f32, f32); PRINT_LEN] = [(0., 0.); PRINT_LEN];
-    let mut acc_z: [(f32, f32); PRINT_LEN] = [(0., 0.); PRINT_LEN];
+        let (mut a_producer, mut g_producer) = (a_producer, g_producer);
+        let accel_dropped = accel.dropped.clone();
+        let gravity_dropped = gravity.dropped.clone();
+        let sender = thread::spawn(move || {
+            serial.read_lines_until(
+                |raw| {
+                    let line = shell::clean_line(raw);
+                    let dropped = match comms::PenselSerial::parse_line(&line) {
+                        types::ParsedLine::Accel(a) => {
+                            a_producer.enqueue(a).is_err().then_some(&accel_dropped)
+                        }
+                        types::ParsedLine::Grav(g) => {
+                            g_producer.enqueue(g).is_err().then_some(&gravity_dropped)
+                        }
+                        types::ParsedLine::None => {
+                            if !line.is_empty() {
+                                log_sender.send(line).ok();
+                            }
+                            None
+                        }
+                    };
+                    if let Some(dropped) = dropped {
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                },
+                &should_run_thread_ref,
+            );
+        });
+        (Connection::Serial(port), device, sender)
+    };
 
-    let mut grav_x: [(f32, f32); PRINT_LEN] = [(0., 0.); PRINT_LEN];
-    let mut grav_y: [(f32, f32); PRINT_LEN] = [(0., 0.); PRINT_LEN];
-    let mut grav_z: [(f32, f32); PRINT_LEN] = [(0., 0.); PRINT_LEN];
+    let mut app = App {
+        connection,
+        finished: false,
+        device,
+        accel,
+        gravity,
+        filter: SampleFilter::new(
+            &filter_specs(&matches, "accel-filter"),
+            &filter_specs(&matches, "gravity-filter"),
+        ),
+        registration: matches.get_one::<Registration>("registration").copied(),
+        // pensel's nominal rate until there's enough acceleration to estimate the real one
+        spectrogram: Spectrogram::new(
+            types::SAMPLE_RATE,
+            SPECTROGRAM_WINDOW,
+            SPECTROGRAM_HOP,
+            SPECTROGRAM_COLUMNS,
+        ),
+        accel_arrivals: VecDeque::with_capacity(SPECTROGRAM_WINDOW),
+        show_spectrogram: matches.get_flag("spectrogram"),
+        logs: VecDeque::new(),
+        window,
+        history,
+        y_range: matches.get_one::<YRange>("y-range").copied(),
+        units: *matches.get_one::<Units>("units").unwrap(),
+        streams,
+        paused_at: None,
+        recording: None,
+        record_dir: PathBuf::from(matches.get_one::<String>("record-dir").unwrap()),
+        status: String::new(),
+        started: Instant::now(),
+    };
 
-    let term = Term::stdout();
-    term.hide_cursor().unwrap();
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut app, || {
+        let mut samples = Vec::new();
+        while let Some(a) = a_consumer.dequeue() {
+            samples.push(types::Sample::Accel(a));
+        }
+        while let Some(g) = g_consumer.dequeue() {
+            samples.push(types::Sample::Grav(g));
+        }
+        (
+            samples,
+            log_receiver.try_iter().collect(),
+            sender.is_finished(),
+        )
+    });
+    ratatui::restore();
 
-    ctrlc::set_handler(move || {
-        let term = Term::stdout();
-        term.show_cursor().unwrap();
-        should_ctrlc_ref.as_ref().store(false, Ordering::Relaxed);
-    })
-    .unwrap();
+    should_run.as_ref().store(false, Ordering::Release);
+    if app.recording.is_some() {
+        app.toggle_recording();
+        println!("{}", app.status);
+    }
+    if let Err(error) = result {
+        eprintln!("dashboard failed: {}", error);
+        std::process::exit(1);
+    }
+}
 
-    // let mut skip = 0;
-    let mut g_update = false;
-    let mut a_update = false;
+/// Runs the dashboard until the user quits. `poll` hands over the samples and log lines that
+/// arrived since it was last called, and whether the source has finished.
+fn run(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    mut poll: impl FnMut() -> (Vec<types::Sample>, Vec<String>, bool),
+) -> std::io::Result<()> {
+    let mut last_frame: Option<Instant> = None;
     loop {
-        if let Some(a) = a_consumer.dequeue() {
-            acc_x.copy_within(1..PRINT_LEN, 0);
-            acc_y.copy_within(1..PRINT_LEN, 0);
-            acc_z.copy_within(1..PRINT_LEN, 0);
-            acc_x[PRINT_LEN - 1] = (0., f32::from(a.x));
-            acc_y[PRINT_LEN - 1] = (0., f32::from(a.y));
-            acc_z[PRINT_LEN - 1] = (0., f32::from(a.z));
-            for index in 0..PRINT_LEN {
-                acc_x[index].0 += 1.;
-                acc_y[index].0 += 1.;
-                acc_z[index].0 += 1.;
-            }
-            a_update = true;
-        }
-        if let Some(g) = g_consumer.dequeue() {
-            grav_x.copy_within(1..PRINT_LEN, 0);
-            grav_y.copy_within(1..PRINT_LEN, 0);
-            grav_z.copy_within(1..PRINT_LEN, 0);
-            grav_x[PRINT_LEN - 1] = (0., f32::from(g.x));
-            grav_y[PRINT_LEN - 1] = (0., f32::from(g.y));
-            grav_z[PRINT_LEN - 1] = (0., f32::from(g.z));
-            for index in 0..PRINT_LEN {
-                grav_x[index].0 += 1.;
-                grav_y[index].0 += 1.;
-                grav_z[index].0 += 1.;
-            }
-            g_update = true;
+        let (samples, logs, finished) = poll();
+        for sample in samples {
+            app.on_sample(sample);
         }
+        for line in logs {
+            app.on_log(line);
+        }
+        app.finished = finished;
 
-        if g_update && a_update {
-            a_update = false;
-            g_update = false;
-
-            term.move_cursor_to(0, 0).unwrap();
-            println!("Gravity Vector");
-            Chart::new(200, 100, 0., 1000.)
-                .linecolorplot(&Shape::Lines(&grav_x), RED)
-                .linecolorplot(&Shape::Lines(&grav_y), GREEN)
-                .linecolorplot(&Shape::Lines(&grav_z), PURPLE)
-                .display();
-            println!("\nLinear Acceleration");
-            Chart::new(200, 100, 0., 1000.)
-                .linecolorplot(&Shape::Lines(&acc_x), RED)
-                .linecolorplot(&Shape::Lines(&acc_y), GREEN)
-                .linecolorplot(&Shape::Lines(&acc_z), PURPLE)
-                .display();
+        if last_frame.is_none_or(|drawn| drawn.elapsed() >= FRAME_PERIOD) {
+            last_frame = Some(Instant::now());
+            terminal.draw(|frame| app.draw(frame))?;
         }
 
-        if !should_run.as_ref().load(Ordering::Acquire) {
-            break;
+        if event::poll(Duration::from_millis(5))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !app.on_key(key.code, key.modifiers) {
+                    return Ok(());
+                }
+            }
         }
     }
 }
+
+/// An argument taking a comma separated chain of filters for the `stream` samples
+fn filter_arg(name: &'static str, stream: &str) -> Arg {
+    Arg::new(name)
+        .long(name)
+        .value_name("FILTERS")
+        .value_parser(clap::value_parser!(FilterSpec))
+        .value_delimiter(',')
+        .action(ArgAction::Append)
+        .help(format!(
+            "Filters {} samples, e.g. 'median:5,lowpass2:10'. Also average:N, highpass[2]:HZ and gravity[:HZ]",
+            stream
+        ))
+}
+
+/// The filters given to [`filter_arg`] `name`, in order
+fn filter_specs(matches: &ArgMatches, name: &str) -> Vec<FilterSpec> {
+    matches
+        .get_many::<FilterSpec>(name)
+        .map(|specs| specs.copied().collect())
+        .unwrap_or_default()
+}
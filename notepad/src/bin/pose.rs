@@ -0,0 +1,317 @@
+//! Streams the pose of pensel's tip to a robot controller: as ROS `geometry_msgs/PoseStamped`
+//! through rosbridge, or as UDP datagrams
+use clap::{Arg, ArgAction, ArgGroup, Command};
+use heapless::spsc::Queue;
+use std::{
+    net::UdpSocket,
+    ptr::addr_of_mut,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use notepad::{
+    angles::AxisMapping,
+    comms::{self, SampleSource},
+    orientation::Mahony,
+    pose::{self, Datagram, Placement, Pose, RosVersion, Rosbridge, Topic},
+    recording::TimedSample,
+    registration::{self, Registration},
+    replay::{Replay, Speed},
+    trajectory::{self, LeverArm, Tracker},
+    types::{self, imu},
+};
+use pensel_types::{cli, mint::Vector3};
+
+static mut A_QUEUE: Queue<imu::AccelerationVector, { types::ACC_QUEUE_SIZE }> = Queue::new();
+static mut G_QUEUE: Queue<imu::GravityVector, { types::GRAV_QUEUE_SIZE }> = Queue::new();
+
+fn main() {
+    let matches = Command::new("pose")
+        .about("Streams the pose of pensel's tip in a robot's base frame, to rosbridge or over UDP")
+        .arg(
+            Arg::new("port")
+                .short('p')
+                .long("port")
+                .value_name("PORT")
+                .help("Serial port pensel is on. Defaults to the first one named PENSEL"),
+        )
+        .arg(
+            Arg::new("axis-map")
+                .long("axis-map")
+                .value_name("MAP")
+                .value_parser(clap::value_parser!(imu::AxisMap))
+                .allow_hyphen_values(true)
+                .conflicts_with("replay")
+                .help("Sets the BNO055's axis map on connecting: P0-P7, or the axes x,y,z take, like -y,+x,+z. Pensel forgets it on reset"),
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .value_name("FILE")
+                .help("Streams the poses of a recording instead of a pen"),
+        )
+        .arg(
+            Arg::new("speed")
+                .long("speed")
+                .value_name("SPEED")
+                .value_parser(clap::value_parser!(Speed))
+                .default_value("realtime")
+                .help("How fast --replay plays back: 'realtime', 'max' or a factor like '4x'"),
+        )
+        .arg(
+            Arg::new("rosbridge")
+                .long("rosbridge")
+                .value_name("URL")
+                .help("Publishes geometry_msgs/PoseStamped to the rosbridge server at URL, like ws://localhost:9090"),
+        )
+        .arg(
+            Arg::new("topic")
+                .long("topic")
+                .value_name("TOPIC")
+                .default_value(pose::DEFAULT_TOPIC)
+                .help("Topic to publish to, for --rosbridge"),
+        )
+        .arg(
+            Arg::new("ros")
+                .long("ros")
+                .value_name("VERSION")
+                .value_parser(clap::value_parser!(RosVersion))
+                .default_value("2")
+                .help("ROS version rosbridge runs on, '1' or '2', for --rosbridge"),
+        )
+        .arg(
+            Arg::new("udp")
+                .long("udp")
+                .value_name("HOST:PORT")
+                .help("Sends each pose as a 44 byte datagram to HOST:PORT"),
+        )
+        .group(
+            ArgGroup::new("output")
+                .args(["rosbridge", "udp"])
+                .multiple(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("frame-id")
+                .long("frame-id")
+                .value_name("FRAME")
+                .default_value(pose::DEFAULT_FRAME_ID)
+                .help("Name of the robot's base frame the poses are in"),
+        )
+        .arg(
+            Arg::new("placement")
+                .long("placement")
+                .value_name("X,Y,Z[,ROLL,PITCH,YAW]")
+                .value_parser(clap::value_parser!(Placement))
+                .allow_hyphen_values(true)
+                .default_value("0,0,0")
+                .help("Where the tip's starting point is in the base frame, in meters, and how the world (z up) is turned relative to it, in degrees"),
+        )
+        .arg(
+            Arg::new("axes")
+                .long("axes")
+                .value_name("TIP,TOP")
+                .value_parser(clap::value_parser!(AxisMapping))
+                .default_value("+x,+z")
+                .help("Sensor axes pointing towards the pen's tip and out of its top. The tool frame's z and x follow them"),
+        )
+        .arg(
+            Arg::new("registration")
+                .long("registration")
+                .value_name("FILE")
+                .value_parser(|path: &str| Registration::load(path))
+                .conflicts_with_all(["axes", "placement"])
+                .help("Uses the work frame saved by `register`, instead of --axes and --placement"),
+        )
+        .arg(
+            Arg::new("lever-arm")
+                .long("lever-arm")
+                .value_name("X,Y,Z")
+                .value_parser(clap::value_parser!(LeverArm))
+                .allow_hyphen_values(true)
+                .default_value("0,0,0")
+                .help("Where the tip is relative to the IMU, in meters along the BNO055's axes"),
+        )
+        .arg(
+            Arg::new("orientation-only")
+                .long("orientation-only")
+                .help("Keeps the position at the starting point, rather than following the tip's drifting estimate")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("rate")
+                .long("rate")
+                .value_name("HZ")
+                .value_parser(clap::value_parser!(f32))
+                .default_value("50")
+                .help("Most poses to send per second"),
+        )
+        .arg(
+            Arg::new("v")
+                .short('v')
+                .action(ArgAction::Count)
+                .help("Sets the level of verbosity"),
+        )
+        .get_matches();
+
+    let level = match matches.get_count("v") {
+        0 => log::Level::Info,
+        1 => log::Level::Debug,
+        _ => log::Level::Trace,
+    };
+    simple_logger::init_with_level(level).unwrap();
+
+    let mut rosbridge = matches.get_one::<String>("rosbridge").map(|url| {
+        let topic = Topic {
+            name: matches.get_one::<String>("topic").unwrap().clone(),
+            frame_id: matches.get_one::<String>("frame-id").unwrap().clone(),
+            ros: *matches.get_one::<RosVersion>("ros").unwrap(),
+        };
+        log::info!("publishing to {} on {}", topic.name, url);
+        Rosbridge::connect(url, topic).unwrap_or_else(|error| {
+            eprintln!("failed to connect to rosbridge at {}: {}", url, error);
+            std::process::exit(1);
+        })
+    });
+    let udp = matches.get_one::<String>("udp").map(|address| {
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .and_then(|socket| socket.connect(address).map(|()| socket))
+            .unwrap_or_else(|error| {
+                eprintln!("failed to send to {}: {}", address, error);
+                std::process::exit(1);
+            });
+        log::info!("sending datagrams to {}", address);
+        socket
+    });
+
+    let should_run = Arc::new(AtomicBool::new(true));
+    let should_run_ctrl_c = should_run.clone();
+    ctrlc::set_handler(move || {
+        should_run_ctrl_c.as_ref().store(false, Ordering::Release);
+    })
+    .unwrap();
+
+    let should_run_thread_ref = should_run.clone();
+    let mut source: Box<dyn SampleSource> = match matches.get_one::<String>("replay") {
+        Some(path) => {
+            let speed = *matches.get_one::<Speed>("speed").unwrap();
+            Box::new(Replay::open(path, speed).expect("failed to open recording"))
+        }
+        None => {
+            let mut serial = match matches.get_one::<String>("port") {
+                Some(name) => comms::PenselSerial::new_from_name(name),
+                None => comms::PenselSerial::new_first_matching(),
+            };
+            // pensel forgets the axis map on reset, so it's sent every time
+            if let Some(map) = matches.get_one::<imu::AxisMap>("axis-map") {
+                serial.set_axis_map(*map).unwrap();
+            }
+            let enable_streaming_cmd = format!(
+                "{} --{} --{}",
+                cli::CMD_IMU,
+                cli::ARG_ACCEL,
+                cli::ARG_GRAVITY
+            );
+            serial.send_command(&enable_streaming_cmd).unwrap();
+            Box::new(serial)
+        }
+    };
+    let (a_producer, mut a_consumer) = unsafe { (*addr_of_mut!(A_QUEUE)).split() };
+    let (g_producer, mut g_consumer) = unsafe { (*addr_of_mut!(G_QUEUE)).split() };
+    let sender = thread::spawn(move || {
+        source.stream_until(a_producer, g_producer, &should_run_thread_ref);
+    });
+
+    let registration = matches.get_one::<Registration>("registration").copied();
+    let config = trajectory::Config {
+        // registered samples are in the tool frame, and so has the lever arm to be
+        lever_arm: registration::register_lever_arm(
+            registration.as_ref(),
+            *matches.get_one::<LeverArm>("lever-arm").unwrap(),
+        ),
+        ..trajectory::Config::default()
+    };
+    let mut tracker = Tracker::new(config, Mahony::default());
+    let (mapping, placement) = match registration.as_ref() {
+        Some(registration) => (Registration::MAPPING, registration.placement()),
+        None => (
+            *matches.get_one::<AxisMapping>("axes").unwrap(),
+            *matches.get_one::<Placement>("placement").unwrap(),
+        ),
+    };
+    let orientation_only = matches.get_flag("orientation-only");
+    let period = Duration::from_secs_f32(1. / matches.get_one::<f32>("rate").unwrap().max(0.1));
+
+    let started = Instant::now();
+    let epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut last_sent: Option<Instant> = None;
+    let mut sequence: u32 = 0;
+    while should_run.as_ref().load(Ordering::Acquire) {
+        // gravity first, so acceleration is rotated by the freshest orientation
+        let g = g_consumer.dequeue().map(types::Sample::Grav);
+        let a = a_consumer.dequeue().map(types::Sample::Accel);
+        if a.is_none() && g.is_none() {
+            if sender.is_finished() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        for sample in g.into_iter().chain(a) {
+            // registered samples are in the tool frame
+            let Some(sample) = registration::register(registration.as_ref(), sample) else {
+                continue;
+            };
+            let timed = TimedSample {
+                timestamp: started.elapsed(),
+                sample,
+            };
+            let Some(point) = tracker.feed(&timed) else {
+                continue;
+            };
+            if last_sent.is_some_and(|sent| sent.elapsed() < period) {
+                continue;
+            }
+            last_sent = Some(Instant::now());
+
+            let position = if orientation_only {
+                Vector3 {
+                    x: 0.,
+                    y: 0.,
+                    z: 0.,
+                }
+            } else {
+                point.position
+            };
+            let pose = Pose::of_tip(position, tracker.orientation(), mapping, &placement);
+            let stamp = epoch + point.timestamp;
+            if let Some(bridge) = rosbridge.as_mut() {
+                if let Err(error) = bridge.publish(stamp, &pose) {
+                    eprintln!("lost rosbridge: {}", error);
+                    should_run.as_ref().store(false, Ordering::Release);
+                }
+            }
+            if let Some(socket) = udp.as_ref() {
+                let datagram = Datagram {
+                    sequence,
+                    stamp,
+                    pose,
+                };
+                if let Err(error) = socket.send(&datagram.encode()) {
+                    log::warn!("failed to send datagram: {}", error);
+                }
+            }
+            sequence = sequence.wrapping_add(1);
+            log::trace!("{:?}", pose);
+        }
+    }
+    should_run.as_ref().store(false, Ordering::Release);
+    log::info!("sent {} poses", sequence);
+}
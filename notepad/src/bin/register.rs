@@ -0,0 +1,202 @@
+//! Registers the pen against a work frame, like a robot's base or a bench, by having it held still
+//! in known orientations. Other tools take the saved registration with `--registration`.
+use clap::{Arg, ArgAction, Command};
+use heapless::spsc::Queue;
+use std::{
+    ptr::addr_of_mut,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use notepad::{
+    angles::AxisMapping,
+    comms::{self, SampleSource},
+    recording,
+    registration::{self, Capture, Hold, Registration},
+    types::{self, imu},
+};
+use pensel_types::{cli, mint::Vector3};
+
+static mut A_QUEUE: Queue<imu::AccelerationVector, { types::ACC_QUEUE_SIZE }> = Queue::new();
+static mut G_QUEUE: Queue<imu::GravityVector, { types::GRAV_QUEUE_SIZE }> = Queue::new();
+
+/// How long we wait for the pen to hold still
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// RMS error of a registration, in degrees, above which the holds were probably off
+const WARN_RESIDUAL: f32 = 3.;
+
+fn main() {
+    let matches = Command::new("register")
+        .about("Registers the pen against a work frame by holding it still in known orientations")
+        .arg(
+            Arg::new("port")
+                .short('p')
+                .long("port")
+                .value_name("PORT")
+                .help("Serial port pensel is on. Defaults to the first one named PENSEL"),
+        )
+        .arg(
+            Arg::new("axis-map")
+                .long("axis-map")
+                .value_name("MAP")
+                .value_parser(clap::value_parser!(imu::AxisMap))
+                .allow_hyphen_values(true)
+                .help("Sets the BNO055's axis map on connecting: P0-P7, or the axes x,y,z take, like -y,+x,+z. Pensel forgets it on reset"),
+        )
+        .arg(
+            Arg::new("hold")
+                .long("hold")
+                .value_name("TIP,TOP")
+                .value_parser(clap::value_parser!(AxisMapping))
+                .action(ArgAction::Append)
+                .allow_hyphen_values(true)
+                .default_values(["-z,+x", "+x,+z", "+y,+z", "+x,-y"])
+                .help("Work frame axes the pen's tip and top point along in each hold. The first is home: how sessions start"),
+        )
+        .arg(
+            Arg::new("home-point")
+                .long("home-point")
+                .value_name("X,Y,Z")
+                .value_parser(clap::value_parser!(f32))
+                .value_delimiter(',')
+                .num_args(1)
+                .allow_hyphen_values(true)
+                .default_value("0,0,0")
+                .help("Where the tip touches in the first hold, in meters in the work frame. It isn't measured, sessions have to start with the tip on it"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .default_value(registration::DEFAULT_PATH)
+                .help("Where to save the registration"),
+        )
+        .arg(
+            Arg::new("v")
+                .short('v')
+                .action(ArgAction::Count)
+                .help("Sets the level of verbosity"),
+        )
+        .get_matches();
+
+    let level = match matches.get_count("v") {
+        0 => log::Level::Warn,
+        1 => log::Level::Info,
+        2 => log::Level::Debug,
+        _ => log::Level::Trace,
+    };
+    simple_logger::init_with_level(level).unwrap();
+
+    let poses: Vec<AxisMapping> = matches
+        .get_many::<AxisMapping>("hold")
+        .unwrap()
+        .copied()
+        .collect();
+    let origin = match matches
+        .get_many::<f32>("home-point")
+        .unwrap()
+        .copied()
+        .collect::<Vec<_>>()[..]
+    {
+        [x, y, z] => Vector3 { x, y, z },
+        _ => {
+            eprintln!("expected --home-point as '<x>,<y>,<z>' in meters");
+            std::process::exit(1);
+        }
+    };
+    let output = matches.get_one::<String>("output").unwrap();
+
+    let mut serial = match matches.get_one::<String>("port") {
+        Some(name) => comms::PenselSerial::new_from_name(name),
+        None => comms::PenselSerial::new_first_matching(),
+    };
+    // pensel forgets the axis map on reset, so it's sent every time
+    if let Some(map) = matches.get_one::<imu::AxisMap>("axis-map") {
+        serial.set_axis_map(*map).unwrap();
+    }
+    let enable_streaming_cmd = format!(
+        "{} --{} --{}",
+        cli::CMD_IMU,
+        cli::ARG_ACCEL,
+        cli::ARG_GRAVITY
+    );
+    serial.send_command(&enable_streaming_cmd).unwrap();
+
+    let should_run = Arc::new(AtomicBool::new(true));
+    let should_run_thread_ref = should_run.clone();
+    let (a_producer, mut a_consumer) = unsafe { (*addr_of_mut!(A_QUEUE)).split() };
+    let (g_producer, mut g_consumer) = unsafe { (*addr_of_mut!(G_QUEUE)).split() };
+    let sender = thread::spawn(move || {
+        serial.stream_until(a_producer, g_producer, &should_run_thread_ref);
+    });
+
+    let started = Instant::now();
+    let mut holds = Vec::new();
+    for (index, pose) in poses.iter().enumerate() {
+        println!(
+            "{}/{}: hold the pen with its tip along {} and its top along {}{}, then press enter",
+            index + 1,
+            poses.len(),
+            pose.tip,
+            pose.top,
+            if index == 0 {
+                ", touching the home point"
+            } else {
+                ""
+            }
+        );
+        std::io::stdin().read_line(&mut String::new()).unwrap();
+
+        // only what comes in from now on counts
+        while a_consumer.dequeue().is_some() || g_consumer.dequeue().is_some() {}
+        let mut capture = Capture::default();
+        let deadline = Instant::now() + TIMEOUT;
+        let gravity = loop {
+            if Instant::now() > deadline || sender.is_finished() {
+                eprintln!("the pen didn't hold still, try again");
+                std::process::exit(1);
+            }
+            let a = a_consumer.dequeue().map(types::Sample::Accel);
+            let g = g_consumer.dequeue().map(types::Sample::Grav);
+            let timestamp = started.elapsed();
+            let captured = a
+                .into_iter()
+                .chain(g)
+                .find_map(|sample| capture.feed(&recording::TimedSample { timestamp, sample }));
+            if let Some(gravity) = captured {
+                break gravity;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        holds.push(Hold {
+            pose: *pose,
+            gravity,
+        });
+    }
+    should_run.as_ref().store(false, Ordering::Release);
+    sender.join().ok();
+
+    let registration = Registration::solve(&holds, origin).unwrap_or_else(|error| {
+        eprintln!("failed to register: {}", error);
+        std::process::exit(1);
+    });
+    println!(
+        "registered with {:.2}° RMS error{}",
+        registration.residual,
+        if registration.residual > WARN_RESIDUAL {
+            ", check the holds matched what was asked"
+        } else {
+            ""
+        }
+    );
+    registration.save(output).unwrap_or_else(|error| {
+        eprintln!("failed to write {}: {}", output, error);
+        std::process::exit(1);
+    });
+    println!("wrote {}", output);
+}
@@ -1,19 +1,55 @@
-//! Example that just prints all packets
-use clap::{Arg, ArgAction, Command};
-use heapless::spsc::Queue;
+//! Records, prints or replays what pensel streams, and shows live pen angles or gestures. Also the
+//! home of the smaller tools working on the pen or its recordings: an interactive shell, and
+//! converting, reporting on, splitting into strokes and reconstructing the tip's path of
+//! recordings.
+//!
+//! The bigger tools have their own binaries: `plot`, `orient`, `spectrum`, `waypoints`,
+//! `register`, `pose` and `bridge`.
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use console::{style, Term};
+use heapless::spsc::{Consumer, Queue};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+    Frame,
+};
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::DefaultHistory, validate::Validator, Editor, ExternalPrinter, Helper,
+};
 
 use std::{
+    collections::VecDeque,
     fs::File,
-    io::prelude::*,
+    io::{prelude::*, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    ptr::addr_of_mut,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        mpsc, Arc, Mutex,
     },
     thread,
+    time::{Duration, Instant},
 };
 
 use notepad::{
-    comms,
+    angles::{AngleRange, AngleWindow, AxisMapping, PenAngles},
+    comms::{self, SampleSource},
+    convert::{self, Format},
+    filters::{FilterSpec, SampleFilter},
+    gestures::{self, Gesture, GestureDetector, Template},
+    orientation::Mahony,
+    pose::Placement,
+    recording,
+    registration::{self, Registration},
+    replay::{Replay, Speed},
+    report::{self, Report, ReportFormat},
+    shell::{self, LineInput, Response, SampleView},
+    strokes,
+    trajectory::{self, LeverArm, Tracker},
     types::{self, imu},
 };
 use pensel_types::cli;
@@ -21,9 +57,24 @@ use pensel_types::cli;
 static mut A_QUEUE: Queue<imu::AccelerationVector, { types::ACC_QUEUE_SIZE }> = Queue::new();
 static mut G_QUEUE: Queue<imu::GravityVector, { types::GRAV_QUEUE_SIZE }> = Queue::new();
 
+/// How often the [`SampleView::Latest`] line gets refreshed
+const LATEST_SAMPLE_PERIOD: Duration = Duration::from_millis(500);
+/// What the shell prompts with
+const SHELL_PROMPT: &str = "pensel> ";
+/// How many lines the full screen shell keeps, of what's been said and of samples
+const PANE_SCROLLBACK: usize = 1000;
+/// Width of the full screen shell's sample pane, borders included
+const SAMPLE_PANE_WIDTH: u16 = 28;
+/// How long the full screen shell waits for a key before checking what pensel sent
+const PANE_POLL_PERIOD: Duration = Duration::from_millis(20);
+/// How often the `--angles` readout gets refreshed
+const ANGLES_PERIOD: Duration = Duration::from_millis(100);
+
 enum Mode {
     Print,
     Record,
+    Angles,
+    Gestures,
 }
 
 fn main() {
@@ -43,17 +94,292 @@ fn main() {
                 .help("just prints out accel/gravity packets")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("angles")
+                .long("angles")
+                .help("Shows a live readout of the pen's tilt and roll")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("gestures")
+                .long("gestures")
+                .help("Prints pen gestures as they're recognized")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("template")
+                .long("template")
+                .value_name("NAME=FILE")
+                .action(ArgAction::Append)
+                .help("Also recognizes the gesture performed in the recording FILE, as NAME"),
+        )
+        .arg(
+            Arg::new("gesture-control")
+                .long("gesture-control")
+                .help("Starts --record paused, and pauses or resumes it on a double tap")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("axes")
+                .long("axes")
+                .value_name("TIP,TOP")
+                .value_parser(clap::value_parser!(AxisMapping))
+                .default_value("+x,+z")
+                .help("Which BNO055 axes point towards the pen's tip and out of its top, for --angles"),
+        )
+        .arg(
+            Arg::new("registration")
+                .long("registration")
+                .value_name("FILE")
+                .value_parser(|path: &str| Registration::load(path))
+                .conflicts_with("axes")
+                .help("Shows --angles and --gestures in the work frame saved by `register`, instead of along --axes"),
+        )
+        .arg(
+            Arg::new("tilt")
+                .long("tilt")
+                .value_name("MIN..MAX")
+                .value_parser(clap::value_parser!(AngleRange))
+                .help("Tilt from vertical the pen should be held at, in degrees, for --angles"),
+        )
+        .arg(
+            Arg::new("roll")
+                .long("roll")
+                .value_name("MIN..MAX")
+                .value_parser(clap::value_parser!(AngleRange))
+                .help("Roll around its long axis the pen should be held at, in degrees, for --angles"),
+        )
+        .arg(
+            Arg::new("axis-map")
+                .long("axis-map")
+                .value_name("MAP")
+                .value_parser(clap::value_parser!(imu::AxisMap))
+                .allow_hyphen_values(true)
+                .conflicts_with("replay")
+                .help("Sets the BNO055's axis map on connecting: P0-P7, or the axes x,y,z take, like -y,+x,+z. Pensel forgets it on reset"),
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .value_name("FILE")
+                .help("Plays back a recording instead of streaming from a pen"),
+        )
+        .arg(
+            Arg::new("speed")
+                .long("speed")
+                .value_name("SPEED")
+                .value_parser(clap::value_parser!(Speed))
+                .default_value("realtime")
+                .help("How fast --replay plays back: 'realtime', 'max' or a factor like '4x'"),
+        )
         .arg(
             Arg::new("v")
                 .short('v')
                 .action(ArgAction::Count)
                 .help("Sets the level of verbosity"),
         )
+        .subcommand(
+            Command::new("shell")
+                .about("Interactive shell for sending commands to pensel")
+                .arg(
+                    Arg::new("port")
+                        .short('p')
+                        .long("port")
+                        .value_name("PORT")
+                        .help("Serial port pensel is on. Defaults to the first one named PENSEL"),
+                )
+                .arg(
+                    Arg::new("samples")
+                        .long("samples")
+                        .value_name("VIEW")
+                        .value_parser(SampleView::ALL.map(SampleView::name))
+                        .default_value(SampleView::Off.name())
+                        .help("How streamed samples are shown. Change it later with :samples"),
+                )
+                .arg(
+                    Arg::new("pane")
+                        .long("pane")
+                        .help("Runs full screen, showing samples in a pane beside the shell rather than between its lines")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("convert")
+                .about("Converts a recording between the native, CSV and JSON lines formats")
+                .arg(Arg::new("input").required(true).value_name("INPUT"))
+                .arg(Arg::new("output").required(true).value_name("OUTPUT"))
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_name("FORMAT")
+                        .value_parser(Format::ALL.map(Format::name))
+                        .help("Format of INPUT. Guessed from its extension by default"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("FORMAT")
+                        .value_parser(Format::ALL.map(Format::name))
+                        .help("Format of OUTPUT. Guessed from its extension by default"),
+                )
+                .arg(filter_arg("accel-filter", "acceleration"))
+                .arg(filter_arg("gravity-filter", "gravity")),
+        )
+        .subcommand(
+            Command::new("strokes")
+                .about("Splits a recording into strokes, printing when each happened and how")
+                .arg(Arg::new("input").required(true).value_name("INPUT"))
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_name("FORMAT")
+                        .value_parser(Format::ALL.map(Format::name))
+                        .help("Format of INPUT. Guessed from its extension by default"),
+                )
+                .arg(
+                    Arg::new("start-threshold")
+                        .long("start-threshold")
+                        .value_name("M/S²")
+                        .value_parser(clap::value_parser!(f32))
+                        .help(format!(
+                            "RMS acceleration above which a stroke starts [default: {}]",
+                            strokes::DEFAULT_START_THRESHOLD
+                        )),
+                )
+                .arg(
+                    Arg::new("stop-threshold")
+                        .long("stop-threshold")
+                        .value_name("M/S²")
+                        .value_parser(clap::value_parser!(f32))
+                        .help(format!(
+                            "RMS acceleration below which a stroke may end [default: {}]",
+                            strokes::DEFAULT_STOP_THRESHOLD
+                        )),
+                )
+                .arg(
+                    Arg::new("min-still")
+                        .long("min-still")
+                        .value_name("MS")
+                        .value_parser(clap::value_parser!(u64))
+                        .help(format!(
+                            "How long the pen has to stay below --stop-threshold to end a stroke [default: {}]",
+                            strokes::DEFAULT_MIN_STILL.as_millis()
+                        )),
+                )
+                .arg(
+                    Arg::new("axes")
+                        .long("axes")
+                        .value_name("TIP,TOP")
+                        .value_parser(clap::value_parser!(AxisMapping))
+                        .default_value("+x,+z")
+                        .help("Which BNO055 axes point towards the pen's tip and out of its top"),
+                )
+                .arg(
+                    Arg::new("registration")
+                        .long("registration")
+                        .value_name("FILE")
+                        .value_parser(|path: &str| Registration::load(path))
+                        .conflicts_with("axes")
+                        .help("Uses the work frame saved by `register`, instead of --axes"),
+                ),
+        )
+        .subcommand(
+            Command::new("trajectory")
+                .about("Reconstructs the path of the pen's tip from a recording, as CSV")
+                .arg(Arg::new("input").required(true).value_name("INPUT"))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Where to write the trajectory. Defaults to stdout"),
+                )
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_name("FORMAT")
+                        .value_parser(Format::ALL.map(Format::name))
+                        .help("Format of INPUT. Guessed from its extension by default"),
+                )
+                .arg(
+                    Arg::new("lever-arm")
+                        .long("lever-arm")
+                        .value_name("X,Y,Z")
+                        .value_parser(clap::value_parser!(LeverArm))
+                        .default_value("0,0,0")
+                        .help("Where the tip is relative to the IMU, in meters along the BNO055's axes"),
+                )
+                .arg(
+                    Arg::new("registration")
+                        .long("registration")
+                        .value_name("FILE")
+                        .value_parser(|path: &str| Registration::load(path))
+                        .help("Writes the path in the work frame saved by `register`, for a recording started at its home"),
+                ),
+        )
+        .subcommand(
+            Command::new("report")
+                .about("Writes an HTML or SVG report of a recording: charts, tilt, strokes and gaps")
+                .arg(Arg::new("input").required(true).value_name("INPUT"))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Where to write the report. Defaults to INPUT with the report's extension"),
+                )
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_name("FORMAT")
+                        .value_parser(Format::ALL.map(Format::name))
+                        .help("Format of INPUT. Guessed from its extension by default"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .value_parser(clap::value_parser!(ReportFormat))
+                        .help("'html' or 'svg'. Guessed from --output's extension by default, else html"),
+                )
+                .arg(
+                    Arg::new("tilt-bin")
+                        .long("tilt-bin")
+                        .value_name("DEGREES")
+                        .value_parser(clap::value_parser!(f32))
+                        .help(format!(
+                            "Width of the tilt histogram's bins [default: {}]",
+                            report::DEFAULT_TILT_BIN
+                        )),
+                )
+                .arg(
+                    Arg::new("axes")
+                        .long("axes")
+                        .value_name("TIP,TOP")
+                        .value_parser(clap::value_parser!(AxisMapping))
+                        .default_value("+x,+z")
+                        .help("Which BNO055 axes point towards the pen's tip and out of its top"),
+                )
+                .arg(
+                    Arg::new("registration")
+                        .long("registration")
+                        .value_name("FILE")
+                        .value_parser(|path: &str| Registration::load(path))
+                        .conflicts_with("axes")
+                        .help("Charts the recording in the work frame saved by `register`, instead of along --axes"),
+                ),
+        )
         .get_matches();
 
     if matches.get_flag("print") {
         mode = Mode::Print;
     }
+    if matches.get_flag("angles") {
+        mode = Mode::Angles;
+    }
+    if matches.get_flag("gestures") {
+        mode = Mode::Gestures;
+    }
 
     let level = match matches.get_count("v") {
         0 => log::Level::Warn,
@@ -63,51 +389,130 @@ fn main() {
     };
     simple_logger::init_with_level(level).unwrap();
 
+    if let Some(shell_matches) = matches.subcommand_matches("shell") {
+        run_shell(shell_matches);
+        return;
+    }
+    if let Some(convert_matches) = matches.subcommand_matches("convert") {
+        run_convert(convert_matches);
+        return;
+    }
+    if let Some(strokes_matches) = matches.subcommand_matches("strokes") {
+        run_strokes(strokes_matches);
+        return;
+    }
+    if let Some(trajectory_matches) = matches.subcommand_matches("trajectory") {
+        run_trajectory(trajectory_matches);
+        return;
+    }
+    if let Some(report_matches) = matches.subcommand_matches("report") {
+        run_report(report_matches);
+        return;
+    }
+
     let should_run = Arc::new(AtomicBool::new(true));
     let should_run_thread_ref = should_run.clone();
     let should_run_ctrl_c = should_run.clone();
-    let mut serial = comms::PenselSerial::new_first_matching();
-
-    // enable streaming, if it isn't already
-    let enable_streaming_cmd = format!(
-        "{} --{} --{}",
-        cli::CMD_IMU,
-        cli::ARG_ACCEL,
-        cli::ARG_GRAVITY
-    );
-    serial.send_command(&enable_streaming_cmd).unwrap();
+    let (mut source, device): (Box<dyn SampleSource>, _) = if let Some(path) =
+        matches.get_one::<String>("replay")
+    {
+        let speed = *matches.get_one::<Speed>("speed").unwrap();
+        let replay = Replay::open(path, speed).expect("failed to open recording");
+        let device = replay.header().device.clone();
+        (Box::new(replay), device)
+    } else {
+        let mut serial = comms::PenselSerial::new_first_matching();
+        // pensel forgets the axis map on reset, so it's sent every time
+        if let Some(map) = matches.get_one::<imu::AxisMap>("axis-map") {
+            serial.set_axis_map(*map).unwrap();
+        }
 
-    let (a_producer, mut a_consumer) = unsafe { A_QUEUE.split() };
-    let (g_producer, mut g_consumer) = unsafe { G_QUEUE.split() };
+        // ask which pen this is before samples start getting in the way of the answer
+        let device = match mode {
+            Mode::Record => serial
+                .device_info(Duration::from_secs(1))
+                .unwrap_or_else(|error| {
+                    log::warn!("couldn't get device info, recording without it: {}", error);
+                    types::DeviceInfo::unknown()
+                }),
+            Mode::Print | Mode::Angles | Mode::Gestures => types::DeviceInfo::unknown(),
+        };
+
+        // enable streaming, if it isn't already
+        let enable_streaming_cmd = format!(
+            "{} --{} --{}",
+            cli::CMD_IMU,
+            cli::ARG_ACCEL,
+            cli::ARG_GRAVITY
+        );
+        serial.send_command(&enable_streaming_cmd).unwrap();
+        (Box::new(serial), device)
+    };
+
+    let (a_producer, mut a_consumer) = unsafe { (*addr_of_mut!(A_QUEUE)).split() };
+    let (g_producer, mut g_consumer) = unsafe { (*addr_of_mut!(G_QUEUE)).split() };
 
     // Parse & stream data until we receive a keyboard interrupt
     ctrlc::set_handler(move || {
         should_run_ctrl_c.as_ref().store(false, Ordering::Release);
     })
     .unwrap();
-    let _sender = thread::spawn(move || {
-        serial.parse_data_until(a_producer, g_producer, &should_run_thread_ref);
+    let sender = thread::spawn(move || {
+        source.stream_until(a_producer, g_producer, &should_run_thread_ref);
     });
+    // a replay runs out eventually, and once everything it sent is handled we're done
+    let is_running = |a_consumer: &Consumer<_, _>, g_consumer: &Consumer<_, _>| {
+        should_run.as_ref().load(Ordering::Acquire)
+            && !(sender.is_finished() && !a_consumer.ready() && !g_consumer.ready())
+    };
 
     // Do the action until we're told to stop
     match mode {
         Mode::Record => {
             println!("recording...");
             let filepath = matches.get_one::<String>("record").unwrap();
-            let mut file = File::create(filepath).unwrap();
-            while should_run.as_ref().load(Ordering::Acquire) {
-                if let Some(a) = a_consumer.dequeue() {
-                    writeln!(file, "{}", a).unwrap();
-                }
-                if let Some(g) = g_consumer.dequeue() {
-                    writeln!(file, "{}", g).unwrap();
+            let header = recording::Header::new(device, recording::Stream::ALL.to_vec());
+            let mut recorder = recording::RecordingWriter::create(filepath, &header).unwrap();
+
+            // with gesture control, double taps pause and resume
+            let mut control = matches
+                .get_flag("gesture-control")
+                .then(|| GestureDetector::new(gestures::Config::default()));
+            let mut paused = control.is_some();
+            if paused {
+                println!("paused, double tap the pen to start");
+            }
+            let started = Instant::now();
+
+            while is_running(&a_consumer, &g_consumer) {
+                let a = a_consumer.dequeue().map(types::Sample::Accel);
+                let g = g_consumer.dequeue().map(types::Sample::Grav);
+                for sample in a.into_iter().chain(g) {
+                    if let Some(detector) = control.as_mut() {
+                        let timed = recording::TimedSample {
+                            timestamp: started.elapsed(),
+                            sample,
+                        };
+                        let toggled = detector
+                            .feed(&timed)
+                            .iter()
+                            .any(|event| event.gesture == Gesture::DoubleTap);
+                        if toggled {
+                            paused = !paused;
+                            println!("{}", if paused { "paused" } else { "recording..." });
+                        }
+                    }
+                    if !paused {
+                        recorder.write_sample(sample).unwrap();
+                    }
                 }
             }
+            recorder.flush().unwrap();
         }
 
         Mode::Print => {
             println!("printing...");
-            while should_run.as_ref().load(Ordering::Acquire) {
+            while is_running(&a_consumer, &g_consumer) {
                 if let Some(a) = a_consumer.dequeue() {
                     println!("{}", a);
                 }
@@ -116,7 +521,744 @@ fn main() {
                 }
             }
         }
+
+        Mode::Angles => {
+            let registration = matches.get_one::<Registration>("registration");
+            let mapping = registration
+                .map_or(*matches.get_one::<AxisMapping>("axes").unwrap(), |_| {
+                    Registration::MAPPING
+                });
+            let window = AngleWindow {
+                tilt: matches.get_one::<AngleRange>("tilt").copied(),
+                roll: matches.get_one::<AngleRange>("roll").copied(),
+                azimuth: None,
+            };
+            println!("showing angles with axes {}...", mapping);
+
+            let term = Term::stdout();
+            let mut latest = None;
+            let mut last_shown = Instant::now();
+            while is_running(&a_consumer, &g_consumer) {
+                // angles only need gravity, but accel still has to be drained
+                a_consumer.dequeue();
+                if let Some(g) = g_consumer.dequeue() {
+                    let gravity = registration
+                        .map_or(g.m_s2(), |registration| registration.to_tool(g.m_s2()));
+                    latest = PenAngles::from_gravity(gravity, mapping).or(latest);
+                }
+                if let Some(angles) = latest {
+                    if last_shown.elapsed() >= ANGLES_PERIOD {
+                        last_shown = Instant::now();
+                        term.clear_line().unwrap();
+                        term.write_str(&describe_angles(&angles, &window)).unwrap();
+                    }
+                }
+            }
+            term.write_line("").unwrap();
+        }
+
+        Mode::Gestures => {
+            // registered, flicks and templates don't depend on how the sensor sits in the pen
+            let registration = matches.get_one::<Registration>("registration");
+            let mut detector = GestureDetector::new(gestures::Config::default());
+            for template in matches.get_many::<String>("template").into_iter().flatten() {
+                detector.add_template(load_template(template, registration));
+            }
+            println!("watching for gestures...");
+
+            let started = Instant::now();
+            while is_running(&a_consumer, &g_consumer) {
+                let a = a_consumer.dequeue().map(types::Sample::Accel);
+                let g = g_consumer.dequeue().map(types::Sample::Grav);
+                for sample in a.into_iter().chain(g) {
+                    let Some(sample) = registration::register(registration, sample) else {
+                        continue;
+                    };
+                    let timed = recording::TimedSample {
+                        timestamp: started.elapsed(),
+                        sample,
+                    };
+                    for event in detector.feed(&timed) {
+                        println!(
+                            "{:9.3}s  {}",
+                            event.timestamp.as_secs_f64(),
+                            style(&event.gesture).cyan().bold()
+                        );
+                    }
+                }
+            }
+        }
     }
 
     println!("done!");
 }
+
+/// Loads a custom gesture given as `NAME=FILE`, in the tool frame if there's a `registration`,
+/// exiting if that doesn't work out
+fn load_template(arg: &str, registration: Option<&Registration>) -> Template {
+    let Some((name, path)) = arg.split_once('=') else {
+        eprintln!("expected --template NAME=FILE, got '{}'", arg);
+        std::process::exit(1);
+    };
+    let reader = BufReader::new(File::open(path).expect("failed to open template"));
+    let samples = convert::reader(reader, Format::from_path(path))
+        .and_then(|samples| {
+            registration::register_all(registration.copied(), samples)
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .unwrap_or_else(|error| {
+            eprintln!("failed to read {}: {}", path, error);
+            std::process::exit(1);
+        });
+    let template = Template::from_samples(name, &samples, gestures::DEFAULT_STILL_THRESHOLD);
+    if template.samples.is_empty() {
+        eprintln!("{} has no motion in it to recognize", path);
+        std::process::exit(1);
+    }
+    template
+}
+
+/// One line describing `angles`, and whether they're in `window` if it checks anything
+fn describe_angles(angles: &PenAngles, window: &AngleWindow) -> String {
+    if window.is_empty() {
+        angles.to_string()
+    } else if window.contains(angles) {
+        format!("{}  {}", angles, style("in window").green().bold())
+    } else {
+        format!("{}  {}", angles, style("out of window").red().bold())
+    }
+}
+
+/// An argument taking a comma separated chain of filters for the `stream` samples
+fn filter_arg(name: &'static str, stream: &str) -> Arg {
+    Arg::new(name)
+        .long(name)
+        .value_name("FILTERS")
+        .value_parser(clap::value_parser!(FilterSpec))
+        .value_delimiter(',')
+        .action(ArgAction::Append)
+        .help(format!(
+            "Filters {} samples, e.g. 'median:5,lowpass2:10'. Also average:N, highpass[2]:HZ and gravity[:HZ]",
+            stream
+        ))
+}
+
+/// The filters given to [`filter_arg`] `name`, in order
+fn filter_specs(matches: &ArgMatches, name: &str) -> Vec<FilterSpec> {
+    matches
+        .get_many::<FilterSpec>(name)
+        .map(|specs| specs.copied().collect())
+        .unwrap_or_default()
+}
+
+/// Converts a recording from one format to another
+fn run_convert(matches: &ArgMatches) {
+    let input = matches.get_one::<String>("input").unwrap();
+    let output = matches.get_one::<String>("output").unwrap();
+    let format_of = |arg: &str, path: &str| {
+        matches
+            .get_one::<String>(arg)
+            .map_or_else(|| Format::from_path(path), |name| name.parse().unwrap())
+    };
+    let from = format_of("from", input);
+    let to = format_of("to", output);
+
+    let mut filter = SampleFilter::new(
+        &filter_specs(matches, "accel-filter"),
+        &filter_specs(matches, "gravity-filter"),
+    );
+
+    let reader = BufReader::new(File::open(input).expect("failed to open input"));
+    let writer = BufWriter::new(File::create(output).expect("failed to create output"));
+    match convert::convert_filtered(reader, from, writer, to, &mut filter) {
+        Ok(count) => println!("converted {} samples from {} to {}", count, from, to),
+        Err(error) => {
+            eprintln!("failed to convert {}: {}", input, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Splits a recording into strokes and prints them
+fn run_strokes(matches: &ArgMatches) {
+    let input = matches.get_one::<String>("input").unwrap();
+    let from = matches
+        .get_one::<String>("from")
+        .map_or_else(|| Format::from_path(input), |name| name.parse().unwrap());
+    let registration = matches.get_one::<Registration>("registration").copied();
+    let defaults = strokes::Config::default();
+    let config = strokes::Config {
+        start_threshold: matches
+            .get_one::<f32>("start-threshold")
+            .copied()
+            .unwrap_or(defaults.start_threshold),
+        stop_threshold: matches
+            .get_one::<f32>("stop-threshold")
+            .copied()
+            .unwrap_or(defaults.stop_threshold),
+        min_still: matches
+            .get_one::<u64>("min-still")
+            .map_or(defaults.min_still, |millis| Duration::from_millis(*millis)),
+        axes: registration.map_or(*matches.get_one::<AxisMapping>("axes").unwrap(), |_| {
+            Registration::MAPPING
+        }),
+        ..defaults
+    };
+
+    let reader = BufReader::new(File::open(input).expect("failed to open input"));
+    let found = convert::reader(reader, from)
+        .and_then(|samples| {
+            strokes::segment(config, registration::register_all(registration, samples))
+        })
+        .unwrap_or_else(|error| {
+            eprintln!("failed to read {}: {}", input, error);
+            std::process::exit(1);
+        });
+
+    println!("{:>4} {:>10} {:>10} {:>8}", "#", "start", "end", "duration");
+    for (index, stroke) in found.iter().enumerate() {
+        println!("{:>4} {}", index + 1, stroke);
+    }
+    println!("{} strokes", found.len());
+}
+
+/// Reconstructs the tip's trajectory from a recording and writes it out as CSV
+fn run_trajectory(matches: &ArgMatches) {
+    let input = matches.get_one::<String>("input").unwrap();
+    let from = matches
+        .get_one::<String>("from")
+        .map_or_else(|| Format::from_path(input), |name| name.parse().unwrap());
+    let registration = matches.get_one::<Registration>("registration").copied();
+    let config = trajectory::Config {
+        // registered samples are in the tool frame, and so has the lever arm to be
+        lever_arm: registration::register_lever_arm(
+            registration.as_ref(),
+            *matches.get_one::<LeverArm>("lever-arm").unwrap(),
+        ),
+        ..trajectory::Config::default()
+    };
+    let placement =
+        registration.map_or(Placement::IDENTITY, |registration| registration.placement());
+
+    let reader = BufReader::new(File::open(input).expect("failed to open input"));
+    let samples = convert::reader(reader, from).unwrap_or_else(|error| {
+        eprintln!("failed to read {}: {}", input, error);
+        std::process::exit(1);
+    });
+    let points = trajectory::reconstruct(
+        &mut Tracker::new(config, Mahony::default()),
+        registration::register_all(registration, samples),
+    )
+    .unwrap_or_else(|error| {
+        eprintln!("failed to read {}: {}", input, error);
+        std::process::exit(1);
+    });
+
+    let out: Box<dyn Write> = match matches.get_one::<String>("output") {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).expect("failed to create output"),
+        )),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut out = csv::Writer::from_writer(out);
+    out.write_record(["time_s", "x", "y", "z", "still"])
+        .unwrap();
+    for point in &points {
+        let position = placement.place(point.position);
+        out.serialize((
+            point.timestamp.as_secs_f64(),
+            position.x,
+            position.y,
+            position.z,
+            point.still,
+        ))
+        .unwrap();
+    }
+    out.flush().unwrap();
+}
+
+/// Writes a report of a recording, for attaching to QA tickets
+fn run_report(matches: &ArgMatches) {
+    let input = matches.get_one::<String>("input").unwrap();
+    let from = matches
+        .get_one::<String>("from")
+        .map_or_else(|| Format::from_path(input), |name| name.parse().unwrap());
+    let output = matches.get_one::<String>("output").map(PathBuf::from);
+    let format = matches
+        .get_one::<ReportFormat>("format")
+        .copied()
+        .unwrap_or_else(|| {
+            output
+                .as_ref()
+                .map_or(ReportFormat::Html, ReportFormat::from_path)
+        });
+    let output = output.unwrap_or_else(|| Path::new(input).with_extension(format.extension()));
+    let registration = matches.get_one::<Registration>("registration").copied();
+    let config = strokes::Config {
+        axes: registration.map_or(*matches.get_one::<AxisMapping>("axes").unwrap(), |_| {
+            Registration::MAPPING
+        }),
+        ..strokes::Config::default()
+    };
+    let tilt_bin = matches
+        .get_one::<f32>("tilt-bin")
+        .copied()
+        .unwrap_or(report::DEFAULT_TILT_BIN);
+
+    let reader = BufReader::new(File::open(input).expect("failed to open input"));
+    let report = convert::reader(reader, from)
+        .and_then(|samples| {
+            let header = samples.header().clone();
+            // registered samples are in the tool frame
+            Report::build(
+                header,
+                registration::register_all(registration, samples),
+                config,
+                tilt_bin,
+            )
+        })
+        .unwrap_or_else(|error| {
+            eprintln!("failed to read {}: {}", input, error);
+            std::process::exit(1);
+        });
+
+    std::fs::write(&output, report.render(format)).unwrap_or_else(|error| {
+        eprintln!("failed to write {}: {}", output.display(), error);
+        std::process::exit(1);
+    });
+    println!("wrote {}", output.display());
+}
+
+/// Completes pensel commands for the shell's line editor
+struct ShellHelper;
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(shell::complete(line, pos))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+/// Runs an interactive shell: every line typed is sent to pensel, and everything pensel sends back
+/// is printed above the prompt, with samples shown according to the selected [`SampleView`]. With
+/// `--pane`, runs full screen with the samples beside the shell instead.
+fn run_shell(matches: &ArgMatches) {
+    let mut serial = match matches.get_one::<String>("port") {
+        Some(name) => comms::PenselSerial::new_from_name(name),
+        None => comms::PenselSerial::new_first_matching(),
+    };
+    let mut reader = serial.try_clone().expect("failed to clone serial port");
+
+    let view: SampleView = matches
+        .get_one::<String>("samples")
+        .unwrap()
+        .parse()
+        .unwrap();
+    if matches.get_flag("pane") {
+        run_shell_pane(serial, reader, view);
+        return;
+    }
+    let view = Arc::new(Mutex::new(view));
+    let last_command = Arc::new(Mutex::new(String::new()));
+
+    let mut editor: Editor<ShellHelper, DefaultHistory> =
+        Editor::new().expect("failed to set up line editing");
+    editor.set_helper(Some(ShellHelper));
+    let mut printer = editor
+        .create_external_printer()
+        .expect("failed to set up printing above the prompt");
+
+    let should_run = Arc::new(AtomicBool::new(true));
+    let should_run_reader = should_run.clone();
+    let reader_view = view.clone();
+    let reader_last_command = last_command.clone();
+    let reader_thread = thread::spawn(move || {
+        let mut latest_accel = None;
+        let mut latest_grav = None;
+        let mut last_shown = Instant::now();
+
+        reader.read_lines_until(
+            |raw| {
+                let line = shell::clean_line(raw);
+                if line.is_empty() {
+                    return;
+                }
+
+                match comms::PenselSerial::parse_line(&line) {
+                    types::ParsedLine::None => {
+                        // pensel echoes what we typed, which we've already seen
+                        if *reader_last_command.lock().unwrap() != line {
+                            printer.print(Response::classify(&line).pretty()).ok();
+                        }
+                    }
+                    sample => {
+                        match sample {
+                            types::ParsedLine::Accel(a) => latest_accel = Some(a),
+                            types::ParsedLine::Grav(g) => latest_grav = Some(g),
+                            types::ParsedLine::None => (),
+                        }
+
+                        match *reader_view.lock().unwrap() {
+                            SampleView::Off => (),
+                            SampleView::All => {
+                                printer.print(line).ok();
+                            }
+                            SampleView::Latest => {
+                                if last_shown.elapsed() >= LATEST_SAMPLE_PERIOD {
+                                    last_shown = Instant::now();
+                                    printer
+                                        .print(format!(
+                                            "{}  {}",
+                                            describe_latest(latest_accel.as_ref()),
+                                            describe_latest(latest_grav.as_ref()),
+                                        ))
+                                        .ok();
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            &should_run_reader,
+        );
+    });
+
+    println!(
+        "connected. {} lists shell commands, tab completes",
+        shell::META_HELP
+    );
+    while should_run.as_ref().load(Ordering::Acquire) {
+        let line = match editor.readline(SHELL_PROMPT) {
+            Ok(line) => line.trim().to_owned(),
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(error) => {
+                eprintln!("failed to read input: {}", error);
+                break;
+            }
+        };
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str()).ok();
+
+        if line.starts_with(shell::META_PREFIX) {
+            let Some(output) = run_meta(&line, &mut view.lock().unwrap()) else {
+                break;
+            };
+            for output in output {
+                println!("{}", output);
+            }
+            continue;
+        }
+
+        *last_command.lock().unwrap() = line.clone();
+        if let Err(error) = serial.write_command(&line) {
+            eprintln!("failed to send command: {}", error);
+        }
+    }
+
+    should_run.as_ref().store(false, Ordering::Release);
+    reader_thread.join().unwrap();
+}
+
+/// The most recent sample of a stream, for [`SampleView::Latest`]
+fn describe_latest(sample: Option<&impl ToString>) -> String {
+    sample.map_or_else(|| "-".to_owned(), ToString::to_string)
+}
+
+/// Runs one of the shell's own commands, changing `view` if asked to
+///
+/// # Returns
+/// The lines to show, or `None` to leave the shell
+fn run_meta(line: &str, view: &mut SampleView) -> Option<Vec<String>> {
+    let mut words = line.split_whitespace();
+    let output = match (words.next(), words.next()) {
+        (Some(shell::META_QUIT), _) => return None,
+        (Some(shell::META_SAMPLES), None) => vec![format!("samples: {}", view.name())],
+        (Some(shell::META_SAMPLES), Some(new_view)) => match new_view.parse() {
+            Ok(new_view) => {
+                *view = new_view;
+                vec![]
+            }
+            Err(error) => vec![error],
+        },
+        _ => {
+            let views: Vec<_> = SampleView::ALL.map(SampleView::name).into();
+            vec![
+                format!(
+                    "{} [{}]  how samples are shown",
+                    shell::META_SAMPLES,
+                    views.join("|")
+                ),
+                format!("{}  leave the shell", shell::META_QUIT),
+                "anything else is sent to pensel, try `help`".to_owned(),
+            ]
+        }
+    };
+    Some(output)
+}
+
+/// Runs the shell full screen, with the samples in a pane beside it
+fn run_shell_pane(
+    mut serial: comms::PenselSerial,
+    mut reader: comms::PenselSerial,
+    view: SampleView,
+) {
+    let (sender, receiver) = mpsc::channel();
+    let should_run = Arc::new(AtomicBool::new(true));
+    let should_run_reader = should_run.clone();
+    let reader_thread = thread::spawn(move || {
+        reader.read_lines_until(
+            |raw| {
+                let line = shell::clean_line(raw);
+                if !line.is_empty() {
+                    sender.send(line).ok();
+                }
+            },
+            &should_run_reader,
+        );
+    });
+
+    let mut pane = ShellPane {
+        view,
+        input: LineInput::default(),
+        transcript: VecDeque::new(),
+        samples: VecDeque::new(),
+        latest_accel: None,
+        latest_grav: None,
+        last_command: String::new(),
+    };
+    pane.push(Line::raw(format!(
+        "connected. {} lists shell commands, tab completes",
+        shell::META_HELP
+    )));
+
+    let mut terminal = ratatui::init();
+    let result = (|| -> std::io::Result<()> {
+        loop {
+            for line in receiver.try_iter() {
+                pane.on_line(line);
+            }
+            terminal.draw(|frame| pane.draw(frame))?;
+
+            if event::poll(PANE_POLL_PERIOD)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press
+                        && !pane.on_key(key.code, key.modifiers, &mut serial)
+                    {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    })();
+    ratatui::restore();
+
+    should_run.as_ref().store(false, Ordering::Release);
+    reader_thread.join().unwrap();
+    if let Err(error) = result {
+        eprintln!("shell failed: {}", error);
+        std::process::exit(1);
+    }
+}
+
+/// The full screen shell: what's been said so far with the prompt under it, and the samples in a
+/// pane beside them
+struct ShellPane {
+    view: SampleView,
+    input: LineInput,
+    transcript: VecDeque<Line<'static>>,
+    /// sample lines for [`SampleView::All`]
+    samples: VecDeque<String>,
+    latest_accel: Option<imu::AccelerationVector>,
+    latest_grav: Option<imu::GravityVector>,
+    last_command: String,
+}
+
+impl ShellPane {
+    fn push(&mut self, line: Line<'static>) {
+        if self.transcript.len() == PANE_SCROLLBACK {
+            self.transcript.pop_front();
+        }
+        self.transcript.push_back(line);
+    }
+
+    fn on_line(&mut self, line: String) {
+        match comms::PenselSerial::parse_line(&line) {
+            types::ParsedLine::None => {
+                // pensel echoes what we typed, which we've already seen
+                if self.last_command != line {
+                    self.push(response_line(&line));
+                }
+                return;
+            }
+            types::ParsedLine::Accel(a) => self.latest_accel = Some(a),
+            types::ParsedLine::Grav(g) => self.latest_grav = Some(g),
+        }
+        if self.view == SampleView::All {
+            if self.samples.len() == PANE_SCROLLBACK {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(line);
+        }
+    }
+
+    /// Handles a key press, returning whether to keep running
+    fn on_key(
+        &mut self,
+        code: KeyCode,
+        modifiers: KeyModifiers,
+        serial: &mut comms::PenselSerial,
+    ) -> bool {
+        match code {
+            KeyCode::Char('c' | 'd') if modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char(c) => self.input.insert(c),
+            KeyCode::Backspace => self.input.backspace(),
+            KeyCode::Delete => self.input.delete(),
+            KeyCode::Left => self.input.left(),
+            KeyCode::Right => self.input.right(),
+            KeyCode::Home => self.input.home(),
+            KeyCode::End => self.input.end(),
+            KeyCode::Up => self.input.previous(),
+            KeyCode::Down => self.input.next(),
+            KeyCode::Tab => {
+                let candidates = self.input.complete();
+                if candidates.len() > 1 {
+                    self.push(Line::styled(
+                        candidates.join("  "),
+                        Style::new().fg(Color::DarkGray),
+                    ));
+                }
+            }
+            KeyCode::Enter => return self.on_enter(serial),
+            _ => (),
+        }
+        true
+    }
+
+    /// Runs the typed line, returning whether to keep running
+    fn on_enter(&mut self, serial: &mut comms::PenselSerial) -> bool {
+        let line = self.input.submit().trim().to_owned();
+        self.push(Line::from(vec![
+            Span::styled(SHELL_PROMPT, Style::new().add_modifier(Modifier::BOLD)),
+            Span::raw(line.clone()),
+        ]));
+        if line.is_empty() {
+            return true;
+        }
+
+        if line.starts_with(shell::META_PREFIX) {
+            let Some(output) = run_meta(&line, &mut self.view) else {
+                return false;
+            };
+            for output in output {
+                self.push(Line::raw(output));
+            }
+            return true;
+        }
+
+        if let Err(error) = serial.write_command(&line) {
+            self.push(Line::styled(
+                format!("failed to send command: {}", error),
+                Style::new().fg(Color::Red),
+            ));
+        }
+        self.last_command = line;
+        true
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [shell_area, samples_area] =
+            Layout::horizontal([Constraint::Min(0), Constraint::Length(SAMPLE_PANE_WIDTH)])
+                .areas(frame.area());
+        let [transcript_area, prompt_area] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(shell_area);
+
+        let shown = usize::from(transcript_area.height);
+        let transcript: Vec<Line> = self
+            .transcript
+            .iter()
+            .skip(self.transcript.len().saturating_sub(shown))
+            .cloned()
+            .collect();
+        frame.render_widget(Paragraph::new(transcript), transcript_area);
+
+        frame.render_widget(
+            Paragraph::new(Line::from(vec![
+                Span::styled(SHELL_PROMPT, Style::new().add_modifier(Modifier::BOLD)),
+                Span::raw(self.input.line()),
+            ])),
+            prompt_area,
+        );
+        let column = SHELL_PROMPT.chars().count() + self.input.cursor();
+        frame.set_cursor_position((
+            prompt_area.x + u16::try_from(column).unwrap_or(u16::MAX),
+            prompt_area.y,
+        ));
+
+        let samples: Vec<Line> = match self.view {
+            SampleView::Off => vec![],
+            SampleView::Latest => vec![
+                Line::raw(describe_latest(self.latest_accel.as_ref())),
+                Line::raw(describe_latest(self.latest_grav.as_ref())),
+            ],
+            SampleView::All => {
+                let shown = usize::from(samples_area.height.saturating_sub(2));
+                self.samples
+                    .iter()
+                    .skip(self.samples.len().saturating_sub(shown))
+                    .map(|line| Line::raw(line.as_str()))
+                    .collect()
+            }
+        };
+        frame.render_widget(
+            Paragraph::new(samples)
+                .block(Block::bordered().title(format!("samples: {}", self.view.name()))),
+            samples_area,
+        );
+    }
+}
+
+/// A line pensel sent back, colored by what kind of [`Response`] it is
+fn response_line(line: &str) -> Line<'static> {
+    match Response::classify(line) {
+        Response::KeyValue { key, value } => Line::from(vec![
+            Span::styled(
+                format!("{:>14}:", key),
+                Style::new().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(format!(" {}", value)),
+        ]),
+        Response::Log { level, message } => {
+            let level_style = match level {
+                log::Level::Error => Style::new().fg(Color::Red).add_modifier(Modifier::BOLD),
+                log::Level::Warn => Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+                log::Level::Info => Style::new().fg(Color::Green),
+                log::Level::Debug | log::Level::Trace => Style::new().fg(Color::DarkGray),
+            };
+            Line::from(vec![
+                Span::raw("["),
+                Span::styled(level.as_str(), level_style),
+                Span::raw(format!("] {}", message)),
+            ])
+        }
+        Response::Error(message) => Line::styled(message.to_owned(), Style::new().fg(Color::Red)),
+        Response::Text(text) => Line::raw(text.to_owned()),
+    }
+}
@@ -0,0 +1,95 @@
+//! Reports the frequencies in a recording's acceleration: dominant peaks, hand tremor and the
+//! power in chosen bands
+use clap::{Arg, ArgAction, Command};
+use std::{fs::File, io::BufReader};
+
+use notepad::{
+    convert::{self, Format},
+    spectrum::{self, Band, Spectrum},
+    types,
+};
+
+fn main() {
+    let matches = Command::new("spectrum")
+        .about("Reports the frequencies in a recording's acceleration, and its tremor")
+        .arg(Arg::new("input").required(true).value_name("INPUT"))
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_name("FORMAT")
+                .value_parser(Format::ALL.map(Format::name))
+                .help("Format of INPUT. Guessed from its extension by default"),
+        )
+        .arg(
+            Arg::new("window")
+                .long("window")
+                .value_name("SAMPLES")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("256")
+                .help("Samples per spectrum. Longer resolves frequencies more finely"),
+        )
+        .arg(
+            Arg::new("band")
+                .long("band")
+                .value_name("LOW..HIGH")
+                .value_parser(clap::value_parser!(Band))
+                .action(ArgAction::Append)
+                .help("Also reports the RMS acceleration between LOW and HIGH Hz"),
+        )
+        .get_matches();
+
+    /// How many of the strongest peaks to list
+    const PEAKS: usize = 5;
+
+    let input = matches.get_one::<String>("input").unwrap();
+    let from = matches
+        .get_one::<String>("from")
+        .map_or_else(|| Format::from_path(input), |name| name.parse().unwrap());
+
+    let reader = BufReader::new(File::open(input).expect("failed to open input"));
+    let samples = convert::reader(reader, from)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .unwrap_or_else(|error| {
+            eprintln!("failed to read {}: {}", input, error);
+            std::process::exit(1);
+        });
+    let (timestamps, accel): (Vec<_>, Vec<_>) = samples
+        .iter()
+        .filter_map(|timed| match timed.sample {
+            types::Sample::Accel(a) => Some((timed.timestamp, a.m_s2())),
+            types::Sample::Grav(_) => None,
+        })
+        .unzip();
+    let Some(sample_rate) = spectrum::estimate_sample_rate(&timestamps) else {
+        eprintln!("{} doesn't have enough acceleration to analyse", input);
+        std::process::exit(1);
+    };
+
+    let window = *matches.get_one::<usize>("window").unwrap();
+    let spectrum = Spectrum::averaged(&accel, sample_rate, window);
+    println!(
+        "{} samples at {:.1} Hz, {:.2} Hz resolution, rms {:.3} m/s²",
+        accel.len(),
+        sample_rate,
+        spectrum.resolution(),
+        spectrum.total_power().sqrt()
+    );
+    for peak in spectrum.dominant(PEAKS) {
+        println!(
+            "  peak {:6.2} Hz  rms {:.3} m/s²",
+            peak.frequency,
+            peak.power.sqrt()
+        );
+    }
+    match spectrum.tremor() {
+        Some(tremor) => println!("{} ({})", tremor, spectrum::TREMOR_BAND),
+        None => println!("no tremor"),
+    }
+    for band in matches.get_many::<Band>("band").into_iter().flatten() {
+        println!(
+            "{}: rms {:.3} m/s²",
+            band,
+            spectrum.band_power(*band).sqrt()
+        );
+    }
+}
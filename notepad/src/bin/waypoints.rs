@@ -0,0 +1,214 @@
+//! Turns a recording into a waypoint program for a machine to follow: G-code for gantries, or JSON
+//! poses with timing
+use clap::{Arg, Command};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use notepad::{
+    angles::AxisMapping,
+    convert::{self, Format},
+    orientation::Mahony,
+    pose::{self, Placement},
+    registration::{self, Registration},
+    trajectory::{self, LeverArm, Tracker},
+    waypoints::{self, ProgramFormat, SpeedLimits, Tolerance},
+};
+
+fn main() {
+    let matches = Command::new("waypoints")
+        .about("Turns a recording into a waypoint program: G-code for gantries, or JSON poses with timing")
+        .arg(Arg::new("input").required(true).value_name("INPUT"))
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .help("Where to write the program. Defaults to INPUT with the program's extension"),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_name("FORMAT")
+                .value_parser(Format::ALL.map(Format::name))
+                .help("Format of INPUT. Guessed from its extension by default"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .value_parser(clap::value_parser!(ProgramFormat))
+                .help("'gcode' or 'json'. Guessed from --output's extension by default, else json"),
+        )
+        .arg(
+            Arg::new("placement")
+                .long("placement")
+                .value_name("X,Y,Z[,ROLL,PITCH,YAW]")
+                .value_parser(clap::value_parser!(Placement))
+                .allow_hyphen_values(true)
+                .default_value("0,0,0")
+                .help("Where the tip's starting point is in the machine's frame, in meters, and how the world (z up) is turned relative to it, in degrees"),
+        )
+        .arg(
+            Arg::new("frame-id")
+                .long("frame-id")
+                .value_name("FRAME")
+                .default_value(pose::DEFAULT_FRAME_ID)
+                .help("Name of the machine's frame, for json"),
+        )
+        .arg(
+            Arg::new("axes")
+                .long("axes")
+                .value_name("TIP,TOP")
+                .value_parser(clap::value_parser!(AxisMapping))
+                .default_value("+x,+z")
+                .help("Which BNO055 axes point towards the pen's tip and out of its top"),
+        )
+        .arg(
+            Arg::new("lever-arm")
+                .long("lever-arm")
+                .value_name("X,Y,Z")
+                .value_parser(clap::value_parser!(LeverArm))
+                .allow_hyphen_values(true)
+                .default_value("0,0,0")
+                .help("Where the tip is relative to the IMU, in meters along the BNO055's axes"),
+        )
+        .arg(
+            Arg::new("tolerance")
+                .long("tolerance")
+                .value_name("METERS")
+                .value_parser(clap::value_parser!(f32))
+                .help(format!(
+                    "How far the simplified path may stray from the recorded one [default: {}]",
+                    waypoints::DEFAULT_TOLERANCE
+                )),
+        )
+        .arg(
+            Arg::new("angle-tolerance")
+                .long("angle-tolerance")
+                .value_name("DEGREES")
+                .value_parser(clap::value_parser!(f32))
+                .help(format!(
+                    "How far the simplified path's orientation may stray from the recorded one [default: {}]",
+                    waypoints::DEFAULT_ANGLE_TOLERANCE
+                )),
+        )
+        .arg(
+            Arg::new("max-speed")
+                .long("max-speed")
+                .value_name("M/S")
+                .value_parser(clap::value_parser!(f32))
+                .help(format!(
+                    "Fastest the machine may move. Faster moves are slowed down [default: {}]",
+                    waypoints::DEFAULT_MAX_SPEED
+                )),
+        )
+        .arg(
+            Arg::new("registration")
+                .long("registration")
+                .value_name("FILE")
+                .value_parser(|path: &str| Registration::load(path))
+                .conflicts_with_all(["axes", "placement"])
+                .help("Puts the waypoints in the work frame saved by `register`, instead of using --axes and --placement"),
+        )
+        .arg(
+            Arg::new("max-angular-speed")
+                .long("max-angular-speed")
+                .value_name("DEG/S")
+                .value_parser(clap::value_parser!(f32))
+                .help(format!(
+                    "Fastest the machine may turn the tool [default: {}]",
+                    waypoints::DEFAULT_MAX_ANGULAR_SPEED
+                )),
+        )
+        .get_matches();
+
+    let input = matches.get_one::<String>("input").unwrap();
+    let from = matches
+        .get_one::<String>("from")
+        .map_or_else(|| Format::from_path(input), |name| name.parse().unwrap());
+    let output = matches.get_one::<String>("output").map(PathBuf::from);
+    let format = matches
+        .get_one::<ProgramFormat>("format")
+        .copied()
+        .unwrap_or_else(|| {
+            output
+                .as_ref()
+                .map_or(ProgramFormat::Json, ProgramFormat::from_path)
+        });
+    let output = output.unwrap_or_else(|| Path::new(input).with_extension(format.extension()));
+    let registration = matches.get_one::<Registration>("registration").copied();
+    let config = trajectory::Config {
+        // registered samples are in the tool frame, and so has the lever arm to be
+        lever_arm: registration::register_lever_arm(
+            registration.as_ref(),
+            *matches.get_one::<LeverArm>("lever-arm").unwrap(),
+        ),
+        ..trajectory::Config::default()
+    };
+    let tolerance = Tolerance {
+        distance: matches
+            .get_one::<f32>("tolerance")
+            .copied()
+            .unwrap_or(waypoints::DEFAULT_TOLERANCE),
+        angle: matches
+            .get_one::<f32>("angle-tolerance")
+            .copied()
+            .unwrap_or(waypoints::DEFAULT_ANGLE_TOLERANCE),
+    };
+    let limits = SpeedLimits {
+        linear: matches
+            .get_one::<f32>("max-speed")
+            .copied()
+            .unwrap_or(waypoints::DEFAULT_MAX_SPEED),
+        angular: matches
+            .get_one::<f32>("max-angular-speed")
+            .copied()
+            .unwrap_or(waypoints::DEFAULT_MAX_ANGULAR_SPEED),
+    };
+
+    let (mapping, placement) = match registration.as_ref() {
+        Some(registration) => (Registration::MAPPING, registration.placement()),
+        None => (
+            *matches.get_one::<AxisMapping>("axes").unwrap(),
+            *matches.get_one::<Placement>("placement").unwrap(),
+        ),
+    };
+
+    let reader = BufReader::new(File::open(input).expect("failed to open input"));
+    let recorded = convert::reader(reader, from)
+        .and_then(|samples| {
+            // registered samples are in the tool frame
+            let samples = registration::register_all(registration, samples);
+            waypoints::record(
+                &mut Tracker::new(config, Mahony::default()),
+                samples,
+                mapping,
+                &placement,
+            )
+        })
+        .unwrap_or_else(|error| {
+            eprintln!("failed to read {}: {}", input, error);
+            std::process::exit(1);
+        });
+    let program = waypoints::limit_speed(&waypoints::simplify(&recorded, tolerance), limits);
+
+    let text = match format {
+        ProgramFormat::Gcode => waypoints::to_gcode(&program, limits),
+        ProgramFormat::Json => {
+            waypoints::to_json(&program, matches.get_one::<String>("frame-id").unwrap())
+        }
+    };
+    std::fs::write(&output, text).unwrap_or_else(|error| {
+        eprintln!("failed to write {}: {}", output.display(), error);
+        std::process::exit(1);
+    });
+    println!(
+        "wrote {} waypoints out of {} to {}",
+        program.len(),
+        recorded.len(),
+        output.display()
+    );
+}
@@ -0,0 +1,883 @@
+//! Shares one pen with many processes. The [`Hub`] rebroadcasts everything pensel sends to any
+//! number of clients, and passes commands from authorized clients back to it.
+//!
+//! Clients can listen over:
+//! - TCP, as binary [`Message`]s each prefixed with their length, see [`Message::encode_frame`]
+//! - WebSocket, as JSON text messages, see [`Message::to_json`]
+//! - UDP multicast, one binary [`Message`] per datagram. This one is receive only.
+//!
+//! Commands are only taken from clients that have sent [`Message::Auth`] with the hub's token.
+//! Without a token, clients can only listen.
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{self, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender, TryRecvError, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+use tungstenite::{protocol::WebSocketConfig, WebSocket};
+
+use crate::{
+    recording::{Stream, TimedSample},
+    types::{self, imu},
+};
+use pensel_types::mint::Vector3;
+
+/// Default [`Config::outbox`]
+pub const DEFAULT_OUTBOX: usize = 1024;
+
+/// Longest [encoded](Message::encode) message a [frame](Message::encode_frame) can hold
+const MAX_FRAME_LEN: usize = u16::MAX as usize;
+/// Longest message a WebSocket client may send. Tokens and commands are short.
+const MAX_WEBSOCKET_MESSAGE: usize = 64 * 1024;
+/// How long a WebSocket client's thread waits for it to say something, before going back to
+/// sending it what's queued up
+const WEBSOCKET_POLL: Duration = Duration::from_millis(5);
+
+const TAG_SAMPLE: u8 = b'S';
+const TAG_LINE: u8 = b'L';
+const TAG_AUTH: u8 = b'T';
+const TAG_COMMAND: u8 = b'C';
+const TAG_REPLY: u8 = b'R';
+/// Length of an encoded [`Message::Sample`]: tag, stream, microseconds and three counts
+const SAMPLE_LEN: usize = 1 + 1 + 8 + 3 * 2;
+
+/// Everything sent between the bridge and its clients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// A sample pensel streamed, timed from when the bridge started
+    Sample(TimedSample),
+    /// Any other line pensel sent, like logs and replies to commands
+    Line(String),
+    /// Client to bridge: the token allowing the client to send commands
+    Auth(String),
+    /// Client to bridge: a command for pensel
+    Command(String),
+    /// Bridge to client: whether its last [`Message::Auth`] or [`Message::Command`] was accepted
+    Reply { ok: bool, text: String },
+}
+
+/// Why a binary message couldn't be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// There was nothing to decode
+    Empty,
+    /// The message starts with a tag we don't know
+    UnknownTag(u8),
+    /// The message is shorter or longer than its tag says
+    WrongLength(usize),
+    /// A sample's stream isn't one we know
+    UnknownStream(u8),
+    /// Text isn't valid UTF-8
+    NotUtf8,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty message"),
+            Self::UnknownTag(tag) => write!(f, "unknown message tag {:#04x}", tag),
+            Self::WrongLength(len) => write!(f, "message of {} bytes has the wrong length", len),
+            Self::UnknownStream(stream) => write!(f, "unknown stream {:#04x}", stream),
+            Self::NotUtf8 => write!(f, "message text isn't UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A [`Message`] as JSON. Samples are in engineering units, like [`crate::convert`]'s JSON lines.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Json {
+    Sample {
+        time_s: f64,
+        stream: String,
+        x: f32,
+        y: f32,
+        z: f32,
+    },
+    Line {
+        text: String,
+    },
+    Auth {
+        token: String,
+    },
+    Command {
+        text: String,
+    },
+    Reply {
+        ok: bool,
+        text: String,
+    },
+}
+
+impl Message {
+    /// The message as bytes: a tag byte, then
+    /// - samples: the stream's line prefix (`A` or `G`), microseconds since the bridge started
+    ///   as a big endian `u64`, and x, y and z in BNO055 counts as big endian `i16`s
+    /// - replies: 1 if ok, else 0, then the text
+    /// - everything else: its text, as UTF-8
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let text = |tag: u8, text: &str| {
+            let mut out = Vec::with_capacity(text.len() + 1);
+            out.push(tag);
+            out.extend_from_slice(text.as_bytes());
+            out
+        };
+        match self {
+            Self::Sample(timed) => {
+                let (prefix, counts) = match timed.sample {
+                    types::Sample::Accel(a) => (b'A', [a.x, a.y, a.z]),
+                    types::Sample::Grav(g) => (b'G', [g.x, g.y, g.z]),
+                };
+                let micros = u64::try_from(timed.timestamp.as_micros()).unwrap_or(u64::MAX);
+                let mut out = Vec::with_capacity(SAMPLE_LEN);
+                out.extend_from_slice(&[TAG_SAMPLE, prefix]);
+                out.extend_from_slice(&micros.to_be_bytes());
+                for count in counts {
+                    out.extend_from_slice(&count.to_be_bytes());
+                }
+                out
+            }
+            Self::Line(line) => text(TAG_LINE, line),
+            Self::Auth(token) => text(TAG_AUTH, token),
+            Self::Command(command) => text(TAG_COMMAND, command),
+            Self::Reply { ok, text: reply } => {
+                let mut out = text(TAG_REPLY, reply);
+                out.insert(1, u8::from(*ok));
+                out
+            }
+        }
+    }
+
+    /// The message [encoded](Message::encode), prefixed with its length as a big endian `u16`,
+    /// as sent over TCP. Longer text is cut short, at the end of a character.
+    #[must_use]
+    pub fn encode_frame(&self) -> Vec<u8> {
+        let mut payload = self.encode();
+        // only text gets this long, and the bytes before it are ASCII, so backing up over UTF-8
+        // continuation bytes lands on the start of a character
+        let mut end = MAX_FRAME_LEN;
+        while payload.get(end).is_some_and(|byte| byte & 0xc0 == 0x80) {
+            end -= 1;
+        }
+        payload.truncate(end);
+        let len = u16::try_from(payload.len()).unwrap_or(u16::MAX);
+        let mut out = Vec::with_capacity(payload.len() + 2);
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(&payload);
+        out
+    }
+
+    /// Decodes a message [encoded](Message::encode) as bytes
+    ///
+    /// # Errors
+    /// If `bytes` isn't a valid message
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (&tag, rest) = bytes.split_first().ok_or(DecodeError::Empty)?;
+        let text =
+            |bytes: &[u8]| String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::NotUtf8);
+        match tag {
+            TAG_SAMPLE => {
+                if bytes.len() != SAMPLE_LEN {
+                    return Err(DecodeError::WrongLength(bytes.len()));
+                }
+                let mut micros = [0; 8];
+                micros.copy_from_slice(&rest[1..9]);
+                let count = |at: usize| i16::from_be_bytes([rest[at], rest[at + 1]]);
+                let (x, y, z) = (count(9), count(11), count(13));
+                let sample = match rest[0] {
+                    b'A' => types::Sample::Accel(imu::AccelerationVector::new(x, y, z)),
+                    b'G' => types::Sample::Grav(imu::GravityVector::new(x, y, z)),
+                    other => return Err(DecodeError::UnknownStream(other)),
+                };
+                Ok(Self::Sample(TimedSample {
+                    timestamp: Duration::from_micros(u64::from_be_bytes(micros)),
+                    sample,
+                }))
+            }
+            TAG_LINE => Ok(Self::Line(text(rest)?)),
+            TAG_AUTH => Ok(Self::Auth(text(rest)?)),
+            TAG_COMMAND => Ok(Self::Command(text(rest)?)),
+            TAG_REPLY => {
+                let (&ok, reply) = rest
+                    .split_first()
+                    .ok_or(DecodeError::WrongLength(bytes.len()))?;
+                Ok(Self::Reply {
+                    ok: ok != 0,
+                    text: text(reply)?,
+                })
+            }
+            other => Err(DecodeError::UnknownTag(other)),
+        }
+    }
+
+    /// Reads one [framed](Message::encode_frame) message from `input`
+    ///
+    /// # Errors
+    /// If reading fails or the message can't be decoded
+    pub fn read_frame(input: &mut impl Read) -> io::Result<Self> {
+        let mut len = [0; 2];
+        input.read_exact(&mut len)?;
+        let mut payload = vec![0; usize::from(u16::from_be_bytes(len))];
+        input.read_exact(&mut payload)?;
+        Self::decode(&payload).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// The message as a JSON object, with a `type` of `sample`, `line`, `auth`, `command` or
+    /// `reply`
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        let json = match self {
+            Self::Sample(timed) => {
+                let (stream, vector) = match timed.sample {
+                    types::Sample::Accel(a) => (Stream::Accel, a.m_s2()),
+                    types::Sample::Grav(g) => (Stream::Gravity, g.m_s2()),
+                };
+                Json::Sample {
+                    time_s: timed.timestamp.as_secs_f64(),
+                    stream: stream.name().to_owned(),
+                    x: vector.x,
+                    y: vector.y,
+                    z: vector.z,
+                }
+            }
+            Self::Line(text) => Json::Line { text: text.clone() },
+            Self::Auth(token) => Json::Auth {
+                token: token.clone(),
+            },
+            Self::Command(text) => Json::Command { text: text.clone() },
+            Self::Reply { ok, text } => Json::Reply {
+                ok: *ok,
+                text: text.clone(),
+            },
+        };
+        // nothing in there can fail to serialize
+        serde_json::to_string(&json).unwrap_or_default()
+    }
+
+    /// Parses a message [written as JSON](Message::to_json)
+    ///
+    /// # Errors
+    /// If `json` isn't a message, or a sample is out of range
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        Ok(
+            match serde_json::from_str::<Json>(json).map_err(|error| error.to_string())? {
+                Json::Sample {
+                    time_s,
+                    stream,
+                    x,
+                    y,
+                    z,
+                } => {
+                    let vector = Vector3 { x, y, z };
+                    let out_of_range = || format!("{} sample out of range", stream);
+                    let sample = match stream.parse::<Stream>()? {
+                        Stream::Accel => types::Sample::Accel(
+                            imu::AccelerationVector::from_m_s2(vector).ok_or_else(out_of_range)?,
+                        ),
+                        Stream::Gravity => types::Sample::Grav(
+                            imu::GravityVector::from_m_s2(vector).ok_or_else(out_of_range)?,
+                        ),
+                    };
+                    if !time_s.is_finite() || time_s < 0. {
+                        return Err(format!("bad time {}", time_s));
+                    }
+                    // to the microsecond, like the binary encoding
+                    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                    let timestamp = Duration::from_micros((time_s * 1e6).round() as u64);
+                    Self::Sample(TimedSample { timestamp, sample })
+                }
+                Json::Line { text } => Self::Line(text),
+                Json::Auth { token } => Self::Auth(token),
+                Json::Command { text } => Self::Command(text),
+                Json::Reply { ok, text } => Self::Reply { ok, text },
+            },
+        )
+    }
+}
+
+/// How clients are connected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// framed binary messages over TCP
+    Tcp,
+    /// JSON over WebSocket
+    WebSocket,
+}
+
+impl Transport {
+    /// `message`, ready to send over this transport: a frame over TCP, JSON over WebSocket
+    fn encode(self, message: &Message) -> Arc<[u8]> {
+        match self {
+            Self::Tcp => message.encode_frame().into(),
+            Self::WebSocket => message.to_json().into_bytes().into(),
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp => write!(f, "tcp"),
+            Self::WebSocket => write!(f, "websocket"),
+        }
+    }
+}
+
+/// How a [`Hub`] shares the pen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// clients that send [`Message::Auth`] with this may send commands. `None` lets no one.
+    pub token: Option<String>,
+    /// where to send every message as a UDP datagram, usually a multicast group
+    pub multicast: Option<SocketAddr>,
+    /// how many messages can queue up for a slow client before it starts missing them
+    pub outbox: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            token: None,
+            multicast: None,
+            outbox: DEFAULT_OUTBOX,
+        }
+    }
+}
+
+/// What's queued up for a client
+enum Outgoing {
+    /// a message, [encoded](Transport::encode) for the client's transport
+    Message(Arc<[u8]>),
+    /// hang up, once everything before has been sent
+    HangUp,
+}
+
+/// A connected client, as far as broadcasting is concerned
+struct Client {
+    transport: Transport,
+    outbox: SyncSender<Outgoing>,
+}
+
+/// Who's on the other end of `stream`, for logs
+fn peer(stream: &TcpStream) -> String {
+    stream
+        .peer_addr()
+        .map_or_else(|_| "unknown".to_owned(), |peer| peer.to_string())
+}
+
+/// Whether `given` is the `expected` token. Takes the same time no matter how much of it
+/// matches, so timing replies doesn't help guessing it.
+fn token_matches(expected: &str, given: &str) -> bool {
+    let difference = expected
+        .bytes()
+        .zip(given.bytes())
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    std::hint::black_box(difference) == 0 && expected.len() == given.len()
+}
+
+/// A WebSocket error as an I/O error. A closed connection is the end of the stream.
+fn websocket_error(error: tungstenite::Error) -> io::Error {
+    match error {
+        tungstenite::Error::Io(error) => error,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            io::ErrorKind::UnexpectedEof.into()
+        }
+        other => io::Error::new(io::ErrorKind::InvalidData, other),
+    }
+}
+
+/// A reply turning the client down because of `text`
+fn refusal(text: String) -> Message {
+    Message::Reply { ok: false, text }
+}
+
+/// Rebroadcasts pensel's messages to every connected client, and collects their commands
+pub struct Hub {
+    config: Config,
+    clients: Mutex<Vec<Client>>,
+    udp: Option<UdpSocket>,
+    commands: Sender<String>,
+    /// messages clients missed because they didn't keep up
+    dropped: AtomicUsize,
+}
+
+impl Hub {
+    /// Starts a hub sharing according to `config`
+    ///
+    /// # Returns
+    /// The hub, and where commands from authorized clients come out, for sending on to pensel
+    ///
+    /// # Errors
+    /// If there's a [`Config::multicast`] address but no socket to send to it from
+    pub fn new(config: Config) -> io::Result<(Arc<Self>, Receiver<String>)> {
+        let udp = config
+            .multicast
+            .map(|group| {
+                let any: SocketAddr = if group.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0_u16; 8], 0).into()
+                };
+                UdpSocket::bind(any)
+            })
+            .transpose()?;
+        let (commands, receiver) = mpsc::channel();
+        let hub = Self {
+            config,
+            clients: Mutex::new(Vec::new()),
+            udp,
+            commands,
+            dropped: AtomicUsize::new(0),
+        };
+        Ok((Arc::new(hub), receiver))
+    }
+
+    /// How many clients are connected. Ones that just left may still be counted until the next
+    /// broadcast.
+    #[must_use]
+    pub fn clients(&self) -> usize {
+        self.clients.lock().map_or(0, |clients| clients.len())
+    }
+
+    /// How many messages clients have missed because they didn't keep up
+    #[must_use]
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sends `message` to every client
+    pub fn broadcast(&self, message: &Message) {
+        if let (Some(udp), Some(group)) = (&self.udp, self.config.multicast) {
+            if let Err(error) = udp.send_to(&message.encode(), group) {
+                log::debug!("failed to multicast to {}: {}", group, error);
+            }
+        }
+
+        let Ok(mut clients) = self.clients.lock() else {
+            return;
+        };
+        let mut encoded: [Option<Arc<[u8]>>; 2] = [None, None];
+        clients.retain(|client| {
+            let bytes = encoded[client.transport as usize]
+                .get_or_insert_with(|| client.transport.encode(message));
+            match client.outbox.try_send(Outgoing::Message(bytes.clone())) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+
+    /// Accepts clients connecting to `listener` over `transport`, in the background
+    pub fn serve(self: &Arc<Self>, listener: TcpListener, transport: Transport) {
+        let hub = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let hub = hub.clone();
+                        thread::spawn(move || hub.handle_client(stream, transport));
+                    }
+                    Err(error) => log::warn!("failed to accept {} client: {}", transport, error),
+                }
+            }
+        });
+    }
+
+    /// Serves one client until it disconnects
+    fn handle_client(&self, stream: TcpStream, transport: Transport) {
+        let peer = peer(&stream);
+        let served = match transport {
+            Transport::Tcp => self.serve_tcp(&stream),
+            Transport::WebSocket => self.serve_websocket(&stream),
+        };
+        if let Err(error) = served {
+            if error.kind() != io::ErrorKind::UnexpectedEof {
+                log::info!("{} client {} left: {}", transport, peer, error);
+            }
+        }
+        // makes the writer fail, so it stops and the client gets dropped from broadcasts
+        stream.shutdown(Shutdown::Both).ok();
+        log::info!("{} client {} disconnected", transport, peer);
+    }
+
+    /// Adds a client on `stream` to the broadcasts
+    ///
+    /// # Returns
+    /// Where to queue up things for the client, and where they come out
+    fn join(
+        &self,
+        stream: &TcpStream,
+        transport: Transport,
+    ) -> (SyncSender<Outgoing>, Receiver<Outgoing>) {
+        let (outbox, pending) = mpsc::sync_channel(self.config.outbox.max(1));
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.push(Client {
+                transport,
+                outbox: outbox.clone(),
+            });
+        }
+        log::info!("{} client {} connected", transport, peer(stream));
+        (outbox, pending)
+    }
+
+    fn serve_tcp(&self, stream: &TcpStream) -> io::Result<()> {
+        let mut input = BufReader::new(stream.try_clone()?);
+        let mut output = stream.try_clone()?;
+        let (outbox, pending) = self.join(stream, Transport::Tcp);
+        let writer = thread::spawn(move || {
+            for outgoing in pending {
+                match outgoing {
+                    Outgoing::Message(bytes) => {
+                        if output.write_all(&bytes).is_err() {
+                            break;
+                        }
+                    }
+                    Outgoing::HangUp => break,
+                }
+            }
+        });
+
+        let mut authorized = false;
+        loop {
+            let message = Message::read_frame(&mut input)?;
+            match self.handle_message(message, &mut authorized) {
+                Ok(reply) => {
+                    let reply = Outgoing::Message(Transport::Tcp.encode(&reply));
+                    outbox.try_send(reply).ok();
+                }
+                Err(reason) => {
+                    // wait for the writer to tell the client why, before hanging up
+                    let reply = Outgoing::Message(Transport::Tcp.encode(&refusal(reason.clone())));
+                    outbox.send(reply).ok();
+                    outbox.send(Outgoing::HangUp).ok();
+                    writer.join().ok();
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, reason));
+                }
+            }
+        }
+    }
+
+    /// Serves a WebSocket client from one thread, which takes turns sending it what's queued up
+    /// and briefly waiting for it to say something
+    fn serve_websocket(&self, stream: &TcpStream) -> io::Result<()> {
+        let config = WebSocketConfig::default().max_message_size(Some(MAX_WEBSOCKET_MESSAGE));
+        let mut socket = tungstenite::accept_with_config(stream.try_clone()?, Some(config))
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?;
+        stream.set_read_timeout(Some(WEBSOCKET_POLL))?;
+        let (_, pending) = self.join(stream, Transport::WebSocket);
+
+        let mut authorized = false;
+        loop {
+            loop {
+                match pending.try_recv() {
+                    Ok(Outgoing::Message(json)) => {
+                        let text = String::from_utf8_lossy(&json).into_owned();
+                        socket
+                            .write(tungstenite::Message::text(text))
+                            .map_err(websocket_error)?;
+                    }
+                    Ok(Outgoing::HangUp) | Err(TryRecvError::Disconnected) => return Ok(()),
+                    Err(TryRecvError::Empty) => break,
+                }
+            }
+            socket.flush().map_err(websocket_error)?;
+
+            let text = match socket.read() {
+                Ok(tungstenite::Message::Text(text)) => text,
+                // tungstenite answers pings and closes itself, and clients don't send binary
+                Ok(_) => continue,
+                Err(tungstenite::Error::Io(error))
+                    if matches!(
+                        error.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(error) => return Err(websocket_error(error)),
+            };
+            let reply = match Message::from_json(&text) {
+                Ok(message) => self.handle_message(message, &mut authorized),
+                Err(error) => Ok(refusal(error)),
+            };
+            match reply {
+                Ok(reply) => send_json(&mut socket, &reply)?,
+                Err(reason) => {
+                    send_json(&mut socket, &refusal(reason.clone()))?;
+                    socket.close(None).ok();
+                    socket.flush().ok();
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, reason));
+                }
+            }
+        }
+    }
+
+    /// Acts on `message` from a client, which is `authorized` to send commands or not
+    ///
+    /// # Returns
+    /// What to reply to the client
+    ///
+    /// # Errors
+    /// Why the client has to go, after it's told so. Clients are dropped when their
+    /// [`Message::Auth`] fails, so they have to reconnect for every guess.
+    fn handle_message(&self, message: Message, authorized: &mut bool) -> Result<Message, String> {
+        let reply = |ok: bool, text: &str| Message::Reply {
+            ok,
+            text: text.to_owned(),
+        };
+        Ok(match message {
+            Message::Auth(token) => match &self.config.token {
+                None => return Err("commands are disabled".to_owned()),
+                Some(expected) if token_matches(expected, &token) => {
+                    *authorized = true;
+                    reply(true, "authorized")
+                }
+                Some(_) => return Err("wrong token".to_owned()),
+            },
+            Message::Command(command) if *authorized => {
+                if self.commands.send(command).is_ok() {
+                    reply(true, "sent")
+                } else {
+                    reply(false, "pen is gone")
+                }
+            }
+            Message::Command(_) => reply(false, "not authorized"),
+            Message::Sample(_) | Message::Line(_) | Message::Reply { .. } => {
+                reply(false, "clients can only send auth and command messages")
+            }
+        })
+    }
+}
+
+/// Sends `message` to a WebSocket client, as JSON
+fn send_json(socket: &mut WebSocket<TcpStream>, message: &Message) -> io::Result<()> {
+    socket
+        .send(tungstenite::Message::text(message.to_json()))
+        .map_err(websocket_error)
+}
+
+#[cfg(test)]
+mod test_bridge {
+    use super::*;
+    use std::time::Instant;
+    use tungstenite::stream::MaybeTlsStream;
+
+    fn sample() -> Message {
+        Message::Sample(TimedSample {
+            timestamp: Duration::from_micros(1_234_567),
+            sample: types::Sample::Grav(imu::GravityVector::new(-12, 345, -981)),
+        })
+    }
+
+    /// A hub listening over `transport` on localhost, and where to reach it
+    fn serve(config: Config, transport: Transport) -> (Arc<Hub>, Receiver<String>, SocketAddr) {
+        let (hub, commands) = Hub::new(config).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        hub.serve(listener, transport);
+        (hub, commands, address)
+    }
+
+    fn wait_for_clients(hub: &Hub, count: usize) {
+        let start = Instant::now();
+        while hub.clients() != count {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "client never connected"
+            );
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn encodes() {
+        let messages = [
+            sample(),
+            Message::Sample(TimedSample {
+                timestamp: Duration::ZERO,
+                sample: types::Sample::Accel(imu::AccelerationVector::new(1, -2, 3)),
+            }),
+            Message::Line("[INFO] calibrated ✓".to_owned()),
+            Message::Auth("secret".to_owned()),
+            Message::Command("imu --accel".to_owned()),
+            Message::Reply {
+                ok: false,
+                text: "not authorized".to_owned(),
+            },
+        ];
+        for message in messages {
+            assert_eq!(Message::decode(&message.encode()), Ok(message.clone()));
+            let frame = message.encode_frame();
+            assert_eq!(Message::read_frame(&mut frame.as_slice()).unwrap(), message);
+            assert_eq!(Message::from_json(&message.to_json()), Ok(message));
+        }
+
+        assert_eq!(sample().encode().len(), SAMPLE_LEN);
+        assert_eq!(
+            sample().to_json(),
+            r#"{"type":"sample","time_s":1.234567,"stream":"gravity","x":-0.12,"y":3.45,"z":-9.81}"#
+        );
+        assert_eq!(Message::decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(Message::decode(b"Z"), Err(DecodeError::UnknownTag(b'Z')));
+        assert_eq!(Message::decode(b"SA"), Err(DecodeError::WrongLength(2)));
+        assert!(Message::from_json(r#"{"type":"command"}"#).is_err());
+
+        // too long for a frame: cut short, but still UTF-8
+        let long = Message::Line(format!("a{}", "é".repeat(40_000)));
+        let frame = long.encode_frame();
+        assert_eq!(frame.len(), 2 + MAX_FRAME_LEN - 1);
+        match Message::read_frame(&mut frame.as_slice()).unwrap() {
+            Message::Line(line) => assert_eq!(line, format!("a{}", "é".repeat(32_766))),
+            other => panic!("expected a line, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn broadcasts_over_tcp() {
+        let (hub, _, address) = serve(Config::default(), Transport::Tcp);
+        let mut first = TcpStream::connect(address).unwrap();
+        let mut second = TcpStream::connect(address).unwrap();
+        wait_for_clients(&hub, 2);
+
+        hub.broadcast(&sample());
+        hub.broadcast(&Message::Line("hello".to_owned()));
+        for client in [&mut first, &mut second] {
+            assert_eq!(Message::read_frame(client).unwrap(), sample());
+            assert_eq!(
+                Message::read_frame(client).unwrap(),
+                Message::Line("hello".to_owned())
+            );
+        }
+
+        // once a client is gone it stops being sent to
+        drop(first);
+        let start = Instant::now();
+        while hub.clients() != 1 {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "client never dropped"
+            );
+            hub.broadcast(&sample());
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn only_takes_commands_when_authorized() {
+        let config = Config {
+            token: Some("secret".to_owned()),
+            ..Config::default()
+        };
+        let (hub, commands, address) = serve(config, Transport::Tcp);
+        let exchange = |client: &mut TcpStream, message: Message| {
+            client.write_all(&message.encode_frame()).unwrap();
+            match Message::read_frame(client).unwrap() {
+                Message::Reply { ok, .. } => ok,
+                other => panic!("expected a reply, got {:?}", other),
+            }
+        };
+
+        let mut client = TcpStream::connect(address).unwrap();
+        wait_for_clients(&hub, 1);
+        assert!(!exchange(&mut client, Message::Command("reset".to_owned())));
+        // a wrong token gets the client hung up on
+        assert!(!exchange(&mut client, Message::Auth("guess".to_owned())));
+        assert!(Message::read_frame(&mut client).is_err());
+
+        let mut client = TcpStream::connect(address).unwrap();
+        assert!(exchange(&mut client, Message::Auth("secret".to_owned())));
+        assert!(exchange(
+            &mut client,
+            Message::Command("imu --gravity".to_owned())
+        ));
+        assert_eq!(commands.try_recv().unwrap(), "imu --gravity");
+        assert!(commands.try_recv().is_err());
+        assert!(!token_matches("secret", "secret2"));
+        assert!(!token_matches("secret", "secreT"));
+    }
+
+    /// The next message a WebSocket client gets
+    fn receive(client: &mut WebSocket<MaybeTlsStream<TcpStream>>) -> Message {
+        match client.read().unwrap() {
+            tungstenite::Message::Text(text) => Message::from_json(&text).unwrap(),
+            other => panic!("expected text, got {:?}", other),
+        }
+    }
+
+    fn send(client: &mut WebSocket<MaybeTlsStream<TcpStream>>, message: &Message) {
+        client
+            .send(tungstenite::Message::text(message.to_json()))
+            .unwrap();
+    }
+
+    #[test]
+    fn speaks_websocket() {
+        let config = Config {
+            token: Some("secret".to_owned()),
+            ..Config::default()
+        };
+        let (hub, commands, address) = serve(config, Transport::WebSocket);
+        let url = format!("ws://{}/", address);
+        let (mut client, _) = tungstenite::connect(&url).unwrap();
+        wait_for_clients(&hub, 1);
+
+        hub.broadcast(&sample());
+        assert_eq!(receive(&mut client), sample());
+
+        for message in [
+            Message::Auth("secret".to_owned()),
+            Message::Command("info".to_owned()),
+        ] {
+            send(&mut client, &message);
+            assert!(matches!(
+                receive(&mut client),
+                Message::Reply { ok: true, .. }
+            ));
+        }
+        assert_eq!(commands.recv().unwrap(), "info");
+
+        // a wrong token gets the client hung up on
+        let (mut client, _) = tungstenite::connect(&url).unwrap();
+        send(&mut client, &Message::Auth("guess".to_owned()));
+        assert!(matches!(
+            receive(&mut client),
+            Message::Reply { ok: false, .. }
+        ));
+        assert!(matches!(
+            client.read(),
+            Ok(tungstenite::Message::Close(_)) | Err(_)
+        ));
+    }
+
+    #[test]
+    fn multicasts() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let config = Config {
+            multicast: Some(receiver.local_addr().unwrap()),
+            ..Config::default()
+        };
+        let (hub, _) = Hub::new(config).unwrap();
+        hub.broadcast(&sample());
+
+        let mut datagram = [0; 64];
+        let len = receiver.recv(&mut datagram).unwrap();
+        assert_eq!(Message::decode(&datagram[..len]), Ok(sample()));
+    }
+}
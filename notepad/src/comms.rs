@@ -1,11 +1,13 @@
 //! Takes care of all of the serial communication & parsing with Pensel
 use heapless::spsc::Producer;
+use pensel_types::{cli, imu};
 use std::{
     str::FromStr,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
 use crate::types;
@@ -54,6 +56,12 @@ impl PenselSerial {
         panic!("no matching port found");
     }
 
+    /// Name of the serial port pensel is on, if the platform knows it
+    #[must_use]
+    pub fn port_name(&self) -> Option<String> {
+        self.port.name()
+    }
+
     /// Sends the given command over serial. Currently doesn't check if pensel received it properly.
     ///
     /// # Errors
@@ -66,6 +74,26 @@ impl PenselSerial {
         Ok(())
     }
 
+    /// Sends the given command over serial without waiting for pensel to echo it back. Useful when
+    /// another handle (see [`PenselSerial::try_clone`]) is busy reading everything pensel sends.
+    ///
+    /// # Errors
+    /// If we fail to write out the command bytes.
+    pub fn write_command(&mut self, command: &str) -> Result<(), std::io::Error> {
+        log::debug!("writing command {:?}", command);
+        self.port.write_all(command.as_bytes())?;
+        self.port.write_all(b"\r")?;
+        self.port.flush()
+    }
+
+    /// Creates another handle to the same serial port, so one thread can read while another writes.
+    ///
+    /// # Errors
+    /// If the underlying serial port can't be cloned.
+    pub fn try_clone(&self) -> Result<Self, serialport::Error> {
+        Ok(Self::new(self.port.try_clone()?))
+    }
+
     fn wait_for(&mut self, line: &str) -> Result<(), std::io::Error> {
         let mut write_index = 0;
         let mut read_buf: [u8; 1024] = [0; 1024];
@@ -105,6 +133,122 @@ impl PenselSerial {
         types::ParsedLine::None
     }
 
+    /// Hands every complete line pensel sends to `on_line`, without its line ending, as long as
+    /// `should_run` is `true`. Invalid UTF-8 is replaced rather than treated as an error.
+    pub fn read_lines_until(
+        &mut self,
+        mut on_line: impl FnMut(&str),
+        should_run: &Arc<AtomicBool>,
+    ) {
+        let mut pending: Vec<u8> = Vec::new();
+
+        while should_run.as_ref().load(Ordering::Acquire) {
+            if let Err(error) = self.read_pending(&mut pending) {
+                if error.kind() == std::io::ErrorKind::BrokenPipe {
+                    eprintln!("serial port disconnected");
+                    should_run.as_ref().store(false, Ordering::Release);
+                }
+            }
+
+            while let Some(line) = Self::take_line(&mut pending) {
+                on_line(&line);
+            }
+        }
+    }
+
+    /// Asks pensel to describe itself with the `info` command.
+    ///
+    /// # Errors
+    /// If we fail to send the command or read the response, or pensel doesn't report everything
+    /// within `timeout` (e.g. firmware too old to have the command).
+    pub fn device_info(&mut self, timeout: Duration) -> Result<types::DeviceInfo, std::io::Error> {
+        self.write_command(cli::CMD_INFO)?;
+
+        let deadline = Instant::now() + timeout;
+        let mut pending: Vec<u8> = Vec::new();
+        let (mut pen_id, mut firmware, mut calibration) = (None, None, None);
+        // older firmware doesn't report it
+        let mut axis_map = String::new();
+        while Instant::now() < deadline {
+            match self.read_pending(&mut pending) {
+                Err(error) if error.kind() != std::io::ErrorKind::TimedOut => return Err(error),
+                _ => (),
+            }
+
+            while let Some(line) = Self::take_line(&mut pending) {
+                let Some((key, value)) = line.trim_start_matches("> ").split_once(": ") else {
+                    continue;
+                };
+                let value = value.trim();
+                match key {
+                    cli::INFO_PEN_ID => pen_id = Some(value.to_owned()),
+                    cli::INFO_FIRMWARE => firmware = Some(value.to_owned()),
+                    cli::INFO_AXIS_MAP => axis_map = value.to_owned(),
+                    cli::INFO_ACCEL_SCALE => match f32::from_str(value) {
+                        Ok(scale) if scale == imu::scale::ACCEL_LSB_PER_M_S2 => (),
+                        _ => log::warn!(
+                            "pensel streams {} counts per m/s², we expect {}",
+                            value,
+                            imu::scale::ACCEL_LSB_PER_M_S2
+                        ),
+                    },
+                    cli::INFO_CALIBRATION => {
+                        calibration = value
+                            .split(',')
+                            .map(|byte| u8::from_str(byte.trim()))
+                            .collect::<Result<Vec<u8>, _>>()
+                            .ok();
+                    }
+                    _ => (),
+                }
+            }
+
+            if let (Some(pen_id), Some(firmware), Some(calibration)) =
+                (&pen_id, &firmware, &calibration)
+            {
+                return Ok(types::DeviceInfo {
+                    pen_id: pen_id.clone(),
+                    firmware: firmware.clone(),
+                    axis_map,
+                    calibration: calibration.clone(),
+                });
+            }
+        }
+
+        Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "pensel didn't describe itself in time",
+        ))
+    }
+
+    /// Sets the BNO055's axis map with `imu --axis-map`. Pensel has nowhere to keep it, so it's
+    /// back to the firmware's default after every reset, and has to be sent on every connect.
+    ///
+    /// # Errors
+    /// If we fail to send the command.
+    pub fn set_axis_map(&mut self, map: imu::AxisMap) -> Result<(), serialport::Error> {
+        self.send_command(&format!("{} --{}={}", cli::CMD_IMU, cli::ARG_AXIS_MAP, map))
+    }
+
+    /// Reads whatever pensel has sent onto the end of `pending`
+    fn read_pending(&mut self, pending: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let bytes_read = self.port.read(&mut read_buf)?;
+        pending.extend_from_slice(&read_buf[..bytes_read]);
+        Ok(())
+    }
+
+    /// Takes the first complete line off the front of `pending`, without its line ending
+    fn take_line(pending: &mut Vec<u8>) -> Option<String> {
+        let newline = pending.iter().position(|b| *b == b'\n')?;
+        let line: Vec<u8> = pending.drain(..=newline).collect();
+        Some(
+            String::from_utf8_lossy(&line)
+                .trim_end_matches(['\r', '\n'])
+                .to_owned(),
+        )
+    }
+
     /// Parses data into `accel_queue` and `grav_queue` as long as `should_run` is `true`.
     ///
     /// # Panics
@@ -164,11 +308,38 @@ impl PenselSerial {
     }
 }
 
+/// Anything that produces a stream of pensel samples: a live pen, or a recording of one.
+///
+/// Tools that only consume samples should take a [`SampleSource`] so they can be pointed at a
+/// file just as easily as at hardware.
+pub trait SampleSource: Send {
+    /// Streams samples into `accel_queue` and `grav_queue` as long as `should_run` is `true`, or
+    /// until the source runs out of samples.
+    fn stream_until(
+        &mut self,
+        accel_queue: Producer<types::imu::AccelerationVector, { types::ACC_QUEUE_SIZE }>,
+        grav_queue: Producer<types::imu::GravityVector, { types::GRAV_QUEUE_SIZE }>,
+        should_run: &Arc<AtomicBool>,
+    );
+}
+
+impl SampleSource for PenselSerial {
+    fn stream_until(
+        &mut self,
+        accel_queue: Producer<types::imu::AccelerationVector, { types::ACC_QUEUE_SIZE }>,
+        grav_queue: Producer<types::imu::GravityVector, { types::GRAV_QUEUE_SIZE }>,
+        should_run: &Arc<AtomicBool>,
+    ) {
+        self.parse_data_until(accel_queue, grav_queue, should_run);
+    }
+}
+
 #[cfg(test)]
 mod comm_test {
     use super::*;
     use crate::mock_serial::MockSerial;
     use heapless::spsc::Queue;
+    use std::ptr::addr_of_mut;
 
     static mut A_QUEUE: Queue<types::imu::AccelerationVector, { types::ACC_QUEUE_SIZE }> =
         Queue::new();
@@ -242,6 +413,60 @@ mod comm_test {
         }
     }
 
+    #[test]
+    fn read_lines() {
+        use std::io::Write;
+
+        let mut port = Box::new(MockSerial::default());
+        port.write_all(b"level: INFO\r\n").unwrap();
+        port.write_all(EXAMPLE_ACCEL_LINE.as_bytes()).unwrap();
+        port.write_all(b"partial").unwrap();
+        let mut serial = PenselSerial::new(port);
+
+        let should_run = Arc::new(AtomicBool::new(true));
+        let mut lines = vec![];
+        serial.read_lines_until(
+            |line| {
+                lines.push(line.to_owned());
+                should_run.store(lines.len() < 2, Ordering::Release);
+            },
+            &should_run.clone(),
+        );
+
+        assert_eq!(lines, ["level: INFO", "A:1,2,3"]);
+    }
+
+    #[test]
+    fn device_info() {
+        use std::io::Write;
+
+        let mut port = Box::new(MockSerial::default());
+        port.write_all(b"> info\r\nfirmware: 0.1.0\r\n").unwrap();
+        port.write_all(EXAMPLE_ACCEL_LINE.as_bytes()).unwrap();
+        port.write_all(b"pen id: 0123abcd\r\naccel scale: 100\r\ncalibration: 2,0,252\r\n> ")
+            .unwrap();
+        let mut serial = PenselSerial::new(port);
+
+        let info = serial.device_info(Duration::from_secs(1)).unwrap();
+        assert_eq!(info.firmware, "0.1.0");
+        assert_eq!(info.pen_id, "0123abcd");
+        assert_eq!(info.calibration, [2, 0, 252]);
+    }
+
+    #[test]
+    fn set_axis_map() {
+        // the mock echoes everything back, like pensel does
+        let mut serial = PenselSerial::new(Box::new(MockSerial::default()));
+        serial.set_axis_map(imu::AxisMap::PLACEMENTS[3]).unwrap();
+    }
+
+    #[test]
+    fn device_info_timeout() {
+        let mut serial = PenselSerial::new(Box::new(MockSerial::default()));
+        let error = serial.device_info(Duration::from_millis(10)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    }
+
     #[test]
     fn parse_until_basic() {
         use std::io::Write;
@@ -252,13 +477,13 @@ mod comm_test {
         let mut port = Box::new(MockSerial::default());
 
         // prime the pipes with some lovely data
-        port.write(EXAMPLE_ACCEL_LINE.as_bytes()).unwrap();
-        port.write(EXAMPLE_GRAVITY_LINE.as_bytes()).unwrap();
+        port.write_all(EXAMPLE_ACCEL_LINE.as_bytes()).unwrap();
+        port.write_all(EXAMPLE_GRAVITY_LINE.as_bytes()).unwrap();
 
         let mut serial = PenselSerial::new(port);
 
-        let (a_producer, mut a_consumer) = unsafe { A_QUEUE.split() };
-        let (g_producer, mut g_consumer) = unsafe { G_QUEUE.split() };
+        let (a_producer, mut a_consumer) = unsafe { (*addr_of_mut!(A_QUEUE)).split() };
+        let (g_producer, mut g_consumer) = unsafe { (*addr_of_mut!(G_QUEUE)).split() };
 
         let sender = std::thread::spawn(move || {
             serial.parse_data_until(a_producer, g_producer, &should_run_thread_ref);
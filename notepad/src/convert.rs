@@ -0,0 +1,568 @@
+//! Converts recordings to and from formats other tools read more easily.
+//!
+//! Both formats carry samples in engineering units (m/s²), timestamped in seconds since the
+//! recording started:
+//!
+//! - **CSV**: the recording's `# key: value` header, then a `time_s,stream,x,y,z` table. Load it
+//!   with `pandas.read_csv(path, comment="#")`.
+//! - **JSON lines**: a header object on the first line, then one object per sample with the same
+//!   fields as the CSV columns. Load the samples with
+//!   `pandas.read_json(path, lines=True).iloc[1:]`.
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    filters::SampleFilter,
+    recording::{Header, RecordingError, RecordingReader, RecordingWriter, Stream, TimedSample},
+    types::{self, imu},
+};
+use pensel_types::mint;
+
+/// Column names of the sample table in CSV files, matching [`Row`]'s fields
+const CSV_COLUMNS: [&str; 5] = ["time_s", "stream", "x", "y", "z"];
+
+/// The formats a recording can be stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The native format written by `scratchpad --record`, see [`crate::recording`]
+    Recording,
+    /// Comma separated values, in engineering units
+    Csv,
+    /// One JSON object per line, in engineering units
+    JsonLines,
+}
+
+impl Format {
+    /// Every format
+    pub const ALL: [Self; 3] = [Self::Recording, Self::Csv, Self::JsonLines];
+
+    /// The name used to pick this format on the command line
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Recording => "recording",
+            Self::Csv => "csv",
+            Self::JsonLines => "jsonl",
+        }
+    }
+
+    /// Guesses the format of `path` from its extension, defaulting to [`Format::Recording`]
+    #[must_use]
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => Self::Csv,
+            Some("jsonl" | "ndjson") => Self::JsonLines,
+            _ => Self::Recording,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| format!("unknown format '{}'", s))
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Something samples can be written out to, in any [`Format`]
+pub trait SampleWriter {
+    /// Writes out a single sample
+    ///
+    /// # Errors
+    /// If writing fails.
+    fn write_timed(&mut self, timed: &TimedSample) -> io::Result<()>;
+
+    /// Flushes everything written so far
+    ///
+    /// # Errors
+    /// If flushing fails.
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+impl<W: Write> SampleWriter for RecordingWriter<W> {
+    fn write_timed(&mut self, timed: &TimedSample) -> io::Result<()> {
+        Self::write_timed(self, timed)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Self::flush(self)
+    }
+}
+
+/// A sample in engineering units, as a CSV row or JSON lines object
+#[derive(Debug, Serialize, Deserialize)]
+struct Row {
+    /// seconds since the recording started
+    time_s: f64,
+    /// the [`Stream`] the sample belongs to
+    stream: String,
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+impl Row {
+    fn from_timed(timed: &TimedSample) -> Self {
+        let (stream, vector) = match &timed.sample {
+            types::Sample::Accel(a) => (Stream::Accel, a.m_s2()),
+            types::Sample::Grav(g) => (Stream::Gravity, g.m_s2()),
+        };
+        Self {
+            time_s: timed.timestamp.as_secs_f64(),
+            stream: stream.name().to_owned(),
+            x: vector.x,
+            y: vector.y,
+            z: vector.z,
+        }
+    }
+
+    /// Converts back to a sample, if everything is in range
+    fn to_timed(&self) -> Option<TimedSample> {
+        let vector = mint::Vector3 {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        };
+        let sample = match Stream::from_str(&self.stream).ok()? {
+            Stream::Accel => types::Sample::Accel(imu::AccelerationVector::from_m_s2(vector)?),
+            Stream::Gravity => types::Sample::Grav(imu::GravityVector::from_m_s2(vector)?),
+        };
+        Some(TimedSample {
+            timestamp: duration_from_secs(self.time_s)?,
+            sample,
+        })
+    }
+}
+
+/// Converts seconds to a [`Duration`], rounded to the microsecond recordings are written with
+fn duration_from_secs(seconds: f64) -> Option<Duration> {
+    (seconds.is_finite() && seconds >= 0.)
+        .then(|| Duration::from_micros((seconds * 1e6).round() as u64))
+}
+
+/// Writes samples out as CSV
+pub struct CsvWriter<W: Write> {
+    out: csv::Writer<W>,
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Starts a CSV file by writing `header` and the column names out to `out`
+    ///
+    /// # Errors
+    /// If writing fails.
+    pub fn new(mut out: W, header: &Header) -> io::Result<Self> {
+        header.write_to(&mut out)?;
+        let mut out = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(out);
+        out.write_record(CSV_COLUMNS)?;
+        Ok(Self { out })
+    }
+}
+
+impl<W: Write> SampleWriter for CsvWriter<W> {
+    fn write_timed(&mut self, timed: &TimedSample) -> io::Result<()> {
+        Ok(self.out.serialize(Row::from_timed(timed))?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// The first line of a JSON lines file
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonHeader {
+    /// format version, same as [`Header::version`]
+    pensel_recording: u32,
+    pen_id: String,
+    firmware: String,
+    /// empty in files from before pensel reported it
+    #[serde(default)]
+    axis_map: String,
+    /// seconds since the unix epoch
+    start: f64,
+    streams: Vec<String>,
+    calibration: Vec<u8>,
+}
+
+impl JsonHeader {
+    fn from_header(header: &Header) -> Self {
+        Self {
+            pensel_recording: crate::recording::FORMAT_VERSION,
+            pen_id: header.device.pen_id.clone(),
+            firmware: header.device.firmware.clone(),
+            axis_map: header.device.axis_map.clone(),
+            start: header
+                .start
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            streams: header
+                .streams
+                .iter()
+                .map(|stream| stream.name().to_owned())
+                .collect(),
+            calibration: header.device.calibration.clone(),
+        }
+    }
+
+    fn into_header(self) -> Result<Header, RecordingError> {
+        let bad_header = |reason: String| RecordingError::BadHeader {
+            line_number: 1,
+            reason,
+        };
+
+        if self.pensel_recording > crate::recording::FORMAT_VERSION {
+            return Err(RecordingError::UnsupportedVersion(self.pensel_recording));
+        }
+        Ok(Header {
+            version: self.pensel_recording,
+            device: types::DeviceInfo {
+                pen_id: self.pen_id,
+                firmware: self.firmware,
+                axis_map: self.axis_map,
+                calibration: self.calibration,
+            },
+            start: SystemTime::UNIX_EPOCH
+                + duration_from_secs(self.start)
+                    .ok_or_else(|| bad_header(format!("bad start time {}", self.start)))?,
+            streams: self
+                .streams
+                .iter()
+                .map(|stream| Stream::from_str(stream))
+                .collect::<Result<_, _>>()
+                .map_err(bad_header)?,
+        })
+    }
+}
+
+/// Writes samples out as JSON lines
+pub struct JsonLinesWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    /// Starts a JSON lines file by writing `header` out to `out`
+    ///
+    /// # Errors
+    /// If writing fails.
+    pub fn new(mut out: W, header: &Header) -> io::Result<Self> {
+        serde_json::to_writer(&mut out, &JsonHeader::from_header(header))?;
+        writeln!(out)?;
+        Ok(Self { out })
+    }
+}
+
+impl<W: Write> SampleWriter for JsonLinesWriter<W> {
+    fn write_timed(&mut self, timed: &TimedSample) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, &Row::from_timed(timed))?;
+        writeln!(self.out)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Starts writing a recording out to `out` in `format`
+///
+/// # Errors
+/// If writing the header fails.
+pub fn writer<'a, W: Write + 'a>(
+    out: W,
+    header: &Header,
+    format: Format,
+) -> io::Result<Box<dyn SampleWriter + 'a>> {
+    Ok(match format {
+        Format::Recording => Box::new(RecordingWriter::new(out, header)?),
+        Format::Csv => Box::new(CsvWriter::new(out, header)?),
+        Format::JsonLines => Box::new(JsonLinesWriter::new(out, header)?),
+    })
+}
+
+/// A recording being read in any [`Format`]: its header, and its samples by iterating
+pub struct Samples<'a> {
+    header: Header,
+    samples: Box<dyn Iterator<Item = Result<TimedSample, RecordingError>> + 'a>,
+}
+
+impl Samples<'_> {
+    /// The recording's header
+    #[must_use]
+    pub const fn header(&self) -> &Header {
+        &self.header
+    }
+}
+
+impl Iterator for Samples<'_> {
+    type Item = Result<TimedSample, RecordingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.samples.next()
+    }
+}
+
+/// Starts reading a recording in `format` from `input`
+///
+/// # Errors
+/// If reading fails or the header isn't valid.
+pub fn reader<'a, R: BufRead + 'a>(
+    mut input: R,
+    format: Format,
+) -> Result<Samples<'a>, RecordingError> {
+    match format {
+        Format::Recording => {
+            let reader = RecordingReader::new(input)?;
+            Ok(Samples {
+                header: reader.header().clone(),
+                samples: Box::new(reader),
+            })
+        }
+        Format::Csv => {
+            let (header, columns, header_lines) = Header::read_from(&mut input)?;
+            let columns_ok = columns
+                .as_deref()
+                .is_none_or(|columns| columns.split(',').map(str::trim).eq(CSV_COLUMNS));
+            if !columns_ok {
+                return Err(RecordingError::BadHeader {
+                    line_number: header_lines,
+                    reason: format!("expected columns '{}'", CSV_COLUMNS.join(",")),
+                });
+            }
+
+            let records = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(input)
+                .into_records();
+            let samples = records.map(move |record| {
+                let bad_sample = |line_number: u64, line: String| RecordingError::BadSample {
+                    line_number: header_lines + usize::try_from(line_number).unwrap_or_default(),
+                    line,
+                };
+                let record = record.map_err(|error| {
+                    let line_number = error.position().map_or(0, csv::Position::line);
+                    bad_sample(line_number, error.to_string())
+                })?;
+                let line_number = record.position().map_or(0, csv::Position::line);
+                record
+                    .deserialize::<Row>(None)
+                    .ok()
+                    .and_then(|row| row.to_timed())
+                    .ok_or_else(|| {
+                        bad_sample(line_number, record.iter().collect::<Vec<_>>().join(","))
+                    })
+            });
+            Ok(Samples {
+                header,
+                samples: Box::new(samples),
+            })
+        }
+        Format::JsonLines => {
+            let mut lines = input.lines().enumerate();
+            let header = match lines.next() {
+                Some((_, line)) => serde_json::from_str::<JsonHeader>(&line?)
+                    .map_err(|_| RecordingError::NotARecording)?
+                    .into_header()?,
+                None => return Err(RecordingError::NotARecording),
+            };
+
+            let samples = lines
+                .filter(|(_, line)| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+                .map(|(index, line)| {
+                    let line = line?;
+                    serde_json::from_str::<Row>(&line)
+                        .ok()
+                        .and_then(|row| row.to_timed())
+                        .ok_or(RecordingError::BadSample {
+                            line_number: index + 1,
+                            line,
+                        })
+                });
+            Ok(Samples {
+                header,
+                samples: Box::new(samples),
+            })
+        }
+    }
+}
+
+/// Converts a recording in `from` format read from `input` into `to` format written to `out`
+///
+/// # Returns
+/// How many samples were converted
+///
+/// # Errors
+/// If reading or writing fails, or the input isn't a valid recording.
+pub fn convert(
+    input: impl BufRead,
+    from: Format,
+    out: impl Write,
+    to: Format,
+) -> Result<usize, RecordingError> {
+    convert_filtered(input, from, out, to, &mut SampleFilter::default())
+}
+
+/// Like [`convert`], but runs every sample through `filter` on the way
+///
+/// # Returns
+/// How many samples were converted
+///
+/// # Errors
+/// If reading or writing fails, or the input isn't a valid recording.
+pub fn convert_filtered(
+    input: impl BufRead,
+    from: Format,
+    out: impl Write,
+    to: Format,
+    filter: &mut SampleFilter,
+) -> Result<usize, RecordingError> {
+    let samples = reader(input, from)?;
+    let mut writer = writer(out, samples.header(), to)?;
+
+    let mut count = 0;
+    for timed in samples {
+        writer.write_timed(&filter.apply(timed?))?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test_convert {
+    use super::*;
+
+    const RECORDING: &str = "# pensel-recording: 2\n\
+                             # pen id: 0123abcd\n\
+                             # firmware: 0.1.0\n\
+                             # axis map: -y,+x,+z\n\
+                             # start: 1697650000.123456\n\
+                             # streams: accel,gravity\n\
+                             # calibration: 2,0,252\n\
+                             0.000512 A:1,-2,3\n\
+                             0.004900 G:0,981,-25\n";
+
+    fn convert_str(input: &str, from: Format, to: Format) -> String {
+        let mut out = vec![];
+        convert(input.as_bytes(), from, &mut out, to).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(Format::from_path("session.csv"), Format::Csv);
+        assert_eq!(Format::from_path("dir/session.jsonl"), Format::JsonLines);
+        assert_eq!(Format::from_path("session.txt"), Format::Recording);
+        for format in Format::ALL {
+            assert_eq!(format.name().parse(), Ok(format));
+        }
+    }
+
+    #[test]
+    fn filters_while_converting() {
+        let input = "# pensel-recording: 2\n\
+                     0.000000 A:0,0,0\n\
+                     0.010000 A:0,0,0\n\
+                     0.020000 A:900,0,0\n\
+                     0.030000 G:0,0,981\n";
+        let mut filter = SampleFilter::new(&[crate::filters::FilterSpec::Median(3)], &[]);
+        let mut out = vec![];
+        let count = convert_filtered(
+            input.as_bytes(),
+            Format::Recording,
+            &mut out,
+            Format::Csv,
+            &mut filter,
+        )
+        .unwrap();
+        assert_eq!(count, 4);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("0.02,accel,0.0,0.0,0.0\n"), "{}", out);
+        assert!(out.contains("0.03,gravity,0.0,0.0,9.81\n"), "{}", out);
+    }
+
+    #[test]
+    fn to_csv() {
+        let csv = convert_str(RECORDING, Format::Recording, Format::Csv);
+        let mut lines = csv.lines().skip_while(|line| line.starts_with('#'));
+        assert_eq!(lines.next(), Some("time_s,stream,x,y,z"));
+        assert_eq!(lines.next(), Some("0.000512,accel,0.01,-0.02,0.03"));
+        assert_eq!(lines.next(), Some("0.0049,gravity,0.0,9.81,-0.25"));
+        assert!(csv.contains("# pen id: 0123abcd\n"));
+    }
+
+    #[test]
+    fn to_json_lines() {
+        let json = convert_str(RECORDING, Format::Recording, Format::JsonLines);
+        let mut lines = json.lines();
+        let header: serde_json::Value = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert_eq!(header["pen_id"], "0123abcd");
+        assert_eq!(header["streams"], serde_json::json!(["accel", "gravity"]));
+        assert_eq!(
+            lines.next(),
+            Some(r#"{"time_s":0.000512,"stream":"accel","x":0.01,"y":-0.02,"z":0.03}"#)
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        for format in [Format::Csv, Format::JsonLines] {
+            let converted = convert_str(RECORDING, Format::Recording, format);
+            assert_eq!(
+                convert_str(&converted, format, Format::Recording),
+                RECORDING,
+                "{}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn bad_input() {
+        let csv = "# pensel-recording: 2\ntime_s,stream,x,y,z\n0.1,accel,1,2,3\n0.2,wobble,1,2,3\n";
+        let mut samples = reader(csv.as_bytes(), Format::Csv).unwrap();
+        assert!(samples.next().unwrap().is_ok());
+        assert!(matches!(
+            samples.next(),
+            Some(Err(RecordingError::BadSample { line_number: 4, .. }))
+        ));
+
+        let csv = "# pensel-recording: 2\ntime,x\n";
+        assert!(matches!(
+            reader(csv.as_bytes(), Format::Csv),
+            Err(RecordingError::BadHeader { .. })
+        ));
+
+        // out of range for the wire format
+        let json = "{\"pensel_recording\":2,\"pen_id\":\"\",\"firmware\":\"\",\"start\":0,\
+                    \"streams\":[],\"calibration\":[]}\n\
+                    {\"time_s\":0,\"stream\":\"accel\",\"x\":1000,\"y\":0,\"z\":0}\n";
+        let mut samples = reader(json.as_bytes(), Format::JsonLines).unwrap();
+        assert!(matches!(
+            samples.next(),
+            Some(Err(RecordingError::BadSample { line_number: 2, .. }))
+        ));
+
+        assert!(matches!(
+            reader("A:1,2,3\n".as_bytes(), Format::JsonLines),
+            Err(RecordingError::NotARecording)
+        ));
+    }
+}
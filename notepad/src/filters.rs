@@ -0,0 +1,592 @@
+//! Filters for smoothing and cleaning up the streamed vectors.
+//!
+//! Each [`Filter`] works on one stream of 3D vectors, filtering every axis separately. They're
+//! described by a [`FilterSpec`], which is what the command line tools parse, and chained
+//! together with [`Chain`]. [`SampleFilter`] runs a chain over each of pensel's streams.
+use pensel_types::mint::Vector3;
+use std::{collections::VecDeque, f32::consts::PI, fmt, str::FromStr, time::Duration};
+
+use crate::{recording::TimedSample, types};
+
+/// Default cutoff of [`FilterSpec::GravityRemoval`], in Hz
+pub const DEFAULT_GRAVITY_CUTOFF: f32 = 0.5;
+
+/// Something that filters a stream of vectors
+pub trait Filter: Send {
+    /// Filters the next `input`, which came `dt` seconds after the previous one
+    fn apply(&mut self, input: Vector3<f32>, dt: f32) -> Vector3<f32>;
+
+    /// Forgets everything seen so far, as if the filter was just built
+    fn reset(&mut self);
+}
+
+/// How steep an IIR filter's rolloff is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// 6 dB per octave, no overshoot
+    First,
+    /// 12 dB per octave, Butterworth
+    Second,
+}
+
+/// One filter, as configured on the command line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterSpec {
+    /// Mean of the last `n` samples
+    MovingAverage(usize),
+    /// Median of the last `n` samples, which removes spikes without smearing them around
+    Median(usize),
+    /// IIR low-pass with the given cutoff in Hz
+    LowPass(Order, f32),
+    /// IIR high-pass with the given cutoff in Hz
+    HighPass(Order, f32),
+    /// Removes gravity (or any slowly changing offset) from raw acceleration, by subtracting
+    /// what a low-pass with the given cutoff in Hz lets through
+    GravityRemoval(f32),
+}
+
+impl FilterSpec {
+    const MOVING_AVERAGE: &'static str = "average";
+    const MEDIAN: &'static str = "median";
+    const LOW_PASS: &'static str = "lowpass";
+    const LOW_PASS_2: &'static str = "lowpass2";
+    const HIGH_PASS: &'static str = "highpass";
+    const HIGH_PASS_2: &'static str = "highpass2";
+    const GRAVITY_REMOVAL: &'static str = "gravity";
+
+    /// Builds the filter described
+    #[must_use]
+    pub fn build(&self) -> Box<dyn Filter> {
+        match *self {
+            Self::MovingAverage(n) => Box::new(MovingAverage::new(n)),
+            Self::Median(n) => Box::new(Median::new(n)),
+            Self::LowPass(order, cutoff) => Box::new(Iir::new(Pass::Low, order, cutoff)),
+            Self::HighPass(order, cutoff) => Box::new(Iir::new(Pass::High, order, cutoff)),
+            Self::GravityRemoval(cutoff) => Box::new(GravityRemoval::new(cutoff)),
+        }
+    }
+}
+
+impl FromStr for FilterSpec {
+    type Err = String;
+
+    /// Parses `<name>:<parameter>`: `average:N`, `median:N`, `lowpass:HZ`, `lowpass2:HZ`,
+    /// `highpass:HZ`, `highpass2:HZ` and `gravity[:HZ]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, parameter) = match s.trim().split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter.trim())),
+            None => (s.trim(), None),
+        };
+        let bad_parameter = |expected: &str| {
+            format!(
+                "filter '{}' needs {}, got '{}'",
+                name,
+                expected,
+                parameter.unwrap_or_default()
+            )
+        };
+        let window = || match parameter.map(usize::from_str) {
+            Some(Ok(n)) if n > 0 => Ok(n),
+            _ => Err(bad_parameter("a window length in samples")),
+        };
+        let cutoff = |default: Option<f32>| match (parameter.map(f32::from_str), default) {
+            (Some(Ok(hz)), _) if hz.is_finite() && hz > 0. => Ok(hz),
+            (None, Some(hz)) => Ok(hz),
+            _ => Err(bad_parameter("a positive cutoff in Hz")),
+        };
+
+        match name {
+            Self::MOVING_AVERAGE => Ok(Self::MovingAverage(window()?)),
+            Self::MEDIAN => Ok(Self::Median(window()?)),
+            Self::LOW_PASS => Ok(Self::LowPass(Order::First, cutoff(None)?)),
+            Self::LOW_PASS_2 => Ok(Self::LowPass(Order::Second, cutoff(None)?)),
+            Self::HIGH_PASS => Ok(Self::HighPass(Order::First, cutoff(None)?)),
+            Self::HIGH_PASS_2 => Ok(Self::HighPass(Order::Second, cutoff(None)?)),
+            Self::GRAVITY_REMOVAL => {
+                Ok(Self::GravityRemoval(cutoff(Some(DEFAULT_GRAVITY_CUTOFF))?))
+            }
+            _ => Err(format!(
+                "unknown filter '{}', expected one of {}",
+                name,
+                [
+                    Self::MOVING_AVERAGE,
+                    Self::MEDIAN,
+                    Self::LOW_PASS,
+                    Self::LOW_PASS_2,
+                    Self::HIGH_PASS,
+                    Self::HIGH_PASS_2,
+                    Self::GRAVITY_REMOVAL
+                ]
+                .join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FilterSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MovingAverage(n) => write!(f, "{}:{}", Self::MOVING_AVERAGE, n),
+            Self::Median(n) => write!(f, "{}:{}", Self::MEDIAN, n),
+            Self::LowPass(Order::First, hz) => write!(f, "{}:{}", Self::LOW_PASS, hz),
+            Self::LowPass(Order::Second, hz) => write!(f, "{}:{}", Self::LOW_PASS_2, hz),
+            Self::HighPass(Order::First, hz) => write!(f, "{}:{}", Self::HIGH_PASS, hz),
+            Self::HighPass(Order::Second, hz) => write!(f, "{}:{}", Self::HIGH_PASS_2, hz),
+            Self::GravityRemoval(hz) => write!(f, "{}:{}", Self::GRAVITY_REMOVAL, hz),
+        }
+    }
+}
+
+/// Filters applied one after the other
+#[derive(Default)]
+pub struct Chain {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl Chain {
+    /// Builds the filters in `specs`, to be applied in order
+    #[must_use]
+    pub fn new(specs: &[FilterSpec]) -> Self {
+        Self {
+            filters: specs.iter().map(FilterSpec::build).collect(),
+        }
+    }
+
+    /// Whether the chain leaves its input alone
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+}
+
+impl Filter for Chain {
+    fn apply(&mut self, input: Vector3<f32>, dt: f32) -> Vector3<f32> {
+        self.filters
+            .iter_mut()
+            .fold(input, |value, filter| filter.apply(value, dt))
+    }
+
+    fn reset(&mut self) {
+        self.filters.iter_mut().for_each(|filter| filter.reset());
+    }
+}
+
+/// Runs a [`Chain`] over each of pensel's streams, keeping track of the time between samples
+#[derive(Default)]
+pub struct SampleFilter {
+    accel: Chain,
+    gravity: Chain,
+    last_accel: Option<Duration>,
+    last_gravity: Option<Duration>,
+}
+
+impl SampleFilter {
+    /// Filters acceleration with `accel` and gravity with `gravity`
+    #[must_use]
+    pub fn new(accel: &[FilterSpec], gravity: &[FilterSpec]) -> Self {
+        Self {
+            accel: Chain::new(accel),
+            gravity: Chain::new(gravity),
+            last_accel: None,
+            last_gravity: None,
+        }
+    }
+
+    /// Filters `timed`. If the filtered value doesn't fit in a sample any more, the unfiltered one
+    /// is passed through.
+    #[must_use]
+    pub fn apply(&mut self, timed: TimedSample) -> TimedSample {
+        let sample = match timed.sample {
+            types::Sample::Accel(accel) if !self.accel.is_empty() => {
+                let dt = Self::dt(&mut self.last_accel, timed.timestamp);
+                types::imu::AccelerationVector::from_m_s2(self.accel.apply(accel.m_s2(), dt))
+                    .map_or(timed.sample, types::Sample::Accel)
+            }
+            types::Sample::Grav(gravity) if !self.gravity.is_empty() => {
+                let dt = Self::dt(&mut self.last_gravity, timed.timestamp);
+                types::imu::GravityVector::from_m_s2(self.gravity.apply(gravity.m_s2(), dt))
+                    .map_or(timed.sample, types::Sample::Grav)
+            }
+            sample => sample,
+        };
+        TimedSample { sample, ..timed }
+    }
+
+    /// Seconds since the `last` sample, updating it to `now`
+    fn dt(last: &mut Option<Duration>, now: Duration) -> f32 {
+        last.replace(now)
+            .map_or(0., |last| now.saturating_sub(last).as_secs_f32())
+    }
+}
+
+/// Applies `f` to each axis of `v`
+fn per_axis(v: Vector3<f32>, mut f: impl FnMut(usize, f32) -> f32) -> Vector3<f32> {
+    Vector3 {
+        x: f(0, v.x),
+        y: f(1, v.y),
+        z: f(2, v.z),
+    }
+}
+
+/// See [`FilterSpec::MovingAverage`]
+struct MovingAverage {
+    window: usize,
+    history: VecDeque<Vector3<f32>>,
+}
+
+impl MovingAverage {
+    fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            history: VecDeque::with_capacity(window),
+        }
+    }
+}
+
+impl Filter for MovingAverage {
+    #[allow(clippy::cast_precision_loss)]
+    fn apply(&mut self, input: Vector3<f32>, _dt: f32) -> Vector3<f32> {
+        if self.history.len() == self.window {
+            self.history.pop_front();
+        }
+        self.history.push_back(input);
+        let n = self.history.len() as f32;
+        per_axis(input, |axis, _| {
+            self.history
+                .iter()
+                .map(|v| <[f32; 3]>::from(*v)[axis])
+                .sum::<f32>()
+                / n
+        })
+    }
+
+    fn reset(&mut self) {
+        self.history.clear();
+    }
+}
+
+/// See [`FilterSpec::Median`]
+struct Median {
+    window: usize,
+    history: VecDeque<Vector3<f32>>,
+}
+
+impl Median {
+    fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            history: VecDeque::with_capacity(window),
+        }
+    }
+}
+
+impl Filter for Median {
+    fn apply(&mut self, input: Vector3<f32>, _dt: f32) -> Vector3<f32> {
+        if self.history.len() == self.window {
+            self.history.pop_front();
+        }
+        self.history.push_back(input);
+        per_axis(input, |axis, _| {
+            let mut values: Vec<f32> = self
+                .history
+                .iter()
+                .map(|v| <[f32; 3]>::from(*v)[axis])
+                .collect();
+            values.sort_by(f32::total_cmp);
+            let middle = values.len() / 2;
+            if values.len().is_multiple_of(2) {
+                (values[middle - 1] + values[middle]) / 2.
+            } else {
+                values[middle]
+            }
+        })
+    }
+
+    fn reset(&mut self) {
+        self.history.clear();
+    }
+}
+
+/// Which frequencies an [`Iir`] lets through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pass {
+    Low,
+    High,
+}
+
+/// Previous inputs and outputs of one axis, newest first
+#[derive(Debug, Clone, Copy, Default)]
+struct History {
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+/// A first or second order IIR filter. Its coefficients are worked out from each sample's `dt`,
+/// so it copes with pensel's sample rate wobbling.
+struct Iir {
+    pass: Pass,
+    order: Order,
+    cutoff: f32,
+    /// `None` until the first sample, which the filter starts settled on
+    history: Option<[History; 3]>,
+}
+
+impl Iir {
+    fn new(pass: Pass, order: Order, cutoff: f32) -> Self {
+        Self {
+            pass,
+            order,
+            cutoff,
+            history: None,
+        }
+    }
+
+    /// `(b, a)` coefficients of the difference equation for `dt`, normalized so `a[0]` is 1
+    fn coefficients(&self, dt: f32) -> ([f32; 3], [f32; 3]) {
+        match self.order {
+            Order::First => {
+                let rc = 1. / (2. * PI * self.cutoff);
+                match self.pass {
+                    Pass::Low => {
+                        let alpha = dt / (rc + dt);
+                        ([alpha, 0., 0.], [1., alpha - 1., 0.])
+                    }
+                    Pass::High => {
+                        let alpha = rc / (rc + dt);
+                        ([alpha, -alpha, 0.], [1., -alpha, 0.])
+                    }
+                }
+            }
+            Order::Second => {
+                // bilinear transform of a Butterworth biquad, keeping the cutoff below Nyquist
+                let w0 = (2. * PI * self.cutoff * dt).min(0.99 * PI);
+                let (sin, cos) = w0.sin_cos();
+                let alpha = sin / 2_f32.sqrt();
+                let a0 = 1. + alpha;
+                let b = match self.pass {
+                    Pass::Low => [(1. - cos) / 2., 1. - cos, (1. - cos) / 2.],
+                    Pass::High => [(1. + cos) / 2., -(1. + cos), (1. + cos) / 2.],
+                };
+                (b.map(|b| b / a0), [1., -2. * cos / a0, (1. - alpha) / a0])
+            }
+        }
+    }
+}
+
+impl Filter for Iir {
+    fn apply(&mut self, input: Vector3<f32>, dt: f32) -> Vector3<f32> {
+        let settled = |x: f32| {
+            let y = match self.pass {
+                Pass::Low => x,
+                Pass::High => 0.,
+            };
+            History {
+                x: [x; 2],
+                y: [y; 2],
+            }
+        };
+        let history = self
+            .history
+            .get_or_insert_with(|| [settled(input.x), settled(input.y), settled(input.z)]);
+        if dt <= 0. {
+            return per_axis(input, |axis, _| history[axis].y[0]);
+        }
+
+        let (b, a) = self.coefficients(dt);
+        let history = self.history.as_mut().expect("set above");
+        per_axis(input, |axis, x| {
+            let h = &mut history[axis];
+            let y = b[0] * x + b[1] * h.x[0] + b[2] * h.x[1] - a[1] * h.y[0] - a[2] * h.y[1];
+            *h = History {
+                x: [x, h.x[0]],
+                y: [y, h.y[0]],
+            };
+            y
+        })
+    }
+
+    fn reset(&mut self) {
+        self.history = None;
+    }
+}
+
+/// See [`FilterSpec::GravityRemoval`]
+struct GravityRemoval {
+    low_pass: Iir,
+}
+
+impl GravityRemoval {
+    fn new(cutoff: f32) -> Self {
+        Self {
+            low_pass: Iir::new(Pass::Low, Order::First, cutoff),
+        }
+    }
+}
+
+impl Filter for GravityRemoval {
+    fn apply(&mut self, input: Vector3<f32>, dt: f32) -> Vector3<f32> {
+        let gravity = self.low_pass.apply(input, dt);
+        Vector3 {
+            x: input.x - gravity.x,
+            y: input.y - gravity.y,
+            z: input.z - gravity.z,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.low_pass.reset();
+    }
+}
+
+#[cfg(test)]
+mod test_filters {
+    use super::*;
+    use crate::{
+        test_util::v,
+        types::{imu, SAMPLE_PERIOD},
+    };
+
+    const EPSILON: f32 = 1e-3;
+    const DT: f32 = SAMPLE_PERIOD.as_secs_f32();
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < EPSILON
+    }
+
+    /// Runs `filter` over `inputs` on the x axis, returning the outputs
+    fn run(filter: &mut dyn Filter, inputs: impl IntoIterator<Item = f32>) -> Vec<f32> {
+        inputs
+            .into_iter()
+            .map(|x| filter.apply(v(x, 0., 0.), DT).x)
+            .collect()
+    }
+
+    /// Peak output of `filter` fed a sine at `hz`, once it's settled in
+    #[allow(clippy::cast_precision_loss)]
+    fn gain_at(spec: FilterSpec, hz: f32) -> f32 {
+        let mut filter = spec.build();
+        let sine = (0..2000).map(|step| (2. * PI * hz * step as f32 * DT).sin());
+        run(filter.as_mut(), sine)[1000..]
+            .iter()
+            .fold(0_f32, |peak, y| peak.max(y.abs()))
+    }
+
+    #[test]
+    fn parse_spec() {
+        assert_eq!("average:5".parse(), Ok(FilterSpec::MovingAverage(5)));
+        assert_eq!("median:3".parse(), Ok(FilterSpec::Median(3)));
+        assert_eq!(
+            "lowpass2: 10".parse(),
+            Ok(FilterSpec::LowPass(Order::Second, 10.))
+        );
+        assert_eq!(
+            "highpass:0.5".parse(),
+            Ok(FilterSpec::HighPass(Order::First, 0.5))
+        );
+        assert_eq!(
+            "gravity".parse(),
+            Ok(FilterSpec::GravityRemoval(DEFAULT_GRAVITY_CUTOFF))
+        );
+        assert!("average:0".parse::<FilterSpec>().is_err());
+        assert!("lowpass".parse::<FilterSpec>().is_err());
+        assert!("lowpass:-1".parse::<FilterSpec>().is_err());
+        assert!("bandpass:1".parse::<FilterSpec>().is_err());
+
+        let spec = FilterSpec::HighPass(Order::Second, 2.5);
+        assert_eq!(spec.to_string().parse(), Ok(spec));
+    }
+
+    #[test]
+    fn moving_average() {
+        let mut filter = MovingAverage::new(3);
+        let out = run(&mut filter, [3., 6., 9., 12.]);
+        assert_eq!(out, [3., 4.5, 6., 9.]);
+        filter.reset();
+        assert_eq!(run(&mut filter, [1.]), [1.]);
+    }
+
+    #[test]
+    fn median_removes_spikes() {
+        let mut filter = Median::new(3);
+        let out = run(&mut filter, [1., 1., 100., 1., 1.]);
+        assert_eq!(out, [1., 1., 1., 1., 1.]);
+    }
+
+    #[test]
+    fn low_pass() {
+        for order in [Order::First, Order::Second] {
+            let spec = FilterSpec::LowPass(order, 2.);
+            // starts settled on the first sample
+            assert!(run(spec.build().as_mut(), [5.; 10])
+                .iter()
+                .all(|y| close(*y, 5.)));
+            assert!(
+                gain_at(spec, 0.2) > 0.95,
+                "{:?} blocks low frequencies",
+                order
+            );
+            assert!(
+                gain_at(spec, 20.) < 0.15,
+                "{:?} lets high frequencies through",
+                order
+            );
+        }
+        // second order rolls off faster
+        assert!(
+            gain_at(FilterSpec::LowPass(Order::Second, 2.), 20.)
+                < gain_at(FilterSpec::LowPass(Order::First, 2.), 20.)
+        );
+    }
+
+    #[test]
+    fn high_pass() {
+        for order in [Order::First, Order::Second] {
+            let spec = FilterSpec::HighPass(order, 2.);
+            let out = run(spec.build().as_mut(), [5.; 500]);
+            assert!(
+                close(*out.last().unwrap(), 0.),
+                "{:?} lets DC through",
+                order
+            );
+            assert!(
+                gain_at(spec, 20.) > 0.9,
+                "{:?} blocks high frequencies",
+                order
+            );
+            assert!(
+                gain_at(spec, 0.1) < 0.15,
+                "{:?} lets low frequencies through",
+                order
+            );
+        }
+    }
+
+    #[test]
+    fn removes_gravity() {
+        let mut filter = GravityRemoval::new(DEFAULT_GRAVITY_CUTOFF);
+        for _ in 0..1000 {
+            filter.apply(v(0., 0., 9.81), DT);
+        }
+        let out = filter.apply(v(1., 0., 9.81), DT);
+        assert!(close(out.z, 0.));
+        assert!(out.x > 0.95);
+    }
+
+    #[test]
+    fn filters_samples() {
+        let mut filter = SampleFilter::new(&[FilterSpec::Median(3)], &[]);
+        let accel = |millis, x| TimedSample {
+            timestamp: Duration::from_millis(millis),
+            sample: types::Sample::Accel(imu::AccelerationVector::new(x, 0, 0)),
+        };
+        let gravity = TimedSample {
+            timestamp: Duration::from_millis(5),
+            sample: types::Sample::Grav(imu::GravityVector::new(0, 0, 981)),
+        };
+
+        assert_eq!(filter.apply(accel(0, 100)), accel(0, 100));
+        assert_eq!(filter.apply(gravity), gravity);
+        assert_eq!(filter.apply(accel(10, 100)), accel(10, 100));
+        assert_eq!(filter.apply(accel(20, 5000)), accel(20, 100));
+    }
+}
@@ -0,0 +1,468 @@
+//! Recognizes pen gestures, so the pen can be used to control things without reaching for a
+//! keyboard.
+//!
+//! The built in gestures are made of *pulses*: bursts of linear acceleration above
+//! [`Config::pulse_threshold`]. Pulses close together are collected until there's a pause of
+//! [`Config::settle_time`], then classified all at once: two short pulses are a double tap, a
+//! single longer one a flick, and many of them a shake. Holding the pen still is recognized on
+//! its own, and custom gestures can be recognized by comparing against recorded [`Template`]s.
+use pensel_types::mint::Vector3;
+use std::{collections::VecDeque, fmt, time::Duration};
+
+use crate::{
+    recording::TimedSample,
+    types,
+    vector::{norm, sub},
+};
+
+/// Default [`Config::pulse_threshold`], in m/s²
+pub const DEFAULT_PULSE_THRESHOLD: f32 = 6.0;
+/// Default [`Config::tap_max`]
+pub const DEFAULT_TAP_MAX: Duration = Duration::from_millis(60);
+/// Default [`Config::flick_max`]
+pub const DEFAULT_FLICK_MAX: Duration = Duration::from_millis(300);
+/// Default [`Config::settle_time`]
+pub const DEFAULT_SETTLE_TIME: Duration = Duration::from_millis(400);
+/// Default [`Config::shake_pulses`]
+pub const DEFAULT_SHAKE_PULSES: usize = 4;
+/// Default [`Config::still_threshold`], in m/s²
+pub const DEFAULT_STILL_THRESHOLD: f32 = 0.3;
+/// Default [`Config::hold_time`]
+pub const DEFAULT_HOLD_TIME: Duration = Duration::from_secs(2);
+/// Default [`Template::threshold`]
+pub const DEFAULT_TEMPLATE_THRESHOLD: f32 = 0.3;
+
+/// A recognized gesture
+#[derive(Debug, Clone, PartialEq)]
+pub enum Gesture {
+    /// Two quick taps on the pen
+    DoubleTap,
+    /// Shaking the pen back and forth
+    Shake,
+    /// One sharp movement, in the given direction in the pen's frame
+    Flick(Vector3<f32>),
+    /// Holding the pen still for [`Config::hold_time`]
+    HoldStill,
+    /// Something matching the [`Template`] with this name
+    Custom(String),
+}
+
+impl fmt::Display for Gesture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DoubleTap => write!(f, "double tap"),
+            Self::Shake => write!(f, "shake"),
+            Self::Flick(direction) => write!(
+                f,
+                "flick ({:.2}, {:.2}, {:.2})",
+                direction.x, direction.y, direction.z
+            ),
+            Self::HoldStill => write!(f, "hold still"),
+            Self::Custom(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A gesture, and when it was recognized
+#[derive(Debug, Clone, PartialEq)]
+pub struct GestureEvent {
+    /// time since the stream started
+    pub timestamp: Duration,
+    /// what was recognized
+    pub gesture: Gesture,
+}
+
+/// Thresholds for recognizing the built in gestures
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    /// linear acceleration a pulse has to reach, in m/s²
+    pub pulse_threshold: f32,
+    /// longest pulse that counts as a tap
+    pub tap_max: Duration,
+    /// longest pulse that counts as a flick. Anything longer is just moving the pen around.
+    pub flick_max: Duration,
+    /// pause after the last pulse before pulses are classified. Also the longest gap between
+    /// the taps of a double tap.
+    pub settle_time: Duration,
+    /// how many pulses make a shake
+    pub shake_pulses: usize,
+    /// linear acceleration below which the pen is still, in m/s²
+    pub still_threshold: f32,
+    /// how long the pen has to be still for [`Gesture::HoldStill`]
+    pub hold_time: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            pulse_threshold: DEFAULT_PULSE_THRESHOLD,
+            tap_max: DEFAULT_TAP_MAX,
+            flick_max: DEFAULT_FLICK_MAX,
+            settle_time: DEFAULT_SETTLE_TIME,
+            shake_pulses: DEFAULT_SHAKE_PULSES,
+            still_threshold: DEFAULT_STILL_THRESHOLD,
+            hold_time: DEFAULT_HOLD_TIME,
+        }
+    }
+}
+
+/// A custom gesture: the linear acceleration while performing it, to compare against
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    /// what to call the gesture
+    pub name: String,
+    /// acceleration samples in m/s², in the pen's frame
+    pub samples: Vec<Vector3<f32>>,
+    /// how close the stream has to get to `samples` to match, see [`Template::distance`]
+    pub threshold: f32,
+}
+
+impl Template {
+    /// Makes a template from the acceleration in `samples`, e.g. from a recording of performing
+    /// the gesture once. Stillness before and after the gesture is trimmed off.
+    #[must_use]
+    pub fn from_samples<'a>(
+        name: &str,
+        samples: impl IntoIterator<Item = &'a TimedSample>,
+        still_threshold: f32,
+    ) -> Self {
+        let mut accel: Vec<_> = samples
+            .into_iter()
+            .filter_map(|timed| match timed.sample {
+                types::Sample::Accel(accel) => Some(accel.m_s2()),
+                types::Sample::Grav(_) => None,
+            })
+            .collect();
+        let moving = |v: &Vector3<f32>| norm(*v) >= still_threshold;
+        let end = accel.iter().rposition(moving).map_or(0, |last| last + 1);
+        accel.truncate(end);
+        let start = accel.iter().position(moving).unwrap_or(end);
+        accel.drain(..start);
+
+        Self {
+            name: name.to_owned(),
+            samples: accel,
+            threshold: DEFAULT_TEMPLATE_THRESHOLD,
+        }
+    }
+
+    /// How far `window` is from the template: the mean distance between their samples, relative
+    /// to the template's mean acceleration. Samples are matched up by dynamic time warping, which
+    /// lets the gesture be performed a bit faster or slower than it was recorded.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn distance(&self, window: &[Vector3<f32>]) -> f32 {
+        if self.samples.is_empty() || window.is_empty() {
+            return f32::INFINITY;
+        }
+        let mut previous = vec![f32::INFINITY; window.len() + 1];
+        let mut current = vec![f32::INFINITY; window.len() + 1];
+        previous[0] = 0.;
+        for expected in &self.samples {
+            current[0] = f32::INFINITY;
+            for (index, actual) in window.iter().enumerate() {
+                let cost = norm(sub(*expected, *actual));
+                current[index + 1] =
+                    cost + previous[index].min(previous[index + 1]).min(current[index]);
+            }
+            std::mem::swap(&mut previous, &mut current);
+        }
+        let mean_distance = previous[window.len()] / (self.samples.len() + window.len()) as f32;
+        let mean_accel =
+            self.samples.iter().map(|v| norm(*v)).sum::<f32>() / self.samples.len() as f32;
+        mean_distance / mean_accel
+    }
+}
+
+/// A burst of acceleration
+#[derive(Debug, Clone, Copy)]
+struct Pulse {
+    start: Duration,
+    end: Duration,
+    /// the largest acceleration during the pulse
+    peak: Vector3<f32>,
+}
+
+impl Pulse {
+    fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// Turns linear acceleration into gestures: taps, flicks and shakes from bursts of it, holds from
+/// its absence, and custom gestures by matching the latest motion against recorded templates
+#[derive(Debug, Clone)]
+pub struct GestureDetector {
+    config: Config,
+    templates: Vec<Template>,
+    /// the pulse in progress
+    pulse: Option<Pulse>,
+    /// finished pulses waiting to be classified
+    pulses: Vec<Pulse>,
+    /// when the pen went still, if it still is
+    still_since: Option<Duration>,
+    /// whether [`Gesture::HoldStill`] was already sent for this stretch of stillness
+    held: bool,
+    /// the most recent acceleration, as long as the longest template
+    recent: VecDeque<Vector3<f32>>,
+}
+
+impl GestureDetector {
+    /// Recognizes the built in gestures according to `config`
+    #[must_use]
+    pub const fn new(config: Config) -> Self {
+        Self {
+            config,
+            templates: Vec::new(),
+            pulse: None,
+            pulses: Vec::new(),
+            still_since: None,
+            held: false,
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Also recognizes `template`
+    pub fn add_template(&mut self, template: Template) {
+        self.templates.push(template);
+    }
+
+    /// Feeds in `timed`
+    ///
+    /// # Returns
+    /// The gestures recognized, usually none
+    pub fn feed(&mut self, timed: &TimedSample) -> Vec<GestureEvent> {
+        let types::Sample::Accel(accel) = timed.sample else {
+            return Vec::new();
+        };
+        let now = timed.timestamp;
+        let accel = accel.m_s2();
+        let magnitude = norm(accel);
+
+        let mut gestures = Vec::new();
+        self.track_pulses(now, accel, magnitude, &mut gestures);
+        self.track_stillness(now, magnitude, &mut gestures);
+        self.match_templates(accel, &mut gestures);
+
+        gestures
+            .into_iter()
+            .map(|gesture| GestureEvent {
+                timestamp: now,
+                gesture,
+            })
+            .collect()
+    }
+
+    fn track_pulses(
+        &mut self,
+        now: Duration,
+        accel: Vector3<f32>,
+        magnitude: f32,
+        gestures: &mut Vec<Gesture>,
+    ) {
+        match self.pulse.as_mut() {
+            Some(pulse) => {
+                pulse.end = now;
+                if magnitude > norm(pulse.peak) {
+                    pulse.peak = accel;
+                }
+                // hysteresis, so a wobbly peak is still one pulse
+                if magnitude < self.config.pulse_threshold / 2. {
+                    self.pulses.extend(self.pulse.take());
+                }
+            }
+            None if magnitude >= self.config.pulse_threshold => {
+                self.pulse = Some(Pulse {
+                    start: now,
+                    end: now,
+                    peak: accel,
+                });
+            }
+            None => {
+                let settled = self
+                    .pulses
+                    .last()
+                    .is_some_and(|last| now.saturating_sub(last.end) >= self.config.settle_time);
+                if settled {
+                    gestures.extend(self.classify());
+                    self.pulses.clear();
+                }
+            }
+        }
+    }
+
+    /// What the pulses collected so far add up to, if anything
+    fn classify(&self) -> Option<Gesture> {
+        let is_tap = |pulse: &Pulse| pulse.duration() <= self.config.tap_max;
+        match self.pulses[..] {
+            _ if self.pulses.len() >= self.config.shake_pulses => Some(Gesture::Shake),
+            [first, second] if is_tap(&first) && is_tap(&second) => Some(Gesture::DoubleTap),
+            [pulse] if !is_tap(&pulse) && pulse.duration() <= self.config.flick_max => {
+                let peak = norm(pulse.peak);
+                Some(Gesture::Flick(Vector3 {
+                    x: pulse.peak.x / peak,
+                    y: pulse.peak.y / peak,
+                    z: pulse.peak.z / peak,
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    fn track_stillness(&mut self, now: Duration, magnitude: f32, gestures: &mut Vec<Gesture>) {
+        if magnitude >= self.config.still_threshold {
+            self.still_since = None;
+            self.held = false;
+            return;
+        }
+        let since = *self.still_since.get_or_insert(now);
+        if !self.held && now.saturating_sub(since) >= self.config.hold_time {
+            self.held = true;
+            gestures.push(Gesture::HoldStill);
+        }
+    }
+
+    fn match_templates(&mut self, accel: Vector3<f32>, gestures: &mut Vec<Gesture>) {
+        let Some(longest) = self.templates.iter().map(|t| t.samples.len()).max() else {
+            return;
+        };
+        if self.recent.len() >= longest {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(accel);
+
+        let recent = self.recent.make_contiguous();
+        let matched = self.templates.iter().find(|template| {
+            let len = template.samples.len();
+            len > 0
+                && recent.len() >= len
+                && template.distance(&recent[recent.len() - len..]) <= template.threshold
+        });
+        if let Some(template) = matched {
+            gestures.push(Gesture::Custom(template.name.clone()));
+            // don't match the same motion again as it slides through
+            self.recent.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_gestures {
+    use super::*;
+    use crate::test_util::samples;
+
+    /// `count` samples of `value`
+    fn run(value: i16, count: usize) -> impl Iterator<Item = i16> {
+        std::iter::repeat_n(value, count)
+    }
+
+    /// Mostly quiet, but not quite still enough to hold still
+    fn quiet(count: usize) -> impl Iterator<Item = i16> {
+        run(50, count)
+    }
+
+    fn detect(detector: &mut GestureDetector, samples: &[TimedSample]) -> Vec<Gesture> {
+        samples
+            .iter()
+            .flat_map(|timed| detector.feed(timed))
+            .map(|event| event.gesture)
+            .collect()
+    }
+
+    fn gestures(accel: impl IntoIterator<Item = i16>) -> Vec<Gesture> {
+        detect(
+            &mut GestureDetector::new(Config::default()),
+            &samples(accel, None),
+        )
+    }
+
+    #[test]
+    fn double_tap() {
+        let accel = quiet(10)
+            .chain(run(1000, 2))
+            .chain(quiet(20))
+            .chain(run(-1000, 2))
+            .chain(quiet(50));
+        assert_eq!(gestures(accel), [Gesture::DoubleTap]);
+    }
+
+    #[test]
+    fn single_tap_is_nothing() {
+        let accel = quiet(10).chain(run(1000, 2)).chain(quiet(50));
+        assert_eq!(gestures(accel), []);
+    }
+
+    #[test]
+    fn shake() {
+        let mut accel: Vec<i16> = quiet(10).collect();
+        for _ in 0..3 {
+            accel.extend(
+                run(800, 8)
+                    .chain(quiet(5))
+                    .chain(run(-800, 8))
+                    .chain(quiet(5)),
+            );
+        }
+        accel.extend(quiet(50));
+        assert_eq!(gestures(accel), [Gesture::Shake]);
+    }
+
+    #[test]
+    fn flick() {
+        let accel = quiet(10).chain(run(-1200, 15)).chain(quiet(50));
+        assert_eq!(
+            gestures(accel),
+            [Gesture::Flick(Vector3 {
+                x: -1.,
+                y: 0.,
+                z: 0.
+            })]
+        );
+
+        // moving for longer is just moving
+        let accel = quiet(10).chain(run(-1200, 50)).chain(quiet(50));
+        assert_eq!(gestures(accel), []);
+    }
+
+    #[test]
+    fn hold_still() {
+        let accel = quiet(10)
+            .chain(run(0, 250))
+            .chain(quiet(10))
+            .chain(run(0, 100));
+        assert_eq!(gestures(accel), [Gesture::HoldStill]);
+    }
+
+    #[test]
+    fn templates() {
+        // a wiggle that's too slow and gentle for any built in gesture
+        let wiggle: Vec<i16> = run(200, 20)
+            .chain(run(-200, 20))
+            .chain(run(200, 20))
+            .collect();
+        let recorded = samples(
+            run(0, 10).chain(wiggle.iter().copied()).chain(run(0, 10)),
+            None,
+        );
+        let template = Template::from_samples("wiggle", &recorded, DEFAULT_STILL_THRESHOLD);
+        assert_eq!(template.samples.len(), wiggle.len());
+
+        let mut detector = GestureDetector::new(Config::default());
+        detector.add_template(template);
+
+        // performed a bit faster
+        let faster = quiet(30)
+            .chain(run(200, 16))
+            .chain(run(-200, 16))
+            .chain(run(200, 16))
+            .chain(quiet(30));
+        assert_eq!(
+            detect(&mut detector, &samples(faster, None)),
+            [Gesture::Custom("wiggle".to_owned())]
+        );
+
+        // something else entirely
+        let other = quiet(30).chain(run(-200, 60)).chain(quiet(30));
+        assert_eq!(detect(&mut detector, &samples(other, None)), []);
+    }
+}
@@ -1,4 +1,24 @@
+pub mod angles;
+pub mod bridge;
 pub mod comms;
+pub mod convert;
+pub mod filters;
+pub mod gestures;
 #[cfg(test)]
 pub(crate) mod mock_serial;
+pub mod orientation;
+pub mod pose;
+pub mod recording;
+pub mod registration;
+pub mod replay;
+pub mod report;
+pub mod shell;
+pub mod spectrum;
+pub mod strokes;
+#[cfg(test)]
+pub(crate) mod test_util;
+pub mod trajectory;
 pub mod types;
+mod vector;
+pub mod waypoints;
+pub mod wireframe;
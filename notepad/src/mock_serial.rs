@@ -1,20 +1,14 @@
 //! Mock serial port for unit testing
 use std::io::Write;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct MockSerial {
     buffer: Vec<u8>,
 }
 
-impl std::default::Default for MockSerial {
-    fn default() -> Self {
-        MockSerial { buffer: vec![] }
-    }
-}
-
 impl std::io::Write for MockSerial {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        Ok(self.buffer.write(buf)?)
+        self.buffer.write(buf)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -163,7 +157,7 @@ mod test_mock_serial {
 
         let mut ms = MockSerial::default();
         let mut buf: [u8; 8] = [0; 8];
-        ms.read(&mut buf).unwrap();
+        assert_eq!(ms.read(&mut buf).unwrap(), 0);
     }
 
     #[test]
@@ -176,9 +170,9 @@ mod test_mock_serial {
         write!(ms, "test").unwrap();
         write!(ms, "t3st").unwrap();
 
-        ms.read(&mut buf).unwrap();
+        assert_eq!(ms.read(&mut buf).unwrap(), 4);
         assert_eq!(buf, "test".as_bytes());
-        ms.read(&mut buf).unwrap();
+        assert_eq!(ms.read(&mut buf).unwrap(), 4);
         assert_eq!(buf, "t3st".as_bytes());
     }
 }
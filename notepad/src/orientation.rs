@@ -0,0 +1,693 @@
+//! Host side sensor fusion: estimates the pen's orientation from gyro, accelerometer and
+//! magnetometer readings, or just from the streamed gravity vector.
+//!
+//! Orientations are unit quaternions rotating vectors from the pen's frame into the world frame,
+//! whose z axis points up (away from gravity). Without a magnetometer the heading around world z
+//! isn't observable, so it only changes as the gyro says it does.
+use pensel_types::mint::{Quaternion, Vector3};
+use std::time::Duration;
+
+use crate::{
+    recording::TimedSample,
+    types,
+    vector::{cross, dot, normalized},
+};
+
+/// Default [`Madgwick`] gain
+pub const DEFAULT_BETA: f32 = 0.1;
+/// Default [`Mahony`] proportional gain
+pub const DEFAULT_KP: f32 = 1.0;
+/// Default [`Mahony`] integral gain
+pub const DEFAULT_KI: f32 = 0.0;
+
+/// One set of sensor readings to fuse
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    /// angular rate in rad/s, if we have it
+    pub gyro: Option<Vector3<f32>>,
+    /// what the accelerometer measures at rest, pointing up: raw acceleration or the gravity
+    /// vector, in any unit
+    pub accel: Vector3<f32>,
+    /// magnetic field, in any unit, if we have it
+    pub mag: Option<Vector3<f32>>,
+}
+
+impl Reading {
+    /// A reading of just the gravity vector, like pensel streams
+    #[must_use]
+    pub const fn gravity(gravity: Vector3<f32>) -> Self {
+        Self {
+            gyro: None,
+            accel: gravity,
+            mag: None,
+        }
+    }
+}
+
+/// A sensor fusion algorithm estimating orientation
+pub trait OrientationFilter {
+    /// Fuses in `reading`, taken `dt` seconds after the previous one
+    fn update(&mut self, reading: &Reading, dt: f32);
+
+    /// The current orientation estimate
+    fn orientation(&self) -> Quaternion<f32>;
+
+    /// Overrides the current orientation estimate, e.g. to skip converging from scratch
+    fn set_orientation(&mut self, orientation: Quaternion<f32>);
+}
+
+/// A quaternion we can do math on. `mint`'s is just storage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Quat {
+    w: f32,
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+impl Quat {
+    const IDENTITY: Self = Self::new(1., 0., 0., 0.);
+
+    const fn new(w: f32, x: f32, y: f32, z: f32) -> Self {
+        Self { w, x, y, z }
+    }
+
+    /// A pure quaternion holding `v`
+    const fn pure(v: Vector3<f32>) -> Self {
+        Self::new(0., v.x, v.y, v.z)
+    }
+
+    const fn vector(self) -> Vector3<f32> {
+        Vector3 {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        }
+    }
+
+    fn mul(self, o: Self) -> Self {
+        Self::new(
+            self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        )
+    }
+
+    fn add(self, o: Self) -> Self {
+        Self::new(self.w + o.w, self.x + o.x, self.y + o.y, self.z + o.z)
+    }
+
+    fn scale(self, s: f32) -> Self {
+        Self::new(self.w * s, self.x * s, self.y * s, self.z * s)
+    }
+
+    const fn conjugate(self) -> Self {
+        Self::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    fn norm(self) -> f32 {
+        (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+
+    /// Scales to unit length. Leaves zero as is.
+    fn normalized(self) -> Self {
+        let norm = self.norm();
+        if norm > 0. {
+            self.scale(1. / norm)
+        } else {
+            self
+        }
+    }
+
+    /// Rotates `v` by this (unit) quaternion
+    fn rotate(self, v: Vector3<f32>) -> Vector3<f32> {
+        self.mul(Self::pure(v)).mul(self.conjugate()).vector()
+    }
+}
+
+impl From<Quat> for Quaternion<f32> {
+    fn from(q: Quat) -> Self {
+        Self {
+            v: q.vector(),
+            s: q.w,
+        }
+    }
+}
+
+impl From<Quaternion<f32>> for Quat {
+    fn from(q: Quaternion<f32>) -> Self {
+        Self::new(q.s, q.v.x, q.v.y, q.v.z)
+    }
+}
+
+/// Rotates `v` from the pen's frame into the world frame, by `orientation`
+#[must_use]
+pub fn rotate(orientation: Quaternion<f32>, v: Vector3<f32>) -> Vector3<f32> {
+    Quat::from(orientation).rotate(v)
+}
+
+/// The orientation that tilts the pen such that `up` (e.g. the gravity vector) points along
+/// world z, with no rotation around world z. Identity if `up` is zero.
+#[must_use]
+pub fn from_up(up: Vector3<f32>) -> Quaternion<f32> {
+    let Some(up) = normalized(up) else {
+        return Quat::IDENTITY.into();
+    };
+    let z = Vector3 {
+        x: 0.,
+        y: 0.,
+        z: 1.,
+    };
+
+    // shortest arc from `up` to z. Upside down there isn't one, so flip around x.
+    let w = 1. + dot(up, z);
+    if w < 1e-6 {
+        return Quat::new(0., 1., 0., 0.).into();
+    }
+    let axis = cross(up, z);
+    Quat::new(w, axis.x, axis.y, axis.z).normalized().into()
+}
+
+/// The rotation applying `second` after `first`
+#[must_use]
+pub fn compose(second: Quaternion<f32>, first: Quaternion<f32>) -> Quaternion<f32> {
+    Quat::from(second)
+        .mul(Quat::from(first))
+        .normalized()
+        .into()
+}
+
+/// The rotation undoing `rotation`
+#[must_use]
+pub fn inverse(rotation: Quaternion<f32>) -> Quaternion<f32> {
+    Quat::from(rotation).conjugate().into()
+}
+
+/// Scales `rotation` to unit length. Leaves zero as is.
+#[must_use]
+pub fn normalize(rotation: Quaternion<f32>) -> Quaternion<f32> {
+    Quat::from(rotation).normalized().into()
+}
+
+/// `rotation` or its negation, whichever has a non-negative scalar part. Both are the same
+/// rotation.
+#[must_use]
+pub fn positive(rotation: Quaternion<f32>) -> Quaternion<f32> {
+    let q = Quat::from(rotation);
+    if q.w < 0. {
+        q.scale(-1.).into()
+    } else {
+        rotation
+    }
+}
+
+/// The rotation turning by `roll` around x, then `pitch` around y, then `yaw` around z, all in
+/// radians around the fixed axes
+#[must_use]
+pub fn from_euler(roll: f32, pitch: f32, yaw: f32) -> Quaternion<f32> {
+    let (sr, cr) = (roll / 2.).sin_cos();
+    let (sp, cp) = (pitch / 2.).sin_cos();
+    let (sy, cy) = (yaw / 2.).sin_cos();
+    Quat::new(
+        cr * cp * cy + sr * sp * sy,
+        sr * cp * cy - cr * sp * sy,
+        cr * sp * cy + sr * cp * sy,
+        cr * cp * sy - sr * sp * cy,
+    )
+    .into()
+}
+
+/// The rotation taking x, y and z onto `x`, `y` and `z`, which have to be orthonormal and
+/// right handed
+#[must_use]
+pub fn from_axes(x: Vector3<f32>, y: Vector3<f32>, z: Vector3<f32>) -> Quaternion<f32> {
+    // Shepperd's method, picking whichever component is largest to divide by
+    let trace = x.x + y.y + z.z;
+    let q = if trace > 0. {
+        let s = (trace + 1.).sqrt() * 2.;
+        Quat::new(s / 4., (y.z - z.y) / s, (z.x - x.z) / s, (x.y - y.x) / s)
+    } else if x.x > y.y && x.x > z.z {
+        let s = (1. + x.x - y.y - z.z).sqrt() * 2.;
+        Quat::new((y.z - z.y) / s, s / 4., (y.x + x.y) / s, (z.x + x.z) / s)
+    } else if y.y > z.z {
+        let s = (1. + y.y - x.x - z.z).sqrt() * 2.;
+        Quat::new((z.x - x.z) / s, (y.x + x.y) / s, s / 4., (z.y + y.z) / s)
+    } else {
+        let s = (1. + z.z - x.x - y.y).sqrt() * 2.;
+        Quat::new((x.y - y.x) / s, (z.x + x.z) / s, (z.y + y.z) / s, s / 4.)
+    };
+    q.normalized().into()
+}
+
+/// Rate of change of `q` while turning at `gyro` rad/s, measured in the pen's frame
+fn gyro_rate(q: Quat, gyro: Option<Vector3<f32>>) -> Quat {
+    gyro.map_or(Quat::new(0., 0., 0., 0.), |gyro| {
+        q.mul(Quat::pure(gyro)).scale(0.5)
+    })
+}
+
+/// The direction `mag` points in the world, flattened so it only has a horizontal (x) and
+/// vertical (z) component. That's the reference both filters steer the measured field towards,
+/// which keeps the magnetometer from affecting tilt.
+fn earth_field(q: Quat, mag: Vector3<f32>) -> (f32, f32) {
+    let h = q.rotate(mag);
+    ((h.x * h.x + h.y * h.y).sqrt(), h.z)
+}
+
+/// Madgwick's gradient descent filter: integrates the gyro, and steps towards the orientation
+/// best explaining the accelerometer (and magnetometer) by a rate of `beta` rad/s.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Madgwick {
+    q: Quat,
+    /// how strongly we correct towards the accelerometer and magnetometer
+    pub beta: f32,
+}
+
+impl Madgwick {
+    /// Creates a filter with gain `beta`, starting at identity
+    #[must_use]
+    pub const fn new(beta: f32) -> Self {
+        Self {
+            q: Quat::IDENTITY,
+            beta,
+        }
+    }
+
+    /// Gradient of Madgwick's objective function at `q`
+    fn gradient(q: Quat, accel: Vector3<f32>, mag: Option<Vector3<f32>>) -> Quat {
+        let Quat {
+            w: q0,
+            x: q1,
+            y: q2,
+            z: q3,
+        } = q;
+
+        // where we think up is in the pen's frame, minus where the accelerometer says it is
+        let f = [
+            2. * (q1 * q3 - q0 * q2) - accel.x,
+            2. * (q0 * q1 + q2 * q3) - accel.y,
+            2. * (0.5 - q1 * q1 - q2 * q2) - accel.z,
+        ];
+        let j = [
+            [-2. * q2, 2. * q3, -2. * q0, 2. * q1],
+            [2. * q1, 2. * q0, 2. * q3, 2. * q2],
+            [0., -4. * q1, -4. * q2, 0.],
+        ];
+        let mut gradient = [0.; 4];
+        for (row, error) in j.iter().zip(f) {
+            for (g, partial) in gradient.iter_mut().zip(row) {
+                *g += partial * error;
+            }
+        }
+
+        if let Some(mag) = mag {
+            let (bx, bz) = earth_field(q, mag);
+            let f = [
+                2. * bx * (0.5 - q2 * q2 - q3 * q3) + 2. * bz * (q1 * q3 - q0 * q2) - mag.x,
+                2. * bx * (q1 * q2 - q0 * q3) + 2. * bz * (q0 * q1 + q2 * q3) - mag.y,
+                2. * bx * (q0 * q2 + q1 * q3) + 2. * bz * (0.5 - q1 * q1 - q2 * q2) - mag.z,
+            ];
+            let j = [
+                [
+                    -2. * bz * q2,
+                    2. * bz * q3,
+                    -4. * bx * q2 - 2. * bz * q0,
+                    -4. * bx * q3 + 2. * bz * q1,
+                ],
+                [
+                    -2. * bx * q3 + 2. * bz * q1,
+                    2. * bx * q2 + 2. * bz * q0,
+                    2. * bx * q1 + 2. * bz * q3,
+                    -2. * bx * q0 + 2. * bz * q2,
+                ],
+                [
+                    2. * bx * q2,
+                    2. * bx * q3 - 4. * bz * q1,
+                    2. * bx * q0 - 4. * bz * q2,
+                    2. * bx * q1,
+                ],
+            ];
+            for (row, error) in j.iter().zip(f) {
+                for (g, partial) in gradient.iter_mut().zip(row) {
+                    *g += partial * error;
+                }
+            }
+        }
+
+        Quat::new(gradient[0], gradient[1], gradient[2], gradient[3])
+    }
+}
+
+impl Default for Madgwick {
+    fn default() -> Self {
+        Self::new(DEFAULT_BETA)
+    }
+}
+
+impl OrientationFilter for Madgwick {
+    fn update(&mut self, reading: &Reading, dt: f32) {
+        let mut rate = gyro_rate(self.q, reading.gyro);
+
+        if let Some(accel) = normalized(reading.accel) {
+            let mag = reading.mag.and_then(normalized);
+            let step = Self::gradient(self.q, accel, mag).normalized();
+            rate = rate.add(step.scale(-self.beta));
+        }
+
+        self.q = self.q.add(rate.scale(dt)).normalized();
+    }
+
+    fn orientation(&self) -> Quaternion<f32> {
+        self.q.into()
+    }
+
+    fn set_orientation(&mut self, orientation: Quaternion<f32>) {
+        self.q = Quat::from(orientation).normalized();
+    }
+}
+
+/// Mahony's complementary filter: integrates the gyro, corrected by a PI controller on the angle
+/// between where we think up (and north) is and where the sensors say it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mahony {
+    q: Quat,
+    /// accumulated integral correction, which ends up tracking gyro bias
+    integral: Vector3<f32>,
+    /// proportional gain
+    pub kp: f32,
+    /// integral gain
+    pub ki: f32,
+}
+
+impl Mahony {
+    /// Creates a filter with gains `kp` and `ki`, starting at identity
+    #[must_use]
+    pub const fn new(kp: f32, ki: f32) -> Self {
+        Self {
+            q: Quat::IDENTITY,
+            integral: Vector3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            kp,
+            ki,
+        }
+    }
+}
+
+impl Default for Mahony {
+    fn default() -> Self {
+        Self::new(DEFAULT_KP, DEFAULT_KI)
+    }
+}
+
+impl OrientationFilter for Mahony {
+    fn update(&mut self, reading: &Reading, dt: f32) {
+        let mut gyro = reading.gyro.unwrap_or(Vector3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        });
+
+        if let Some(accel) = normalized(reading.accel) {
+            // where we think up is in the pen's frame
+            let up = self.q.conjugate().rotate(Vector3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            });
+            let mut error = cross(accel, up);
+
+            if let Some(mag) = reading.mag.and_then(normalized) {
+                let (bx, bz) = earth_field(self.q, mag);
+                let north = self.q.conjugate().rotate(Vector3 {
+                    x: bx,
+                    y: 0.,
+                    z: bz,
+                });
+                let mag_error = cross(mag, north);
+                error = Vector3 {
+                    x: error.x + mag_error.x,
+                    y: error.y + mag_error.y,
+                    z: error.z + mag_error.z,
+                };
+            }
+
+            if self.ki > 0. {
+                self.integral = Vector3 {
+                    x: self.integral.x + self.ki * error.x * dt,
+                    y: self.integral.y + self.ki * error.y * dt,
+                    z: self.integral.z + self.ki * error.z * dt,
+                };
+            }
+            gyro = Vector3 {
+                x: gyro.x + self.kp * error.x + self.integral.x,
+                y: gyro.y + self.kp * error.y + self.integral.y,
+                z: gyro.z + self.kp * error.z + self.integral.z,
+            };
+        }
+
+        self.q = self
+            .q
+            .add(gyro_rate(self.q, Some(gyro)).scale(dt))
+            .normalized();
+    }
+
+    fn orientation(&self) -> Quaternion<f32> {
+        self.q.into()
+    }
+
+    fn set_orientation(&mut self, orientation: Quaternion<f32>) {
+        self.q = Quat::from(orientation).normalized();
+    }
+}
+
+/// Runs an [`OrientationFilter`] over the samples pensel streams, live or from a recording
+#[derive(Debug, Clone)]
+pub struct Estimator<F: OrientationFilter> {
+    filter: F,
+    /// timestamp of the last gravity sample we fused
+    last: Option<Duration>,
+}
+
+impl<F: OrientationFilter> Estimator<F> {
+    /// Estimates orientation with `filter`
+    #[must_use]
+    pub const fn new(filter: F) -> Self {
+        Self { filter, last: None }
+    }
+
+    /// Fuses in `timed`, if it's a sample we can use.
+    ///
+    /// The first usable sample snaps the estimate straight to the tilt it measures rather than
+    /// having the filter converge from identity.
+    ///
+    /// # Returns
+    /// The updated orientation, or `None` if the sample wasn't used
+    pub fn feed(&mut self, timed: &TimedSample) -> Option<Quaternion<f32>> {
+        let types::Sample::Grav(gravity) = timed.sample else {
+            // linear acceleration has gravity removed, so says nothing about orientation
+            return None;
+        };
+        let gravity = gravity.m_s2();
+
+        match self.last.replace(timed.timestamp) {
+            None => self.filter.set_orientation(from_up(gravity)),
+            Some(last) => {
+                let dt = timed.timestamp.saturating_sub(last).as_secs_f32();
+                self.filter.update(&Reading::gravity(gravity), dt);
+            }
+        }
+        Some(self.filter.orientation())
+    }
+
+    /// The current orientation estimate
+    #[must_use]
+    pub fn orientation(&self) -> Quaternion<f32> {
+        self.filter.orientation()
+    }
+
+    /// The filter doing the estimating, e.g. to tune its gains
+    pub fn filter_mut(&mut self) -> &mut F {
+        &mut self.filter
+    }
+}
+
+#[cfg(test)]
+mod test_orientation {
+    use super::*;
+    use crate::{
+        test_util::{assert_close, v},
+        types::imu,
+    };
+
+    /// Madgwick's fixed size steps keep it hopping around the optimum by about `beta * dt`
+    const EPSILON: f32 = 1e-2;
+    const DT: f32 = types::SAMPLE_PERIOD.as_secs_f32();
+
+    /// Up in the pen's frame, according to `q`
+    fn up_in_pen(q: Quaternion<f32>) -> Vector3<f32> {
+        Quat::from(q).conjugate().rotate(v(0., 0., 1.))
+    }
+
+    #[test]
+    fn from_up_tilts() {
+        assert_close(
+            rotate(from_up(v(0., 0., 9.81)), v(0., 0., 1.)),
+            v(0., 0., 1.),
+            EPSILON,
+        );
+        let up = v(1., 2., 3.);
+        let q = from_up(up);
+        assert_close(rotate(q, normalized(up).unwrap()), v(0., 0., 1.), EPSILON);
+        assert_close(
+            rotate(from_up(v(0., 0., -1.)), v(0., 0., -1.)),
+            v(0., 0., 1.),
+            EPSILON,
+        );
+        assert_eq!(from_up(v(0., 0., 0.)), Quat::IDENTITY.into());
+    }
+
+    #[test]
+    fn composes() {
+        use std::f32::consts::FRAC_PI_2;
+
+        let yaw = from_euler(0., 0., FRAC_PI_2);
+        assert_close(rotate(yaw, v(1., 0., 0.)), v(0., 1., 0.), EPSILON);
+        let roll = from_euler(FRAC_PI_2, 0., 0.);
+        assert_close(rotate(roll, v(0., 1., 0.)), v(0., 0., 1.), EPSILON);
+        // rolling, then yawing
+        let both = from_euler(FRAC_PI_2, 0., FRAC_PI_2);
+        assert_close(rotate(both, v(0., 1., 0.)), v(0., 0., 1.), EPSILON);
+        assert_close(rotate(both, v(1., 0., 0.)), v(0., 1., 0.), EPSILON);
+        let composed = compose(yaw, roll);
+        for axis in [v(1., 0., 0.), v(0., 1., 0.), v(0., 0., 1.)] {
+            assert_close(rotate(composed, axis), rotate(both, axis), EPSILON);
+            assert_close(rotate(inverse(both), rotate(both, axis)), axis, EPSILON);
+        }
+
+        // every right handed frame made of signed axes, to cover each branch of from_axes
+        let axes = [
+            v(1., 0., 0.),
+            v(-1., 0., 0.),
+            v(0., 1., 0.),
+            v(0., -1., 0.),
+            v(0., 0., 1.),
+            v(0., 0., -1.),
+        ];
+        for x in axes {
+            for y in axes.into_iter().filter(|y| dot(x, *y) == 0.) {
+                let z = cross(x, y);
+                let q = from_axes(x, y, z);
+                assert_close(rotate(q, v(1., 0., 0.)), x, EPSILON);
+                assert_close(rotate(q, v(0., 1., 0.)), y, EPSILON);
+                assert_close(rotate(q, v(0., 0., 1.)), z, EPSILON);
+            }
+        }
+    }
+
+    fn converges_to_gravity(mut filter: impl OrientationFilter) {
+        let gravity = v(0., 6.94, 6.94);
+        for _ in 0..2000 {
+            filter.update(&Reading::gravity(gravity), DT);
+        }
+        assert_close(
+            up_in_pen(filter.orientation()),
+            normalized(gravity).unwrap(),
+            EPSILON,
+        );
+    }
+
+    #[test]
+    fn madgwick_converges_to_gravity() {
+        converges_to_gravity(Madgwick::new(0.5));
+    }
+
+    #[test]
+    fn mahony_converges_to_gravity() {
+        converges_to_gravity(Mahony::new(2., 0.));
+    }
+
+    fn integrates_gyro(mut filter: impl OrientationFilter) {
+        // a quarter turn around z over one second, level so the accelerometer agrees throughout
+        let reading = Reading {
+            gyro: Some(v(0., 0., std::f32::consts::FRAC_PI_2)),
+            accel: v(0., 0., 9.81),
+            mag: None,
+        };
+        for _ in 0..1000 {
+            filter.update(&reading, 0.001);
+        }
+        assert_close(
+            rotate(filter.orientation(), v(1., 0., 0.)),
+            v(0., 1., 0.),
+            EPSILON,
+        );
+    }
+
+    #[test]
+    fn madgwick_integrates_gyro() {
+        integrates_gyro(Madgwick::default());
+    }
+
+    #[test]
+    fn mahony_integrates_gyro() {
+        integrates_gyro(Mahony::default());
+    }
+
+    fn finds_north(mut filter: impl OrientationFilter) {
+        // level, but turned so magnetic north (tilted down, as in the northern hemisphere) shows
+        // up along the pen's -y axis
+        let reading = Reading {
+            gyro: Some(v(0., 0., 0.)),
+            accel: v(0., 0., 9.81),
+            mag: Some(v(0., -20., -40.)),
+        };
+        for _ in 0..5000 {
+            filter.update(&reading, DT);
+        }
+        // so the pen's -y axis points along world x
+        assert_close(
+            rotate(filter.orientation(), v(0., -1., 0.)),
+            v(1., 0., 0.),
+            EPSILON,
+        );
+    }
+
+    #[test]
+    fn madgwick_finds_north() {
+        finds_north(Madgwick::new(0.5));
+    }
+
+    #[test]
+    fn mahony_finds_north() {
+        finds_north(Mahony::new(2., 0.));
+    }
+
+    #[test]
+    fn estimator_uses_gravity() {
+        let mut estimator = Estimator::new(Madgwick::default());
+        let accel = TimedSample {
+            timestamp: Duration::ZERO,
+            sample: types::Sample::Accel(imu::AccelerationVector::new(1, 2, 3)),
+        };
+        assert_eq!(estimator.feed(&accel), None);
+
+        let gravity = imu::GravityVector::new(0, 694, 694);
+        let first = TimedSample {
+            timestamp: types::SAMPLE_PERIOD,
+            sample: types::Sample::Grav(gravity),
+        };
+        let q = estimator.feed(&first).unwrap();
+        assert_close(up_in_pen(q), normalized(gravity.m_s2()).unwrap(), EPSILON);
+
+        let second = TimedSample {
+            timestamp: Duration::from_millis(20),
+            ..first
+        };
+        let q = estimator.feed(&second).unwrap();
+        assert_close(up_in_pen(q), normalized(gravity.m_s2()).unwrap(), EPSILON);
+    }
+}
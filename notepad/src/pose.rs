@@ -0,0 +1,505 @@
+//! Poses of the pen's tip in a robot's base frame, in formats robot controllers read.
+//!
+//! A [`Pose`] is where the tip is, and how a tool frame on the pen is oriented: z along the tip
+//! axis and x out of the pen's top (see [`AxisMapping`]), as robot tools usually are. Poses are
+//! published either as ROS `geometry_msgs/PoseStamped` messages through a rosbridge server (see
+//! [`Rosbridge`]), or as [`Datagram`]s for controllers that don't speak ROS.
+use pensel_types::mint::{Quaternion, Vector3};
+use serde_json::json;
+use std::{fmt, io, net::TcpStream, str::FromStr, time::Duration};
+use tungstenite::{stream::MaybeTlsStream, WebSocket};
+
+use crate::{
+    angles::AxisMapping,
+    orientation,
+    vector::{add, cross},
+};
+
+/// Default [`Topic::name`]
+pub const DEFAULT_TOPIC: &str = "/pensel/pose";
+/// Default [`Topic::frame_id`]
+pub const DEFAULT_FRAME_ID: &str = "base_link";
+
+/// Where the world frame the pen is tracked in sits in the robot's base frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    /// the world frame's origin (where the tip started), in the base frame, in meters
+    pub translation: Vector3<f32>,
+    /// the rotation from world axes to base axes
+    pub rotation: Quaternion<f32>,
+}
+
+impl Placement {
+    /// The world frame is the base frame
+    pub const IDENTITY: Self = Self {
+        translation: Vector3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        },
+        rotation: Quaternion {
+            v: Vector3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            s: 1.,
+        },
+    };
+
+    /// `position` in the world frame, moved into the base frame
+    #[must_use]
+    pub fn place(&self, position: Vector3<f32>) -> Vector3<f32> {
+        add(
+            self.translation,
+            orientation::rotate(self.rotation, position),
+        )
+    }
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl FromStr for Placement {
+    type Err = String;
+
+    /// Parses `<x>,<y>,<z>` in meters, optionally followed by `,<roll>,<pitch>,<yaw>` in
+    /// degrees, like `0.4,0,0.02,0,0,90`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad_placement = || {
+            format!(
+                "expected '<x>,<y>,<z>[,<roll>,<pitch>,<yaw>]' in meters and degrees, got '{}'",
+                s
+            )
+        };
+        let components = s
+            .split(',')
+            .map(|component| f32::from_str(component.trim()).map_err(|_| bad_placement()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (translation, (roll, pitch, yaw)) = match components[..] {
+            [x, y, z] => (Vector3 { x, y, z }, (0., 0., 0.)),
+            [x, y, z, roll, pitch, yaw] => (Vector3 { x, y, z }, (roll, pitch, yaw)),
+            _ => return Err(bad_placement()),
+        };
+        Ok(Self {
+            translation,
+            rotation: orientation::from_euler(
+                roll.to_radians(),
+                pitch.to_radians(),
+                yaw.to_radians(),
+            ),
+        })
+    }
+}
+
+/// A position and orientation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pose {
+    /// in meters
+    pub position: Vector3<f32>,
+    /// a unit quaternion, with `s` (w) never negative
+    pub orientation: Quaternion<f32>,
+}
+
+impl Pose {
+    /// The pose of the pen's tool frame in the base frame, from the tip's `position` in the world
+    /// frame and the pen's `orientation` (see [`crate::orientation`])
+    #[must_use]
+    pub fn of_tip(
+        position: Vector3<f32>,
+        orientation: Quaternion<f32>,
+        mapping: AxisMapping,
+        placement: &Placement,
+    ) -> Self {
+        let tool = orientation::compose(orientation, tool_frame(mapping));
+        // q and -q are the same rotation, but some controllers only take one of them
+        let orientation = orientation::positive(orientation::compose(placement.rotation, tool));
+        Self {
+            position: placement.place(position),
+            orientation,
+        }
+    }
+}
+
+/// The rotation from the tool frame into the pen's frame, for a pen whose IMU is mounted as
+/// described by `mapping`
+#[must_use]
+pub fn tool_frame(mapping: AxisMapping) -> Quaternion<f32> {
+    let z = mapping.tip.unit();
+    let x = mapping.top.unit();
+    orientation::from_axes(x, cross(z, x), z)
+}
+
+/// Which ROS a rosbridge server is running on. Their messages differ in small ways.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RosVersion {
+    Ros1,
+    Ros2,
+}
+
+impl FromStr for RosVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1" | "ros1" => Ok(Self::Ros1),
+            "2" | "ros2" => Ok(Self::Ros2),
+            _ => Err(format!("unknown ROS version '{}', expected '1' or '2'", s)),
+        }
+    }
+}
+
+impl fmt::Display for RosVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ros1 => write!(f, "1"),
+            Self::Ros2 => write!(f, "2"),
+        }
+    }
+}
+
+/// A ROS topic of `geometry_msgs/PoseStamped` messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topic {
+    /// like `/pensel/pose`
+    pub name: String,
+    /// the base frame poses are in, like `base_link`
+    pub frame_id: String,
+    pub ros: RosVersion,
+}
+
+impl Topic {
+    /// The rosbridge request to advertise the topic, which has to come before publishing to it
+    #[must_use]
+    pub fn advertise(&self) -> String {
+        let message_type = match self.ros {
+            RosVersion::Ros1 => "geometry_msgs/PoseStamped",
+            RosVersion::Ros2 => "geometry_msgs/msg/PoseStamped",
+        };
+        json!({
+            "op": "advertise",
+            "topic": self.name,
+            "type": message_type,
+        })
+        .to_string()
+    }
+
+    /// The rosbridge request publishing `pose`, stamped with `stamp` since the Unix epoch
+    #[must_use]
+    pub fn publish(&self, stamp: Duration, pose: &Pose) -> String {
+        let stamp = match self.ros {
+            RosVersion::Ros1 => json!({"secs": stamp.as_secs(), "nsecs": stamp.subsec_nanos()}),
+            RosVersion::Ros2 => json!({"sec": stamp.as_secs(), "nanosec": stamp.subsec_nanos()}),
+        };
+        json!({
+            "op": "publish",
+            "topic": self.name,
+            "msg": {
+                "header": {"stamp": stamp, "frame_id": self.frame_id},
+                "pose": {
+                    "position": {
+                        "x": pose.position.x,
+                        "y": pose.position.y,
+                        "z": pose.position.z,
+                    },
+                    "orientation": {
+                        "x": pose.orientation.v.x,
+                        "y": pose.orientation.v.y,
+                        "z": pose.orientation.v.z,
+                        "w": pose.orientation.s,
+                    },
+                },
+            },
+        })
+        .to_string()
+    }
+}
+
+/// A connection to a rosbridge server, publishing to one [`Topic`]
+#[derive(Debug)]
+pub struct Rosbridge {
+    topic: Topic,
+    socket: WebSocket<MaybeTlsStream<TcpStream>>,
+}
+
+impl Rosbridge {
+    /// Connects to the rosbridge server at `url`, like `ws://localhost:9090`, and advertises
+    /// `topic`
+    ///
+    /// # Errors
+    /// If `url` isn't a `ws://` URL, or connecting fails
+    pub fn connect(url: &str, topic: Topic) -> io::Result<Self> {
+        if !url.starts_with("ws://") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("expected a ws:// URL, got '{}'", url),
+            ));
+        }
+        let (socket, _) = tungstenite::connect(url).map_err(io::Error::other)?;
+        if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream.set_nodelay(true)?;
+        }
+        let mut bridge = Self { topic, socket };
+        bridge.send(&bridge.topic.advertise())?;
+        Ok(bridge)
+    }
+
+    /// Publishes `pose`, stamped with `stamp` since the Unix epoch
+    ///
+    /// # Errors
+    /// If the connection fails
+    pub fn publish(&mut self, stamp: Duration, pose: &Pose) -> io::Result<()> {
+        self.send(&self.topic.publish(stamp, pose))
+    }
+
+    fn send(&mut self, text: &str) -> io::Result<()> {
+        self.socket
+            .send(tungstenite::Message::text(text))
+            .map_err(io::Error::other)
+    }
+}
+
+/// A pose in a UDP datagram, for controllers without ROS. [`Datagram::LEN`] bytes, big endian:
+///
+/// | bytes  | contents                                          |
+/// |--------|---------------------------------------------------|
+/// | 0..4   | [`Datagram::MAGIC`]                               |
+/// | 4..8   | sequence number, `u32`                            |
+/// | 8..16  | stamp, microseconds since the Unix epoch, `u64`   |
+/// | 16..28 | position x, y and z in meters, `f32`              |
+/// | 28..44 | orientation quaternion x, y, z and w, `f32`       |
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Datagram {
+    /// counts up with each datagram, so controllers can spot lost or reordered ones
+    pub sequence: u32,
+    /// since the Unix epoch
+    pub stamp: Duration,
+    pub pose: Pose,
+}
+
+impl Datagram {
+    /// What every datagram starts with
+    pub const MAGIC: [u8; 4] = *b"PPOS";
+    /// Length of an encoded datagram
+    pub const LEN: usize = 4 + 4 + 8 + 7 * 4;
+
+    #[must_use]
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut out = [0; Self::LEN];
+        out[..4].copy_from_slice(&Self::MAGIC);
+        out[4..8].copy_from_slice(&self.sequence.to_be_bytes());
+        #[allow(clippy::cast_possible_truncation)]
+        let micros = self.stamp.as_micros() as u64;
+        out[8..16].copy_from_slice(&micros.to_be_bytes());
+        let Pose {
+            position,
+            orientation,
+        } = self.pose;
+        let values = [
+            position.x,
+            position.y,
+            position.z,
+            orientation.v.x,
+            orientation.v.y,
+            orientation.v.z,
+            orientation.s,
+        ];
+        for (chunk, value) in out[16..].chunks_exact_mut(4).zip(values) {
+            chunk.copy_from_slice(&value.to_be_bytes());
+        }
+        out
+    }
+
+    /// Decodes an [encoded](Datagram::encode) datagram, or `None` if `bytes` isn't one
+    #[must_use]
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::LEN || bytes[..4] != Self::MAGIC {
+            return None;
+        }
+        let sequence = u32::from_be_bytes(bytes[4..8].try_into().ok()?);
+        let micros = u64::from_be_bytes(bytes[8..16].try_into().ok()?);
+        let value = |at: usize| f32::from_be_bytes(bytes[at..at + 4].try_into().unwrap());
+        Some(Self {
+            sequence,
+            stamp: Duration::from_micros(micros),
+            pose: Pose {
+                position: Vector3 {
+                    x: value(16),
+                    y: value(20),
+                    z: value(24),
+                },
+                orientation: Quaternion {
+                    v: Vector3 {
+                        x: value(28),
+                        y: value(32),
+                        z: value(36),
+                    },
+                    s: value(40),
+                },
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_pose {
+    use super::*;
+    use crate::{
+        angles::SignedAxis,
+        test_util::{assert_close, v},
+    };
+    use std::{net::TcpListener, thread};
+
+    const EPSILON: f32 = 1e-5;
+
+    #[test]
+    fn parses_placements() {
+        let placement: Placement = "0.4, 0, 0.02".parse().unwrap();
+        assert_eq!(placement.translation, v(0.4, 0., 0.02));
+        assert_eq!(placement.rotation, Placement::IDENTITY.rotation);
+
+        let turned: Placement = "0,0,0,0,0,90".parse().unwrap();
+        assert_close(
+            orientation::rotate(turned.rotation, v(1., 0., 0.)),
+            v(0., 1., 0.),
+            EPSILON,
+        );
+        assert!("1,2".parse::<Placement>().is_err());
+        assert!("1,2,3,4".parse::<Placement>().is_err());
+        assert!("a,b,c".parse::<Placement>().is_err());
+    }
+
+    #[test]
+    fn tool_frame_follows_the_tip() {
+        let mapping = AxisMapping {
+            tip: SignedAxis::Y.flipped(),
+            top: SignedAxis::X,
+        };
+        let tool = tool_frame(mapping);
+        assert_close(
+            orientation::rotate(tool, v(0., 0., 1.)),
+            v(0., -1., 0.),
+            EPSILON,
+        );
+        assert_close(
+            orientation::rotate(tool, v(1., 0., 0.)),
+            v(1., 0., 0.),
+            EPSILON,
+        );
+
+        // a level pen pointing along world x, on a base turned a quarter around z and lifted
+        // 10 cm above the world's origin
+        let placement: Placement = "0,0,0.1,0,0,90".parse().unwrap();
+        let pose = Pose::of_tip(
+            v(0.2, 0., 0.),
+            Placement::IDENTITY.rotation,
+            AxisMapping::DEFAULT,
+            &placement,
+        );
+        assert_close(pose.position, v(0., 0.2, 0.1), EPSILON);
+        assert!(pose.orientation.s >= 0.);
+        // the tool's z is the tip, pointing along the base's y
+        assert_close(
+            orientation::rotate(pose.orientation, v(0., 0., 1.)),
+            v(0., 1., 0.),
+            EPSILON,
+        );
+    }
+
+    #[test]
+    fn encodes_datagrams() {
+        let datagram = Datagram {
+            sequence: 7,
+            stamp: Duration::from_micros(1_700_000_000_123_456),
+            pose: Pose {
+                position: v(0.1, -0.2, 0.3),
+                orientation: orientation::from_euler(0.1, 0.2, 0.3),
+            },
+        };
+        let bytes = datagram.encode();
+        assert_eq!(bytes[..4], *b"PPOS");
+        assert_eq!(bytes[4..8], [0, 0, 0, 7]);
+        assert_eq!(Datagram::decode(&bytes), Some(datagram));
+        assert_eq!(Datagram::decode(&bytes[1..]), None);
+        let mut wrong_magic = bytes;
+        wrong_magic[0] = b'X';
+        assert_eq!(Datagram::decode(&wrong_magic), None);
+    }
+
+    #[test]
+    fn publishes_to_rosbridge() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut socket = tungstenite::accept(stream).unwrap();
+            (0..2)
+                .map(|_| {
+                    let text = socket.read().unwrap().into_text().unwrap();
+                    serde_json::from_str::<serde_json::Value>(&text).unwrap()
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let topic = Topic {
+            name: DEFAULT_TOPIC.to_owned(),
+            frame_id: "world".to_owned(),
+            ros: RosVersion::Ros1,
+        };
+        let url = format!("ws://{}/", address);
+        let mut bridge = Rosbridge::connect(&url, topic).unwrap();
+        let pose = Pose {
+            position: v(1., 2., 3.),
+            orientation: Placement::IDENTITY.rotation,
+        };
+        bridge
+            .publish(Duration::new(1_700_000_000, 500), &pose)
+            .unwrap();
+
+        let messages = server.join().unwrap();
+        assert_eq!(messages[0]["op"], "advertise");
+        assert_eq!(messages[0]["type"], "geometry_msgs/PoseStamped");
+        assert_eq!(messages[1]["op"], "publish");
+        assert_eq!(messages[1]["topic"], DEFAULT_TOPIC);
+        let msg = &messages[1]["msg"];
+        assert_eq!(msg["header"]["frame_id"], "world");
+        assert_eq!(msg["header"]["stamp"]["secs"], 1_700_000_000);
+        assert_eq!(msg["header"]["stamp"]["nsecs"], 500);
+        assert_eq!(msg["pose"]["position"]["z"], 3.);
+        assert_eq!(msg["pose"]["orientation"]["w"], 1.);
+
+        assert!(Rosbridge::connect(
+            "http://localhost:9090",
+            Topic {
+                name: DEFAULT_TOPIC.to_owned(),
+                frame_id: DEFAULT_FRAME_ID.to_owned(),
+                ros: RosVersion::Ros2,
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn stamps_ros2() {
+        let topic = Topic {
+            name: DEFAULT_TOPIC.to_owned(),
+            frame_id: DEFAULT_FRAME_ID.to_owned(),
+            ros: RosVersion::Ros2,
+        };
+        let advertise: serde_json::Value = serde_json::from_str(&topic.advertise()).unwrap();
+        assert_eq!(advertise["type"], "geometry_msgs/msg/PoseStamped");
+        let pose = Pose {
+            position: v(0., 0., 0.),
+            orientation: Placement::IDENTITY.rotation,
+        };
+        let publish: serde_json::Value =
+            serde_json::from_str(&topic.publish(Duration::new(3, 4), &pose)).unwrap();
+        assert_eq!(publish["msg"]["header"]["stamp"]["sec"], 3);
+        assert_eq!(publish["msg"]["header"]["stamp"]["nanosec"], 4);
+        assert_eq!("2".parse::<RosVersion>(), Ok(RosVersion::Ros2));
+        assert!("3".parse::<RosVersion>().is_err());
+    }
+}
@@ -0,0 +1,624 @@
+//! Reading and writing recorded pensel sessions.
+//!
+//! A recording is a text file. It starts with a header of `# key: value` lines describing the
+//! session, followed by one sample per line, prefixed with the seconds since the session started:
+//!
+//! ```text
+//! # pensel-recording: 2
+//! # pen id: 0123456789abcdef0123456789abcdef
+//! # firmware: 0.1.0
+//! # axis map: P1
+//! # start: 1697650000.123456
+//! # streams: accel,gravity
+//! # calibration: 2,0,252,255,231,255,215,254,174,1,228,1,1,0,0,0,0,0,232,3,241,2
+//! 0.000512 A:1,2,3
+//! 0.004900 G:-1,2,-3
+//! ```
+//!
+//! Readers skip header keys they don't know, so new keys can be added without bumping
+//! [`FORMAT_VERSION`].
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    comms::PenselSerial,
+    types::{self, imu},
+};
+
+/// The version of the recording format we write, and the newest we can read
+pub const FORMAT_VERSION: u32 = 2;
+/// The last version whose samples were streamed by firmware that multiplied the BNO055's counts
+/// by ten. We scale them back down when reading.
+const LAST_TIMES_TEN_VERSION: u32 = 1;
+
+/// Key of the first header line, whose value is the format version
+const KEY_VERSION: &str = "pensel-recording";
+const KEY_PEN_ID: &str = "pen id";
+const KEY_FIRMWARE: &str = "firmware";
+const KEY_AXIS_MAP: &str = "axis map";
+const KEY_START: &str = "start";
+const KEY_STREAMS: &str = "streams";
+const KEY_CALIBRATION: &str = "calibration";
+
+/// What header lines start with
+const HEADER_PREFIX: &str = "# ";
+
+/// The sample streams pensel can send
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Stream {
+    /// Linear acceleration, [`types::imu::AccelerationVector`]
+    Accel,
+    /// The gravity vector, [`types::imu::GravityVector`]
+    Gravity,
+}
+
+impl Stream {
+    /// Every stream
+    pub const ALL: [Self; 2] = [Self::Accel, Self::Gravity];
+
+    /// The stream's name, which matches the argument enabling it on pensel's `imu` command
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Accel => pensel_types::cli::ARG_ACCEL,
+            Self::Gravity => pensel_types::cli::ARG_GRAVITY,
+        }
+    }
+
+    /// The stream `sample` belongs to
+    #[must_use]
+    pub const fn of(sample: &types::Sample) -> Self {
+        match sample {
+            types::Sample::Accel(_) => Self::Accel,
+            types::Sample::Grav(_) => Self::Gravity,
+        }
+    }
+}
+
+impl FromStr for Stream {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|stream| stream.name() == s)
+            .ok_or_else(|| format!("unknown stream '{}'", s))
+    }
+}
+
+/// Everything describing a recorded session
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Header {
+    /// Version of the format the recording was written in
+    pub version: u32,
+    /// The pen that was recorded
+    pub device: types::DeviceInfo,
+    /// Wall clock time the recording started
+    pub start: SystemTime,
+    /// Which streams were enabled
+    pub streams: Vec<Stream>,
+}
+
+impl Header {
+    /// Creates a header for a session starting now
+    #[must_use]
+    pub fn new(device: types::DeviceInfo, streams: Vec<Stream>) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            device,
+            start: SystemTime::now(),
+            streams,
+        }
+    }
+
+    /// Writes the header lines out to `out`
+    pub(crate) fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let start = self
+            .start
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let streams: Vec<&str> = self.streams.iter().map(|s| s.name()).collect();
+        let calibration: Vec<String> = self
+            .device
+            .calibration
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        // whatever version we read, what we write matches the current format
+        writeln!(out, "{}{}: {}", HEADER_PREFIX, KEY_VERSION, FORMAT_VERSION)?;
+        writeln!(
+            out,
+            "{}{}: {}",
+            HEADER_PREFIX, KEY_PEN_ID, self.device.pen_id
+        )?;
+        writeln!(
+            out,
+            "{}{}: {}",
+            HEADER_PREFIX, KEY_FIRMWARE, self.device.firmware
+        )?;
+        // left out when unknown, so older recordings come out as they went in
+        if !self.device.axis_map.is_empty() {
+            writeln!(
+                out,
+                "{}{}: {}",
+                HEADER_PREFIX, KEY_AXIS_MAP, self.device.axis_map
+            )?;
+        }
+        writeln!(out, "{}{}: {}", HEADER_PREFIX, KEY_START, Seconds(start))?;
+        writeln!(
+            out,
+            "{}{}: {}",
+            HEADER_PREFIX,
+            KEY_STREAMS,
+            streams.join(",")
+        )?;
+        writeln!(
+            out,
+            "{}{}: {}",
+            HEADER_PREFIX,
+            KEY_CALIBRATION,
+            calibration.join(",")
+        )
+    }
+
+    /// Reads header lines from `input` up to the first line that isn't one.
+    ///
+    /// # Returns
+    /// The header, the first line after it (if there is one), and how many lines were read
+    pub(crate) fn read_from(
+        input: &mut impl BufRead,
+    ) -> Result<(Self, Option<String>, usize), RecordingError> {
+        let mut line_number = 0;
+        let mut version = None;
+        let mut header = Self {
+            version: FORMAT_VERSION,
+            device: types::DeviceInfo::unknown(),
+            start: SystemTime::UNIX_EPOCH,
+            streams: vec![],
+        };
+
+        let mut line = String::new();
+        let next_line = loop {
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                break None;
+            }
+            line_number += 1;
+            let Some(entry) = line
+                .trim_end_matches(['\r', '\n'])
+                .strip_prefix(HEADER_PREFIX)
+            else {
+                break Some(line.trim_end_matches(['\r', '\n']).to_owned());
+            };
+            let bad_header = |reason: String| RecordingError::BadHeader {
+                line_number,
+                reason,
+            };
+            let (key, value) = entry
+                .split_once(": ")
+                .or_else(|| entry.strip_suffix(':').map(|key| (key, "")))
+                .ok_or_else(|| bad_header(format!("expected 'key: value', got {:?}", entry)))?;
+            let value = value.trim();
+
+            if version.is_none() {
+                if key != KEY_VERSION {
+                    return Err(RecordingError::NotARecording);
+                }
+                let parsed = u32::from_str(value)
+                    .map_err(|_| bad_header(format!("bad version {:?}", value)))?;
+                if parsed > FORMAT_VERSION {
+                    return Err(RecordingError::UnsupportedVersion(parsed));
+                }
+                version = Some(parsed);
+                header.version = parsed;
+                continue;
+            }
+
+            match key {
+                KEY_PEN_ID => header.device.pen_id = value.to_owned(),
+                KEY_FIRMWARE => header.device.firmware = value.to_owned(),
+                KEY_AXIS_MAP => header.device.axis_map = value.to_owned(),
+                KEY_START => {
+                    let since_epoch = parse_seconds(value)
+                        .ok_or_else(|| bad_header(format!("bad start time {:?}", value)))?;
+                    header.start = SystemTime::UNIX_EPOCH + since_epoch;
+                }
+                KEY_STREAMS => {
+                    header.streams = value
+                        .split(',')
+                        .filter(|stream| !stream.is_empty())
+                        .map(Stream::from_str)
+                        .collect::<Result<_, _>>()
+                        .map_err(bad_header)?;
+                }
+                KEY_CALIBRATION => {
+                    header.device.calibration = value
+                        .split(',')
+                        .filter(|byte| !byte.is_empty())
+                        .map(u8::from_str)
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| bad_header(format!("bad calibration {:?}", value)))?;
+                }
+                // written by a newer version of us, but nothing we need
+                _ => (),
+            }
+        };
+
+        if version.is_none() {
+            return Err(RecordingError::NotARecording);
+        }
+        Ok((header, next_line, line_number))
+    }
+}
+
+/// A sample along with when it was received, relative to the start of the recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimedSample {
+    /// Time since the recording started
+    pub timestamp: Duration,
+    /// The sample itself
+    pub sample: types::Sample,
+}
+
+impl fmt::Display for TimedSample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", Seconds(self.timestamp), self.sample)
+    }
+}
+
+/// Displays a duration as `<seconds>.<microseconds>`, the way recordings store times
+struct Seconds(Duration);
+
+impl fmt::Display for Seconds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:06}", self.0.as_secs(), self.0.subsec_micros())
+    }
+}
+
+/// Why a recording couldn't be read
+#[derive(Debug)]
+pub enum RecordingError {
+    /// Reading the underlying file failed
+    Io(io::Error),
+    /// The file doesn't start with a recording header
+    NotARecording,
+    /// The recording was written in a newer format than we understand
+    UnsupportedVersion(u32),
+    /// A header line couldn't be understood
+    BadHeader {
+        /// 1-based line number in the file
+        line_number: usize,
+        /// what was wrong with it
+        reason: String,
+    },
+    /// A sample line couldn't be understood
+    BadSample {
+        /// 1-based line number in the file
+        line_number: usize,
+        /// the offending line
+        line: String,
+    },
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "recording I/O failed: {}", error),
+            Self::NotARecording => write!(f, "missing '{}' header", KEY_VERSION),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "recording format version {} is newer than supported version {}",
+                version, FORMAT_VERSION
+            ),
+            Self::BadHeader {
+                line_number,
+                reason,
+            } => write!(f, "line {}: bad header: {}", line_number, reason),
+            Self::BadSample { line_number, line } => {
+                write!(f, "line {}: bad sample {:?}", line_number, line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RecordingError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// Writes a recording: the header up front, then samples as they arrive
+pub struct RecordingWriter<W: Write> {
+    out: W,
+    started: Instant,
+}
+
+impl RecordingWriter<BufWriter<File>> {
+    /// Creates a recording file at `path`, overwriting any existing file.
+    ///
+    /// # Errors
+    /// If the file can't be created or the header can't be written.
+    pub fn create(path: impl AsRef<Path>, header: &Header) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), header)
+    }
+}
+
+impl<W: Write> RecordingWriter<W> {
+    /// Starts a recording by writing `header` out to `out`. Sample timestamps count from now.
+    ///
+    /// # Errors
+    /// If the header can't be written.
+    pub fn new(mut out: W, header: &Header) -> io::Result<Self> {
+        header.write_to(&mut out)?;
+        Ok(Self {
+            out,
+            started: Instant::now(),
+        })
+    }
+
+    /// Records `sample`, timestamped with the time since the recording started
+    ///
+    /// # Errors
+    /// If writing fails.
+    pub fn write_sample(&mut self, sample: types::Sample) -> io::Result<()> {
+        let timestamp = self.started.elapsed();
+        self.write_timed(&TimedSample { timestamp, sample })
+    }
+
+    /// Records a sample that already has a timestamp, e.g. when copying another recording
+    ///
+    /// # Errors
+    /// If writing fails.
+    pub fn write_timed(&mut self, timed: &TimedSample) -> io::Result<()> {
+        writeln!(self.out, "{}", timed)
+    }
+
+    /// Flushes everything written so far
+    ///
+    /// # Errors
+    /// If flushing fails.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Reads a recording back: the header up front, then samples by iterating
+pub struct RecordingReader<R: BufRead> {
+    lines: io::Lines<R>,
+    header: Header,
+    /// The first sample line, which we had to read to find the end of the header
+    first_sample: Option<String>,
+    line_number: usize,
+}
+
+impl RecordingReader<BufReader<File>> {
+    /// Opens the recording at `path` and reads its header.
+    ///
+    /// # Errors
+    /// If the file can't be opened or its header isn't valid.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: BufRead> RecordingReader<R> {
+    /// Reads the header from `input`, leaving the samples to be read by iterating.
+    ///
+    /// # Errors
+    /// If reading fails or the header isn't valid.
+    pub fn new(mut input: R) -> Result<Self, RecordingError> {
+        let (header, first_sample, line_number) = Header::read_from(&mut input)?;
+        Ok(Self {
+            lines: input.lines(),
+            header,
+            first_sample,
+            line_number,
+        })
+    }
+
+    /// The recording's header
+    #[must_use]
+    pub const fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Parses a `<seconds> <sample>` line
+    fn parse_sample(&self, line: &str) -> Result<TimedSample, RecordingError> {
+        let bad_sample = || RecordingError::BadSample {
+            line_number: self.line_number,
+            line: line.to_owned(),
+        };
+
+        let (timestamp, sample) = line.trim().split_once(' ').ok_or_else(bad_sample)?;
+        let timestamp = parse_seconds(timestamp).ok_or_else(bad_sample)?;
+        let mut sample =
+            types::Sample::from_parsed(PenselSerial::parse_line(sample)).ok_or_else(bad_sample)?;
+        if self.header.version <= LAST_TIMES_TEN_VERSION {
+            sample = match sample {
+                types::Sample::Accel(a) => {
+                    types::Sample::Accel(imu::AccelerationVector::new(a.x / 10, a.y / 10, a.z / 10))
+                }
+                types::Sample::Grav(g) => {
+                    types::Sample::Grav(imu::GravityVector::new(g.x / 10, g.y / 10, g.z / 10))
+                }
+            };
+        }
+
+        Ok(TimedSample { timestamp, sample })
+    }
+}
+
+/// Parses a `<seconds>.<fraction>` time as written in recordings. Times are only written to the
+/// microsecond, so anything finer is dropped rather than risk float rounding creeping in.
+fn parse_seconds(value: &str) -> Option<Duration> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let whole = u64::from_str(whole).ok()?;
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let micros = fraction
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(6)
+        .fold(0, |micros, digit| micros * 10 + u32::from(digit - b'0'));
+    Some(Duration::new(whole, micros * 1000))
+}
+
+impl<R: BufRead> Iterator for RecordingReader<R> {
+    type Item = Result<TimedSample, RecordingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.first_sample.take() {
+                Some(line) => line,
+                None => match self.lines.next()? {
+                    Ok(line) => {
+                        self.line_number += 1;
+                        line
+                    }
+                    Err(error) => return Some(Err(error.into())),
+                },
+            };
+
+            // tolerate blank lines, e.g. a trailing one
+            if !line.trim().is_empty() {
+                return Some(self.parse_sample(&line));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_recording {
+    use super::*;
+
+    fn example_header() -> Header {
+        Header {
+            version: FORMAT_VERSION,
+            device: types::DeviceInfo {
+                pen_id: "0123abcd".to_owned(),
+                firmware: "0.1.0".to_owned(),
+                axis_map: "-y,+x,+z".to_owned(),
+                calibration: vec![2, 0, 252],
+            },
+            start: SystemTime::UNIX_EPOCH + Duration::from_micros(1_697_650_000_123_456),
+            streams: vec![Stream::Accel, Stream::Gravity],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let header = example_header();
+        let samples = [
+            TimedSample {
+                timestamp: Duration::from_micros(512),
+                sample: types::Sample::Accel(imu::AccelerationVector::new(1, 2, 3)),
+            },
+            TimedSample {
+                timestamp: Duration::from_micros(4900),
+                sample: types::Sample::Grav(imu::GravityVector::new(-1, 2, -3)),
+            },
+        ];
+
+        let mut writer = RecordingWriter::new(vec![], &header).unwrap();
+        for sample in &samples {
+            writer.write_timed(sample).unwrap();
+        }
+        let written = writer.out;
+
+        let reader = RecordingReader::new(written.as_slice()).unwrap();
+        assert_eq!(reader.header(), &header);
+        let read: Vec<TimedSample> = reader.map(Result::unwrap).collect();
+        assert_eq!(read, samples);
+    }
+
+    #[test]
+    fn write_sample_timestamps() {
+        let mut writer = RecordingWriter::new(vec![], &example_header()).unwrap();
+        writer
+            .write_sample(types::Sample::Accel(imu::AccelerationVector::new(1, 2, 3)))
+            .unwrap();
+        let written = String::from_utf8(writer.out).unwrap();
+        assert!(written.lines().last().unwrap().ends_with(" A:1,2,3"));
+    }
+
+    #[test]
+    fn reads_example() {
+        let recording = "# pensel-recording: 2\n\
+                         # pen id: 0123abcd\n\
+                         # from the future: ignored\n\
+                         # streams: gravity\n\
+                         0.5 G:1,2,3\n\
+                         \n";
+        let reader = RecordingReader::new(recording.as_bytes()).unwrap();
+        assert_eq!(reader.header().device.pen_id, "0123abcd");
+        assert_eq!(reader.header().device.firmware, "unknown");
+        assert_eq!(reader.header().streams, [Stream::Gravity]);
+
+        let samples: Vec<TimedSample> = reader.map(Result::unwrap).collect();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].timestamp, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn scales_down_version_1() {
+        let recording = "# pensel-recording: 1\n0.5 G:10,-20,9810\n";
+        let mut reader = RecordingReader::new(recording.as_bytes()).unwrap();
+        assert_eq!(reader.header().version, 1);
+        assert_eq!(
+            reader.next().unwrap().unwrap().sample,
+            types::Sample::Grav(imu::GravityVector::new(1, -2, 981))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(matches!(
+            RecordingReader::new("A:1,2,3\n".as_bytes()),
+            Err(RecordingError::NotARecording)
+        ));
+        assert!(matches!(
+            RecordingReader::new("".as_bytes()),
+            Err(RecordingError::NotARecording)
+        ));
+        assert!(matches!(
+            RecordingReader::new("# pensel-recording: 99\n".as_bytes()),
+            Err(RecordingError::UnsupportedVersion(99))
+        ));
+        assert!(matches!(
+            RecordingReader::new("# pensel-recording: 2\n# streams: wobble\n".as_bytes()),
+            Err(RecordingError::BadHeader { line_number: 2, .. })
+        ));
+
+        let mut reader =
+            RecordingReader::new("# pensel-recording: 2\n0.1 A:1,2,3\nnope\n".as_bytes()).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(
+            reader.next(),
+            Some(Err(RecordingError::BadSample { line_number: 3, .. }))
+        ));
+    }
+}
@@ -0,0 +1,616 @@
+//! Registers the pen against a work frame, like a bench or a robot's base, so angles and poses
+//! come out in the work frame's axes instead of the BNO055's.
+//!
+//! The user holds the pen still in a few known orientations ("tip pointing down, top facing
+//! +x"), and we [capture](Capture) which way gravity points in the sensor's frame during each.
+//! From those pairs [`Registration::solve`] finds the rotation from the sensor's frame into the
+//! pen's tool frame (z along the tip, x out of the top, see [`crate::pose`]), however the board
+//! sits in the pen. The first hold is also where sessions start, which pins down the heading of
+//! the work frame. Where the tip touches then isn't measured: it's taken as given, and sessions
+//! have to start with the tip on that point for positions to line up.
+use pensel_types::mint::{Quaternion, Vector3};
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, io, path::Path, time::Duration};
+
+use crate::{
+    angles::{AxisMapping, SignedAxis},
+    orientation,
+    pose::{self, Placement},
+    recording::TimedSample,
+    trajectory::LeverArm,
+    types::{self, imu},
+    vector::{dot, norm, normalized},
+};
+
+/// Where registrations are saved by default
+pub const DEFAULT_PATH: &str = "pensel-registration.json";
+/// Default [`Capture`] duration
+pub const DEFAULT_CAPTURE_TIME: Duration = Duration::from_secs(1);
+/// Default [`Capture`] still threshold, in m/s²
+pub const DEFAULT_STILL_THRESHOLD: f32 = 0.3;
+/// Holds have to have gravity at least this far apart, and this far from opposite, in degrees, to
+/// tell us anything
+const MIN_SPREAD: f32 = 20.;
+/// Power iterations when solving for the mounting
+const ITERATIONS: usize = 500;
+
+/// The angle between two vectors, in degrees
+fn angle_between(a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+    let lengths = norm(a) * norm(b);
+    if lengths > 0. {
+        (dot(a, b) / lengths).clamp(-1., 1.).acos().to_degrees()
+    } else {
+        0.
+    }
+}
+
+/// Averages the gravity vector while the pen is held still
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    /// linear acceleration above which the pen isn't still, in m/s²
+    still_threshold: f32,
+    /// how long the pen has to be still for
+    duration: Duration,
+    /// when the pen was last seen starting to hold still
+    since: Option<Duration>,
+    sum: Vector3<f32>,
+    count: u32,
+}
+
+impl Capture {
+    #[must_use]
+    pub const fn new(still_threshold: f32, duration: Duration) -> Self {
+        Self {
+            still_threshold,
+            duration,
+            since: None,
+            sum: Vector3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            count: 0,
+        }
+    }
+
+    /// Feeds in `timed`. Any movement starts the capture over.
+    ///
+    /// # Returns
+    /// The average gravity vector in the sensor's frame, in m/s², once the pen has been still
+    /// for long enough
+    pub fn feed(&mut self, timed: &TimedSample) -> Option<Vector3<f32>> {
+        match timed.sample {
+            types::Sample::Accel(accel) => {
+                if norm(accel.m_s2()) >= self.still_threshold {
+                    *self = Self::new(self.still_threshold, self.duration);
+                }
+                None
+            }
+            types::Sample::Grav(gravity) => {
+                let gravity = gravity.m_s2();
+                let since = *self.since.get_or_insert(timed.timestamp);
+                self.sum.x += gravity.x;
+                self.sum.y += gravity.y;
+                self.sum.z += gravity.z;
+                self.count += 1;
+                #[allow(clippy::cast_precision_loss)]
+                let count = self.count as f32;
+                (timed.timestamp.saturating_sub(since) >= self.duration).then(|| Vector3 {
+                    x: self.sum.x / count,
+                    y: self.sum.y / count,
+                    z: self.sum.z / count,
+                })
+            }
+        }
+    }
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Self::new(DEFAULT_STILL_THRESHOLD, DEFAULT_CAPTURE_TIME)
+    }
+}
+
+/// Why holds couldn't be registered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegistrationError {
+    /// There have to be at least two holds
+    TooFewHolds(usize),
+    /// The holds were too alike to tell how the sensor is turned. Two holds have to tip the pen
+    /// over by at least [`MIN_SPREAD`] degrees, and not turn it over within that either: opposite
+    /// holds share an axis.
+    TooAlike,
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooFewHolds(holds) => {
+                write!(f, "need at least 2 holds to register, got {}", holds)
+            }
+            Self::TooAlike => write!(
+                f,
+                "the holds are too alike, two need to tip the pen at least {}° apart and not \
+                 turn it over within that",
+                MIN_SPREAD
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RegistrationError {}
+
+/// One orientation the pen was held in during registration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hold {
+    /// which work frame axes the pen's tip and top pointed along
+    pub pose: AxisMapping,
+    /// the gravity vector measured in the sensor's frame, pointing up
+    pub gravity: Vector3<f32>,
+}
+
+impl Hold {
+    /// Which way is up in the tool frame, while held like this
+    fn up_in_tool(&self) -> Vector3<f32> {
+        up_in_tool(self.pose)
+    }
+}
+
+/// Which way is up in the tool frame, while the pen's tip and top point along `pose`'s work axes
+fn up_in_tool(pose: AxisMapping) -> Vector3<f32> {
+    let up = Vector3 {
+        x: 0.,
+        y: 0.,
+        z: 1.,
+    };
+    orientation::rotate(orientation::inverse(pose::tool_frame(pose)), up)
+}
+
+/// The rotation best taking each of `from` onto the matching `to`, with Horn's quaternion method
+fn best_rotation(pairs: &[(Vector3<f32>, Vector3<f32>)]) -> Quaternion<f32> {
+    let mut s = [[0_f32; 3]; 3];
+    let mut scale = 0.;
+    for (from, to) in pairs {
+        let from = [from.x, from.y, from.z];
+        let to = [to.x, to.y, to.z];
+        for (row, from) in s.iter_mut().zip(from) {
+            for (cell, to) in row.iter_mut().zip(to) {
+                *cell += from * to;
+            }
+        }
+        scale += norm(from.into()) * norm(to.into());
+    }
+    let [[xx, xy, xz], [yx, yy, yz], [zx, zy, zz]] = s;
+    // shifted by `scale` so every eigenvalue is positive, and power iteration finds the largest
+    let n = [
+        [scale + xx + yy + zz, yz - zy, zx - xz, xy - yx],
+        [yz - zy, scale + xx - yy - zz, xy + yx, zx + xz],
+        [zx - xz, xy + yx, scale - xx + yy - zz, yz + zy],
+        [xy - yx, zx + xz, yz + zy, scale - xx - yy + zz],
+    ];
+    let multiply = |q: [f32; 4]| -> [f32; 4] {
+        let mut out = [0.; 4];
+        for (out, row) in out.iter_mut().zip(n) {
+            *out = row.iter().zip(q).map(|(a, b)| a * b).sum();
+        }
+        out
+    };
+    let normalize = |q: [f32; 4]| -> [f32; 4] {
+        let length = q.iter().map(|c| c * c).sum::<f32>().sqrt();
+        if length > 0. {
+            q.map(|c| c / length)
+        } else {
+            q
+        }
+    };
+
+    // start from each axis, as any one of them might have nothing of the answer in it
+    let best = (0..4)
+        .map(|axis| {
+            let mut q = [0.; 4];
+            q[axis] = 1.;
+            for _ in 0..ITERATIONS {
+                q = normalize(multiply(q));
+            }
+            let fit: f32 = multiply(q).iter().zip(q).map(|(a, b)| a * b).sum();
+            (q, fit)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map_or([1., 0., 0., 0.], |(q, _)| q);
+    Quaternion {
+        v: Vector3 {
+            x: best[1],
+            y: best[2],
+            z: best[3],
+        },
+        s: best[0],
+    }
+}
+
+/// How the pen's sensor sits in it, and where the work frame is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Registration {
+    /// rotation from the sensor's frame into the tool frame
+    pub mounting: Quaternion<f32>,
+    /// which work frame axes the tip and top point along when sessions start
+    pub home: AxisMapping,
+    /// where the tip touches when sessions start, in the work frame, in meters. Taken as given,
+    /// registering doesn't measure it.
+    pub origin: Vector3<f32>,
+    /// how far the measured holds were from fitting the solution, RMS in degrees
+    pub residual: f32,
+}
+
+/// A [`Registration`] as saved, see [`Registration::save`]
+#[derive(Debug, Serialize, Deserialize)]
+struct Json {
+    /// `[x, y, z, w]`
+    mounting: [f32; 4],
+    home: String,
+    origin: [f32; 3],
+    residual_deg: f32,
+}
+
+impl Registration {
+    /// Axes to use with samples that have been [registered](Registration::apply): they're in the
+    /// tool frame, with the tip along z and the top along x
+    pub const MAPPING: AxisMapping = AxisMapping {
+        tip: SignedAxis::Z,
+        top: SignedAxis::X,
+    };
+
+    /// Solves for how the sensor is mounted from `holds`, of which the first is home: how the pen
+    /// is held when sessions start, with its tip on the given `origin`
+    ///
+    /// # Errors
+    /// With fewer than two holds, or holds too alike to tell which way the sensor is turned
+    pub fn solve(holds: &[Hold], origin: Vector3<f32>) -> Result<Self, RegistrationError> {
+        let Some(home) = holds.first() else {
+            return Err(RegistrationError::TooFewHolds(0));
+        };
+        if holds.len() < 2 {
+            return Err(RegistrationError::TooFewHolds(holds.len()));
+        }
+        // opposite holds only pin down the axis they share, so count how far a pair is from both
+        // alike and opposite
+        let spread = |up: &dyn Fn(&Hold) -> Vector3<f32>| {
+            holds
+                .iter()
+                .flat_map(|a| holds.iter().map(move |b| (a, b)))
+                .map(|(a, b)| {
+                    let angle = angle_between(up(a), up(b));
+                    angle.min(180. - angle)
+                })
+                .fold(0., f32::max)
+        };
+        if spread(&|hold| hold.gravity) < MIN_SPREAD || spread(&Hold::up_in_tool) < MIN_SPREAD {
+            return Err(RegistrationError::TooAlike);
+        }
+
+        let pairs: Vec<_> = holds
+            .iter()
+            .filter_map(|hold| Some((normalized(hold.gravity)?, hold.up_in_tool())))
+            .collect();
+        let mounting = best_rotation(&pairs);
+        #[allow(clippy::cast_precision_loss)]
+        let residual = (pairs
+            .iter()
+            .map(|(gravity, up)| {
+                angle_between(orientation::rotate(mounting, *gravity), *up).powi(2)
+            })
+            .sum::<f32>()
+            / pairs.len() as f32)
+            .sqrt();
+
+        Ok(Self {
+            mounting,
+            home: home.pose,
+            origin,
+            residual,
+        })
+    }
+
+    /// `sample` turned from the sensor's frame into the tool frame, or `None` if it no longer
+    /// fits in a sample. Use [`Registration::MAPPING`] with the result.
+    #[must_use]
+    pub fn apply(&self, sample: types::Sample) -> Option<types::Sample> {
+        Some(match sample {
+            types::Sample::Accel(accel) => types::Sample::Accel(
+                imu::AccelerationVector::from_m_s2(self.to_tool(accel.m_s2()))?,
+            ),
+            types::Sample::Grav(gravity) => {
+                types::Sample::Grav(imu::GravityVector::from_m_s2(self.to_tool(gravity.m_s2()))?)
+            }
+        })
+    }
+
+    /// `v` turned from the sensor's frame into the tool frame
+    #[must_use]
+    pub fn to_tool(&self, v: Vector3<f32>) -> Vector3<f32> {
+        orientation::rotate(self.mounting, v)
+    }
+
+    /// Where the world frame a session's poses are tracked in sits in the work frame, for a
+    /// session started at home
+    #[must_use]
+    pub fn placement(&self) -> Placement {
+        // tracking starts off oriented straight from gravity, with no heading, so whatever
+        // heading home has in the work frame is the world's
+        let start = orientation::from_up(up_in_tool(self.home));
+        Placement {
+            translation: self.origin,
+            rotation: orientation::compose(
+                pose::tool_frame(self.home),
+                orientation::inverse(start),
+            ),
+        }
+    }
+
+    /// Saves the registration to `path`, as JSON
+    ///
+    /// # Errors
+    /// If writing fails
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let json = Json {
+            mounting: [
+                self.mounting.v.x,
+                self.mounting.v.y,
+                self.mounting.v.z,
+                self.mounting.s,
+            ],
+            home: self.home.to_string(),
+            origin: [self.origin.x, self.origin.y, self.origin.z],
+            residual_deg: self.residual,
+        };
+        let text = serde_json::to_string_pretty(&json).map_err(io::Error::other)?;
+        fs::write(path, text + "\n")
+    }
+
+    /// Loads a registration [saved](Registration::save) to `path`. The mounting is scaled back
+    /// to unit length, in case it was edited by hand.
+    ///
+    /// # Errors
+    /// If reading fails, or the file isn't a registration or its mounting isn't a rotation
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let invalid = |error: String| io::Error::new(io::ErrorKind::InvalidData, error);
+        let json: Json = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|error| invalid(error.to_string()))?;
+        let [x, y, z, w] = json.mounting;
+        let length = (x * x + y * y + z * z + w * w).sqrt();
+        if !(length > 0. && length.is_finite()) {
+            return Err(invalid(format!(
+                "mounting {:?} isn't a rotation",
+                json.mounting
+            )));
+        }
+        let [ox, oy, oz] = json.origin;
+        Ok(Self {
+            mounting: orientation::normalize(Quaternion {
+                v: Vector3 { x, y, z },
+                s: w,
+            }),
+            home: json.home.parse().map_err(invalid)?,
+            origin: Vector3 {
+                x: ox,
+                y: oy,
+                z: oz,
+            },
+            residual: json.residual_deg,
+        })
+    }
+}
+
+/// `sample` [turned into the tool frame](Registration::apply) if there's a `registration`, or
+/// as it is if not
+#[must_use]
+pub fn register(
+    registration: Option<&Registration>,
+    sample: types::Sample,
+) -> Option<types::Sample> {
+    registration.map_or(Some(sample), |registration| registration.apply(sample))
+}
+
+/// `lever_arm`, given along the sensor's axes, along the tool frame's if there's a
+/// `registration` to go with [registered](register) samples
+#[must_use]
+pub fn register_lever_arm(registration: Option<&Registration>, lever_arm: LeverArm) -> LeverArm {
+    LeverArm(registration.map_or(lever_arm.0, |registration| {
+        registration.to_tool(lever_arm.0)
+    }))
+}
+
+/// [`register`] over all of `samples`, dropping those that no longer fit
+pub fn register_all<E>(
+    registration: Option<Registration>,
+    samples: impl Iterator<Item = Result<TimedSample, E>>,
+) -> impl Iterator<Item = Result<TimedSample, E>> {
+    samples.filter_map(move |timed| match timed {
+        Ok(timed) => register(registration.as_ref(), timed.sample)
+            .map(|sample| Ok(TimedSample { sample, ..timed })),
+        Err(error) => Some(Err(error)),
+    })
+}
+
+#[cfg(test)]
+mod test_registration {
+    use super::*;
+    use crate::{
+        angles::PenAngles,
+        test_util::{assert_close, infallible, samples, v},
+    };
+
+    /// Gravity as a sensor mounted by `mounting` would measure it, held as `pose`
+    fn hold(pose: &str, mounting: Quaternion<f32>) -> Hold {
+        let pose: AxisMapping = pose.parse().unwrap();
+        let up = up_in_tool(pose);
+        let gravity = orientation::rotate(orientation::inverse(mounting), up);
+        Hold {
+            pose,
+            gravity: v(gravity.x * 9.81, gravity.y * 9.81, gravity.z * 9.81),
+        }
+    }
+
+    #[test]
+    fn captures_still_gravity() {
+        let mut capture = Capture::new(0.3, Duration::from_millis(100));
+        let at = |millis: u64, sample: types::Sample| TimedSample {
+            timestamp: Duration::from_millis(millis),
+            sample,
+        };
+        let gravity = |z: i16| types::Sample::Grav(imu::GravityVector::new(0, 0, z));
+        assert_eq!(capture.feed(&at(0, gravity(900))), None);
+        // moving starts it over
+        let shake = types::Sample::Accel(imu::AccelerationVector::new(100, 0, 0));
+        assert_eq!(capture.feed(&at(50, shake)), None);
+        assert_eq!(capture.feed(&at(60, gravity(980))), None);
+        let still = types::Sample::Accel(imu::AccelerationVector::new(5, 0, 0));
+        assert_eq!(capture.feed(&at(100, still)), None);
+        assert_eq!(capture.feed(&at(110, gravity(982))), None);
+        assert_close(
+            capture.feed(&at(160, gravity(981))).unwrap(),
+            v(0., 0., 9.81),
+            1e-3,
+        );
+    }
+
+    #[test]
+    fn solves_the_mounting() {
+        // a board turned at some odd angle inside the pen
+        let mounting = orientation::from_euler(0.3, -1.1, 2.5);
+        let holds = [
+            hold("-z,+x", mounting),
+            hold("+x,+z", mounting),
+            hold("+y,-x", mounting),
+        ];
+        let registration = Registration::solve(&holds, v(0.1, 0.2, 0.)).unwrap();
+        assert!(registration.residual < 0.1, "{}", registration.residual);
+        for axis in [v(1., 0., 0.), v(0., 1., 0.), v(0., 0., 1.)] {
+            assert_close(
+                orientation::rotate(registration.mounting, axis),
+                orientation::rotate(mounting, axis),
+                1e-3,
+            );
+        }
+
+        // registered gravity held tip down reads as vertical
+        let tip_down =
+            types::Sample::Grav(imu::GravityVector::from_m_s2(holds[0].gravity).unwrap());
+        let Some(types::Sample::Grav(registered)) = registration.apply(tip_down) else {
+            panic!("gravity stays gravity");
+        };
+        let angles = PenAngles::from_gravity(registered.m_s2(), Registration::MAPPING).unwrap();
+        assert!(angles.tilt < 1., "{}", angles.tilt);
+    }
+
+    #[test]
+    fn places_the_world() {
+        let registration = Registration {
+            mounting: Placement::IDENTITY.rotation,
+            home: "+y,+z".parse().unwrap(),
+            origin: v(0.1, 0.2, 0.3),
+            residual: 0.,
+        };
+        let placement = registration.placement();
+        assert_eq!(placement.translation, v(0.1, 0.2, 0.3));
+        // starting at home, the pen points along work y: the world's starting orientation has
+        // to come out that way
+        let start = orientation::from_up(up_in_tool(registration.home));
+        let tip = orientation::rotate(
+            placement.rotation,
+            orientation::rotate(start, v(0., 0., 1.)),
+        );
+        assert_close(tip, v(0., 1., 0.), 1e-5);
+        // and up stays up
+        assert_close(
+            orientation::rotate(placement.rotation, v(0., 0., 1.)),
+            v(0., 0., 1.),
+            1e-5,
+        );
+    }
+
+    #[test]
+    fn needs_different_holds() {
+        let mounting = Placement::IDENTITY.rotation;
+        assert_eq!(
+            Registration::solve(&[hold("-z,+x", mounting)], v(0., 0., 0.)),
+            Err(RegistrationError::TooFewHolds(1))
+        );
+        // turning around the vertical doesn't change gravity
+        assert_eq!(
+            Registration::solve(
+                &[hold("+x,+z", mounting), hold("+y,+z", mounting)],
+                v(0., 0., 0.)
+            ),
+            Err(RegistrationError::TooAlike)
+        );
+        // nor does turning the pen over
+        assert_eq!(
+            Registration::solve(
+                &[hold("-z,+x", mounting), hold("+z,+x", mounting)],
+                v(0., 0., 0.)
+            ),
+            Err(RegistrationError::TooAlike)
+        );
+    }
+
+    #[test]
+    fn registers_samples() {
+        let registration = Registration {
+            mounting: orientation::from_euler(0., 0., std::f32::consts::FRAC_PI_2),
+            home: "-z,+x".parse().unwrap(),
+            origin: v(0., 0., 0.),
+            residual: 0.,
+        };
+        let recorded = samples([100], Some(imu::GravityVector::new(0, 0, 981)));
+        let registered: Vec<_> = register_all(Some(registration), infallible(recorded.clone()))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(registered.len(), 2);
+        assert_eq!(registered[0].timestamp, recorded[0].timestamp);
+        let Some(types::Sample::Accel(accel)) = registration.apply(recorded[0].sample) else {
+            panic!("accel stays accel");
+        };
+        assert_eq!(registered[0].sample, types::Sample::Accel(accel));
+        assert_close(accel.m_s2(), v(0., 1., 0.), 1e-2);
+
+        // without a registration nothing changes
+        let unregistered: Vec<_> = register_all(None, infallible(recorded.clone()))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(unregistered, recorded);
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let mounting = orientation::from_euler(0.5, 0., 0.);
+        let registration = Registration::solve(
+            &[hold("-z,+x", mounting), hold("+x,+z", mounting)],
+            v(0.4, 0., 0.02),
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join("pensel-test-registration.json");
+        registration.save(&path).unwrap();
+        let loaded = Registration::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, registration);
+
+        fs::write(&path, "{}").unwrap();
+        assert!(Registration::load(&path).is_err());
+
+        // hand edited mountings are brought back to unit length, or refused if they can't be
+        let json = |mounting: &str| {
+            format!(
+                r#"{{"mounting": {}, "home": "-z,+x", "origin": [0, 0, 0], "residual_deg": 0}}"#,
+                mounting
+            )
+        };
+        fs::write(&path, json("[0, 0, 0, 2]")).unwrap();
+        assert_eq!(
+            Registration::load(&path).unwrap().mounting,
+            Placement::IDENTITY.rotation
+        );
+        fs::write(&path, json("[0, 0, 0, 0]")).unwrap();
+        assert!(Registration::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+//! Plays a recorded session back as if it were a live pen.
+//!
+//! [`Replay`] is a [`SampleSource`], so anything that can stream from a [`PenselSerial`] can
+//! stream from a recording instead.
+//!
+//! [`PenselSerial`]: crate::comms::PenselSerial
+use heapless::spsc::Producer;
+use std::{
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    comms::SampleSource,
+    recording::{Header, RecordingError, RecordingReader},
+    types,
+};
+
+/// Longest we sleep before checking whether we've been asked to stop
+const MAX_SLEEP: Duration = Duration::from_millis(20);
+/// How long we wait for a full queue to be drained before trying again
+const QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(1);
+
+/// How fast a recording is played back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    /// Scales the recorded timing: `1.0` is real-time, `2.0` twice as fast
+    Scaled(f64),
+    /// As fast as the consumer keeps up, ignoring the recorded timing
+    Unlimited,
+}
+
+impl Speed {
+    /// Plays back with the recorded timing
+    pub const REAL_TIME: Self = Self::Scaled(1.0);
+
+    /// Name of [`Speed::Unlimited`] on the command line
+    const UNLIMITED_NAME: &'static str = "max";
+    /// Name of [`Speed::REAL_TIME`] on the command line
+    const REAL_TIME_NAME: &'static str = "realtime";
+}
+
+impl FromStr for Speed {
+    type Err = String;
+
+    /// Parses `realtime`, `max`, or a factor like `2` or `0.5x`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            Self::UNLIMITED_NAME => Ok(Self::Unlimited),
+            Self::REAL_TIME_NAME => Ok(Self::REAL_TIME),
+            _ => {
+                let factor = s.strip_suffix('x').unwrap_or(s);
+                match f64::from_str(factor) {
+                    Ok(factor) if factor.is_finite() && factor > 0. => Ok(Self::Scaled(factor)),
+                    _ => Err(format!(
+                        "expected '{}', '{}' or a positive factor like '2x', got '{}'",
+                        Self::REAL_TIME_NAME,
+                        Self::UNLIMITED_NAME,
+                        s
+                    )),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unlimited => write!(f, "{}", Self::UNLIMITED_NAME),
+            Self::Scaled(factor) => write!(f, "{}x", factor),
+        }
+    }
+}
+
+/// A recording being played back as a [`SampleSource`]
+pub struct Replay<R: BufRead> {
+    reader: RecordingReader<R>,
+    speed: Speed,
+}
+
+impl Replay<BufReader<File>> {
+    /// Opens the recording at `path` to be played back at `speed`.
+    ///
+    /// # Errors
+    /// If the file can't be opened or isn't a valid recording.
+    pub fn open(path: impl AsRef<Path>, speed: Speed) -> Result<Self, RecordingError> {
+        Ok(Self::new(RecordingReader::open(path)?, speed))
+    }
+}
+
+impl<R: BufRead> Replay<R> {
+    /// Plays back the samples left in `reader` at `speed`
+    #[must_use]
+    pub const fn new(reader: RecordingReader<R>, speed: Speed) -> Self {
+        Self { reader, speed }
+    }
+
+    /// The header of the recording being played
+    #[must_use]
+    pub const fn header(&self) -> &Header {
+        self.reader.header()
+    }
+
+    /// Sleeps until `deadline`, waking up regularly to check `should_run`.
+    ///
+    /// # Returns
+    /// `false` if we were asked to stop before reaching the deadline
+    fn sleep_until(deadline: Instant, should_run: &Arc<AtomicBool>) -> bool {
+        loop {
+            if !should_run.as_ref().load(Ordering::Acquire) {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::sleep((deadline - now).min(MAX_SLEEP));
+        }
+    }
+
+    /// Enqueues `item`, waiting for the consumer to make room rather than dropping it like a live
+    /// pen would have to. A recording can always wait.
+    ///
+    /// # Returns
+    /// `false` if we were asked to stop before there was room
+    fn enqueue<T, const N: usize>(
+        queue: &mut Producer<T, N>,
+        mut item: T,
+        should_run: &Arc<AtomicBool>,
+    ) -> bool {
+        loop {
+            match queue.enqueue(item) {
+                Ok(()) => return true,
+                Err(rejected) => item = rejected,
+            }
+            if !should_run.as_ref().load(Ordering::Acquire) {
+                return false;
+            }
+            thread::sleep(QUEUE_FULL_BACKOFF);
+        }
+    }
+}
+
+impl<R: BufRead + Send> SampleSource for Replay<R> {
+    fn stream_until(
+        &mut self,
+        mut accel_queue: Producer<types::imu::AccelerationVector, { types::ACC_QUEUE_SIZE }>,
+        mut grav_queue: Producer<types::imu::GravityVector, { types::GRAV_QUEUE_SIZE }>,
+        should_run: &Arc<AtomicBool>,
+    ) {
+        let started = Instant::now();
+
+        for timed in self.reader.by_ref() {
+            if !should_run.as_ref().load(Ordering::Acquire) {
+                return;
+            }
+            let timed = match timed {
+                Ok(timed) => timed,
+                Err(error) => {
+                    log::error!("stopping replay: {}", error);
+                    return;
+                }
+            };
+
+            if let Speed::Scaled(factor) = self.speed {
+                let deadline = started + timed.timestamp.div_f64(factor);
+                if !Self::sleep_until(deadline, should_run) {
+                    return;
+                }
+            }
+
+            let queued = match timed.sample {
+                types::Sample::Accel(acc) => Self::enqueue(&mut accel_queue, acc, should_run),
+                types::Sample::Grav(grav) => Self::enqueue(&mut grav_queue, grav, should_run),
+            };
+            if !queued {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_replay {
+    use super::*;
+    use heapless::spsc::Queue;
+    use std::ptr::addr_of_mut;
+
+    const RECORDING: &str = "# pensel-recording: 2\n\
+                             # streams: accel,gravity\n\
+                             0.000000 A:1,2,3\n\
+                             0.010000 G:4,5,6\n\
+                             0.020000 A:7,8,9\n";
+
+    fn replay(speed: Speed) -> Replay<&'static [u8]> {
+        Replay::new(RecordingReader::new(RECORDING.as_bytes()).unwrap(), speed)
+    }
+
+    #[test]
+    fn speed_from_str() {
+        assert_eq!("realtime".parse(), Ok(Speed::REAL_TIME));
+        assert_eq!("max".parse(), Ok(Speed::Unlimited));
+        assert_eq!("2x".parse(), Ok(Speed::Scaled(2.)));
+        assert_eq!("0.5".parse(), Ok(Speed::Scaled(0.5)));
+        assert!("0x".parse::<Speed>().is_err());
+        assert!("-1".parse::<Speed>().is_err());
+        assert!("fast".parse::<Speed>().is_err());
+    }
+
+    #[test]
+    fn replays_everything() {
+        static mut A_QUEUE: Queue<types::imu::AccelerationVector, { types::ACC_QUEUE_SIZE }> =
+            Queue::new();
+        static mut G_QUEUE: Queue<types::imu::GravityVector, { types::GRAV_QUEUE_SIZE }> =
+            Queue::new();
+        let (a_producer, mut a_consumer) = unsafe { (*addr_of_mut!(A_QUEUE)).split() };
+        let (g_producer, mut g_consumer) = unsafe { (*addr_of_mut!(G_QUEUE)).split() };
+
+        let should_run = Arc::new(AtomicBool::new(true));
+        replay(Speed::Unlimited).stream_until(a_producer, g_producer, &should_run);
+
+        assert_eq!(
+            a_consumer.dequeue(),
+            Some(types::imu::AccelerationVector::new(1, 2, 3))
+        );
+        assert_eq!(
+            a_consumer.dequeue(),
+            Some(types::imu::AccelerationVector::new(7, 8, 9))
+        );
+        assert_eq!(a_consumer.dequeue(), None);
+        assert_eq!(
+            g_consumer.dequeue(),
+            Some(types::imu::GravityVector::new(4, 5, 6))
+        );
+        assert_eq!(g_consumer.dequeue(), None);
+    }
+
+    #[test]
+    fn keeps_recorded_timing() {
+        static mut A_QUEUE: Queue<types::imu::AccelerationVector, { types::ACC_QUEUE_SIZE }> =
+            Queue::new();
+        static mut G_QUEUE: Queue<types::imu::GravityVector, { types::GRAV_QUEUE_SIZE }> =
+            Queue::new();
+        let (a_producer, _) = unsafe { (*addr_of_mut!(A_QUEUE)).split() };
+        let (g_producer, _) = unsafe { (*addr_of_mut!(G_QUEUE)).split() };
+
+        let should_run = Arc::new(AtomicBool::new(true));
+        let started = Instant::now();
+        replay(Speed::REAL_TIME).stream_until(a_producer, g_producer, &should_run);
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn stops_when_asked() {
+        static mut A_QUEUE: Queue<types::imu::AccelerationVector, { types::ACC_QUEUE_SIZE }> =
+            Queue::new();
+        static mut G_QUEUE: Queue<types::imu::GravityVector, { types::GRAV_QUEUE_SIZE }> =
+            Queue::new();
+        let (a_producer, mut a_consumer) = unsafe { (*addr_of_mut!(A_QUEUE)).split() };
+        let (g_producer, _) = unsafe { (*addr_of_mut!(G_QUEUE)).split() };
+
+        let should_run = Arc::new(AtomicBool::new(false));
+        replay(Speed::Unlimited).stream_until(a_producer, g_producer, &should_run);
+
+        assert_eq!(a_consumer.dequeue(), None);
+    }
+}
@@ -0,0 +1,710 @@
+//! Summarises a recording as a self-contained SVG image or HTML page, e.g. to attach to QA
+//! tickets: every axis over time, how the pen was tilted, the strokes found, how regularly
+//! samples came in, and which pen it was.
+use pensel_types::mint::Vector3;
+use std::{
+    fmt::{self, Write},
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    angles::PenAngles,
+    recording::{Header, Stream, TimedSample},
+    spectrum,
+    strokes::{self, Stroke},
+    types,
+};
+
+/// Default width of the tilt histogram's bins, in degrees
+pub const DEFAULT_TILT_BIN: f32 = 10.;
+/// Time series are thinned out to about this many points, which is plenty for the width
+const MAX_POINTS: usize = 1500;
+/// A pause between samples longer than this many sample periods counts as a gap
+const GAP_PERIODS: f32 = 1.5;
+
+const WIDTH: u32 = 900;
+const CHART_HEIGHT: u32 = 200;
+const MARGIN: u32 = 50;
+const AXIS_COLORS: [&str; 3] = ["#e53935", "#43a047", "#8e24aa"];
+const STROKE_COLOR: &str = "#fff3c4";
+
+/// What a report is written as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// A page with the charts and tables
+    Html,
+    /// A single image, with the tables as text
+    Svg,
+}
+
+impl ReportFormat {
+    /// Guesses the format from `path`'s extension, defaulting to [`ReportFormat::Html`]
+    #[must_use]
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("svg") => Self::Svg,
+            _ => Self::Html,
+        }
+    }
+
+    /// The file extension reports in this format get
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::Svg => "svg",
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(Self::Html),
+            "svg" => Ok(Self::Svg),
+            _ => Err(format!(
+                "unknown report format '{}', expected 'html' or 'svg'",
+                s
+            )),
+        }
+    }
+}
+
+/// How regularly one stream's samples came in
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamStats {
+    pub stream: Stream,
+    pub samples: usize,
+    /// typical samples per second, `None` with fewer than two samples
+    pub rate: Option<f32>,
+    /// how many times samples stopped for longer than they should have
+    pub gaps: usize,
+    /// roughly how many samples went missing in those gaps
+    pub missing: usize,
+    /// the longest time between two samples
+    pub longest_gap: Duration,
+}
+
+impl StreamStats {
+    fn new(stream: Stream, timestamps: &[Duration]) -> Self {
+        let rate = spectrum::estimate_sample_rate(timestamps);
+        let mut stats = Self {
+            stream,
+            samples: timestamps.len(),
+            rate,
+            gaps: 0,
+            missing: 0,
+            longest_gap: Duration::ZERO,
+        };
+        for pair in timestamps.windows(2) {
+            let interval = pair[1].saturating_sub(pair[0]);
+            stats.longest_gap = stats.longest_gap.max(interval);
+            let Some(rate) = rate else {
+                continue;
+            };
+            let periods = interval.as_secs_f32() * rate;
+            if periods > GAP_PERIODS {
+                stats.gaps += 1;
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                {
+                    stats.missing += periods.round() as usize - 1;
+                }
+            }
+        }
+        stats
+    }
+}
+
+/// Everything that goes into a report
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub header: Header,
+    /// time of the last sample
+    pub duration: Duration,
+    /// one per stream that has samples
+    pub streams: Vec<StreamStats>,
+    /// linear acceleration over time, in m/s²
+    pub accel: Vec<(Duration, Vector3<f32>)>,
+    /// the gravity vector over time, in m/s²
+    pub gravity: Vec<(Duration, Vector3<f32>)>,
+    /// how many gravity samples had their tilt in each bin, starting from 0°
+    pub tilt_histogram: Vec<usize>,
+    /// width of [`Report::tilt_histogram`]'s bins, in degrees
+    pub tilt_bin: f32,
+    pub strokes: Vec<Stroke>,
+}
+
+impl Report {
+    /// Reads all of `samples`, finding strokes with `config` and binning tilt every `tilt_bin`
+    /// degrees
+    ///
+    /// # Errors
+    /// The first error reading `samples`
+    pub fn build<E>(
+        header: Header,
+        samples: impl IntoIterator<Item = Result<TimedSample, E>>,
+        config: strokes::Config,
+        tilt_bin: f32,
+    ) -> Result<Self, E> {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let bins = (180. / tilt_bin).ceil().max(1.) as usize;
+        let mut report = Self {
+            header,
+            duration: Duration::ZERO,
+            streams: Vec::new(),
+            accel: Vec::new(),
+            gravity: Vec::new(),
+            tilt_histogram: vec![0; bins],
+            tilt_bin,
+            strokes: Vec::new(),
+        };
+
+        let mut segmenter = strokes::Segmenter::new(config);
+        for timed in samples {
+            let timed = timed?;
+            report.duration = report.duration.max(timed.timestamp);
+            report.strokes.extend(segmenter.feed(&timed));
+            match timed.sample {
+                types::Sample::Accel(accel) => report.accel.push((timed.timestamp, accel.m_s2())),
+                types::Sample::Grav(gravity) => {
+                    let gravity = gravity.m_s2();
+                    report.gravity.push((timed.timestamp, gravity));
+                    if let Some(angles) = PenAngles::from_gravity(gravity, config.axes) {
+                        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                        let bin = ((angles.tilt / tilt_bin) as usize).min(bins - 1);
+                        report.tilt_histogram[bin] += 1;
+                    }
+                }
+            }
+        }
+        report.strokes.extend(segmenter.finish(report.duration));
+
+        for (stream, series) in [
+            (Stream::Accel, &report.accel),
+            (Stream::Gravity, &report.gravity),
+        ] {
+            if !series.is_empty() {
+                let timestamps: Vec<Duration> = series.iter().map(|(t, _)| *t).collect();
+                report.streams.push(StreamStats::new(stream, &timestamps));
+            }
+        }
+        Ok(report)
+    }
+
+    /// The report written out as `format`
+    #[must_use]
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Html => self.html(),
+            ReportFormat::Svg => self.svg(),
+        }
+    }
+
+    /// The report as a single SVG image
+    #[must_use]
+    pub fn svg(&self) -> String {
+        let mut lines = vec![self.title()];
+        lines.extend(
+            self.device_rows()
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value)),
+        );
+        lines.push(String::new());
+        lines.extend(self.streams.iter().map(|stats| {
+            format!(
+                "{}: {} samples at {} Hz, {} gaps, ~{} missing, longest {:.3}s",
+                stats.stream.name(),
+                stats.samples,
+                stats
+                    .rate
+                    .map_or_else(|| "-".to_owned(), |rate| format!("{:.1}", rate)),
+                stats.gaps,
+                stats.missing,
+                stats.longest_gap.as_secs_f64()
+            )
+        }));
+        lines.push(String::new());
+        lines.push(format!("{} strokes", self.strokes.len()));
+        lines.extend(
+            self.strokes
+                .iter()
+                .enumerate()
+                .map(|(index, stroke)| format!("{:>3} {}", index + 1, stroke)),
+        );
+
+        let text_height = 18 * u32::try_from(lines.len()).unwrap_or(u32::MAX) + 20;
+        let charts = [
+            self.time_series("Linear acceleration (m/s²)", &self.accel),
+            self.time_series("Gravity (m/s²)", &self.gravity),
+            self.tilt_chart(),
+        ];
+        let height = text_height + (CHART_HEIGHT + 20) * 3;
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"monospace\" font-size=\"13\">\n<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n",
+            WIDTH, height, WIDTH, height
+        );
+        for (index, line) in lines.iter().enumerate() {
+            let y = 24 + 18 * u32::try_from(index).unwrap_or(u32::MAX);
+            let weight = if index == 0 {
+                " font-weight=\"bold\""
+            } else {
+                ""
+            };
+            writeln!(
+                svg,
+                "<text x=\"10\" y=\"{}\"{}>{}</text>",
+                y,
+                weight,
+                escape(line)
+            )
+            .ok();
+        }
+        for (index, chart) in (0_u32..).zip(charts) {
+            let y = text_height + (CHART_HEIGHT + 20) * index;
+            writeln!(svg, "<g transform=\"translate(0,{})\">{}</g>", y, chart).ok();
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// The report as a self-contained HTML page
+    #[must_use]
+    pub fn html(&self) -> String {
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\nbody {{ font-family: sans-serif; margin: 2em; }}\ntable {{ border-collapse: collapse; margin-bottom: 1em; }}\nth, td {{ border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }}\ntd.number {{ text-align: right; font-family: monospace; }}\n</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+            escape(&self.title()),
+            escape(&self.title())
+        );
+
+        html.push_str("<h2>Device</h2>\n<table>\n");
+        for (name, value) in self.device_rows() {
+            writeln!(
+                html,
+                "<tr><th>{}</th><td>{}</td></tr>",
+                escape(name),
+                escape(&value)
+            )
+            .ok();
+        }
+        html.push_str("</table>\n");
+
+        html.push_str("<h2>Streams</h2>\n<table>\n<tr><th>stream</th><th>samples</th><th>rate (Hz)</th><th>gaps</th><th>missing</th><th>longest gap (s)</th></tr>\n");
+        for stats in &self.streams {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{:.3}</td></tr>",
+                stats.stream.name(),
+                stats.samples,
+                stats.rate.map_or_else(|| "-".to_owned(), |rate| format!("{:.1}", rate)),
+                stats.gaps,
+                stats.missing,
+                stats.longest_gap.as_secs_f64()
+            )
+            .ok();
+        }
+        html.push_str("</table>\n");
+
+        for chart in [
+            self.time_series("Linear acceleration (m/s²)", &self.accel),
+            self.time_series("Gravity (m/s²)", &self.gravity),
+            self.tilt_chart(),
+        ] {
+            writeln!(
+                html,
+                "<div><svg width=\"{}\" height=\"{}\" font-family=\"monospace\" font-size=\"13\">{}</svg></div>",
+                WIDTH, CHART_HEIGHT, chart
+            )
+            .ok();
+        }
+
+        writeln!(html, "<h2>Strokes ({})</h2>", self.strokes.len()).ok();
+        html.push_str("<table>\n<tr><th>#</th><th>start (s)</th><th>end (s)</th><th>duration (s)</th><th>peak (m/s²)</th><th>rms (m/s²)</th><th>tilt (°)</th></tr>\n");
+        for (index, stroke) in self.strokes.iter().enumerate() {
+            writeln!(
+                html,
+                "<tr><td class=\"number\">{}</td><td class=\"number\">{:.3}</td><td class=\"number\">{:.3}</td><td class=\"number\">{:.3}</td><td class=\"number\">{:.2}</td><td class=\"number\">{:.2}</td><td class=\"number\">{}</td></tr>",
+                index + 1,
+                stroke.start.as_secs_f64(),
+                stroke.end.as_secs_f64(),
+                stroke.duration().as_secs_f64(),
+                stroke.peak_accel,
+                stroke.rms_accel,
+                stroke.mean_tilt.map_or_else(|| "-".to_owned(), |tilt| format!("{:.1}", tilt))
+            )
+            .ok();
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+
+    fn title(&self) -> String {
+        format!(
+            "pensel {} recording, {:.1}s",
+            self.header.device.pen_id,
+            self.duration.as_secs_f64()
+        )
+    }
+
+    fn device_rows(&self) -> Vec<(&'static str, String)> {
+        let device = &self.header.device;
+        let streams: Vec<&str> = self
+            .header
+            .streams
+            .iter()
+            .map(|stream| stream.name())
+            .collect();
+        vec![
+            ("pen", device.pen_id.clone()),
+            ("firmware", device.firmware.clone()),
+            (
+                "axis map",
+                if device.axis_map.is_empty() {
+                    "unknown".to_owned()
+                } else {
+                    device.axis_map.clone()
+                },
+            ),
+            (
+                "calibration",
+                if device.calibration.is_empty() {
+                    "unknown".to_owned()
+                } else {
+                    device
+                        .calibration
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect()
+                },
+            ),
+            ("started", Utc(self.header.start).to_string()),
+            ("streams", streams.join(", ")),
+            ("format version", self.header.version.to_string()),
+        ]
+    }
+
+    /// A chart of every axis of `series` over the whole recording, with strokes shaded in
+    fn time_series(&self, title: &str, series: &[(Duration, Vector3<f32>)]) -> String {
+        let plot_width = f64::from(WIDTH - 2 * MARGIN);
+        let plot_height = f64::from(CHART_HEIGHT - 2 * MARGIN / 2 - 10);
+        let (left, top) = (f64::from(MARGIN), 25.);
+        let duration = self.duration.as_secs_f64().max(1e-3);
+
+        let (mut low, mut high) = series
+            .iter()
+            .flat_map(|(_, v)| [v.x, v.y, v.z])
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), v| {
+                (low.min(v), high.max(v))
+            });
+        if !low.is_finite() {
+            (low, high) = (-1., 1.);
+        }
+        if high - low < 1e-3 {
+            (low, high) = (low - 0.5, high + 0.5);
+        }
+        let x = |t: Duration| left + t.as_secs_f64() / duration * plot_width;
+        let y = |v: f32| top + f64::from((high - v) / (high - low)) * plot_height;
+
+        let mut chart = chart_frame(title, left, top, plot_width, plot_height);
+        for stroke in &self.strokes {
+            writeln!(
+                chart,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"/>",
+                x(stroke.start),
+                top,
+                (x(stroke.end) - x(stroke.start)).max(1.),
+                plot_height,
+                STROKE_COLOR
+            )
+            .ok();
+        }
+
+        let step = series.len().div_ceil(MAX_POINTS).max(1);
+        for (axis, color) in AXIS_COLORS.iter().enumerate() {
+            let points: Vec<String> = series
+                .iter()
+                .step_by(step)
+                .map(|(t, v)| {
+                    let value = [v.x, v.y, v.z][axis];
+                    format!("{:.1},{:.1}", x(*t), y(value))
+                })
+                .collect();
+            writeln!(
+                chart,
+                "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1\" points=\"{}\"/>",
+                color,
+                points.join(" ")
+            )
+            .ok();
+        }
+
+        axis_labels(
+            &mut chart,
+            left,
+            top,
+            plot_width,
+            plot_height,
+            [format!("{:.2}", high), format!("{:.2}", low)],
+            ["0s".to_owned(), format!("{:.1}s", duration)],
+        );
+        for (index, (name, color)) in ["x", "y", "z"].iter().zip(AXIS_COLORS).enumerate() {
+            writeln!(
+                chart,
+                "<text x=\"{:.1}\" y=\"18\" fill=\"{}\">{}</text>",
+                left + plot_width - 60. + 20. * index as f64,
+                color,
+                name
+            )
+            .ok();
+        }
+        chart
+    }
+
+    /// A bar chart of how long the pen spent at each tilt
+    fn tilt_chart(&self) -> String {
+        let plot_width = f64::from(WIDTH - 2 * MARGIN);
+        let plot_height = f64::from(CHART_HEIGHT - 2 * MARGIN / 2 - 10);
+        let (left, top) = (f64::from(MARGIN), 25.);
+        let most = self
+            .tilt_histogram
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        #[allow(clippy::cast_precision_loss)]
+        let bar_width = plot_width / self.tilt_histogram.len() as f64;
+
+        let mut chart = chart_frame("Tilt from vertical (°)", left, top, plot_width, plot_height);
+        for (index, count) in self.tilt_histogram.iter().enumerate() {
+            #[allow(clippy::cast_precision_loss)]
+            let height = plot_height * *count as f64 / most as f64;
+            #[allow(clippy::cast_precision_loss)]
+            let x = left + bar_width * index as f64;
+            writeln!(
+                chart,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#1e88e5\"><title>{}-{}°: {}</title></rect>",
+                x + 1.,
+                top + plot_height - height,
+                (bar_width - 2.).max(1.),
+                height,
+                self.tilt_bin * index as f32,
+                self.tilt_bin * (index + 1) as f32,
+                count
+            )
+            .ok();
+        }
+        axis_labels(
+            &mut chart,
+            left,
+            top,
+            plot_width,
+            plot_height,
+            [most.to_string(), "0".to_owned()],
+            [
+                "0°".to_owned(),
+                format!("{}°", self.tilt_bin * self.tilt_histogram.len() as f32),
+            ],
+        );
+        chart
+    }
+}
+
+/// The title and outline of a chart
+fn chart_frame(title: &str, left: f64, top: f64, width: f64, height: f64) -> String {
+    format!(
+        "<text x=\"{}\" y=\"18\" font-weight=\"bold\">{}</text>\n<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"#999\"/>\n",
+        left,
+        escape(title),
+        left,
+        top,
+        width,
+        height
+    )
+}
+
+/// Labels the top and bottom of a chart's y axis, and the start and end of its x axis
+fn axis_labels(
+    chart: &mut String,
+    left: f64,
+    top: f64,
+    width: f64,
+    height: f64,
+    [y_top, y_bottom]: [String; 2],
+    [x_start, x_end]: [String; 2],
+) {
+    writeln!(
+        chart,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n<text x=\"{}\" y=\"{}\">{}</text>\n<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>",
+        left - 4.,
+        top + 10.,
+        y_top,
+        left - 4.,
+        top + height,
+        y_bottom,
+        left,
+        top + height + 16.,
+        x_start,
+        left + width,
+        top + height + 16.,
+        x_end
+    )
+    .ok();
+}
+
+/// Escapes `text` for use in XML and HTML
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Displays a wall clock time as UTC, like "2024-03-01 12:34:56 UTC"
+struct Utc(SystemTime);
+
+impl fmt::Display for Utc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self
+            .0
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let (days, time) = (secs / 86_400, secs % 86_400);
+
+        // days since the epoch to a calendar date, from Howard Hinnant's `civil_from_days`
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let day_of_era = z - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            year,
+            month,
+            day,
+            time / 3600,
+            time % 3600 / 60,
+            time % 60
+        )
+    }
+}
+
+#[cfg(test)]
+mod test_report {
+    use super::*;
+    use crate::{
+        test_util::{infallible, samples},
+        types::{imu, SAMPLE_PERIOD},
+    };
+
+    fn header() -> Header {
+        Header {
+            version: 2,
+            device: types::DeviceInfo {
+                pen_id: "pen<7>".to_owned(),
+                firmware: "1.2.3".to_owned(),
+                axis_map: "P1".to_owned(),
+                calibration: vec![1, 0xab],
+            },
+            start: SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_296_496),
+            streams: Stream::ALL.to_vec(),
+        }
+    }
+
+    /// A report on accel and gravity for `steps` periods, skipping those in `missing`, with a
+    /// burst of motion in the middle and the pen held vertical
+    fn build(steps: u32, missing: &[u32]) -> Report {
+        let accel = (0..steps).map(|step| {
+            if (steps / 3..steps / 2).contains(&step) {
+                300
+            } else {
+                0
+            }
+        });
+        let mut samples = samples(accel, Some(imu::GravityVector::new(-981, 0, 0)));
+        samples.retain(|timed| {
+            let step = timed.timestamp.as_millis() / SAMPLE_PERIOD.as_millis();
+            !missing.iter().any(|missing| u128::from(*missing) == step)
+        });
+        Report::build(
+            header(),
+            infallible(samples),
+            strokes::Config::default(),
+            DEFAULT_TILT_BIN,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn counts_gaps() {
+        let report = build(300, &[100, 101, 102, 200]);
+        assert_eq!(report.streams.len(), 2);
+        let accel = &report.streams[0];
+        assert_eq!(accel.stream, Stream::Accel);
+        assert_eq!(accel.samples, 296);
+        assert!((accel.rate.unwrap() - 100.).abs() < 0.5);
+        assert_eq!(accel.gaps, 2);
+        assert_eq!(accel.missing, 4);
+        assert_eq!(accel.longest_gap, SAMPLE_PERIOD * 4);
+        assert_eq!(report.duration, SAMPLE_PERIOD * 299 + SAMPLE_PERIOD / 2);
+    }
+
+    #[test]
+    fn bins_tilt_and_finds_strokes() {
+        let report = build(300, &[]);
+        assert_eq!(report.tilt_histogram.len(), 18);
+        // gravity along -x with the tip along +x: pointing straight down
+        assert_eq!(report.tilt_histogram[0], 300);
+        assert_eq!(report.tilt_histogram.iter().sum::<usize>(), 300);
+        assert_eq!(report.strokes.len(), 1);
+    }
+
+    #[test]
+    fn renders() {
+        let report = build(300, &[150]);
+        let svg = report.render(ReportFormat::Svg);
+        assert!(svg.starts_with("<svg xmlns"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert_eq!(svg.matches("<polyline").count(), 6);
+        assert!(svg.contains("pen&lt;7&gt;"));
+        assert!(!svg.contains("pen<7>"));
+
+        let html = report.render(ReportFormat::Html);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("2024-03-01 12:34:56 UTC"));
+        assert!(html.contains("01ab"));
+        assert!(html.contains("<h2>Strokes (1)</h2>"));
+        assert_eq!(html.matches("<svg").count(), 3);
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(ReportFormat::from_path("qa/joint.svg"), ReportFormat::Svg);
+        assert_eq!(ReportFormat::from_path("qa/joint.htm"), ReportFormat::Html);
+        assert_eq!("svg".parse(), Ok(ReportFormat::Svg));
+        assert!("png".parse::<ReportFormat>().is_err());
+        assert_eq!(
+            Utc(SystemTime::UNIX_EPOCH + Duration::from_secs(951_782_400)).to_string(),
+            "2000-02-29 00:00:00 UTC"
+        );
+    }
+}
@@ -0,0 +1,491 @@
+//! Building blocks for the interactive pensel shell: completion of pensel's CLI, a line editor for
+//! the full screen shell, and sorting & pretty printing of what pensel sends back.
+use console::style;
+use pensel_types::cli;
+
+/// Prefix for commands handled by the shell itself rather than sent to pensel
+pub const META_PREFIX: char = ':';
+/// Shell command to change how streamed samples are shown
+pub const META_SAMPLES: &str = ":samples";
+/// Shell command to list the shell's own commands
+pub const META_HELP: &str = ":help";
+/// Shell command to leave the shell
+pub const META_QUIT: &str = ":quit";
+
+/// pensel's built in command for listing its commands
+const PENSEL_HELP: &str = "help";
+/// The prompt pensel's CLI draws
+const PENSEL_PROMPT: &str = "> ";
+
+/// How streamed samples are shown while the shell is running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleView {
+    /// Don't show samples at all
+    Off,
+    /// Periodically show the most recent sample of each stream on one line
+    Latest,
+    /// Show every sample as it arrives
+    All,
+}
+
+impl SampleView {
+    /// Every view, in the order they're listed to the user
+    pub const ALL: [Self; 3] = [Self::Off, Self::Latest, Self::All];
+
+    /// The name used to pick this view on the command line or with [`META_SAMPLES`]
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Latest => "latest",
+            Self::All => "all",
+        }
+    }
+}
+
+impl std::str::FromStr for SampleView {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|view| view.name() == s)
+            .ok_or_else(|| format!("unknown sample view '{}'", s))
+    }
+}
+
+/// Completes the word ending at `pos` in `line`. The first word completes to one of pensel's
+/// commands (or a shell command), later words starting with `-` complete to that command's
+/// `--arguments`.
+///
+/// # Returns
+/// Where the word being completed starts in `line`, and the possible replacements for it
+#[must_use]
+pub fn complete(line: &str, pos: usize) -> (usize, Vec<String>) {
+    let before_cursor = &line[..pos];
+    let word_start = before_cursor.rfind(' ').map_or(0, |space| space + 1);
+    let word = &before_cursor[word_start..];
+    let mut words = before_cursor[..word_start].split_whitespace();
+
+    let candidates: Vec<String> = match words.next() {
+        None => cli::COMMANDS
+            .iter()
+            .map(|command| command.name)
+            .chain([PENSEL_HELP, META_SAMPLES, META_HELP, META_QUIT])
+            .map(|name| format!("{} ", name))
+            .collect(),
+        Some(META_SAMPLES) => SampleView::ALL
+            .into_iter()
+            .map(|view| view.name().to_owned())
+            .collect(),
+        Some(command) if word.starts_with('-') => cli::COMMANDS
+            .iter()
+            .find(|candidate| candidate.name == command)
+            .map(|command| command.args)
+            .unwrap_or_default()
+            .iter()
+            .map(|arg| {
+                if arg.takes_value {
+                    format!("--{}=", arg.name)
+                } else {
+                    format!("--{} ", arg.name)
+                }
+            })
+            .collect(),
+        Some(_) => vec![],
+    };
+
+    let candidates = candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(word))
+        .collect();
+    (word_start, candidates)
+}
+
+/// The line being typed into the full screen shell, which draws its own prompt and so can't lean on
+/// a terminal line editor. Keeps the lines entered before, to step back through.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineInput {
+    line: String,
+    /// byte offset of the cursor into `line`
+    cursor: usize,
+    history: Vec<String>,
+    /// which `history` entry is shown, while stepping through it
+    browsing: Option<usize>,
+}
+
+impl LineInput {
+    /// What's been typed so far
+    #[must_use]
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// How many characters come before the cursor
+    #[must_use]
+    pub fn cursor(&self) -> usize {
+        self.line[..self.cursor].chars().count()
+    }
+
+    /// Types `c` at the cursor
+    pub fn insert(&mut self, c: char) {
+        self.line.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Deletes the character before the cursor
+    pub fn backspace(&mut self) {
+        if let Some(c) = self.line[..self.cursor].chars().next_back() {
+            self.cursor -= c.len_utf8();
+            self.line.remove(self.cursor);
+        }
+    }
+
+    /// Deletes the character under the cursor
+    pub fn delete(&mut self) {
+        if self.cursor < self.line.len() {
+            self.line.remove(self.cursor);
+        }
+    }
+
+    /// Moves the cursor one character left
+    pub fn left(&mut self) {
+        if let Some(c) = self.line[..self.cursor].chars().next_back() {
+            self.cursor -= c.len_utf8();
+        }
+    }
+
+    /// Moves the cursor one character right
+    pub fn right(&mut self) {
+        if let Some(c) = self.line[self.cursor..].chars().next() {
+            self.cursor += c.len_utf8();
+        }
+    }
+
+    /// Moves the cursor to the start of the line
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    /// Moves the cursor to the end of the line
+    pub fn end(&mut self) {
+        self.cursor = self.line.len();
+    }
+
+    /// Replaces the line with the one entered before the one shown
+    pub fn previous(&mut self) {
+        let shown = match self.browsing {
+            Some(0) => return,
+            Some(shown) => shown - 1,
+            None => match self.history.len().checked_sub(1) {
+                Some(last) => last,
+                None => return,
+            },
+        };
+        self.show(Some(shown));
+    }
+
+    /// Replaces the line with the one entered after the one shown, or an empty line after the
+    /// last one
+    pub fn next(&mut self) {
+        if let Some(shown) = self.browsing {
+            self.show(Some(shown + 1).filter(|next| *next < self.history.len()));
+        }
+    }
+
+    fn show(&mut self, entry: Option<usize>) {
+        self.browsing = entry;
+        self.line = entry.map_or_else(String::new, |entry| self.history[entry].clone());
+        self.end();
+    }
+
+    /// Completes the word at the cursor with [`complete`]. A single candidate is filled in, with
+    /// more only what they all start with is.
+    ///
+    /// # Returns
+    /// The candidates, for showing when there's more than one
+    pub fn complete(&mut self) -> Vec<String> {
+        let (start, candidates) = complete(&self.line, self.cursor);
+        let Some(first) = candidates.first() else {
+            return candidates;
+        };
+        let common = candidates.iter().fold(first.as_str(), |common, candidate| {
+            let same = common
+                .char_indices()
+                .zip(candidate.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(common.len().min(candidate.len()), |((at, _), _)| at);
+            &common[..same]
+        });
+        self.line.replace_range(start..self.cursor, common);
+        self.cursor = start + common.len();
+        candidates
+    }
+
+    /// Takes the typed line, leaving an empty one. Lines that aren't blank go into the history.
+    pub fn submit(&mut self) -> String {
+        let line = std::mem::take(&mut self.line);
+        self.cursor = 0;
+        self.browsing = None;
+        if !line.trim().is_empty() && self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        line
+    }
+}
+
+/// Strips what pensel's terminal handling wraps around a line: cursor movement escape codes,
+/// carriage return redraws and the prompt.
+#[must_use]
+pub fn clean_line(raw: &str) -> String {
+    let raw = raw.trim_end_matches(['\r', '\n']);
+    // only what was drawn after the last carriage return is still visible
+    let visible = raw.rsplit('\r').next().unwrap_or(raw);
+
+    let mut cleaned = String::with_capacity(visible.len());
+    let mut chars = visible.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // skip the rest of the CSI sequence, which ends with a letter or `~`
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() || c == '~' {
+                    break;
+                }
+            }
+        } else if !c.is_control() {
+            cleaned.push(c);
+        }
+    }
+
+    let mut line = cleaned.as_str();
+    while let Some(rest) = line.strip_prefix(PENSEL_PROMPT) {
+        line = rest;
+    }
+    line.trim().to_owned()
+}
+
+/// A non-sample line from pensel, sorted into what kind of response it is
+#[derive(Debug, PartialEq, Eq)]
+pub enum Response<'a> {
+    /// A `key: value` report, like `level: INFO`
+    KeyValue {
+        /// what is being reported
+        key: &'a str,
+        /// its value
+        value: &'a str,
+    },
+    /// A log message with a level prefix, like `WARN: something happened`
+    Log {
+        /// the log level
+        level: log::Level,
+        /// the message
+        message: &'a str,
+    },
+    /// Pensel telling us it didn't like the command
+    Error(&'a str),
+    /// Anything else, like `help` output
+    Text(&'a str),
+}
+
+impl<'a> Response<'a> {
+    /// Sorts an already cleaned (see [`clean_line`]) line from pensel
+    #[must_use]
+    pub fn classify(line: &'a str) -> Self {
+        if let Some((prefix, message)) = line.split_once(": ") {
+            if let Ok(level) = prefix.parse::<log::Level>() {
+                if prefix.chars().all(|c| c.is_ascii_uppercase()) {
+                    return Self::Log { level, message };
+                }
+            }
+        }
+
+        let lowercase = line.to_ascii_lowercase();
+        if lowercase.starts_with("invalid")
+            || lowercase.starts_with("failed")
+            || lowercase.starts_with("error")
+            || lowercase.contains("not found")
+        {
+            return Self::Error(line);
+        }
+
+        if let Some((key, value)) = line.split_once(": ") {
+            // keys are short labels, not the start of a sentence
+            let is_label = key.split_whitespace().count() <= 3
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_');
+            if is_label && !value.is_empty() {
+                return Self::KeyValue { key, value };
+            }
+        }
+
+        Self::Text(line)
+    }
+
+    /// Renders the response for the terminal, with colors
+    #[must_use]
+    pub fn pretty(&self) -> String {
+        match self {
+            Self::KeyValue { key, value } => {
+                format!("{} {}", style(format!("{:>14}:", key)).cyan().bold(), value)
+            }
+            Self::Log { level, message } => {
+                let level_style = match level {
+                    log::Level::Error => style(level.as_str()).red().bold(),
+                    log::Level::Warn => style(level.as_str()).yellow().bold(),
+                    log::Level::Info => style(level.as_str()).green(),
+                    log::Level::Debug | log::Level::Trace => style(level.as_str()).dim(),
+                };
+                format!("[{}] {}", level_style, message)
+            }
+            Self::Error(message) => style(message).red().to_string(),
+            Self::Text(text) => (*text).to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_shell {
+    use super::*;
+
+    #[test]
+    fn complete_command() {
+        let (start, candidates) = complete("im", 2);
+        assert_eq!(start, 0);
+        assert_eq!(candidates, ["imu "]);
+
+        let (_, candidates) = complete("", 0);
+        assert!(candidates.contains(&"log ".to_owned()));
+        assert!(candidates.contains(&"help ".to_owned()));
+        assert!(candidates.contains(&":quit ".to_owned()));
+    }
+
+    #[test]
+    fn complete_args() {
+        let (start, candidates) = complete("imu --accel --g", 15);
+        assert_eq!(start, 12);
+        assert_eq!(candidates, ["--gravity "]);
+
+        let (_, candidates) = complete("log --level", 11);
+        assert_eq!(candidates, ["--level=", "--level-get "]);
+
+        let (_, candidates) = complete("nope --", 7);
+        assert!(candidates.is_empty());
+    }
+
+    #[test]
+    fn complete_sample_view() {
+        let (start, candidates) = complete(":samples l", 10);
+        assert_eq!(start, 9);
+        assert_eq!(candidates, ["latest"]);
+    }
+
+    #[test]
+    fn sample_view_from_str() {
+        for view in SampleView::ALL {
+            assert_eq!(view.name().parse::<SampleView>(), Ok(view));
+        }
+        assert!("sideways".parse::<SampleView>().is_err());
+    }
+
+    #[test]
+    fn edit_line() {
+        let mut input = LineInput::default();
+        for c in "lg".chars() {
+            input.insert(c);
+        }
+        input.left();
+        input.insert('o');
+        assert_eq!((input.line(), input.cursor()), ("log", 2));
+        input.end();
+        input.backspace();
+        input.home();
+        input.delete();
+        input.right();
+        input.insert('µ');
+        input.insert('s');
+        assert_eq!((input.line(), input.cursor()), ("oµs", 3));
+        input.left();
+        input.backspace();
+        assert_eq!((input.line(), input.cursor()), ("os", 1));
+        assert_eq!(input.submit(), "os");
+        assert_eq!((input.line(), input.cursor()), ("", 0));
+    }
+
+    #[test]
+    fn line_history() {
+        let mut input = LineInput::default();
+        input.previous();
+        assert_eq!(input.line(), "");
+        for line in ["info", " ", "imu --accel", "imu --accel"] {
+            line.chars().for_each(|c| input.insert(c));
+            input.submit();
+        }
+
+        input.previous();
+        assert_eq!((input.line(), input.cursor()), ("imu --accel", 11));
+        input.previous();
+        input.previous();
+        assert_eq!(input.line(), "info");
+        input.next();
+        assert_eq!(input.line(), "imu --accel");
+        input.next();
+        assert_eq!(input.line(), "");
+    }
+
+    #[test]
+    fn complete_line() {
+        let mut input = LineInput::default();
+        "im".chars().for_each(|c| input.insert(c));
+        assert_eq!(input.complete(), ["imu "]);
+        assert_eq!((input.line(), input.cursor()), ("imu ", 4));
+
+        let mut input = LineInput::default();
+        "log --l".chars().for_each(|c| input.insert(c));
+        assert_eq!(input.complete().len(), 2);
+        assert_eq!(input.line(), "log --level");
+
+        let mut input = LineInput::default();
+        "nope --".chars().for_each(|c| input.insert(c));
+        assert!(input.complete().is_empty());
+        assert_eq!(input.line(), "nope --");
+    }
+
+    #[test]
+    fn clean() {
+        assert_eq!(clean_line("> imu --accel"), "imu --accel");
+        assert_eq!(clean_line("\r> lo\x1b[K\r> log\x1b[2D"), "log");
+        assert_eq!(clean_line("> > A:1,2,3\r"), "A:1,2,3");
+        assert_eq!(clean_line("> "), "");
+    }
+
+    #[test]
+    fn classify() {
+        assert_eq!(
+            Response::classify("level: INFO"),
+            Response::KeyValue {
+                key: "level",
+                value: "INFO"
+            }
+        );
+        assert_eq!(
+            Response::classify("WARN: low battery"),
+            Response::Log {
+                level: log::Level::Warn,
+                message: "low battery"
+            }
+        );
+        assert_eq!(
+            Response::classify("invalid usage"),
+            Response::Error("invalid usage")
+        );
+        assert_eq!(
+            Response::classify("AVAILABLE ITEMS:"),
+            Response::Text("AVAILABLE ITEMS:")
+        );
+        assert_eq!(
+            Response::classify("imu [ --accel ] [ --gravity ]"),
+            Response::Text("imu [ --accel ] [ --gravity ]")
+        );
+    }
+}
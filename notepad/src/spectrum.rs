@@ -0,0 +1,574 @@
+//! Frequency analysis of acceleration: spectra, spectrograms, and tremor metrics.
+//!
+//! Spectra are of the acceleration's three axes added together, with the mean removed, so they
+//! don't depend on how the pen is held. Power is scaled so that summing it over all frequencies
+//! gives the mean square acceleration, and the square root of a band's power is the RMS
+//! acceleration in that band, in m/s².
+use pensel_types::mint::Vector3;
+use std::{collections::VecDeque, f32::consts::PI, fmt, str::FromStr, time::Duration};
+
+/// Frequencies of human hand tremor, in Hz: from pathological (4-7 Hz) to physiological
+/// (8-12 Hz)
+pub const TREMOR_BAND: Band = Band { low: 4., high: 12. };
+/// Shades of the terminal spectrogram, from no power to the most
+const SHADES: &[u8] = b" .:-=+*#%@";
+
+/// A range of frequencies in Hz, inclusive on both ends
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Band {
+    /// lowest frequency in the band
+    pub low: f32,
+    /// highest frequency in the band
+    pub high: f32,
+}
+
+impl FromStr for Band {
+    type Err = String;
+
+    /// Parses `<low>..<high>` in Hz, like `4..12`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad_band = || format!("expected '<low>..<high>' in Hz, got '{}'", s);
+        let (low, high) = s.split_once("..").ok_or_else(bad_band)?;
+        let low = f32::from_str(low.trim()).map_err(|_| bad_band())?;
+        let high = f32::from_str(high.trim()).map_err(|_| bad_band())?;
+        if low < 0. || high < low {
+            return Err(bad_band());
+        }
+        Ok(Self { low, high })
+    }
+}
+
+impl fmt::Display for Band {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{} Hz", self.low, self.high)
+    }
+}
+
+/// A peak in a [`Spectrum`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peak {
+    /// in Hz
+    pub frequency: f32,
+    /// in (m/s²)²
+    pub power: f32,
+}
+
+/// How much a spectrum looks like hand tremor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tremor {
+    /// strongest frequency within [`TREMOR_BAND`], in Hz
+    pub frequency: f32,
+    /// RMS acceleration within [`TREMOR_BAND`], in m/s²
+    pub rms: f32,
+    /// fraction of the total power that's within [`TREMOR_BAND`]
+    pub ratio: f32,
+}
+
+impl fmt::Display for Tremor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tremor {:5.2} Hz  rms {:6.3} m/s²  {:5.1}% of power",
+            self.frequency,
+            self.rms,
+            self.ratio * 100.
+        )
+    }
+}
+
+/// The power spectrum of a window of acceleration
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spectrum {
+    /// width of each bin, in Hz
+    resolution: f32,
+    /// power of each bin from 0 Hz up to the Nyquist frequency, in (m/s²)²
+    power: Vec<f32>,
+}
+
+impl Spectrum {
+    /// Computes the spectrum of `samples` in m/s², taken at `sample_rate` Hz. The samples are
+    /// Hann windowed and zero padded to a power of two.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new(samples: &[Vector3<f32>], sample_rate: f32) -> Self {
+        let len = samples.len().max(2).next_power_of_two();
+        let mut power = vec![0.; len / 2 + 1];
+        let window: Vec<f32> = (0..samples.len())
+            .map(|index| {
+                0.5 - 0.5 * (2. * PI * index as f32 / (samples.len().max(2) - 1) as f32).cos()
+            })
+            .collect();
+        let window_power: f32 = window.iter().map(|w| w * w).sum();
+
+        for axis in 0..3 {
+            let values: Vec<f32> = samples.iter().map(|v| <[f32; 3]>::from(*v)[axis]).collect();
+            let mean = values.iter().sum::<f32>() / values.len().max(1) as f32;
+            let mut bins: Vec<Complex> = values
+                .iter()
+                .zip(&window)
+                .map(|(value, w)| Complex::real((value - mean) * w))
+                .chain(std::iter::repeat(Complex::ZERO))
+                .take(len)
+                .collect();
+            fft(&mut bins);
+
+            for (index, bin) in bins.iter().take(power.len()).enumerate() {
+                // everything but DC and Nyquist also has a negative frequency twin
+                let sides = if index == 0 || index == len / 2 {
+                    1.
+                } else {
+                    2.
+                };
+                power[index] +=
+                    sides * bin.norm_squared() / (len as f32 * window_power.max(f32::EPSILON));
+            }
+        }
+
+        Self {
+            resolution: sample_rate / len as f32,
+            power,
+        }
+    }
+
+    /// Averages the spectra of half overlapping windows of `window` samples across all of
+    /// `samples` (Welch's method), for a steadier spectrum of a long recording
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn averaged(samples: &[Vector3<f32>], sample_rate: f32, window: usize) -> Self {
+        let window = window.clamp(2, samples.len().max(2));
+        let step = (window / 2).max(1);
+        let spectra: Vec<Self> = (0..=samples.len().saturating_sub(window))
+            .step_by(step)
+            .map(|start| {
+                Self::new(
+                    &samples[start..(start + window).min(samples.len())],
+                    sample_rate,
+                )
+            })
+            .collect();
+        let Some(first) = spectra.first() else {
+            return Self::new(samples, sample_rate);
+        };
+        let mut power = vec![0.; first.power.len()];
+        for spectrum in &spectra {
+            for (total, bin) in power.iter_mut().zip(&spectrum.power) {
+                *total += bin / spectra.len() as f32;
+            }
+        }
+        Self {
+            resolution: first.resolution,
+            power,
+        }
+    }
+
+    /// Width of each bin, in Hz
+    #[must_use]
+    pub const fn resolution(&self) -> f32 {
+        self.resolution
+    }
+
+    /// Power of each bin from 0 Hz up to the Nyquist frequency, in (m/s²)²
+    #[must_use]
+    pub fn power(&self) -> &[f32] {
+        &self.power
+    }
+
+    /// Frequency in the middle of bin `index`, in Hz
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn frequency(&self, index: usize) -> f32 {
+        index as f32 * self.resolution
+    }
+
+    /// Mean square acceleration, in (m/s²)²
+    #[must_use]
+    pub fn total_power(&self) -> f32 {
+        self.power.iter().sum()
+    }
+
+    /// Power in `band`, in (m/s²)²
+    #[must_use]
+    pub fn band_power(&self, band: Band) -> f32 {
+        self.bins_in(band).map(|(_, power)| power).sum()
+    }
+
+    /// The `count` strongest peaks, strongest first
+    #[must_use]
+    pub fn dominant(&self, count: usize) -> Vec<Peak> {
+        let mut peaks: Vec<Peak> = (1..self.power.len())
+            .filter(|&index| {
+                let power = self.power[index];
+                power > self.power[index - 1]
+                    && self.power.get(index + 1).is_none_or(|next| power >= *next)
+            })
+            .map(|index| Peak {
+                frequency: self.frequency(index),
+                power: self.power[index],
+            })
+            .collect();
+        peaks.sort_by(|a, b| b.power.total_cmp(&a.power));
+        peaks.truncate(count);
+        peaks
+    }
+
+    /// Tremor metrics, or `None` if there's no power at all in [`TREMOR_BAND`]
+    #[must_use]
+    pub fn tremor(&self) -> Option<Tremor> {
+        let (index, _) = self
+            .bins_in(TREMOR_BAND)
+            .filter(|(_, power)| *power > 0.)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        let band_power = self.band_power(TREMOR_BAND);
+        Some(Tremor {
+            frequency: self.frequency(index),
+            rms: band_power.sqrt(),
+            ratio: band_power / self.total_power(),
+        })
+    }
+
+    /// `(index, power)` of the bins within `band`
+    fn bins_in(&self, band: Band) -> impl Iterator<Item = (usize, f32)> + '_ {
+        self.power
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(move |(index, _)| {
+                let frequency = self.frequency(*index);
+                (band.low..=band.high).contains(&frequency)
+            })
+    }
+}
+
+/// Spectra of a sliding window over a stream of acceleration
+#[derive(Debug, Clone)]
+pub struct Spectrogram {
+    sample_rate: f32,
+    window: usize,
+    hop: usize,
+    history: usize,
+    /// the most recent samples, a window's worth
+    recent: VecDeque<Vector3<f32>>,
+    /// samples since the last spectrum
+    since_last: usize,
+    /// the most recent spectra, oldest first
+    columns: VecDeque<Spectrum>,
+}
+
+impl Spectrogram {
+    /// Computes a spectrum of the last `window` samples every `hop` samples, keeping the last
+    /// `history` of them
+    #[must_use]
+    pub fn new(sample_rate: f32, window: usize, hop: usize, history: usize) -> Self {
+        Self {
+            sample_rate,
+            window: window.max(2),
+            hop: hop.max(1),
+            history: history.max(1),
+            recent: VecDeque::with_capacity(window),
+            since_last: 0,
+            columns: VecDeque::with_capacity(history),
+        }
+    }
+
+    /// Adds `sample`, in m/s²
+    ///
+    /// # Returns
+    /// The new spectrum, if it's time for one
+    pub fn push(&mut self, sample: Vector3<f32>) -> Option<&Spectrum> {
+        if self.recent.len() == self.window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(sample);
+        self.since_last += 1;
+        if self.recent.len() < self.window || self.since_last < self.hop {
+            return None;
+        }
+
+        self.since_last = 0;
+        if self.columns.len() == self.history {
+            self.columns.pop_front();
+        }
+        let spectrum = Spectrum::new(self.recent.make_contiguous(), self.sample_rate);
+        self.columns.push_back(spectrum);
+        self.columns.back()
+    }
+
+    /// The sample rate in Hz spectra are computed with
+    #[must_use]
+    pub const fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Changes the sample rate spectra are computed with from now on, e.g. as
+    /// [`estimate_sample_rate`] settles on a live stream
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// The most recent spectrum
+    #[must_use]
+    pub fn latest(&self) -> Option<&Spectrum> {
+        self.columns.back()
+    }
+
+    /// Draws the spectrogram as `rows` lines of text: time runs left to right one column per
+    /// spectrum, frequency bottom to top, and darker characters are more power. Power is shaded
+    /// logarithmically, relative to the strongest bin shown.
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn render(&self, rows: usize) -> Vec<String> {
+        let rows = rows.max(1);
+        let bins = self
+            .columns
+            .back()
+            .map_or(0, |spectrum| spectrum.power.len());
+        // merge bins into rows, skipping DC
+        let row_power = |spectrum: &Spectrum, row: usize| {
+            let start = 1 + row * (bins - 1) / rows;
+            let end = (1 + (row + 1) * (bins - 1) / rows).max(start + 1).min(bins);
+            spectrum.power[start.min(bins)..end].iter().sum::<f32>()
+        };
+        let cells: Vec<Vec<f32>> = self
+            .columns
+            .iter()
+            .map(|spectrum| (0..rows).map(|row| row_power(spectrum, row)).collect())
+            .collect();
+        let max = cells.iter().flatten().copied().fold(0_f32, f32::max);
+
+        let shade = |power: f32| {
+            if max <= 0. || power <= 0. {
+                return SHADES[0] as char;
+            }
+            // 30 dB of range below the strongest
+            let level = (1. + 10. * (power / max).log10() / 30.).clamp(0., 1.);
+            SHADES[(level * (SHADES.len() - 1) as f32).round() as usize] as char
+        };
+        (0..rows)
+            .rev()
+            .map(|row| cells.iter().map(|column| shade(column[row])).collect())
+            .collect()
+    }
+}
+
+/// Estimates the sample rate in Hz from sample timestamps, using the median time between them
+/// so a few dropped samples don't throw it off
+#[must_use]
+pub fn estimate_sample_rate(timestamps: &[Duration]) -> Option<f32> {
+    let mut periods: Vec<Duration> = timestamps
+        .windows(2)
+        .map(|pair| pair[1].saturating_sub(pair[0]))
+        .filter(|period| !period.is_zero())
+        .collect();
+    periods.sort_unstable();
+    periods
+        .get(periods.len() / 2)
+        .map(|period| 1. / period.as_secs_f32())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    const ZERO: Self = Self { re: 0., im: 0. };
+
+    const fn real(re: f32) -> Self {
+        Self { re, im: 0. }
+    }
+
+    fn norm_squared(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            re: self.re + other.re,
+            im: self.im + other.im,
+        }
+    }
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            re: self.re - other.re,
+            im: self.im - other.im,
+        }
+    }
+
+    fn mul(self, other: Self) -> Self {
+        Self {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
+}
+
+/// In place radix 2 FFT. `values.len()` has to be a power of two.
+#[allow(clippy::cast_precision_loss)]
+fn fft(values: &mut [Complex]) {
+    let len = values.len();
+    debug_assert!(len.is_power_of_two());
+
+    // bit reversal permutation
+    let mut j = 0;
+    for i in 1..len {
+        let mut bit = len >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            values.swap(i, j);
+        }
+    }
+
+    let mut size = 2;
+    while size <= len {
+        let angle = -2. * PI / size as f32;
+        for start in (0..len).step_by(size) {
+            for k in 0..size / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let twiddle = Complex { re: cos, im: sin };
+                let even = values[start + k];
+                let odd = values[start + k + size / 2].mul(twiddle);
+                values[start + k] = even.add(odd);
+                values[start + k + size / 2] = even.sub(odd);
+            }
+        }
+        size *= 2;
+    }
+}
+
+#[cfg(test)]
+mod test_spectrum {
+    use super::*;
+    use crate::types::SAMPLE_RATE;
+
+    /// `len` samples of a sine of `amplitude` m/s² at `hz` along x, plus `offset` along z
+    #[allow(clippy::cast_precision_loss)]
+    fn sine(hz: f32, amplitude: f32, len: usize) -> Vec<Vector3<f32>> {
+        (0..len)
+            .map(|index| Vector3 {
+                x: amplitude * (2. * PI * hz * index as f32 / SAMPLE_RATE).sin(),
+                y: 0.,
+                z: 9.81,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fft_matches_dft() {
+        let mut values: Vec<Complex> = [1., 2., 0., -1., 3., 0.5, -2., 1.]
+            .iter()
+            .map(|re| Complex::real(*re))
+            .collect();
+        let input = values.clone();
+        fft(&mut values);
+        for (k, actual) in values.iter().enumerate() {
+            let expected = input.iter().enumerate().fold(Complex::ZERO, |sum, (n, x)| {
+                let angle = -2. * PI * (k * n) as f32 / 8.;
+                sum.add(x.mul(Complex {
+                    re: angle.cos(),
+                    im: angle.sin(),
+                }))
+            });
+            assert!(actual.sub(expected).norm_squared() < 1e-6, "bin {}", k);
+        }
+    }
+
+    #[test]
+    fn finds_dominant_frequency() {
+        let spectrum = Spectrum::new(&sine(10., 1., 256), SAMPLE_RATE);
+        let peaks = spectrum.dominant(1);
+        assert!((peaks[0].frequency - 10.).abs() <= spectrum.resolution());
+        // a sine's mean square is half its amplitude squared, and gravity is removed as DC
+        assert!(
+            (spectrum.total_power() - 0.5).abs() < 0.05,
+            "{}",
+            spectrum.total_power()
+        );
+        assert!(spectrum.band_power(Band { low: 8., high: 12. }) > 0.45);
+        assert!(
+            spectrum.band_power(Band {
+                low: 20.,
+                high: 50.
+            }) < 0.01
+        );
+    }
+
+    #[test]
+    fn measures_tremor() {
+        let mut samples = sine(6., 0.2, 512);
+        for (sample, slow) in samples.iter_mut().zip(sine(1., 1., 512)) {
+            sample.x += slow.x;
+        }
+        let tremor = Spectrum::averaged(&samples, SAMPLE_RATE, 128)
+            .tremor()
+            .unwrap();
+        assert!((tremor.frequency - 6.).abs() < 1., "{}", tremor);
+        assert!((tremor.rms - 0.2 / 2_f32.sqrt()).abs() < 0.03, "{}", tremor);
+        assert!(tremor.ratio < 0.2, "{}", tremor);
+
+        let still = Spectrum::new(&sine(0., 0., 64), SAMPLE_RATE);
+        assert!(still.tremor().is_none_or(|tremor| tremor.rms < 1e-6));
+    }
+
+    #[test]
+    fn spectrogram() {
+        let mut spectrogram = Spectrogram::new(SAMPLE_RATE, 64, 16, 4);
+        let computed = sine(25., 1., 200)
+            .into_iter()
+            .filter(|sample| spectrogram.push(*sample).is_some())
+            .count();
+        assert_eq!(computed, (200 - 64) / 16 + 1);
+
+        let rows = spectrogram.render(4);
+        assert_eq!(rows.len(), 4);
+        assert!(rows.iter().all(|row| row.chars().count() == 4));
+        // 25 Hz is half way to Nyquist, at the top of the second row from the bottom
+        assert!(rows[2].chars().all(|c| c == '@'), "{:?}", rows);
+        assert!(rows[0].chars().all(|c| c == ' '), "{:?}", rows);
+    }
+
+    #[test]
+    fn spectrogram_sample_rate() {
+        let mut spectrogram = Spectrogram::new(SAMPLE_RATE, 64, 64, 4);
+        // the same samples taken twice as fast are twice the frequency
+        spectrogram.set_sample_rate(2. * SAMPLE_RATE);
+        assert_eq!(spectrogram.sample_rate(), 2. * SAMPLE_RATE);
+        let spectrum = sine(25., 1., 64)
+            .into_iter()
+            .find_map(|sample| spectrogram.push(sample).cloned())
+            .unwrap();
+        let peak = spectrum.dominant(1)[0];
+        assert!(
+            (peak.frequency - 50.).abs() < 2. * SAMPLE_RATE / 64.,
+            "{:?}",
+            peak
+        );
+    }
+
+    #[test]
+    fn parses_band() {
+        assert_eq!("4..12".parse(), Ok(TREMOR_BAND));
+        assert!("12..4".parse::<Band>().is_err());
+        assert!("4-12".parse::<Band>().is_err());
+    }
+
+    #[test]
+    fn estimates_sample_rate() {
+        let timestamps: Vec<Duration> = [0, 10, 20, 40, 50, 60]
+            .iter()
+            .map(|millis| Duration::from_millis(*millis))
+            .collect();
+        let rate = estimate_sample_rate(&timestamps).unwrap();
+        assert!((rate - 100.).abs() < 1e-3);
+        assert_eq!(estimate_sample_rate(&[]), None);
+    }
+}
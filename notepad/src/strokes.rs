@@ -0,0 +1,318 @@
+//! Splits a continuous stream of samples into strokes: stretches of motion between moments of
+//! holding still, like moving to and soldering one joint.
+//!
+//! Motion is detected from the energy of the linear acceleration, with hysteresis: a stroke
+//! starts once the energy rises above [`Config::start_threshold`] and ends once it has stayed
+//! below the lower [`Config::stop_threshold`] for [`Config::min_still`].
+use std::{collections::VecDeque, fmt, time::Duration};
+
+use crate::{
+    angles::{AxisMapping, PenAngles},
+    recording::TimedSample,
+    types,
+};
+
+/// Default [`Config::start_threshold`], in m/s²
+pub const DEFAULT_START_THRESHOLD: f32 = 1.0;
+/// Default [`Config::stop_threshold`], in m/s²
+pub const DEFAULT_STOP_THRESHOLD: f32 = 0.4;
+/// Default [`Config::min_still`]
+pub const DEFAULT_MIN_STILL: Duration = Duration::from_millis(200);
+/// Default [`Config::min_duration`]
+pub const DEFAULT_MIN_DURATION: Duration = Duration::from_millis(100);
+/// Default [`Config::energy_window`]
+pub const DEFAULT_ENERGY_WINDOW: usize = 5;
+
+/// How a [`Segmenter`] decides where strokes are
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    /// energy above which a stroke starts, as RMS acceleration in m/s²
+    pub start_threshold: f32,
+    /// energy below which the pen might be stopping, as RMS acceleration in m/s². Lower than
+    /// `start_threshold`, so noise around either doesn't chop strokes up.
+    pub stop_threshold: f32,
+    /// how long energy has to stay below `stop_threshold` for a stroke to end
+    pub min_still: Duration,
+    /// strokes shorter than this are dropped as bumps
+    pub min_duration: Duration,
+    /// how many acceleration samples the energy is averaged over
+    pub energy_window: usize,
+    /// how the IMU is mounted, for working out tilt
+    pub axes: AxisMapping,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            start_threshold: DEFAULT_START_THRESHOLD,
+            stop_threshold: DEFAULT_STOP_THRESHOLD,
+            min_still: DEFAULT_MIN_STILL,
+            min_duration: DEFAULT_MIN_DURATION,
+            energy_window: DEFAULT_ENERGY_WINDOW,
+            axes: AxisMapping::DEFAULT,
+        }
+    }
+}
+
+/// One stroke, and some statistics about it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stroke {
+    /// when the energy rose above the start threshold
+    pub start: Duration,
+    /// when the energy last dropped below the stop threshold
+    pub end: Duration,
+    /// largest linear acceleration during the stroke, in m/s²
+    pub peak_accel: f32,
+    /// RMS linear acceleration during the stroke, in m/s²
+    pub rms_accel: f32,
+    /// average tilt from vertical during the stroke in degrees, if there was gravity to tell
+    pub mean_tilt: Option<f32>,
+}
+
+impl Stroke {
+    /// How long the stroke took
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+impl fmt::Display for Stroke {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:9.3}s {:9.3}s {:7.3}s  peak {:6.2} m/s²  rms {:6.2} m/s²",
+            self.start.as_secs_f64(),
+            self.end.as_secs_f64(),
+            self.duration().as_secs_f64(),
+            self.peak_accel,
+            self.rms_accel
+        )?;
+        match self.mean_tilt {
+            Some(tilt) => write!(f, "  tilt {:5.1}°", tilt),
+            None => write!(f, "  tilt     -"),
+        }
+    }
+}
+
+/// What we've seen of the stroke in progress
+#[derive(Debug, Clone, Copy)]
+struct InProgress {
+    start: Duration,
+    /// when energy dropped below the stop threshold, if it's still below it
+    quiet_since: Option<Duration>,
+    peak_accel: f32,
+    sum_squares: f32,
+    accel_count: u32,
+    tilt_sum: f32,
+    tilt_count: u32,
+}
+
+impl InProgress {
+    #[allow(clippy::cast_precision_loss)]
+    fn finish(self, end: Duration) -> Stroke {
+        Stroke {
+            start: self.start,
+            end,
+            peak_accel: self.peak_accel,
+            rms_accel: (self.sum_squares / self.accel_count.max(1) as f32).sqrt(),
+            mean_tilt: (self.tilt_count > 0).then(|| self.tilt_sum / self.tilt_count as f32),
+        }
+    }
+}
+
+/// Splits linear acceleration into strokes as it arrives, one sample at a time
+#[derive(Debug, Clone)]
+pub struct Segmenter {
+    config: Config,
+    /// squared magnitudes of the last few acceleration samples
+    recent: VecDeque<f32>,
+    stroke: Option<InProgress>,
+}
+
+impl Segmenter {
+    /// Finds strokes according to `config`
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            recent: VecDeque::with_capacity(config.energy_window),
+            stroke: None,
+        }
+    }
+
+    /// Whether a stroke is in progress
+    #[must_use]
+    pub const fn in_stroke(&self) -> bool {
+        self.stroke.is_some()
+    }
+
+    /// Feeds in `timed`
+    ///
+    /// # Returns
+    /// The stroke that just ended, if any
+    pub fn feed(&mut self, timed: &TimedSample) -> Option<Stroke> {
+        let accel = match timed.sample {
+            types::Sample::Accel(accel) => accel.m_s2(),
+            types::Sample::Grav(gravity) => {
+                if let Some(stroke) = self.stroke.as_mut() {
+                    if let Some(angles) = PenAngles::from_gravity(gravity.m_s2(), self.config.axes)
+                    {
+                        stroke.tilt_sum += angles.tilt;
+                        stroke.tilt_count += 1;
+                    }
+                }
+                return None;
+            }
+        };
+        let square = accel.x * accel.x + accel.y * accel.y + accel.z * accel.z;
+        let energy = self.energy(square);
+
+        let Some(stroke) = self.stroke.as_mut() else {
+            if energy >= self.config.start_threshold {
+                self.stroke = Some(InProgress {
+                    start: timed.timestamp,
+                    quiet_since: None,
+                    peak_accel: square.sqrt(),
+                    sum_squares: square,
+                    accel_count: 1,
+                    tilt_sum: 0.,
+                    tilt_count: 0,
+                });
+            }
+            return None;
+        };
+
+        stroke.peak_accel = stroke.peak_accel.max(square.sqrt());
+        stroke.sum_squares += square;
+        stroke.accel_count += 1;
+        if energy >= self.config.stop_threshold {
+            stroke.quiet_since = None;
+            return None;
+        }
+        let quiet_since = *stroke.quiet_since.get_or_insert(timed.timestamp);
+        if timed.timestamp.saturating_sub(quiet_since) < self.config.min_still {
+            return None;
+        }
+        let stroke = self.stroke.take()?.finish(quiet_since);
+        (stroke.duration() >= self.config.min_duration).then_some(stroke)
+    }
+
+    /// Ends the stroke in progress, e.g. because the recording ran out, at `end`
+    ///
+    /// # Returns
+    /// The stroke, if one was in progress and long enough to count
+    pub fn finish(&mut self, end: Duration) -> Option<Stroke> {
+        let stroke = self.stroke.take()?;
+        let stroke = stroke.finish(stroke.quiet_since.unwrap_or(end));
+        (stroke.duration() >= self.config.min_duration).then_some(stroke)
+    }
+
+    /// Adds `square` to the recent accelerations, returning their RMS
+    #[allow(clippy::cast_precision_loss)]
+    fn energy(&mut self, square: f32) -> f32 {
+        if self.recent.len() >= self.config.energy_window.max(1) {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(square);
+        (self.recent.iter().sum::<f32>() / self.recent.len() as f32).sqrt()
+    }
+}
+
+/// Finds all the strokes in a recording
+///
+/// # Errors
+/// The first error reading `samples`
+pub fn segment<E>(
+    config: Config,
+    samples: impl IntoIterator<Item = Result<TimedSample, E>>,
+) -> Result<Vec<Stroke>, E> {
+    let mut segmenter = Segmenter::new(config);
+    let mut strokes = Vec::new();
+    let mut last = Duration::ZERO;
+    for timed in samples {
+        let timed = timed?;
+        last = timed.timestamp;
+        strokes.extend(segmenter.feed(&timed));
+    }
+    strokes.extend(segmenter.finish(last));
+    Ok(strokes)
+}
+
+#[cfg(test)]
+mod test_strokes {
+    use super::*;
+    use crate::{
+        test_util::{infallible, samples},
+        types::imu,
+    };
+
+    /// Strokes in `accel`, with the pen tilted 45° from vertical throughout
+    fn strokes(accel: impl IntoIterator<Item = i16>) -> Vec<Stroke> {
+        let tilted = imu::GravityVector::new(-694, 0, 694);
+        segment(config(), infallible(samples(accel, Some(tilted)))).unwrap()
+    }
+
+    /// `count` samples of `value`
+    fn run(value: i16, count: usize) -> impl Iterator<Item = i16> {
+        std::iter::repeat_n(value, count)
+    }
+
+    fn config() -> Config {
+        Config {
+            energy_window: 1,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn finds_strokes() {
+        // still, move for 300ms, still, move for 200ms, still
+        let accel: Vec<i16> = run(0, 50)
+            .chain(run(300, 30))
+            .chain(run(0, 50))
+            .chain(run(-200, 20))
+            .chain(run(0, 50))
+            .collect();
+        let strokes = strokes(accel);
+
+        assert_eq!(strokes.len(), 2, "{:?}", strokes);
+        assert_eq!(strokes[0].start, Duration::from_millis(500));
+        assert_eq!(strokes[0].end, Duration::from_millis(800));
+        assert_eq!(strokes[0].duration(), Duration::from_millis(300));
+        assert!((strokes[0].peak_accel - 3.).abs() < 1e-3);
+        assert!((strokes[1].peak_accel - 2.).abs() < 1e-3);
+        assert!((strokes[1].mean_tilt.unwrap() - 45.).abs() < 0.1);
+    }
+
+    #[test]
+    fn hysteresis_keeps_strokes_whole() {
+        // dips between the thresholds, and briefly below them, don't end the stroke
+        let accel: Vec<i16> = run(0, 10)
+            .chain(run(150, 10))
+            .chain(run(60, 10))
+            .chain(run(150, 10))
+            .chain(run(0, 5))
+            .chain(run(150, 10))
+            .chain(run(0, 50))
+            .collect();
+        let strokes = strokes(accel);
+        assert_eq!(strokes.len(), 1, "{:?}", strokes);
+        assert_eq!(strokes[0].start, Duration::from_millis(100));
+        assert_eq!(strokes[0].end, Duration::from_millis(550));
+    }
+
+    #[test]
+    fn drops_bumps() {
+        let accel: Vec<i16> = run(0, 10).chain(run(500, 3)).chain(run(0, 50)).collect();
+        assert_eq!(strokes(accel), []);
+    }
+
+    #[test]
+    fn finishes_at_end() {
+        let accel: Vec<i16> = run(0, 10).chain(run(300, 20)).collect();
+        let strokes = strokes(accel);
+        assert_eq!(strokes.len(), 1);
+        assert_eq!(strokes[0].end, Duration::from_millis(295));
+    }
+}
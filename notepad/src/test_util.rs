@@ -0,0 +1,47 @@
+//! Fixtures shared by the unit tests
+use pensel_types::mint::Vector3;
+use std::convert::Infallible;
+
+use crate::{
+    recording::TimedSample,
+    types::{self, imu, SAMPLE_PERIOD},
+    vector::{norm, sub},
+};
+
+pub(crate) fn v(x: f32, y: f32, z: f32) -> Vector3<f32> {
+    Vector3 { x, y, z }
+}
+
+/// Fails unless `a` and `b` are less than `epsilon` apart
+pub(crate) fn assert_close(a: Vector3<f32>, b: Vector3<f32>, epsilon: f32) {
+    assert!(norm(sub(a, b)) < epsilon, "{:?} != {:?}", a, b);
+}
+
+/// Linear acceleration along x in counts, one sample per [`SAMPLE_PERIOD`] starting at zero,
+/// each followed half a period later by `gravity` if there is one
+pub(crate) fn samples(
+    accel: impl IntoIterator<Item = i16>,
+    gravity: Option<imu::GravityVector>,
+) -> Vec<TimedSample> {
+    (0_u32..)
+        .zip(accel)
+        .flat_map(|(step, x)| {
+            let accel = TimedSample {
+                timestamp: SAMPLE_PERIOD * step,
+                sample: types::Sample::Accel(imu::AccelerationVector::new(x, 0, 0)),
+            };
+            let gravity = gravity.map(|gravity| TimedSample {
+                timestamp: SAMPLE_PERIOD * step + SAMPLE_PERIOD / 2,
+                sample: types::Sample::Grav(gravity),
+            });
+            std::iter::once(accel).chain(gravity)
+        })
+        .collect()
+}
+
+/// `samples` as read from a source that could fail, like a recording
+pub(crate) fn infallible(
+    samples: Vec<TimedSample>,
+) -> impl Iterator<Item = Result<TimedSample, Infallible>> {
+    samples.into_iter().map(Ok)
+}
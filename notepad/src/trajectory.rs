@@ -0,0 +1,335 @@
+//! Reconstructs the path of the pen's tip from the streamed linear acceleration and the
+//! orientation estimated from gravity.
+//!
+//! Acceleration is rotated into the world frame and integrated twice. Integrating twice makes
+//! any bias grow quadratically, so whenever the pen is held still we know its velocity is zero
+//! and reset it: a zero velocity update (ZUPT). That keeps drift bounded to what accumulates
+//! during a single movement.
+use pensel_types::mint::{Quaternion, Vector3};
+use std::{fmt, str::FromStr, time::Duration};
+
+use crate::{
+    orientation::{self, Estimator, OrientationFilter},
+    recording::TimedSample,
+    types,
+    vector::{add, norm, scale},
+};
+
+/// Default [`Config::still_threshold`], in m/s²
+pub const DEFAULT_STILL_THRESHOLD: f32 = 0.3;
+/// Default [`Config::still_time`]
+pub const DEFAULT_STILL_TIME: Duration = Duration::from_millis(100);
+
+/// Where the pen's tip is relative to the IMU, in the pen's frame, in meters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LeverArm(pub Vector3<f32>);
+
+impl Default for LeverArm {
+    fn default() -> Self {
+        Self(Vector3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        })
+    }
+}
+
+impl FromStr for LeverArm {
+    type Err = String;
+
+    /// Parses `<x>,<y>,<z>` in meters, like `0.12,0,-0.005`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad_arm = || format!("expected '<x>,<y>,<z>' in meters, got '{}'", s);
+        let components = s
+            .split(',')
+            .map(|component| f32::from_str(component.trim()).map_err(|_| bad_arm()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match components[..] {
+            [x, y, z] => Ok(Self(Vector3 { x, y, z })),
+            _ => Err(bad_arm()),
+        }
+    }
+}
+
+impl fmt::Display for LeverArm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{}", self.0.x, self.0.y, self.0.z)
+    }
+}
+
+/// How a [`Tracker`] reconstructs the trajectory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    /// where the tip is relative to the IMU
+    pub lever_arm: LeverArm,
+    /// linear acceleration below which the pen might be still, in m/s²
+    pub still_threshold: f32,
+    /// how long acceleration has to stay below `still_threshold` for the pen to count as still
+    pub still_time: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            lever_arm: LeverArm::default(),
+            still_threshold: DEFAULT_STILL_THRESHOLD,
+            still_time: DEFAULT_STILL_TIME,
+        }
+    }
+}
+
+/// Where the pen's tip was at some point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrajectoryPoint {
+    /// time since the recording started
+    pub timestamp: Duration,
+    /// position of the tip in the world frame, in meters from where it started
+    pub position: Vector3<f32>,
+    /// velocity of the IMU in the world frame, in m/s
+    pub velocity: Vector3<f32>,
+    /// whether the pen was detected as still, and its velocity reset
+    pub still: bool,
+}
+
+const ZERO: Vector3<f32> = Vector3 {
+    x: 0.,
+    y: 0.,
+    z: 0.,
+};
+
+/// Integration state, once we know the orientation and have had an acceleration sample
+#[derive(Debug, Clone, Copy)]
+struct State {
+    /// timestamp of the last acceleration sample
+    last: Duration,
+    /// position of the IMU, placed so the tip starts at the origin
+    position: Vector3<f32>,
+    velocity: Vector3<f32>,
+    /// when acceleration last dropped below the still threshold, if it's still below it
+    quiet_since: Option<Duration>,
+}
+
+/// Follows the tip through space. Feed it every sample in order; it orients itself on gravity and
+/// integrates linear acceleration, resetting velocity whenever the pen rests.
+#[derive(Debug, Clone)]
+pub struct Tracker<F: OrientationFilter> {
+    config: Config,
+    estimator: Estimator<F>,
+    /// whether `estimator` has seen any gravity yet
+    oriented: bool,
+    state: Option<State>,
+}
+
+impl<F: OrientationFilter> Tracker<F> {
+    /// Tracks the tip using `filter` to estimate orientation
+    #[must_use]
+    pub const fn new(config: Config, filter: F) -> Self {
+        Self {
+            config,
+            estimator: Estimator::new(filter),
+            oriented: false,
+            state: None,
+        }
+    }
+
+    /// Fuses in `timed`.
+    ///
+    /// Acceleration samples are dropped until there's been a gravity sample to orient them with.
+    ///
+    /// # Returns
+    /// The tip's new position if `timed` was an acceleration sample that moved it
+    pub fn feed(&mut self, timed: &TimedSample) -> Option<TrajectoryPoint> {
+        let types::Sample::Accel(accel) = timed.sample else {
+            self.oriented |= self.estimator.feed(timed).is_some();
+            return None;
+        };
+        if !self.oriented {
+            return None;
+        }
+
+        let orientation = self.estimator.orientation();
+        let accel = accel.m_s2();
+        let quiet = norm(accel) < self.config.still_threshold;
+        let accel = orientation::rotate(orientation, accel);
+
+        let state = match self.state.as_mut() {
+            Some(state) => state,
+            None => {
+                let start = State {
+                    last: timed.timestamp,
+                    position: scale(self.tip_offset(orientation), -1.),
+                    velocity: ZERO,
+                    quiet_since: quiet.then_some(timed.timestamp),
+                };
+                self.state = Some(start);
+                return Some(self.point(timed.timestamp, orientation, false));
+            }
+        };
+
+        // trapezoidal integration of velocity into position
+        let dt = timed.timestamp.saturating_sub(state.last).as_secs_f32();
+        let previous_velocity = state.velocity;
+        state.velocity = add(state.velocity, scale(accel, dt));
+        state.position = add(
+            state.position,
+            scale(add(previous_velocity, state.velocity), dt / 2.),
+        );
+        state.last = timed.timestamp;
+
+        state.quiet_since = if quiet {
+            state.quiet_since.or(Some(timed.timestamp))
+        } else {
+            None
+        };
+        let still = state
+            .quiet_since
+            .is_some_and(|since| timed.timestamp.saturating_sub(since) >= self.config.still_time);
+        if still {
+            state.velocity = ZERO;
+        }
+
+        Some(self.point(timed.timestamp, orientation, still))
+    }
+
+    /// The current orientation estimate
+    #[must_use]
+    pub fn orientation(&self) -> Quaternion<f32> {
+        self.estimator.orientation()
+    }
+
+    /// Where the tip is relative to the IMU in the world frame, when oriented as `orientation`
+    fn tip_offset(&self, orientation: Quaternion<f32>) -> Vector3<f32> {
+        orientation::rotate(orientation, self.config.lever_arm.0)
+    }
+
+    fn point(
+        &self,
+        timestamp: Duration,
+        orientation: Quaternion<f32>,
+        still: bool,
+    ) -> TrajectoryPoint {
+        let state = self.state.expect("only called once integrating");
+        TrajectoryPoint {
+            timestamp,
+            position: add(state.position, self.tip_offset(orientation)),
+            velocity: state.velocity,
+            still,
+        }
+    }
+}
+
+/// Reconstructs the whole trajectory of a recording with `tracker`
+///
+/// # Errors
+/// The first error reading `samples`
+pub fn reconstruct<F: OrientationFilter, E>(
+    tracker: &mut Tracker<F>,
+    samples: impl IntoIterator<Item = Result<TimedSample, E>>,
+) -> Result<Vec<TrajectoryPoint>, E> {
+    let mut trajectory = Vec::new();
+    for timed in samples {
+        trajectory.extend(tracker.feed(&timed?));
+    }
+    Ok(trajectory)
+}
+
+#[cfg(test)]
+mod test_trajectory {
+    use super::*;
+    use crate::{
+        orientation::Mahony,
+        test_util::{assert_close, infallible, samples, v},
+        types::imu,
+    };
+
+    const EPSILON: f32 = 1e-3;
+
+    fn flat() -> imu::GravityVector {
+        imu::GravityVector::new(0, 0, 981)
+    }
+
+    fn tracker(config: Config) -> Tracker<Mahony> {
+        Tracker::new(config, Mahony::default())
+    }
+
+    /// The trajectory of a pen resting with gravity `up` before it starts, then accelerating along
+    /// x by `accel` (in counts) each step
+    fn track(
+        config: Config,
+        up: imu::GravityVector,
+        accel: impl IntoIterator<Item = i16>,
+    ) -> Vec<TrajectoryPoint> {
+        let mut tracker = tracker(config);
+        let rest = TimedSample {
+            timestamp: Duration::ZERO,
+            sample: types::Sample::Grav(up),
+        };
+        assert_eq!(tracker.feed(&rest), None);
+        reconstruct(&mut tracker, infallible(samples(accel, Some(up)))).unwrap()
+    }
+
+    #[test]
+    fn parse_lever_arm() {
+        assert_eq!("0.1, 0, -0.01".parse(), Ok(LeverArm(v(0.1, 0., -0.01))));
+        assert!("0.1,0".parse::<LeverArm>().is_err());
+        assert!("a,b,c".parse::<LeverArm>().is_err());
+    }
+
+    #[test]
+    fn needs_orientation() {
+        let mut tracker = tracker(Config::default());
+        let accel = TimedSample {
+            timestamp: Duration::ZERO,
+            sample: types::Sample::Accel(imu::AccelerationVector::new(0, 0, 0)),
+        };
+        assert_eq!(tracker.feed(&accel), None);
+    }
+
+    #[test]
+    fn integrates_twice() {
+        // 1 m/s² along x for a second
+        let trajectory = track(Config::default(), flat(), std::iter::repeat_n(100, 101));
+        let last = trajectory.last().unwrap();
+        assert_eq!(last.timestamp, Duration::from_secs(1));
+        assert_close(last.position, v(0.5, 0., 0.), EPSILON);
+        assert_close(last.velocity, v(1., 0., 0.), EPSILON);
+        assert!(!last.still);
+    }
+
+    #[test]
+    fn rotates_into_world() {
+        // on its side with x up, so the pen's x is the world's z
+        let on_side = imu::GravityVector::new(981, 0, 0);
+        let trajectory = track(Config::default(), on_side, std::iter::repeat_n(100, 101));
+        assert_close(trajectory.last().unwrap().position, v(0., 0., 0.5), EPSILON);
+    }
+
+    #[test]
+    fn resets_velocity_when_still() {
+        // speed up for half a second, then stop dead and sit there
+        let accel = (0..=100).map(|step| if step <= 50 { 200 } else { 0 });
+        let trajectory = track(Config::default(), flat(), accel);
+
+        let last = trajectory.last().unwrap();
+        assert!(last.still);
+        assert_close(last.velocity, v(0., 0., 0.), EPSILON);
+        let settled = trajectory
+            .iter()
+            .find(|point| point.still)
+            .expect("never detected as still");
+        assert_close(last.position, settled.position, EPSILON);
+        assert!(settled.timestamp >= Duration::from_millis(500) + DEFAULT_STILL_TIME);
+    }
+
+    #[test]
+    fn tip_starts_at_origin() {
+        let config = Config {
+            lever_arm: LeverArm(v(0.15, 0., 0.)),
+            ..Config::default()
+        };
+        let trajectory = track(config, flat(), std::iter::repeat_n(0, 11));
+        for point in trajectory {
+            assert_close(point.position, v(0., 0., 0.), EPSILON);
+        }
+    }
+}
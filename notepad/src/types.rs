@@ -1,16 +1,87 @@
 //! Types notepad uses
+use std::time::Duration;
 
 /// the shared vector type pensel firmware produces
 pub use pensel_types::imu;
 
+/// Rate pensel streams each vector at, in Hz
+pub const SAMPLE_RATE: f32 = 100.;
+/// Time between two samples of the same vector, at [`SAMPLE_RATE`]
+pub const SAMPLE_PERIOD: Duration = Duration::from_millis(10);
+
 /// The possible outcomes of parsing a line of data from Pensel
 #[derive(PartialEq, Eq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum ParsedLine {
     None,
     Grav(imu::GravityVector),
     Accel(imu::AccelerationVector),
 }
 
+/// A single sample streamed from Pensel
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Sample {
+    Grav(imu::GravityVector),
+    Accel(imu::AccelerationVector),
+}
+
+impl Sample {
+    /// Pulls the sample out of a parsed line, if it held one
+    #[must_use]
+    pub const fn from_parsed(line: ParsedLine) -> Option<Self> {
+        match line {
+            ParsedLine::None => None,
+            ParsedLine::Grav(g) => Some(Self::Grav(g)),
+            ParsedLine::Accel(a) => Some(Self::Accel(a)),
+        }
+    }
+}
+
+impl std::fmt::Display for Sample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Grav(g) => g.fmt(f),
+            Self::Accel(a) => a.fmt(f),
+        }
+    }
+}
+
+/// What Pensel reports about itself through the `info` command
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceInfo {
+    /// The pen's unique ID
+    pub pen_id: String,
+    /// The firmware version it's running
+    pub firmware: String,
+    /// The BNO055 axis map it runs with, like `P1`. Empty if unknown.
+    pub axis_map: String,
+    /// The BNO055 calibration profile it loaded. Empty if unknown.
+    pub calibration: Vec<u8>,
+}
+
+impl DeviceInfo {
+    /// Placeholder for when the pen can't tell us about itself, e.g. on older firmware
+    #[must_use]
+    pub fn unknown() -> Self {
+        Self {
+            pen_id: "unknown".to_owned(),
+            firmware: "unknown".to_owned(),
+            axis_map: String::new(),
+            calibration: vec![],
+        }
+    }
+}
+
 pub const ACC_QUEUE_SIZE: usize = 100;
 pub const GRAV_QUEUE_SIZE: usize = 100;
 
@@ -26,4 +97,47 @@ mod test_types {
         test_line = ParsedLine::Accel(imu::AccelerationVector::new(1, 2, 3));
         assert_ne!(test_line, ParsedLine::None);
     }
+
+    #[test]
+    fn sample_from_parsed() {
+        assert_eq!(Sample::from_parsed(ParsedLine::None), None);
+        let grav = imu::GravityVector::new(-1, 2, -3);
+        let sample = Sample::from_parsed(ParsedLine::Grav(grav)).unwrap();
+        assert_eq!(sample, Sample::Grav(grav));
+        assert_eq!(sample.to_string(), "G:-1,2,-3");
+    }
+
+    /// The serialized names are part of our data formats, so changing them breaks files
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_field_names() {
+        let sample = Sample::Accel(imu::AccelerationVector::new(1, -2, 3));
+        assert_eq!(
+            serde_json::to_string(&sample).unwrap(),
+            r#"{"accel":{"x":1,"y":-2,"z":3}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&ParsedLine::Grav(imu::GravityVector::new(4, 5, 6))).unwrap(),
+            r#"{"grav":{"x":4,"y":5,"z":6}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&ParsedLine::None).unwrap(),
+            r#""none""#
+        );
+        assert_eq!(
+            serde_json::from_str::<Sample>(r#"{"grav":{"x":4,"y":5,"z":6}}"#).unwrap(),
+            Sample::Grav(imu::GravityVector::new(4, 5, 6))
+        );
+
+        let info = DeviceInfo {
+            pen_id: "0123abcd".to_owned(),
+            firmware: "0.1.0".to_owned(),
+            axis_map: "P1".to_owned(),
+            calibration: vec![1, 2],
+        };
+        assert_eq!(
+            serde_json::to_string(&info).unwrap(),
+            r#"{"pen_id":"0123abcd","firmware":"0.1.0","axis_map":"P1","calibration":[1,2]}"#
+        );
+    }
 }
@@ -0,0 +1,48 @@
+//! The bits of 3D vector math we need on `mint`'s vectors, which are just storage.
+use pensel_types::mint::Vector3;
+
+pub(crate) fn add(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3 {
+        x: a.x + b.x,
+        y: a.y + b.y,
+        z: a.z + b.z,
+    }
+}
+
+pub(crate) fn sub(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3 {
+        x: a.x - b.x,
+        y: a.y - b.y,
+        z: a.z - b.z,
+    }
+}
+
+pub(crate) fn scale(v: Vector3<f32>, factor: f32) -> Vector3<f32> {
+    Vector3 {
+        x: v.x * factor,
+        y: v.y * factor,
+        z: v.z * factor,
+    }
+}
+
+pub(crate) fn dot(a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+pub(crate) fn cross(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3 {
+        x: a.y * b.z - a.z * b.y,
+        y: a.z * b.x - a.x * b.z,
+        z: a.x * b.y - a.y * b.x,
+    }
+}
+
+pub(crate) fn norm(v: Vector3<f32>) -> f32 {
+    dot(v, v).sqrt()
+}
+
+/// Scales `v` to unit length, or `None` if it has none
+pub(crate) fn normalized(v: Vector3<f32>) -> Option<Vector3<f32>> {
+    let norm = norm(v);
+    (norm > 0. && norm.is_finite()).then(|| scale(v, 1. / norm))
+}
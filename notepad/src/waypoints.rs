@@ -0,0 +1,515 @@
+//! Turns a recorded pen path into a program a machine can follow: teaching by demonstration.
+//!
+//! The tip's [poses](Pose) are reconstructed from a recording, thinned out with the
+//! Ramer-Douglas-Peucker algorithm ([`simplify`]), slowed down wherever the pen moved faster than
+//! the machine may ([`limit_speed`]), and written out as G-code for gantries ([`to_gcode`]) or as
+//! JSON poses with timing for robot arms ([`to_json`]).
+use pensel_types::mint::{Quaternion, Vector3};
+use serde_json::json;
+use std::{fmt::Write, path::Path, str::FromStr, time::Duration};
+
+use crate::{
+    angles::AxisMapping,
+    orientation::{self, OrientationFilter},
+    pose::{Placement, Pose},
+    recording::TimedSample,
+    trajectory::Tracker,
+    vector::{dot, norm, sub},
+};
+
+/// Default [`Tolerance::distance`], in meters
+pub const DEFAULT_TOLERANCE: f32 = 0.001;
+/// Default [`Tolerance::angle`], in degrees
+pub const DEFAULT_ANGLE_TOLERANCE: f32 = 5.;
+/// Default [`SpeedLimits::linear`], in m/s
+pub const DEFAULT_MAX_SPEED: f32 = 0.05;
+/// Default [`SpeedLimits::angular`], in degrees per second
+pub const DEFAULT_MAX_ANGULAR_SPEED: f32 = 90.;
+/// The slowest feed [`to_gcode`] writes, in mm/min: the smallest it prints, so it never writes a
+/// feed of zero
+const MIN_FEED: f32 = 0.1;
+
+/// What a waypoint program is written as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramFormat {
+    /// G-code for gantries: positions only, in millimeters
+    Gcode,
+    /// JSON poses with timing
+    Json,
+}
+
+impl ProgramFormat {
+    /// Guesses the format from `path`'s extension, defaulting to [`ProgramFormat::Json`]
+    #[must_use]
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gcode" | "nc" | "ngc") => Self::Gcode,
+            _ => Self::Json,
+        }
+    }
+
+    /// The file extension programs in this format get
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Gcode => "gcode",
+            Self::Json => "json",
+        }
+    }
+}
+
+impl FromStr for ProgramFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gcode" => Ok(Self::Gcode),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown program format '{}', expected 'gcode' or 'json'",
+                s
+            )),
+        }
+    }
+}
+
+/// Where the machine should be, and when
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Waypoint {
+    /// since the program started
+    pub time: Duration,
+    pub pose: Pose,
+}
+
+/// How far a simplified path may stray from the recorded one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    /// in meters
+    pub distance: f32,
+    /// in degrees
+    pub angle: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            distance: DEFAULT_TOLERANCE,
+            angle: DEFAULT_ANGLE_TOLERANCE,
+        }
+    }
+}
+
+/// How fast the machine may move
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedLimits {
+    /// in m/s
+    pub linear: f32,
+    /// in degrees per second
+    pub angular: f32,
+}
+
+impl Default for SpeedLimits {
+    fn default() -> Self {
+        Self {
+            linear: DEFAULT_MAX_SPEED,
+            angular: DEFAULT_MAX_ANGULAR_SPEED,
+        }
+    }
+}
+
+/// Reconstructs a waypoint for every acceleration sample of a recording with `tracker`, posed in
+/// the base frame given by `placement`
+///
+/// # Errors
+/// The first error reading `samples`
+pub fn record<F: OrientationFilter, E>(
+    tracker: &mut Tracker<F>,
+    samples: impl IntoIterator<Item = Result<TimedSample, E>>,
+    mapping: AxisMapping,
+    placement: &Placement,
+) -> Result<Vec<Waypoint>, E> {
+    let mut waypoints = Vec::new();
+    for timed in samples {
+        if let Some(point) = tracker.feed(&timed?) {
+            waypoints.push(Waypoint {
+                time: point.timestamp,
+                pose: Pose::of_tip(point.position, tracker.orientation(), mapping, placement),
+            });
+        }
+    }
+    Ok(waypoints)
+}
+
+fn quat_dot(a: Quaternion<f32>, b: Quaternion<f32>) -> f32 {
+    a.s * b.s + dot(a.v, b.v)
+}
+
+/// The angle between two orientations, in degrees
+fn angle_between(a: Quaternion<f32>, b: Quaternion<f32>) -> f32 {
+    (2. * quat_dot(a, b).abs().min(1.).acos()).to_degrees()
+}
+
+/// Normalized linear interpolation from `a` (at 0) to `b` (at 1), the short way around
+fn nlerp(a: Quaternion<f32>, b: Quaternion<f32>, t: f32) -> Quaternion<f32> {
+    let sign = if quat_dot(a, b) < 0. { -1. } else { 1. };
+    let mix = |a: f32, b: f32| a * (1. - t) + sign * b * t;
+    let q = Quaternion {
+        v: Vector3 {
+            x: mix(a.v.x, b.v.x),
+            y: mix(a.v.y, b.v.y),
+            z: mix(a.v.z, b.v.z),
+        },
+        s: mix(a.s, b.s),
+    };
+    if quat_dot(q, q) > 0. {
+        orientation::normalize(q)
+    } else {
+        a
+    }
+}
+
+/// `value` as a multiple of `tolerance`. Anything above a zero tolerance is too much.
+fn relative(value: f32, tolerance: f32) -> f32 {
+    if tolerance > 0. {
+        value / tolerance
+    } else if value > 0. {
+        f32::INFINITY
+    } else {
+        0.
+    }
+}
+
+/// How far `point` strays from going straight from `start` to `end`, relative to `tolerance`:
+/// above 1 is too far
+fn deviation(point: &Pose, start: &Pose, end: &Pose, tolerance: Tolerance) -> f32 {
+    let along = sub(end.position, start.position);
+    let length_squared = dot(along, along);
+    let t = if length_squared > 0. {
+        (dot(sub(point.position, start.position), along) / length_squared).clamp(0., 1.)
+    } else {
+        0.
+    };
+    let closest = Vector3 {
+        x: start.position.x + along.x * t,
+        y: start.position.y + along.y * t,
+        z: start.position.z + along.z * t,
+    };
+    let offset = sub(point.position, closest);
+    let distance = norm(offset);
+    let angle = angle_between(
+        point.orientation,
+        nlerp(start.orientation, end.orientation, t),
+    );
+    relative(distance, tolerance.distance).max(relative(angle, tolerance.angle))
+}
+
+/// Drops the waypoints that going straight between their neighbours would pass within
+/// `tolerance` of, with the Ramer-Douglas-Peucker algorithm. The first and last are always kept.
+#[must_use]
+pub fn simplify(waypoints: &[Waypoint], tolerance: Tolerance) -> Vec<Waypoint> {
+    if waypoints.len() < 3 {
+        return waypoints.to_vec();
+    }
+    let mut keep = vec![false; waypoints.len()];
+    keep[0] = true;
+    keep[waypoints.len() - 1] = true;
+
+    let mut spans = vec![(0, waypoints.len() - 1)];
+    while let Some((first, last)) = spans.pop() {
+        let (start, end) = (&waypoints[first].pose, &waypoints[last].pose);
+        let furthest = (first + 1..last)
+            .map(|i| (i, deviation(&waypoints[i].pose, start, end, tolerance)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, deviation)) = furthest {
+            if deviation > 1. {
+                keep[i] = true;
+                spans.push((first, i));
+                spans.push((i, last));
+            }
+        }
+    }
+
+    waypoints
+        .iter()
+        .zip(keep)
+        .filter_map(|(waypoint, keep)| keep.then_some(*waypoint))
+        .collect()
+}
+
+/// Retimes `waypoints` so no move between two of them is faster than `limits`. Moves that were
+/// slower keep their timing. Limits that aren't positive don't limit anything.
+#[must_use]
+pub fn limit_speed(waypoints: &[Waypoint], limits: SpeedLimits) -> Vec<Waypoint> {
+    let Some(first) = waypoints.first() else {
+        return Vec::new();
+    };
+    let mut time = first.time;
+    let mut out = vec![*first];
+    for pair in waypoints.windows(2) {
+        let (from, to) = (&pair[0], &pair[1]);
+        let offset = sub(to.pose.position, from.pose.position);
+        let distance = norm(offset);
+        let angle = angle_between(from.pose.orientation, to.pose.orientation);
+        let needed = |amount: f32, limit: f32| {
+            if limit > 0. {
+                amount / limit
+            } else {
+                0.
+            }
+        };
+        let seconds = needed(distance, limits.linear).max(needed(angle, limits.angular));
+        // tiny limits can need more time than a `Duration` holds, and garbage poses none at all
+        let fastest = Duration::try_from_secs_f32(seconds).unwrap_or(if seconds > 0. {
+            Duration::MAX
+        } else {
+            Duration::ZERO
+        });
+        time = time.saturating_add(to.time.saturating_sub(from.time).max(fastest));
+        out.push(Waypoint {
+            time,
+            pose: to.pose,
+        });
+    }
+    out
+}
+
+/// Millimeters, as G-code wants them
+fn mm(meters: f32) -> f32 {
+    meters * 1000.
+}
+
+/// The waypoints as G-code: a rapid move to the first, then linear moves at the speed needed to
+/// keep to their timing, in millimeters. Moves with no time to take go at the `limits`' linear
+/// speed, and none go slower than [`MIN_FEED`]. Pauses in place become dwells in seconds (`G4 S<seconds>`, as Marlin
+/// and RepRapFirmware read it). Orientation is dropped, gantries can't follow it.
+#[must_use]
+pub fn to_gcode(waypoints: &[Waypoint], limits: SpeedLimits) -> String {
+    let mut out = String::new();
+    let duration = match (waypoints.first(), waypoints.last()) {
+        (Some(first), Some(last)) => last.time.saturating_sub(first.time),
+        _ => Duration::ZERO,
+    };
+    writeln!(
+        out,
+        "; pensel waypoint program: {} waypoints over {:.2} s",
+        waypoints.len(),
+        duration.as_secs_f32()
+    )
+    .unwrap();
+    writeln!(out, "G21 ; millimeters").unwrap();
+    writeln!(out, "G90 ; absolute positions").unwrap();
+
+    if let Some(first) = waypoints.first() {
+        let p = first.pose.position;
+        writeln!(out, "G0 X{:.3} Y{:.3} Z{:.3}", mm(p.x), mm(p.y), mm(p.z)).unwrap();
+    }
+    for pair in waypoints.windows(2) {
+        let (from, to) = (&pair[0], &pair[1]);
+        let seconds = to.time.saturating_sub(from.time).as_secs_f32();
+        let offset = sub(to.pose.position, from.pose.position);
+        let distance = mm(norm(offset));
+        // anything under a thousandth of a millimeter rounds away in the output anyway
+        if distance < 1e-3 {
+            if seconds > 0. {
+                writeln!(out, "G4 S{:.3}", seconds).unwrap();
+            }
+            continue;
+        }
+        let p = to.pose.position;
+        // in mm/min. Always given, so nothing runs at whatever speed the machine was left at.
+        let feed = if seconds > 0. {
+            distance / seconds * 60.
+        } else {
+            mm(limits.linear) * 60.
+        }
+        .max(MIN_FEED);
+        writeln!(
+            out,
+            "G1 X{:.3} Y{:.3} Z{:.3} F{:.1}",
+            mm(p.x),
+            mm(p.y),
+            mm(p.z),
+            feed
+        )
+        .unwrap();
+    }
+    writeln!(out, "M2 ; end of program").unwrap();
+    out
+}
+
+/// The waypoints as JSON: an object with the `frame_id` they're in, and a list of `waypoints`
+/// each with a `time_s`, a `position` in meters and an `orientation` quaternion
+#[must_use]
+pub fn to_json(waypoints: &[Waypoint], frame_id: &str) -> String {
+    let waypoints: Vec<_> = waypoints
+        .iter()
+        .map(|waypoint| {
+            let Pose {
+                position,
+                orientation,
+            } = waypoint.pose;
+            json!({
+                "time_s": waypoint.time.as_secs_f64(),
+                "position": {"x": position.x, "y": position.y, "z": position.z},
+                "orientation": {
+                    "x": orientation.v.x,
+                    "y": orientation.v.y,
+                    "z": orientation.v.z,
+                    "w": orientation.s,
+                },
+            })
+        })
+        .collect();
+    serde_json::to_string_pretty(&json!({
+        "frame_id": frame_id,
+        "waypoints": waypoints,
+    }))
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test_waypoints {
+    use super::*;
+    use crate::orientation;
+
+    fn at(millis: u64, x: f32, y: f32, yaw: f32) -> Waypoint {
+        Waypoint {
+            time: Duration::from_millis(millis),
+            pose: Pose {
+                position: Vector3 { x, y, z: 0. },
+                orientation: orientation::from_euler(0., 0., yaw.to_radians()),
+            },
+        }
+    }
+
+    #[test]
+    fn simplifies_straight_lines() {
+        // along x, wobbling well within a millimeter, then a corner up y
+        let mut path: Vec<Waypoint> = (0..=10)
+            .map(|i| {
+                at(
+                    i * 10,
+                    i as f32 * 0.01,
+                    if i % 2 == 0 { 0. } else { 0.0002 },
+                    0.,
+                )
+            })
+            .collect();
+        path.extend((1..=5).map(|i| at(100 + i * 10, 0.1, i as f32 * 0.01, 0.)));
+
+        let simple = simplify(&path, Tolerance::default());
+        let corners: Vec<_> = simple.iter().map(|w| w.time.as_millis()).collect();
+        assert_eq!(corners, [0, 100, 150]);
+
+        // the wobbles stay without any tolerance
+        let exact = Tolerance {
+            distance: 0.,
+            angle: 0.,
+        };
+        assert!(simplify(&path, exact).len() >= 10);
+        assert_eq!(simplify(&path[..2], Tolerance::default()), path[..2]);
+    }
+
+    #[test]
+    fn keeps_turns_in_place() {
+        // the pen turns without moving
+        let path = [at(0, 0., 0., 0.), at(100, 0., 0., 45.), at(200, 0., 0., 0.)];
+        assert_eq!(simplify(&path, Tolerance::default()).len(), 3);
+        let loose = Tolerance {
+            distance: DEFAULT_TOLERANCE,
+            angle: 60.,
+        };
+        assert_eq!(simplify(&path, loose).len(), 2);
+    }
+
+    #[test]
+    fn limits_speed() {
+        // 10 cm in 100 ms is 1 m/s, so at 0.05 m/s it should take 2 s
+        let path = [
+            at(0, 0., 0., 0.),
+            at(100, 0.1, 0., 0.),
+            at(5000, 0.1, 0.01, 0.),
+        ];
+        let limited = limit_speed(&path, SpeedLimits::default());
+        assert_eq!(limited[0].time, Duration::ZERO);
+        assert!((limited[1].time.as_secs_f32() - 2.).abs() < 1e-3);
+        // the slow move keeps its 4.9 s
+        assert!((limited[2].time.as_secs_f32() - 6.9).abs() < 1e-3);
+
+        // turning 90° at 90°/s takes a second
+        let turn = [at(0, 0., 0., 0.), at(10, 0., 0., 90.)];
+        let limited = limit_speed(&turn, SpeedLimits::default());
+        assert!((limited[1].time.as_secs_f32() - 1.).abs() < 1e-3);
+        assert!(limit_speed(&[], SpeedLimits::default()).is_empty());
+
+        // limits too tight for a `Duration` saturate rather than panic
+        let crawl = SpeedLimits {
+            linear: 1e-30,
+            angular: 0.,
+        };
+        let limited = limit_speed(&path, crawl);
+        assert_eq!(limited[1].time, Duration::MAX);
+        assert_eq!(limited[2].time, Duration::MAX);
+        let mut garbage = path;
+        garbage[1].pose.position.x = f32::NAN;
+        let limited = limit_speed(&garbage, SpeedLimits::default());
+        assert_eq!(limited[1].time, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn writes_gcode() {
+        let path = [
+            at(0, 0.01, 0.02, 0.),
+            at(1000, 0.02, 0.02, 0.),
+            at(1500, 0.02, 0.02, 0.),
+        ];
+        let gcode = to_gcode(&path, SpeedLimits::default());
+        let lines: Vec<_> = gcode
+            .lines()
+            .filter(|line| !line.starts_with(';'))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "G21 ; millimeters",
+                "G90 ; absolute positions",
+                "G0 X10.000 Y20.000 Z0.000",
+                "G1 X20.000 Y20.000 Z0.000 F600.0",
+                "G4 S0.500",
+                "M2 ; end of program",
+            ]
+        );
+
+        // even moves without timing get a feed rate, the limit's
+        let limits = SpeedLimits {
+            linear: 0.02,
+            ..SpeedLimits::default()
+        };
+        let instant = to_gcode(&[at(0, 0., 0., 0.), at(0, 0.01, 0., 0.)], limits);
+        assert!(instant.contains("G1 X10.000 Y0.000 Z0.000 F1200.0\n"));
+        // and a crawl doesn't round down to no feed at all
+        let crawl = to_gcode(
+            &[at(0, 0., 0., 0.), at(3_600_000, 0.000_01, 0., 0.)],
+            limits,
+        );
+        assert!(
+            crawl.contains("G1 X0.010 Y0.000 Z0.000 F0.1\n"),
+            "{}",
+            crawl
+        );
+    }
+
+    #[test]
+    fn writes_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&to_json(&[at(250, 0.1, 0.2, 0.)], "base_link")).unwrap();
+        assert_eq!(json["frame_id"], "base_link");
+        let waypoint = &json["waypoints"][0];
+        assert_eq!(waypoint["time_s"], 0.25);
+        assert!((waypoint["position"]["y"].as_f64().unwrap() - 0.2).abs() < 1e-6);
+        assert_eq!(waypoint["orientation"]["w"], 1.);
+        assert_eq!(ProgramFormat::from_path("joints.ngc"), ProgramFormat::Gcode);
+        assert_eq!(ProgramFormat::from_path("joints.txt"), ProgramFormat::Json);
+        assert_eq!("gcode".parse(), Ok(ProgramFormat::Gcode));
+    }
+}
@@ -0,0 +1,380 @@
+//! Wireframe models of the pen, posed by an orientation and projected onto a flat view, for
+//! drawing where the pen is pointing in a terminal.
+//!
+//! Models are built in the sensor's frame, so they line up with the pen however the IMU is
+//! mounted (see [`AxisMapping`]). They're then rotated into the world frame (z up) by an
+//! orientation, and looked at by a [`Camera`] circling the origin.
+use pensel_types::mint::{Quaternion, Vector3};
+use std::{f32::consts::TAU, fmt, str::FromStr};
+
+use crate::{
+    angles::AxisMapping,
+    orientation,
+    vector::{add, cross, scale},
+};
+
+/// Length of the pen model, back to tip
+pub const PEN_LENGTH: f32 = 2.;
+/// Radius of the pen model's barrel
+pub const PEN_RADIUS: f32 = 0.15;
+/// Length of the cone at the pen model's tip
+const TIP_LENGTH: f32 = 0.4;
+/// Sides of the pen model's barrel
+const SIDES: u8 = 6;
+
+/// Which part of a model a [`Segment`] outlines, e.g. to colour it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    /// the pen's barrel
+    Body,
+    /// the cone at the pen's tip
+    Tip,
+    /// the edge of the barrel along the pen's top, to show its roll
+    Top,
+    /// one of the sensor's axes: 0 for x, 1 for y, 2 for z
+    Axis(usize),
+    /// the floor the pen is drawn over
+    Ground,
+}
+
+/// A straight line of a wireframe
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub from: Vector3<f32>,
+    pub to: Vector3<f32>,
+    pub part: Part,
+}
+
+impl Segment {
+    /// The segment rotated from the pen's frame into the world frame, by `orientation`
+    #[must_use]
+    pub fn rotated(self, orientation: Quaternion<f32>) -> Self {
+        Self {
+            from: orientation::rotate(orientation, self.from),
+            to: orientation::rotate(orientation, self.to),
+            part: self.part,
+        }
+    }
+}
+
+/// What to draw for the pen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// a pen, with a pointed tip and a stripe along its top
+    Pen,
+    /// just the sensor's three axes
+    Axes,
+}
+
+impl Model {
+    /// The model's segments in the sensor's frame, centred on the origin, for a pen whose IMU is
+    /// mounted as described by `mapping`
+    #[must_use]
+    pub fn segments(self, mapping: AxisMapping) -> Vec<Segment> {
+        match self {
+            Self::Pen => pen(mapping),
+            Self::Axes => (0..3)
+                .map(|axis| {
+                    let mut to = [0.; 3];
+                    to[axis] = PEN_LENGTH / 2.;
+                    Segment {
+                        from: Vector3 {
+                            x: 0.,
+                            y: 0.,
+                            z: 0.,
+                        },
+                        to: to.into(),
+                        part: Part::Axis(axis),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pen" => Ok(Self::Pen),
+            "axes" => Ok(Self::Axes),
+            _ => Err(format!("unknown model '{}', expected 'pen' or 'axes'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pen => write!(f, "pen"),
+            Self::Axes => write!(f, "axes"),
+        }
+    }
+}
+
+/// `a * a_scale + b * b_scale + c * c_scale`
+fn combine(
+    a: Vector3<f32>,
+    a_scale: f32,
+    b: Vector3<f32>,
+    b_scale: f32,
+    c: Vector3<f32>,
+    c_scale: f32,
+) -> Vector3<f32> {
+    add(add(scale(a, a_scale), scale(b, b_scale)), scale(c, c_scale))
+}
+
+/// A hexagonal barrel along the tip axis, ending in a cone
+fn pen(mapping: AxisMapping) -> Vec<Segment> {
+    let tip = mapping.tip.unit();
+    let top = mapping.top.unit();
+    let side = cross(tip, top);
+
+    let back = -PEN_LENGTH / 2.;
+    let front = PEN_LENGTH / 2. - TIP_LENGTH;
+    let point = scale(tip, PEN_LENGTH / 2.);
+    // the barrel's edges, starting at the top and going around
+    let edges: Vec<(f32, f32)> = (0..SIDES)
+        .map(|i| {
+            let angle = TAU * f32::from(i) / f32::from(SIDES);
+            (PEN_RADIUS * angle.cos(), PEN_RADIUS * angle.sin())
+        })
+        .collect();
+
+    let mut segments = Vec::new();
+    for (i, (up, across)) in edges.iter().enumerate() {
+        let (next_up, next_across) = edges[(i + 1) % edges.len()];
+        let at = |along: f32, up: f32, across: f32| combine(tip, along, top, up, side, across);
+        segments.extend([
+            Segment {
+                from: at(back, *up, *across),
+                to: at(front, *up, *across),
+                part: if i == 0 { Part::Top } else { Part::Body },
+            },
+            Segment {
+                from: at(back, *up, *across),
+                to: at(back, next_up, next_across),
+                part: Part::Body,
+            },
+            Segment {
+                from: at(front, *up, *across),
+                to: at(front, next_up, next_across),
+                part: Part::Body,
+            },
+            Segment {
+                from: at(front, *up, *across),
+                to: point,
+                part: Part::Tip,
+            },
+        ]);
+    }
+    segments
+}
+
+/// A square grid `size` across, in the world frame, `height` below the origin with `lines`
+/// lines each way
+#[must_use]
+pub fn ground(size: f32, height: f32, lines: u8) -> Vec<Segment> {
+    let half = size / 2.;
+    let step = size / f32::from(lines.max(2) - 1);
+    (0..lines.max(2))
+        .flat_map(|i| {
+            let offset = -half + step * f32::from(i);
+            [
+                Segment {
+                    from: Vector3 {
+                        x: -half,
+                        y: offset,
+                        z: -height,
+                    },
+                    to: Vector3 {
+                        x: half,
+                        y: offset,
+                        z: -height,
+                    },
+                    part: Part::Ground,
+                },
+                Segment {
+                    from: Vector3 {
+                        x: offset,
+                        y: -half,
+                        z: -height,
+                    },
+                    to: Vector3 {
+                        x: offset,
+                        y: half,
+                        z: -height,
+                    },
+                    part: Part::Ground,
+                },
+            ]
+        })
+        .collect()
+}
+
+/// Where a model is looked at from: circling the origin, always facing it, with world z up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    /// degrees counter clockwise from world x, seen from above
+    pub azimuth: f32,
+    /// degrees above the horizon, within ±90°
+    pub elevation: f32,
+}
+
+impl Camera {
+    /// Off to the side and a little above, so both tilt and heading are easy to see
+    pub const DEFAULT: Self = Self {
+        azimuth: 300.,
+        elevation: 20.,
+    };
+
+    /// The camera moved around the origin by `azimuth` and `elevation` degrees. It stops short
+    /// of looking straight down or up, where which way is up on screen flips.
+    #[must_use]
+    pub fn orbited(self, azimuth: f32, elevation: f32) -> Self {
+        Self {
+            azimuth: (self.azimuth + azimuth).rem_euclid(360.),
+            elevation: (self.elevation + elevation).clamp(-89., 89.),
+        }
+    }
+
+    /// Where `point`, in the world frame, lands on screen: `(right, up)`, in the same units
+    #[must_use]
+    pub fn project(&self, point: Vector3<f32>) -> (f32, f32) {
+        let (az_sin, az_cos) = self.azimuth.to_radians().sin_cos();
+        let (el_sin, el_cos) = self.elevation.to_radians().sin_cos();
+        let right = -az_sin * point.x + az_cos * point.y;
+        let up = -el_sin * az_cos * point.x - el_sin * az_sin * point.y + el_cos * point.z;
+        (right, up)
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[cfg(test)]
+mod test_wireframe {
+    use super::*;
+    use crate::{
+        angles::SignedAxis,
+        vector::{norm, sub},
+    };
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4
+    }
+
+    fn length(segment: &Segment) -> f32 {
+        norm(sub(segment.to, segment.from))
+    }
+
+    #[test]
+    fn pen_points_along_tip_axis() {
+        let mapping = AxisMapping {
+            tip: SignedAxis::Y.flipped(),
+            top: SignedAxis::X,
+        };
+        let segments = Model::Pen.segments(mapping);
+        let tip = segments
+            .iter()
+            .find(|segment| segment.part == Part::Tip)
+            .unwrap()
+            .to;
+        assert!(tip.x.abs() < 1e-6 && (tip.y + PEN_LENGTH / 2.).abs() < 1e-6);
+
+        // the top stripe runs along the top of the barrel
+        let top = segments
+            .iter()
+            .find(|segment| segment.part == Part::Top)
+            .unwrap();
+        assert!((top.from.x - PEN_RADIUS).abs() < 1e-6);
+        assert!((top.to.x - PEN_RADIUS).abs() < 1e-6);
+        assert_eq!(
+            segments.len(),
+            usize::from(SIDES) * 4,
+            "barrel edges, both rims and the cone"
+        );
+    }
+
+    #[test]
+    fn rotation_keeps_shape() {
+        let orientation = orientation::from_up(Vector3 {
+            x: 0.3,
+            y: -0.5,
+            z: 0.8,
+        });
+        for segment in Model::Pen.segments(AxisMapping::DEFAULT) {
+            let rotated = segment.rotated(orientation);
+            assert!((length(&segment) - length(&rotated)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn vertical_pen_points_down_on_screen() {
+        // gravity along -x means the tip (+x) points at the floor
+        let orientation = orientation::from_up(Vector3 {
+            x: -9.81,
+            y: 0.,
+            z: 0.,
+        });
+        let camera = Camera::DEFAULT.orbited(45., 10.);
+        let tip = Model::Axes.segments(AxisMapping::DEFAULT)[0]
+            .rotated(orientation)
+            .to;
+        assert!(close(
+            camera.project(tip),
+            (0., -PEN_LENGTH / 2. * 30_f32.to_radians().cos())
+        ));
+    }
+
+    #[test]
+    fn camera_projects() {
+        let camera = Camera {
+            azimuth: 0.,
+            elevation: 0.,
+        };
+        let x = Vector3 {
+            x: 1.,
+            y: 0.,
+            z: 0.,
+        };
+        let y = Vector3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        };
+        let z = Vector3 {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        };
+        // looking from +x towards the origin, y is to the right and z up
+        assert!(close(camera.project(x), (0., 0.)));
+        assert!(close(camera.project(y), (1., 0.)));
+        assert!(close(camera.project(z), (0., 1.)));
+
+        // from straight above-ish, x is down the screen
+        let above = camera.orbited(0., 120.);
+        assert_eq!(above.elevation, 89.);
+        assert!(close(above.project(x), (0., -89_f32.to_radians().sin())));
+        assert_eq!(camera.orbited(-30., 0.).azimuth, 330.);
+    }
+
+    #[test]
+    fn ground_grid() {
+        let grid = ground(2., 1., 5);
+        assert_eq!(grid.len(), 10);
+        assert!(grid
+            .iter()
+            .all(|segment| segment.from.z == -1. && segment.to.z == -1.));
+        assert!(grid
+            .iter()
+            .all(|segment| (length(segment) - 2.).abs() < 1e-6));
+        assert_eq!("axes".parse::<Model>().unwrap(), Model::Axes);
+        assert!("cube".parse::<Model>().is_err());
+    }
+}
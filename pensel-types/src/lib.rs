@@ -1,5 +1,5 @@
 //! The types shared between pensel FW and the SW that talks to it
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 #![warn(missing_docs)]
 
 /// re-export of [`bno055`] for downstream crates
@@ -22,6 +22,8 @@ pub mod cli {
     pub const ARG_GRAVITY: &str = "gravity";
     /// argument to `imu` command to enable streaming of the accel vector
     pub const ARG_ACCEL: &str = "accel";
+    /// argument to `imu` command to set the BNO055's [axis map](crate::imu::AxisMap)
+    pub const ARG_AXIS_MAP: &str = "axis-map";
 
     /// control our logging facilities
     pub const CMD_LOG: &str = "log";
@@ -29,6 +31,99 @@ pub mod cli {
     pub const ARG_LEVEL_SET: &str = "level";
     /// retrieve current log level
     pub const ARG_LEVEL_GET: &str = "level-get";
+
+    /// inspect our USB serial connection
+    pub const CMD_USB: &str = "usb";
+    /// argument to `usb` command to print transmit buffer usage and dropped byte counters
+    pub const ARG_STATS: &str = "stats";
+
+    /// describes this pen, one `key: value` line per `INFO_*` key
+    pub const CMD_INFO: &str = "info";
+    /// `info` key for the firmware version
+    pub const INFO_FIRMWARE: &str = "firmware";
+    /// `info` key for how many counts the streamed acceleration and gravity have per m/s²
+    /// ([`ACCEL_LSB_PER_M_S2`](crate::imu::scale::ACCEL_LSB_PER_M_S2))
+    pub const INFO_ACCEL_SCALE: &str = "accel scale";
+    /// `info` key for the pen's unique ID (the MCU's serial number, in hex)
+    pub const INFO_PEN_ID: &str = "pen id";
+    /// `info` key for the BNO055 [axis map](crate::imu::AxisMap) in use
+    pub const INFO_AXIS_MAP: &str = "axis map";
+    /// `info` key for the BNO055 calibration profile in use, as comma separated bytes
+    pub const INFO_CALIBRATION: &str = "calibration";
+
+    /// An argument a [`Command`] takes, given on the command line as `--name` or `--name=value`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    pub struct Arg {
+        /// the argument's name, without the leading `--`
+        pub name: &'static str,
+        /// whether the argument is given as `--name=value`
+        pub takes_value: bool,
+    }
+
+    /// A command pensel's CLI understands, along with its arguments
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    pub struct Command {
+        /// the command itself
+        pub name: &'static str,
+        /// the arguments it takes
+        pub args: &'static [Arg],
+    }
+
+    /// Every command pensel's CLI understands. Lets host side tools complete and check commands
+    /// without talking to the pen.
+    pub const COMMANDS: &[Command] = &[
+        Command {
+            name: CMD_PANIC,
+            args: &[],
+        },
+        Command {
+            name: CMD_RESET,
+            args: &[],
+        },
+        Command {
+            name: CMD_IMU,
+            args: &[
+                Arg {
+                    name: ARG_ACCEL,
+                    takes_value: false,
+                },
+                Arg {
+                    name: ARG_GRAVITY,
+                    takes_value: false,
+                },
+                Arg {
+                    name: ARG_AXIS_MAP,
+                    takes_value: true,
+                },
+            ],
+        },
+        Command {
+            name: CMD_LOG,
+            args: &[
+                Arg {
+                    name: ARG_LEVEL_SET,
+                    takes_value: true,
+                },
+                Arg {
+                    name: ARG_LEVEL_GET,
+                    takes_value: false,
+                },
+            ],
+        },
+        Command {
+            name: CMD_INFO,
+            args: &[],
+        },
+        Command {
+            name: CMD_USB,
+            args: &[Arg {
+                name: ARG_STATS,
+                takes_value: false,
+            }],
+        },
+    ];
 }
 
 pub mod imu {
@@ -36,52 +131,131 @@ pub mod imu {
 
     use core::fmt;
 
-    /// A fixed point 3D vector coming from pensel. Could be linear acceleration or gravity.
+    use bno055::mint;
+
+    pub mod scale {
+        //! How many BNO055 fixed point counts (LSB) make up one engineering unit, for the BNO055's
+        //! default unit selection. These are also the units pensel streams in.
+
+        /// counts per m/s², for acceleration, linear acceleration and gravity
+        pub const ACCEL_LSB_PER_M_S2: f32 = 100.;
+        /// counts per degree per second, for angular rate
+        pub const GYRO_LSB_PER_DPS: f32 = 16.;
+        /// counts per µT, for the magnetic field
+        pub const MAG_LSB_PER_UT: f32 = 16.;
+        /// counts per unit, for each quaternion component
+        pub const QUATERNION_LSB_PER_UNIT: f32 = 16384.;
+    }
+
+    /// A fixed point 3D vector coming from pensel, in BNO055 counts. `PREFIX` says which
+    /// quantity it holds, and picks the unit conversions available on it.
+    ///
+    /// With the `serde` feature, serializes as a struct with `x`, `y` and `z` fields.
     #[repr(transparent)]
-    #[derive(Debug, PartialEq, Eq, derive_more::From, derive_more::Deref)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, derive_more::From, derive_more::Deref)]
+    #[cfg_attr(
+        feature = "serde",
+        derive(serde::Serialize, serde::Deserialize),
+        serde(from = "Xyz", into = "Xyz")
+    )]
     pub struct FixedPointVector<const PREFIX: char>(bno055::mint::Vector3<i16>);
 
+    /// What a [`FixedPointVector`] looks like serialized. Kept separate so the field names don't
+    /// depend on `mint`'s serde support.
+    #[cfg(feature = "serde")]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Xyz {
+        x: i16,
+        y: i16,
+        z: i16,
+    }
+
+    #[cfg(feature = "serde")]
+    impl<const P: char> From<Xyz> for FixedPointVector<P> {
+        fn from(xyz: Xyz) -> Self {
+            Self::new(xyz.x, xyz.y, xyz.z)
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    impl<const P: char> From<FixedPointVector<P>> for Xyz {
+        fn from(vector: FixedPointVector<P>) -> Self {
+            Self {
+                x: vector.x,
+                y: vector.y,
+                z: vector.z,
+            }
+        }
+    }
+
+    /// Why a line couldn't be parsed into a [`FixedPointVector`]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ParseVectorError {
+        /// The line doesn't start with the vector's `P:` prefix
+        MissingPrefix {
+            /// the prefix character we expected
+            expected: char,
+        },
+        /// The line doesn't have exactly three comma separated components
+        WrongComponentCount(usize),
+        /// A component isn't a whole number, or has stray characters around it
+        BadNumber {
+            /// index of the offending component
+            component: usize,
+        },
+        /// A component is a number, but doesn't fit in an `i16`
+        Overflow {
+            /// index of the offending component
+            component: usize,
+        },
+    }
+
+    impl fmt::Display for ParseVectorError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::MissingPrefix { expected } => write!(f, "missing '{}:' prefix", expected),
+                Self::WrongComponentCount(count) => {
+                    write!(f, "expected 3 components, found {}", count)
+                }
+                Self::BadNumber { component } => {
+                    write!(f, "component {} is not a number", component)
+                }
+                Self::Overflow { component } => {
+                    write!(f, "component {} is out of range", component)
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl std::error::Error for ParseVectorError {}
+
     impl<const P: char> core::str::FromStr for FixedPointVector<P> {
-        type Err = core::fmt::Error;
+        type Err = ParseVectorError;
 
+        /// Parses the `P:x,y,z` format [`FixedPointVector`] displays as. Whitespace around the
+        /// numbers, like a trailing line ending, is ignored.
         fn from_str(s: &str) -> Result<Self, Self::Err> {
-            const FUNC_NAME: &str = "FixedPointVector::from_str";
-            /// checks for a decimal digit
-            const fn is_decimal_digit(input: char) -> bool {
-                input.is_ascii_digit() || input == '-'
-            }
+            let components = s
+                .strip_prefix(P)
+                .and_then(|rest| rest.strip_prefix(':'))
+                .ok_or(ParseVectorError::MissingPrefix { expected: P })?;
 
-            log::debug!("{}: parsing {:?}", FUNC_NAME, s);
-            if !s.starts_with(P) {
-                log::error!("{}: missing prefix {} in {:#?}", FUNC_NAME, P, s);
-                return Err(core::fmt::Error);
+            let count = components.split(',').count();
+            if count != 3 {
+                return Err(ParseVectorError::WrongComponentCount(count));
             }
 
-            // split by comma and look for 3 digits to parse out
             let mut values: [i16; 3] = [0; 3];
-            for (count, item) in s.split(',').enumerate() {
-                if count == 3 {
-                    log::error!("{}: Too many comma separated values in {:#?}", FUNC_NAME, s);
-                    return Err(core::fmt::Error);
-                }
-
-                // parse out the digit, if we can find it
-                if let Some(start_ind) = item.find(is_decimal_digit) {
-                    let slice_to_parse = item[start_ind..].trim_end();
-                    if let Ok(digit) = i16::from_str(slice_to_parse) {
-                        values[count] = digit;
-                    } else {
-                        log::error!(
-                            "{}: failed to convert '{}' to i16",
-                            FUNC_NAME,
-                            slice_to_parse
-                        );
-                        return Err(core::fmt::Error);
+            for (component, (value, item)) in
+                values.iter_mut().zip(components.split(',')).enumerate()
+            {
+                *value = i16::from_str(item.trim()).map_err(|error| match error.kind() {
+                    core::num::IntErrorKind::PosOverflow | core::num::IntErrorKind::NegOverflow => {
+                        ParseVectorError::Overflow { component }
                     }
-                } else {
-                    log::error!("{}: failed to find a number in {}", FUNC_NAME, item);
-                    return Err(core::fmt::Error);
-                }
+                    _ => ParseVectorError::BadNumber { component },
+                })?;
             }
 
             Ok(Self::new(values[0], values[1], values[2]))
@@ -100,6 +274,38 @@ pub mod imu {
         pub const fn new(x: i16, y: i16, z: i16) -> Self {
             Self(bno055::mint::Vector3::<i16> { x, y, z })
         }
+
+        /// Divides each component by `lsb_per_unit`
+        fn scaled(&self, lsb_per_unit: f32) -> mint::Vector3<f32> {
+            mint::Vector3 {
+                x: f32::from(self.x) / lsb_per_unit,
+                y: f32::from(self.y) / lsb_per_unit,
+                z: f32::from(self.z) / lsb_per_unit,
+            }
+        }
+
+        /// Multiplies each component by `lsb_per_unit`, if the result fits in our counts
+        fn from_scaled(value: mint::Vector3<f32>, lsb_per_unit: f32) -> Option<Self> {
+            /// rounds to the nearest count, if in range
+            fn to_counts(value: f32, lsb_per_unit: f32) -> Option<i16> {
+                let counts = value * lsb_per_unit;
+                // `as` saturates, so check the range the value rounds into first. NaN fails both
+                // comparisons.
+                (counts > f32::from(i16::MIN) - 0.5 && counts < f32::from(i16::MAX) + 0.5).then(
+                    || {
+                        // no `f32::round` without std, so round half away from zero by hand
+                        let half = if counts < 0. { -0.5 } else { 0.5 };
+                        (counts + half) as i16
+                    },
+                )
+            }
+
+            Some(Self::new(
+                to_counts(value.x, lsb_per_unit)?,
+                to_counts(value.y, lsb_per_unit)?,
+                to_counts(value.z, lsb_per_unit)?,
+            ))
+        }
     }
 
     /// Gravity vector
@@ -107,4 +313,472 @@ pub mod imu {
 
     /// Linear acceleration vector
     pub type AccelerationVector = FixedPointVector<'A'>;
+
+    /// Angular rate vector
+    pub type GyroVector = FixedPointVector<'R'>;
+
+    /// Magnetic field vector
+    pub type MagVector = FixedPointVector<'M'>;
+
+    impl GravityVector {
+        /// The gravity vector in m/s²
+        #[must_use]
+        pub fn m_s2(&self) -> mint::Vector3<f32> {
+            self.scaled(scale::ACCEL_LSB_PER_M_S2)
+        }
+
+        /// Converts a gravity vector in m/s² to counts. `None` if it's out of range.
+        #[must_use]
+        pub fn from_m_s2(value: mint::Vector3<f32>) -> Option<Self> {
+            Self::from_scaled(value, scale::ACCEL_LSB_PER_M_S2)
+        }
+    }
+
+    impl AccelerationVector {
+        /// The linear acceleration in m/s²
+        #[must_use]
+        pub fn m_s2(&self) -> mint::Vector3<f32> {
+            self.scaled(scale::ACCEL_LSB_PER_M_S2)
+        }
+
+        /// Converts a linear acceleration in m/s² to counts. `None` if it's out of range.
+        #[must_use]
+        pub fn from_m_s2(value: mint::Vector3<f32>) -> Option<Self> {
+            Self::from_scaled(value, scale::ACCEL_LSB_PER_M_S2)
+        }
+    }
+
+    impl GyroVector {
+        /// The angular rate in rad/s
+        #[must_use]
+        pub fn rad_s(&self) -> mint::Vector3<f32> {
+            self.scaled(scale::GYRO_LSB_PER_DPS * (180. / core::f32::consts::PI))
+        }
+    }
+
+    impl MagVector {
+        /// The magnetic field in µT
+        #[must_use]
+        pub fn micro_tesla(&self) -> mint::Vector3<f32> {
+            self.scaled(scale::MAG_LSB_PER_UT)
+        }
+    }
+
+    /// A fixed point orientation quaternion from the BNO055, in counts
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct FixedPointQuaternion {
+        /// scalar part
+        pub w: i16,
+        /// `i` component
+        pub x: i16,
+        /// `j` component
+        pub y: i16,
+        /// `k` component
+        pub z: i16,
+    }
+
+    impl FixedPointQuaternion {
+        /// Initializes a new `FixedPointQuaternion`.
+        #[must_use]
+        pub const fn new(w: i16, x: i16, y: i16, z: i16) -> Self {
+            Self { w, x, y, z }
+        }
+
+        /// The orientation as a unit quaternion. The BNO055 scales its output so this has unit
+        /// length, give or take its fixed point resolution.
+        #[must_use]
+        pub fn unit(&self) -> mint::Quaternion<f32> {
+            let component = |counts: i16| f32::from(counts) / scale::QUATERNION_LSB_PER_UNIT;
+            mint::Quaternion {
+                v: mint::Vector3 {
+                    x: component(self.x),
+                    y: component(self.y),
+                    z: component(self.z),
+                },
+                s: component(self.w),
+            }
+        }
+    }
+
+    /// How the BNO055 remaps its axes, so they follow the pen however the board sits in its
+    /// shell. Each of the remapped x, y and z takes one of the chip's axes, possibly negated.
+    ///
+    /// Displays and parses as one of the datasheet's placements, `P0` to `P7`, or as the chip
+    /// axes x, y and z take, like `-y,+x,+z`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct AxisMap {
+        /// the `AXIS_MAP_CONFIG` register: two bits per remapped axis, x lowest, naming the chip
+        /// axis it takes (0 for x, 1 for y, 2 for z)
+        config: u8,
+        /// the `AXIS_MAP_SIGN` register: bit 2 negates x, bit 1 y and bit 0 z
+        sign: u8,
+    }
+
+    impl AxisMap {
+        /// The placements from section 3.4 of the BNO055 datasheet
+        pub const PLACEMENTS: [Self; 8] = [
+            Self::placement(0x21, 0x04),
+            Self::placement(0x24, 0x00),
+            Self::placement(0x24, 0x06),
+            Self::placement(0x21, 0x02),
+            Self::placement(0x24, 0x03),
+            Self::placement(0x21, 0x01),
+            Self::placement(0x21, 0x07),
+            Self::placement(0x24, 0x05),
+        ];
+
+        /// What the BNO055 starts with: placement `P1`, which leaves its axes as they are
+        pub const DEFAULT: Self = Self::PLACEMENTS[1];
+
+        const fn placement(config: u8, sign: u8) -> Self {
+            Self { config, sign }
+        }
+
+        /// An axis map from the values of the `AXIS_MAP_CONFIG` and `AXIS_MAP_SIGN` registers.
+        /// `None` unless each chip axis is taken exactly once.
+        #[must_use]
+        pub fn from_registers(config: u8, sign: u8) -> Option<Self> {
+            let map = Self { config, sign };
+            let mut taken = [false; 3];
+            for axis in map.chip_axes() {
+                *taken.get_mut(usize::from(axis))? = true;
+            }
+            (taken == [true; 3] && config >> 6 == 0 && sign >> 3 == 0).then_some(map)
+        }
+
+        /// The value of the `AXIS_MAP_CONFIG` register
+        #[must_use]
+        pub const fn config(&self) -> u8 {
+            self.config
+        }
+
+        /// The value of the `AXIS_MAP_SIGN` register
+        #[must_use]
+        pub const fn sign(&self) -> u8 {
+            self.sign
+        }
+
+        /// Which of the datasheet's placements this is, if any
+        #[must_use]
+        pub fn placement_number(&self) -> Option<usize> {
+            Self::PLACEMENTS
+                .iter()
+                .position(|placement| placement == self)
+        }
+
+        /// The chip axis the remapped x, y and z each take, 0 for x to 2 for z
+        fn chip_axes(&self) -> [u8; 3] {
+            [0, 1, 2].map(|axis| (self.config >> (2 * axis)) & 0b11)
+        }
+
+        /// Whether the remapped x, y and z are each negated
+        fn negated(&self) -> [bool; 3] {
+            [0, 1, 2].map(|axis| self.sign & (0b100 >> axis) != 0)
+        }
+
+        /// The remap to hand [`bno055::Bno055::set_axis_remap`]
+        #[must_use]
+        pub fn remap(&self) -> bno055::AxisRemap {
+            use bno055::BNO055AxisConfig as Config;
+
+            let [x, y, _] = self.chip_axes().map(|axis| match axis {
+                0 => Config::AXIS_AS_X,
+                1 => Config::AXIS_AS_Y,
+                _ => Config::AXIS_AS_Z,
+            });
+            // each swap writes the old value into the field named by its argument, which is only
+            // the field holding that value while it's still in place. Starting from the identity,
+            // x is, and what then sits in y tells whether and how y and z need swapping.
+            let builder = bno055::AxisRemap::builder().swap_x_with(x);
+            let y_after_x = if x == Config::AXIS_AS_Y {
+                Config::AXIS_AS_X
+            } else {
+                Config::AXIS_AS_Y
+            };
+            let builder = if y == y_after_x {
+                builder
+            } else if y == Config::AXIS_AS_Z {
+                builder.swap_y_with(Config::AXIS_AS_Z)
+            } else {
+                builder.swap_z_with(Config::AXIS_AS_Y)
+            };
+            builder.build().expect("axis maps take each axis once")
+        }
+
+        /// The signs to hand [`bno055::Bno055::set_axis_sign`]
+        #[must_use]
+        pub fn axis_sign(&self) -> bno055::BNO055AxisSign {
+            bno055::BNO055AxisSign::from_bits_truncate(self.sign)
+        }
+    }
+
+    impl Default for AxisMap {
+        fn default() -> Self {
+            Self::DEFAULT
+        }
+    }
+
+    /// Why a string couldn't be parsed into an [`AxisMap`]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ParseAxisMapError {
+        /// A placement number past `P7`
+        UnknownPlacement,
+        /// Not exactly three comma separated axes
+        WrongAxisCount(usize),
+        /// An axis that isn't `x`, `y` or `z`, optionally signed
+        BadAxis {
+            /// index of the offending axis
+            axis: usize,
+        },
+        /// A chip axis taken by more than one remapped axis
+        RepeatedAxis,
+    }
+
+    impl fmt::Display for ParseAxisMapError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::UnknownPlacement => write!(f, "placements go from P0 to P7"),
+                Self::WrongAxisCount(count) => write!(f, "expected 3 axes, found {}", count),
+                Self::BadAxis { axis } => write!(f, "axis {} isn't one of x, y or z", axis),
+                Self::RepeatedAxis => write!(f, "each axis has to be used once"),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl std::error::Error for ParseAxisMapError {}
+
+    impl core::str::FromStr for AxisMap {
+        type Err = ParseAxisMapError;
+
+        /// Parses a placement like `P3`, or the chip axes x, y and z take, like `-y,+x,+z`
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let s = s.trim();
+            if let Some(number) = s.strip_prefix(['P', 'p']) {
+                return number
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|number| Self::PLACEMENTS.get(number).copied())
+                    .ok_or(ParseAxisMapError::UnknownPlacement);
+            }
+
+            let count = s.split(',').count();
+            if count != 3 {
+                return Err(ParseAxisMapError::WrongAxisCount(count));
+            }
+
+            let (mut config, mut sign) = (0, 0);
+            for (axis, item) in s.split(',').enumerate() {
+                let item = item.trim();
+                let (negated, name) = match item.strip_prefix('-') {
+                    Some(name) => (true, name),
+                    None => (false, item.strip_prefix('+').unwrap_or(item)),
+                };
+                let chip_axis = match name {
+                    "x" | "X" => 0,
+                    "y" | "Y" => 1,
+                    "z" | "Z" => 2,
+                    _ => return Err(ParseAxisMapError::BadAxis { axis }),
+                };
+                config |= chip_axis << (2 * axis);
+                if negated {
+                    sign |= 0b100 >> axis;
+                }
+            }
+
+            Self::from_registers(config, sign).ok_or(ParseAxisMapError::RepeatedAxis)
+        }
+    }
+
+    impl fmt::Display for AxisMap {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            if let Some(number) = self.placement_number() {
+                return write!(f, "P{}", number);
+            }
+            for (index, (axis, negated)) in self.chip_axes().iter().zip(self.negated()).enumerate()
+            {
+                let sign = if negated { '-' } else { '+' };
+                let name = ['x', 'y', 'z'][usize::from(*axis)];
+                if index != 0 {
+                    write!(f, ",")?;
+                }
+                write!(f, "{}{}", sign, name)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod test_imu {
+        use super::*;
+
+        #[test]
+        fn parse_vector() {
+            assert_eq!("G:1,-2,3".parse(), Ok(GravityVector::new(1, -2, 3)));
+            assert_eq!(
+                "A: 1, 2 ,3\r\n".parse(),
+                Ok(AccelerationVector::new(1, 2, 3))
+            );
+            let vector = GravityVector::new(i16::MIN, 0, i16::MAX);
+            assert_eq!(vector.to_string().parse(), Ok(vector));
+        }
+
+        #[test]
+        fn parse_vector_errors() {
+            assert_eq!(
+                "A:1,2,3".parse::<GravityVector>(),
+                Err(ParseVectorError::MissingPrefix { expected: 'G' })
+            );
+            assert_eq!(
+                "G1,2,3".parse::<GravityVector>(),
+                Err(ParseVectorError::MissingPrefix { expected: 'G' })
+            );
+            assert_eq!(
+                "G:1,2".parse::<GravityVector>(),
+                Err(ParseVectorError::WrongComponentCount(2))
+            );
+            assert_eq!(
+                "G:1,2,3,4".parse::<GravityVector>(),
+                Err(ParseVectorError::WrongComponentCount(4))
+            );
+            assert_eq!(
+                "G:1,2,3x".parse::<GravityVector>(),
+                Err(ParseVectorError::BadNumber { component: 2 })
+            );
+            assert_eq!(
+                "G:1,,3".parse::<GravityVector>(),
+                Err(ParseVectorError::BadNumber { component: 1 })
+            );
+            assert_eq!(
+                "G:40000,2,3".parse::<GravityVector>(),
+                Err(ParseVectorError::Overflow { component: 0 })
+            );
+        }
+
+        #[test]
+        fn scale_to_m_s2() {
+            let m_s2 = GravityVector::new(981, -981, 0).m_s2();
+            assert_eq!((m_s2.x, m_s2.y, m_s2.z), (9.81, -9.81, 0.));
+            let m_s2 = AccelerationVector::new(150, 0, -25).m_s2();
+            assert_eq!((m_s2.x, m_s2.y, m_s2.z), (1.5, 0., -0.25));
+        }
+
+        #[test]
+        fn scale_to_rad_s() {
+            let rad_s = GyroVector::new(16 * 180, -16 * 90, 0).rad_s();
+            let pi = core::f32::consts::PI;
+            assert!((rad_s.x - pi).abs() < 1e-6);
+            assert!((rad_s.y + pi / 2.).abs() < 1e-6);
+            assert_eq!(rad_s.z, 0.);
+        }
+
+        #[test]
+        fn scale_to_micro_tesla() {
+            let micro_tesla = MagVector::new(800, -40, 8).micro_tesla();
+            assert_eq!(
+                (micro_tesla.x, micro_tesla.y, micro_tesla.z),
+                (50., -2.5, 0.5)
+            );
+        }
+
+        #[test]
+        fn quaternion_to_unit() {
+            let unit = FixedPointQuaternion::new(16384, 0, -8192, 0).unit();
+            assert_eq!((unit.s, unit.v.x, unit.v.y, unit.v.z), (1., 0., -0.5, 0.));
+            let unit = FixedPointQuaternion::new(-16384, 4096, 0, 2).unit();
+            assert_eq!(
+                (unit.s, unit.v.x, unit.v.y, unit.v.z),
+                (-1., 0.25, 0., 1. / 8192.)
+            );
+        }
+
+        #[test]
+        fn scale_from_m_s2() {
+            let value = |x: f32| mint::Vector3 { x, y: 0., z: 0. };
+            // rounds half away from zero
+            assert_eq!(
+                GravityVector::from_m_s2(value(0.125)),
+                Some(GravityVector::new(13, 0, 0))
+            );
+            assert_eq!(
+                GravityVector::from_m_s2(value(-0.125)),
+                Some(GravityVector::new(-13, 0, 0))
+            );
+            assert_eq!(
+                AccelerationVector::from_m_s2(value(0.124)),
+                Some(AccelerationVector::new(12, 0, 0))
+            );
+            assert_eq!(
+                AccelerationVector::from_m_s2(value(327.67)),
+                Some(AccelerationVector::new(i16::MAX, 0, 0))
+            );
+            assert_eq!(GravityVector::from_m_s2(value(1e6)), None);
+            assert_eq!(GravityVector::from_m_s2(value(-1e6)), None);
+            assert_eq!(AccelerationVector::from_m_s2(value(f32::NAN)), None);
+        }
+
+        #[test]
+        fn parse_axis_map() {
+            assert_eq!("P1".parse(), Ok(AxisMap::DEFAULT));
+            assert_eq!("+x,+y,+z".parse(), Ok(AxisMap::DEFAULT));
+            assert_eq!(
+                "p0".parse::<AxisMap>()
+                    .map(|map| (map.config(), map.sign())),
+                Ok((0x21, 0x04))
+            );
+            // P0 swaps x and y and negates the new x
+            assert_eq!("-y, x, z".parse(), Ok(AxisMap::PLACEMENTS[0]));
+
+            let custom: AxisMap = "-z,+x,-y".parse().unwrap();
+            assert_eq!(custom.placement_number(), None);
+            assert_eq!(custom.to_string(), "-z,+x,-y");
+            for placement in AxisMap::PLACEMENTS {
+                assert_eq!(placement.to_string().parse(), Ok(placement));
+            }
+
+            assert_eq!(
+                "P8".parse::<AxisMap>(),
+                Err(ParseAxisMapError::UnknownPlacement)
+            );
+            assert_eq!(
+                "x,y".parse::<AxisMap>(),
+                Err(ParseAxisMapError::WrongAxisCount(2))
+            );
+            assert_eq!(
+                "x,w,z".parse::<AxisMap>(),
+                Err(ParseAxisMapError::BadAxis { axis: 1 })
+            );
+            assert_eq!(
+                "x,-x,z".parse::<AxisMap>(),
+                Err(ParseAxisMapError::RepeatedAxis)
+            );
+            assert_eq!(AxisMap::from_registers(0x24, 0x08), None);
+        }
+
+        #[test]
+        fn axis_map_remaps() {
+            use bno055::BNO055AxisConfig as Config;
+
+            let configs = [Config::AXIS_AS_X, Config::AXIS_AS_Y, Config::AXIS_AS_Z];
+            for (x, y, z) in [
+                (0, 1, 2),
+                (0, 2, 1),
+                (1, 0, 2),
+                (1, 2, 0),
+                (2, 0, 1),
+                (2, 1, 0),
+            ] {
+                let map = AxisMap::from_registers(x | y << 2 | z << 4, 0b101).unwrap();
+                let remap = map.remap();
+                // `AxisRemap::y` returns x, but with x and z right, y is what's left
+                assert_eq!(remap.x(), configs[usize::from(x)]);
+                assert_eq!(remap.z(), configs[usize::from(z)]);
+                assert_eq!(
+                    map.axis_sign(),
+                    bno055::BNO055AxisSign::X_NEGATIVE | bno055::BNO055AxisSign::Z_NEGATIVE
+                );
+            }
+        }
+    }
 }
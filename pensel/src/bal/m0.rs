@@ -69,3 +69,7 @@ impl BoardAbstractionLayer for Bal {
 
 /// List of USB interrupts to enable/disable when needed
 pub const USB_INTERRUPTS: [pac::interrupt; 1] = [pac::interrupt::USB];
+
+/// Addresses of the four words making up the SAMD21's unique serial number (datasheet 10.3.3)
+pub const SERIAL_NUMBER_ADDRESSES: [usize; 4] =
+    [0x0080_A00C, 0x0080_A040, 0x0080_A044, 0x0080_A048];
@@ -75,3 +75,7 @@ pub const USB_INTERRUPTS: [pac::interrupt; 3] = [
     pac::interrupt::USB_TRCPT0,
     pac::interrupt::USB_TRCPT1,
 ];
+
+/// Addresses of the four words making up the SAMD51's unique serial number (datasheet 9.6)
+pub const SERIAL_NUMBER_ADDRESSES: [usize; 4] =
+    [0x0080_61FC, 0x0080_6010, 0x0080_6014, 0x0080_6018];
@@ -41,3 +41,11 @@ pub trait BoardAbstractionLayer {
         dm: impl Into<bsp::UsbDm>,
     ) -> UsbBusAllocator<UsbBus>;
 }
+
+/// Reads this MCU's 128 bit unique serial number, which makes a good ID for the pen
+#[must_use]
+pub fn serial_number() -> [u32; 4] {
+    // Safety: these are fixed, always readable addresses in the MCU's NVM calibration area
+    SERIAL_NUMBER_ADDRESSES
+        .map(|address| unsafe { core::ptr::read_volatile(address as *const u32) })
+}
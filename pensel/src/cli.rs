@@ -1,96 +1,65 @@
 //! Manages the command line interface. Uses `menu` under the hood.
-use heapless::spsc::{Producer, Queue};
-
+use crate::{bal, line_editor::LineEditor, usb_serial};
 use pensel_types::cli as pt_cli;
 
-/// The size of our CLI queue structures. Current largest output: `help imu` at ~200 bytes
+/// The size of our CLI input queue and menu line buffer
 pub const CLI_QUEUE_SIZE: usize = 512;
 
 static mut MENU_BUFFER: [u8; CLI_QUEUE_SIZE] = [0; CLI_QUEUE_SIZE];
 
-/// The queue for our CLI abstraction to write out to the serial port
-pub static mut CLI_OUTPUT_QUEUE: Queue<u8, { CLI_QUEUE_SIZE }> = Queue::new();
-
 /// How our `menu` based CLI outputs to the user. Not for direct consumption.
-pub struct Output<'a, const N: usize> {
-    /// Bytes coming from our CLI to be output to the serial port
-    cli_output_queue: Producer<'a, u8, N>,
+///
+/// Output is queued on the USB serial transmit buffer as [`usb_serial::TxSource::Cli`], so it
+/// waits for room rather than getting dropped.
+pub struct Output {
+    /// Swallow everything written while set. Lets the line editor hide `menu`'s own echo.
+    muted: bool,
 }
 
-impl<'a, const N: usize> Output<'a, { N }> {
-    const fn new(cli_output_queue: Producer<'a, u8, N>) -> Output<'a, N> {
-        Output { cli_output_queue }
+impl Output {
+    /// Mutes or unmutes all CLI output
+    pub(crate) fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
     }
 }
 
-impl<'a, const N: usize> core::fmt::Write for Output<'a, { N }> {
+impl core::fmt::Write for Output {
     fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
-        #[cfg(debug_assertions)]
-        let high_watermark = N * 3 / 4;
-
-        for byte in s.bytes() {
-            #[cfg(debug_assertions)]
-            {
-                if self.cli_output_queue.len() > high_watermark {
-                    // dump out CLI queue bytes. Disable interrupts to make this
-                    //   sketchy global unsafe mess a little less unsafe. We're
-                    //   crashing and burning anyways
-                    cortex_m::interrupt::free(|_| unsafe {
-                        let cli_queue = &mut CLI_OUTPUT_QUEUE;
-                        let recent_bytes = [
-                            cli_queue.dequeue_unchecked(),
-                            cli_queue.dequeue_unchecked(),
-                            cli_queue.dequeue_unchecked(),
-                            cli_queue.dequeue_unchecked(),
-                            cli_queue.dequeue_unchecked(),
-                            cli_queue.dequeue_unchecked(),
-                            cli_queue.dequeue_unchecked(),
-                            cli_queue.dequeue_unchecked(),
-                            cli_queue.dequeue_unchecked(),
-                            cli_queue.dequeue_unchecked(),
-                        ];
-                        panic!(
-                            "Output hit high watermark. Recent input: '{}'",
-                            core::str::from_utf8_unchecked(&recent_bytes)
-                        );
-                    });
-                }
-            }
-            if self.cli_output_queue.enqueue(byte).is_err() {
-                return Err(core::fmt::Error);
-            }
+        if self.muted || usb_serial::write_bytes(usb_serial::TxSource::Cli, s.as_bytes()) {
+            Ok(())
+        } else {
+            Err(core::fmt::Error)
         }
-        Ok(())
     }
 }
 
 /// The type we need to return if we want an item in the CLI
-pub type Item = menu::Item<'static, Output<'static, CLI_QUEUE_SIZE>>;
+pub type Item = menu::Item<'static, Output>;
 
 /// Our encapsulation of the CLI
-pub struct Cli<'a, const N: usize> {
+pub struct Cli<'a> {
     /// the CLI runner
-    runner: menu::Runner<'a, Output<'a, N>>,
+    runner: menu::Runner<'a, Output>,
+    /// history, cursor movement and completion in front of `runner`
+    editor: LineEditor,
 }
 
-impl<'a> Cli<'a, CLI_QUEUE_SIZE> {
-    /// Creates our Cli encapsulation
-    ///
-    /// # Parameters
-    /// `cli_output_queue`: where we write our bytes to be sent to the serial port by the application
+impl<'a> Cli<'a> {
+    /// Creates our Cli encapsulation. Output goes straight to the USB serial transmit buffer.
     #[must_use]
-    pub fn new(
-        cli_output_queue: Producer<'static, u8, CLI_QUEUE_SIZE>,
-    ) -> Cli<'static, CLI_QUEUE_SIZE> {
-        let buffer = unsafe { &mut MENU_BUFFER };
-        let runner = menu::Runner::new(&ROOT_MENU, buffer, Output::new(cli_output_queue));
+    pub fn new() -> Cli<'static> {
+        let buffer = unsafe { &mut *core::ptr::addr_of_mut!(MENU_BUFFER) };
+        let runner = menu::Runner::new(&ROOT_MENU, buffer, Output { muted: false });
 
-        Cli { runner }
+        Cli {
+            runner,
+            editor: LineEditor::new(),
+        }
     }
 
-    /// Give a byte coming from our serial connection to our CLI runner
+    /// Give a byte coming from our serial connection to our CLI, by way of our line editor
     pub fn input_from_serial(&mut self, byte: u8) {
-        self.runner.input_byte(byte);
+        self.editor.input(byte, &ROOT_MENU, &mut self.runner);
     }
 
     /// Give the bytes coming from our serial connection to our CLI runner
@@ -119,32 +88,97 @@ const RESET_CLI_ITEM: Item = Item {
     help: Some("initiates an MCU reset"),
 };
 
-const ROOT_MENU: menu::Menu<Output<CLI_QUEUE_SIZE>> = menu::Menu {
+const INFO_CLI_ITEM: Item = Item {
+    item_type: menu::ItemType::Callback {
+        function: info,
+        parameters: &[],
+    },
+    command: pt_cli::CMD_INFO,
+    help: Some("describes this pen: firmware version, ID, accel scale, axis map and calibration"),
+};
+
+const ROOT_MENU: menu::Menu<Output> = menu::Menu {
     label: "root",
     items: &[
         &PANIC_CLI_ITEM,
         &RESET_CLI_ITEM,
+        &INFO_CLI_ITEM,
         &crate::imu::IMU_CLI_ITEM,
         &crate::usb_serial_log::LOG_CLI_ITEM,
+        &crate::usb_serial::USB_CLI_ITEM,
     ],
     entry: None,
     exit: None,
 };
 
-fn panic<const N: usize>(
-    _menu: &menu::Menu<Output<N>>,
-    _item: &menu::Item<Output<N>>,
+fn panic(
+    _menu: &menu::Menu<Output>,
+    _item: &menu::Item<Output>,
     _args: &[&str],
-    _context: &mut Output<N>,
+    _context: &mut Output,
 ) {
     panic!("test panic");
 }
 
-fn reset<const N: usize>(
-    _menu: &menu::Menu<Output<N>>,
-    _item: &menu::Item<Output<N>>,
+fn reset(
+    _menu: &menu::Menu<Output>,
+    _item: &menu::Item<Output>,
     _args: &[&str],
-    _context: &mut Output<N>,
+    _context: &mut Output,
 ) {
     cortex_m::peripheral::SCB::sys_reset();
 }
+
+fn info(
+    _menu: &menu::Menu<Output>,
+    _item: &menu::Item<Output>,
+    _args: &[&str],
+    context: &mut Output,
+) {
+    use core::fmt::Write;
+
+    writeln!(
+        context,
+        "{}: {}",
+        pt_cli::INFO_FIRMWARE,
+        env!("CARGO_PKG_VERSION")
+    )
+    .unwrap();
+
+    let [id0, id1, id2, id3] = bal::serial_number();
+    writeln!(
+        context,
+        "{}: {:08x}{:08x}{:08x}{:08x}",
+        pt_cli::INFO_PEN_ID,
+        id0,
+        id1,
+        id2,
+        id3
+    )
+    .unwrap();
+
+    writeln!(
+        context,
+        "{}: {}",
+        pt_cli::INFO_ACCEL_SCALE,
+        pensel_types::imu::scale::ACCEL_LSB_PER_M_S2
+    )
+    .unwrap();
+
+    writeln!(
+        context,
+        "{}: {}",
+        pt_cli::INFO_AXIS_MAP,
+        crate::imu::axis_map()
+    )
+    .unwrap();
+
+    write!(context, "{}: ", pt_cli::INFO_CALIBRATION).unwrap();
+    for (index, byte) in crate::imu::BNO055_CALIBRATION.as_bytes().iter().enumerate() {
+        if index != 0 {
+            write!(context, ",").unwrap();
+        }
+        write!(context, "{}", byte).unwrap();
+    }
+    writeln!(context).unwrap();
+}
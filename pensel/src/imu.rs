@@ -15,7 +15,8 @@ pub struct Imu<I> {
     bno: bno055::Bno055<I>,
 }
 
-const BNO055_CALIBRATION: bno055::BNO055Calibration = bno055::BNO055Calibration {
+/// The calibration profile we load into the bno055 on startup
+pub const BNO055_CALIBRATION: bno055::BNO055Calibration = bno055::BNO055Calibration {
     acc_offset_x_lsb: 2,
     acc_offset_x_msb: 0,
     acc_offset_y_lsb: 252,
@@ -40,8 +41,32 @@ const BNO055_CALIBRATION: bno055::BNO055Calibration = bno055::BNO055Calibration
     mag_radius_msb: 2,
 };
 
+/// The axis map we set on the bno055 on startup, for how the board sits in the pen's shell.
+/// `imu --axis-map` changes it until the next reset. Keeping it in NVM is out of scope: hosts
+/// send it again every time they connect.
+pub const BNO055_AXIS_MAP: imu::AxisMap = imu::AxisMap::DEFAULT;
+
 static CLI_CONTROL_STREAM_GRAVITY: atomic::AtomicBool = atomic::AtomicBool::new(false);
 static CLI_CONTROL_STREAM_ACCEL: atomic::AtomicBool = atomic::AtomicBool::new(false);
+/// The axis map the CLI asked for, [packed](pack_axis_map), or 0 once it's been set
+static CLI_CONTROL_AXIS_MAP: atomic::AtomicU16 = atomic::AtomicU16::new(0);
+/// The axis map the bno055 runs with, [packed](pack_axis_map)
+static AXIS_MAP: atomic::AtomicU16 = atomic::AtomicU16::new(pack_axis_map(BNO055_AXIS_MAP));
+
+/// Packs an axis map's registers into one atomic. Never 0, as each axis has to be taken once.
+const fn pack_axis_map(map: imu::AxisMap) -> u16 {
+    (map.config() as u16) << 8 | map.sign() as u16
+}
+
+/// The axis map [`pack_axis_map`] packed
+fn unpack_axis_map(packed: u16) -> Option<imu::AxisMap> {
+    imu::AxisMap::from_registers((packed >> 8) as u8, packed as u8)
+}
+
+/// The axis map the bno055 runs with
+pub fn axis_map() -> imu::AxisMap {
+    unpack_axis_map(AXIS_MAP.load(atomic::Ordering::Acquire)).unwrap_or_default()
+}
 
 impl<I, E> Imu<I>
 where
@@ -57,6 +82,11 @@ where
         log::debug!("initializing IMU");
         let mut bno = bno055::Bno055::new(i2c).with_alternative_address();
         bno.init(delay).expect("bno init err");
+        // init leaves it in config mode, the only one the axis map can be set in
+        bno.set_axis_remap(BNO055_AXIS_MAP.remap())
+            .expect("set_axis_remap fail");
+        bno.set_axis_sign(BNO055_AXIS_MAP.axis_sign())
+            .expect("set_axis_sign fail");
         bno.set_mode(bno055::BNO055OperationMode::NDOF, delay)
             .expect("set_mode fail");
 
@@ -66,6 +96,33 @@ where
         Self { bno }
     }
 
+    /// Sets the axis map the CLI asked for, if it asked since we last looked
+    ///
+    /// # Arguments
+    /// `delay`: Facility for bno055 to delay during mode changes
+    pub fn update_axis_map(&mut self, delay: &mut dyn DelayMs<u16>) {
+        let requested = CLI_CONTROL_AXIS_MAP.load(atomic::Ordering::Acquire);
+        let Some(map) = unpack_axis_map(requested) else {
+            return;
+        };
+        CLI_CONTROL_AXIS_MAP.store(0, atomic::Ordering::Release);
+
+        log::debug!("IMU - setting axis map {}", map);
+        let result = self
+            .bno
+            .set_mode(bno055::BNO055OperationMode::CONFIG_MODE, delay)
+            .and_then(|()| self.bno.set_axis_remap(map.remap()))
+            .and_then(|()| self.bno.set_axis_sign(map.axis_sign()))
+            .and_then(|()| self.bno.set_mode(bno055::BNO055OperationMode::NDOF, delay));
+        match result {
+            Ok(()) => {
+                AXIS_MAP.store(requested, atomic::Ordering::Release);
+                log::info!("axis map: {}", map);
+            }
+            Err(error) => log::error!("failed to set axis map {}: {:?}", map, error),
+        }
+    }
+
     /// Retrieves the current gravity vector as calculated by the bno055
     pub fn gravity_fixed(&mut self) -> Option<imu::GravityVector> {
         if CLI_CONTROL_STREAM_GRAVITY.load(atomic::Ordering::Acquire) {
@@ -91,12 +148,26 @@ where
     }
 }
 
-fn imu_control<const N: usize>(
-    _menu: &menu::Menu<cli::Output<N>>,
-    item: &menu::Item<cli::Output<N>>,
+fn imu_control(
+    _menu: &menu::Menu<cli::Output>,
+    item: &menu::Item<cli::Output>,
     args: &[&str],
-    _context: &mut cli::Output<N>,
+    context: &mut cli::Output,
 ) {
+    use core::fmt::Write;
+
+    // setting the axis map leaves streaming as it is
+    if let Ok(Some(value)) = menu::argument_finder(item, args, pt_cli::ARG_AXIS_MAP) {
+        match value.parse::<imu::AxisMap>() {
+            Ok(map) => {
+                CLI_CONTROL_AXIS_MAP.store(pack_axis_map(map), atomic::Ordering::Release);
+                writeln!(context, "{}: {}", pt_cli::INFO_AXIS_MAP, map).unwrap();
+            }
+            Err(error) => writeln!(context, "failed to parse '{}': {}", value, error).unwrap(),
+        }
+        return;
+    }
+
     let mut enable_accel = false;
     let mut enable_grav = false;
     if let Ok(Some(_)) = menu::argument_finder(item, args, pt_cli::ARG_ACCEL) {
@@ -124,6 +195,11 @@ pub const IMU_CLI_ITEM: cli::Item = cli::Item {
                 parameter_name: pt_cli::ARG_GRAVITY,
                 help: Some("Enable streaming of gravity vector"),
             },
+            menu::Parameter::NamedValue {
+                parameter_name: pt_cli::ARG_AXIS_MAP,
+                argument_name: "map",
+                help: Some("Sets the axis map until reset, it isn't stored: P0-P7 from the BNO055 datasheet, or the axes x,y,z take, like -y,+x,+z"),
+            },
         ],
     },
     command: pt_cli::CMD_IMU,
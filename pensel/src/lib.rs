@@ -4,6 +4,7 @@
 pub mod bal;
 pub mod cli;
 pub mod imu;
+pub mod line_editor;
 pub mod prelude;
 pub mod usb_serial;
 pub mod usb_serial_log;
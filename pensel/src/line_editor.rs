@@ -0,0 +1,379 @@
+//! Line editing that sits in front of our `menu` based CLI.
+//!
+//! `menu::Runner` only knows how to append and backspace. This keeps its own copy of the line so
+//! we can offer a small command history (up/down), cursor movement (left/right/home/end), and tab
+//! completion of commands and `--arguments`. Finished lines are handed to the runner on enter.
+use core::fmt::Write;
+use heapless::{HistoryBuffer, Vec};
+
+use crate::cli::Output;
+
+/// Longest line we can edit
+pub const LINE_LEN: usize = 128;
+/// Number of previous commands we remember
+pub const HISTORY_LEN: usize = 8;
+
+/// The prompt `menu` draws. We redraw it ourselves whenever we repaint the line.
+const PROMPT: &str = "> ";
+/// Most completion candidates we'll consider at once
+const MAX_CANDIDATES: usize = 16;
+
+const CTRL_A: u8 = 0x01;
+const CTRL_C: u8 = 0x03;
+const CTRL_E: u8 = 0x05;
+const BELL: &str = "\x07";
+const BACKSPACE: u8 = 0x08;
+const TAB: u8 = b'\t';
+const CR: u8 = b'\r';
+const ESC: u8 = 0x1B;
+const DELETE: u8 = 0x7F;
+
+/// A line of input
+type Line = Vec<u8, LINE_LEN>;
+
+/// Where we are in parsing an ANSI escape sequence
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    /// Not in an escape sequence
+    None,
+    /// Got `ESC`
+    Start,
+    /// Got `ESC [`, possibly followed by a single digit parameter
+    Csi(Option<u8>),
+}
+
+/// A possible completion of the word under the cursor
+struct Candidate<'a> {
+    /// the full word, without any `--` prefix
+    word: &'a str,
+    /// what to add after the word once it is complete
+    terminator: &'static str,
+}
+
+/// Our line editor. Feed it every byte coming from the serial port with [`LineEditor::input`].
+pub struct LineEditor {
+    line: Line,
+    cursor: usize,
+    escape: Escape,
+    history: HistoryBuffer<Line, HISTORY_LEN>,
+    /// How far back in `history` we're currently showing. `None` when editing a fresh line.
+    browsing: Option<usize>,
+    /// The fresh line we were editing before we started browsing history
+    draft: Line,
+}
+
+impl LineEditor {
+    /// Creates an empty line editor with no history
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            cursor: 0,
+            escape: Escape::None,
+            history: HistoryBuffer::new(),
+            browsing: None,
+            draft: Vec::new(),
+        }
+    }
+
+    /// Handles a byte of user input, editing the line and redrawing it through `runner`'s output.
+    /// Completed lines are passed along to `runner` to execute.
+    ///
+    /// # Arguments
+    /// `byte`: the byte the user typed
+    /// `menu`: the menu to complete commands and arguments from
+    /// `runner`: the `menu` runner to execute lines with
+    pub fn input(
+        &mut self,
+        byte: u8,
+        menu: &menu::Menu<Output>,
+        runner: &mut menu::Runner<Output>,
+    ) {
+        let out = &mut runner.context;
+        match self.escape {
+            Escape::Start => {
+                self.escape = if byte == b'[' {
+                    Escape::Csi(None)
+                } else {
+                    Escape::None
+                };
+                return;
+            }
+            Escape::Csi(param) => {
+                if byte.is_ascii_digit() && param.is_none() {
+                    self.escape = Escape::Csi(Some(byte));
+                } else {
+                    self.escape = Escape::None;
+                    self.handle_csi(param, byte, out);
+                }
+                return;
+            }
+            Escape::None => (),
+        }
+
+        match byte {
+            ESC => self.escape = Escape::Start,
+            CR => self.submit(runner),
+            TAB => self.complete(menu, out),
+            BACKSPACE | DELETE if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+                self.redraw(out);
+            }
+            CTRL_A => self.move_to(0, out),
+            CTRL_E => self.move_to(self.line.len(), out),
+            CTRL_C => {
+                self.clear();
+                write!(out, "^C\r\n{}", PROMPT).ok();
+            }
+            b' '..=b'~' => self.insert(&[byte], out),
+            // `menu` drops line feeds too, and we ignore anything else we don't understand
+            _ => (),
+        }
+    }
+
+    /// Handles the final byte of an `ESC [` sequence
+    fn handle_csi(&mut self, param: Option<u8>, byte: u8, out: &mut Output) {
+        match (param, byte) {
+            (None, b'A') => self.history_back(out),
+            (None, b'B') => self.history_forward(out),
+            (None, b'C') => self.move_to((self.cursor + 1).min(self.line.len()), out),
+            (None, b'D') => self.move_to(self.cursor.saturating_sub(1), out),
+            (None, b'H') | (Some(b'1' | b'7'), b'~') => self.move_to(0, out),
+            (None, b'F') | (Some(b'4' | b'8'), b'~') => self.move_to(self.line.len(), out),
+            (Some(b'3'), b'~') if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+                self.redraw(out);
+            }
+            _ => (),
+        }
+    }
+
+    /// Inserts `bytes` at the cursor, if they fit
+    fn insert(&mut self, bytes: &[u8], out: &mut Output) {
+        if self.line.len() + bytes.len() > LINE_LEN {
+            out.write_str(BELL).ok();
+            return;
+        }
+
+        let appending = self.cursor == self.line.len();
+        for (offset, byte) in bytes.iter().enumerate() {
+            // can't fail, we checked for room above
+            self.line.insert(self.cursor + offset, *byte).ok();
+        }
+        self.cursor += bytes.len();
+
+        if appending {
+            // only printable ASCII ever makes it into the line
+            out.write_str(core::str::from_utf8(bytes).unwrap_or(""))
+                .ok();
+        } else {
+            self.redraw(out);
+        }
+    }
+
+    /// Moves the cursor to `position` in the line
+    fn move_to(&mut self, position: usize, out: &mut Output) {
+        if position < self.cursor {
+            write!(out, "\x1b[{}D", self.cursor - position).ok();
+        } else if position > self.cursor {
+            write!(out, "\x1b[{}C", position - self.cursor).ok();
+        }
+        self.cursor = position;
+    }
+
+    /// Repaints the prompt and the whole line, then puts the terminal cursor back where ours is
+    fn redraw(&self, out: &mut Output) {
+        write!(out, "\r{}{}\x1b[K", PROMPT, self.line_str()).ok();
+        if self.cursor < self.line.len() {
+            write!(out, "\x1b[{}D", self.line.len() - self.cursor).ok();
+        }
+    }
+
+    /// Replaces the line with `line` and puts the cursor at the end
+    fn replace_line(&mut self, line: &Line, out: &mut Output) {
+        self.line.clone_from(line);
+        self.cursor = self.line.len();
+        self.redraw(out);
+    }
+
+    /// Shows the next older line from our history
+    fn history_back(&mut self, out: &mut Output) {
+        let back = match self.browsing {
+            None if !self.history.is_empty() => {
+                self.draft.clone_from(&self.line);
+                0
+            }
+            Some(back) if back + 1 < self.history.len() => back + 1,
+            _ => {
+                out.write_str(BELL).ok();
+                return;
+            }
+        };
+
+        self.browsing = Some(back);
+        if let Some(line) = self.history_entry(back) {
+            let line = line.clone();
+            self.replace_line(&line, out);
+        }
+    }
+
+    /// Shows the next newer line from our history, or the draft we started with
+    fn history_forward(&mut self, out: &mut Output) {
+        match self.browsing {
+            Some(0) => {
+                self.browsing = None;
+                let draft = core::mem::take(&mut self.draft);
+                self.replace_line(&draft, out);
+            }
+            Some(back) => {
+                self.browsing = Some(back - 1);
+                if let Some(line) = self.history_entry(back - 1) {
+                    let line = line.clone();
+                    self.replace_line(&line, out);
+                }
+            }
+            None => {
+                out.write_str(BELL).ok();
+            }
+        }
+    }
+
+    /// Gets the history entry `back` commands ago, where 0 is the most recent
+    fn history_entry(&self, back: usize) -> Option<&Line> {
+        let oldest_first_index = self.history.len().checked_sub(back + 1)?;
+        self.history.oldest_ordered().nth(oldest_first_index)
+    }
+
+    /// Records the line in our history and hands it to `runner` to execute
+    fn submit(&mut self, runner: &mut menu::Runner<Output>) {
+        // so whatever the command prints doesn't start in the middle of the line
+        self.move_to(self.line.len(), &mut runner.context);
+
+        let is_repeat = self.history.recent() == Some(&self.line);
+        if !self.line.iter().all(u8::is_ascii_whitespace) && !is_repeat {
+            self.history.write(self.line.clone());
+        }
+
+        // we've already drawn the line, so keep `menu` from echoing it a second time
+        runner.context.set_muted(true);
+        for byte in &self.line {
+            runner.input_byte(*byte);
+        }
+        runner.context.set_muted(false);
+        runner.input_byte(CR);
+
+        self.clear();
+    }
+
+    /// Resets to a fresh, empty line
+    fn clear(&mut self) {
+        self.line.clear();
+        self.draft.clear();
+        self.cursor = 0;
+        self.browsing = None;
+    }
+
+    /// Tab completion of the word directly before the cursor. The first word completes to a
+    /// command; later words starting with `-` complete to that command's `--arguments`.
+    fn complete(&mut self, menu: &menu::Menu<Output>, out: &mut Output) {
+        let before_cursor = &self.line[..self.cursor];
+        let word_start = before_cursor
+            .iter()
+            .rposition(|b| *b == b' ')
+            .map_or(0, |space| space + 1);
+        let word = core::str::from_utf8(&before_cursor[word_start..]).unwrap_or("");
+        let leading = core::str::from_utf8(&before_cursor[..word_start]).unwrap_or("");
+
+        let mut candidates: Vec<Candidate, MAX_CANDIDATES> = Vec::new();
+        let typed = if leading.trim().is_empty() {
+            for command in menu.items.iter().map(|item| item.command).chain(["help"]) {
+                candidates
+                    .push(Candidate {
+                        word: command,
+                        terminator: " ",
+                    })
+                    .ok();
+            }
+            word
+        } else if let Some(argument) = word.strip_prefix("--") {
+            let command = leading.split_whitespace().next().unwrap_or("");
+            if let Some(item) = menu.items.iter().find(|item| item.command == command) {
+                if let menu::ItemType::Callback { parameters, .. } = &item.item_type {
+                    for parameter in parameters.iter() {
+                        let candidate = match parameter {
+                            menu::Parameter::Named { parameter_name, .. } => Candidate {
+                                word: parameter_name,
+                                terminator: " ",
+                            },
+                            menu::Parameter::NamedValue { parameter_name, .. } => Candidate {
+                                word: parameter_name,
+                                terminator: "=",
+                            },
+                            _ => continue,
+                        };
+                        candidates.push(candidate).ok();
+                    }
+                }
+            }
+            argument
+        } else if word == "-" {
+            // nudge a lone `-` towards the `--` form our arguments use
+            self.insert(b"-", out);
+            return;
+        } else {
+            out.write_str(BELL).ok();
+            return;
+        };
+        candidates.retain(|candidate| candidate.word.starts_with(typed));
+
+        match candidates.as_slice() {
+            [] => {
+                out.write_str(BELL).ok();
+            }
+            [only] => {
+                let terminator = only.terminator;
+                let rest = &only.word.as_bytes()[typed.len()..];
+                let mut addition: Vec<u8, LINE_LEN> = Vec::new();
+                addition.extend_from_slice(rest).ok();
+                addition.extend_from_slice(terminator.as_bytes()).ok();
+                self.insert(&addition, out);
+            }
+            [first, others @ ..] => {
+                // extend to the longest prefix every candidate shares
+                let common = others.iter().fold(first.word.len(), |common, candidate| {
+                    first.word.as_bytes()[..common]
+                        .iter()
+                        .zip(candidate.word.as_bytes())
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                });
+                if common > typed.len() {
+                    let mut addition: Vec<u8, LINE_LEN> = Vec::new();
+                    addition
+                        .extend_from_slice(&first.word.as_bytes()[typed.len()..common])
+                        .ok();
+                    self.insert(&addition, out);
+                } else {
+                    out.write_str("\r\n").ok();
+                    for candidate in &candidates {
+                        write!(out, "{}  ", candidate.word).ok();
+                    }
+                    out.write_str("\r\n").ok();
+                    self.redraw(out);
+                }
+            }
+        }
+    }
+
+    /// The current line as a string. Only printable ASCII ever makes it into the line.
+    fn line_str(&self) -> &str {
+        core::str::from_utf8(&self.line).unwrap_or("")
+    }
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,7 +1,9 @@
 #![no_std]
 #![no_main]
 
-use pensel::{bal, cli, imu::Imu, prelude::*, usb_serial, usb_serial_log};
+use pensel::{
+    bal, cli, imu::Imu, prelude::*, serial_write, usb_serial, usb_serial::TxSource, usb_serial_log,
+};
 
 use panic_persist as _;
 
@@ -33,20 +35,17 @@ fn main() -> ! {
 
     // initialize the CLI
     usb_serial_log::init().unwrap();
-    let (cli_producer, mut cli_bytes_to_write) = unsafe { cli::CLI_OUTPUT_QUEUE.split() };
-    let mut cli = cli::Cli::new(cli_producer);
+    let mut cli = cli::Cli::new();
     let mut serial_read_queue = usb_serial::get_serial_input_pipe();
 
     // Check if there was a panic message, if so, send to UART
     if let Some(msg) = panic_persist::get_panic_message_bytes() {
+        // goes out as CLI output, which waits for the host to read rather than dropping what
+        // doesn't fit in the transmit buffer. Without a configured host, or if it stops reading,
+        // whatever hasn't gone out yet is dropped.
+        usb_serial::wait_configured();
         log::error!("panic from previous boot:");
-        let mut bytes_written = 0;
-        // Write it out in chunks, waiting for USB interrupt handler to run in between before trying to shove more bytes
-        while bytes_written != msg.len() {
-            let chunk_written = usb_serial::get(|usbserial| usbserial.write(&msg[bytes_written..]));
-            bytes_written += chunk_written;
-            cortex_m::asm::wfi();
-        }
+        usb_serial::write_bytes(TxSource::Cli, msg);
     }
 
     let mut imu = Imu::new(&mut delay, i2c);
@@ -55,33 +54,23 @@ fn main() -> ! {
     loop {
         log::trace!("loop");
         // handle our CLI
-        while let Some(new_byte) = cli_bytes_to_write.dequeue() {
-            usb_serial::get(|usbserial| usbserial.write(&[new_byte]));
-        }
         while let Some(new_byte) = serial_read_queue.dequeue() {
             cli.input_from_serial(new_byte);
         }
 
+        imu.update_axis_map(&mut delay);
+
         // Get gravity vector
         let angles_res = imu.gravity_fixed();
         if let Some(angles) = angles_res {
-            log::info!(
-                "G:{},{},{}",
-                (angles.x as isize * 10),
-                (angles.y as isize * 10),
-                (angles.z as isize * 10)
-            );
+            // raw BNO055 counts, see `pensel_types::imu::scale`
+            serial_write!(TxSource::Data, "{}\n", angles);
         }
 
         // get acceleration
         let lin_accel = imu.linear_acceleration_fixed();
         if let Some(acc) = lin_accel {
-            log::info!(
-                "A:{},{},{}",
-                (acc.x as isize * 10),
-                (acc.y as isize * 10),
-                (acc.z as isize * 10)
-            );
+            serial_write!(TxSource::Data, "{}\n", acc);
         }
     }
 }
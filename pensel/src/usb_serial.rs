@@ -2,8 +2,9 @@
 use crate::{bal, cli, prelude::*};
 use hal::usb::UsbBus;
 use pac::interrupt;
+use pensel_types::cli as pt_cli;
 
-use core::sync::atomic;
+use core::{fmt, ptr, sync::atomic};
 
 use cortex_m::peripheral::NVIC;
 use heapless::spsc::{Consumer, Producer, Queue};
@@ -28,6 +29,95 @@ static mut CLI_INPUT_PRODUCER: Option<Producer<u8, { cli::CLI_QUEUE_SIZE }>> = N
 /// Once we see our first user interaction, we set this to true
 static USER_PRESENT: atomic::AtomicBool = atomic::AtomicBool::new(false);
 
+/// The size of our USB serial transmit ring buffer
+pub const TX_QUEUE_SIZE: usize = 1024;
+/// The ring buffer of bytes waiting to go out over USB serial. Filled by `write_bytes`/`write_fmt`
+/// and drained by the USB interrupt handlers. Only accessed with USB interrupts masked or from
+/// within those handlers.
+static mut TX_QUEUE: Queue<u8, TX_QUEUE_SIZE> = Queue::new();
+/// Set once a blocking write gave up on the host reading, so later ones drop right away instead of
+/// waiting all over again. Cleared as soon as there's room again.
+static TX_STALLED: atomic::AtomicBool = atomic::AtomicBool::new(false);
+/// How many times a blocking write polls for room without the host reading anything before it
+/// drops the rest, so a host that keeps the port open but stops reading can't hang us
+const TX_BLOCK_MAX_POLLS: u32 = 500;
+/// CPU cycles between those polls: a millisecond at 48 MHz, less on faster boards
+const TX_BLOCK_POLL_CYCLES: u32 = 48_000;
+/// How many times [`wait_configured`] polls for the host to configure us before giving up
+const CONFIGURE_MAX_POLLS: u32 = 1000;
+/// Bytes dropped per [`TxSource`] because they didn't fit in `TX_QUEUE`. Only updated with USB
+/// interrupts masked from thread mode, so plain load/store is enough (thumbv6m has no `fetch_add`).
+static TX_DROPPED: [atomic::AtomicUsize; TxSource::ALL.len()] = [
+    atomic::AtomicUsize::new(0),
+    atomic::AtomicUsize::new(0),
+    atomic::AtomicUsize::new(0),
+];
+
+/// Where bytes headed out over USB serial come from. Each source has its own backpressure
+/// policy and dropped byte counter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxSource {
+    /// Output from our CLI
+    Cli = 0,
+    /// Output from our `log` implementation
+    Log = 1,
+    /// Streamed IMU samples
+    Data = 2,
+}
+
+/// What to do when a message doesn't fit in the transmit buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxPolicy {
+    /// Wait for the USB interrupt to drain the buffer until the whole message has been queued.
+    /// Falls back to dropping if the host isn't connected or stops reading for a while, so we
+    /// never wait forever.
+    Block,
+    /// Drop the whole message if it doesn't fit, so partial lines never go out
+    DropMessage,
+}
+
+impl TxSource {
+    /// Every source, in dropped counter order
+    pub const ALL: [Self; 3] = [Self::Cli, Self::Log, Self::Data];
+
+    /// The backpressure policy applied to this source. CLI responses are what the user is
+    /// waiting on, so those block. Logs and data are periodic and can afford to lose a line.
+    #[must_use]
+    pub const fn policy(self) -> TxPolicy {
+        match self {
+            Self::Cli => TxPolicy::Block,
+            Self::Log | Self::Data => TxPolicy::DropMessage,
+        }
+    }
+
+    /// Human readable name of this source
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Cli => "cli",
+            Self::Log => "log",
+            Self::Data => "data",
+        }
+    }
+
+    /// Number of bytes from this source dropped since boot
+    #[must_use]
+    pub fn dropped(self) -> usize {
+        TX_DROPPED[self as usize].load(atomic::Ordering::Acquire)
+    }
+
+    /// Adds `count` bytes to this source's dropped counter. Only call with USB interrupts masked.
+    fn record_dropped(self, count: usize) {
+        let counter = &TX_DROPPED[self as usize];
+        counter.store(
+            counter
+                .load(atomic::Ordering::Acquire)
+                .saturating_add(count),
+            atomic::Ordering::Release,
+        );
+    }
+}
+
 impl<'a> UsbSerial<'a> {
     /// Initializes everything we need for USB serial communication
     fn init(nvic: &mut NVIC, usb_allocator: UsbBusAllocator<UsbBus>) {
@@ -94,6 +184,174 @@ impl<'a> UsbSerial<'a> {
         let message_bytes = message.as_bytes();
         self.usb_serial.write(message_bytes).unwrap_or(0)
     }
+
+    /// Whether the host has configured us and is able to receive data
+    #[must_use]
+    pub fn configured(&self) -> bool {
+        self.usb_dev.state() == UsbDeviceState::Configured
+    }
+
+    /// Hands as many bytes from the transmit ring buffer to the USB serial class as it will take.
+    ///
+    /// # Safety
+    /// Must be called with USB interrupts masked or from within a USB interrupt handler, as it
+    /// accesses `TX_QUEUE`.
+    unsafe fn drain_tx(&mut self) {
+        let queue = tx_queue();
+        let mut chunk = [0_u8; 64];
+        loop {
+            let mut len = 0;
+            for (slot, byte) in chunk.iter_mut().zip(queue.iter()) {
+                *slot = *byte;
+                len += 1;
+            }
+            if len == 0 {
+                break;
+            }
+
+            let written = self.write(&chunk[..len]);
+            for _ in 0..written {
+                queue.dequeue();
+            }
+            if written < len {
+                // the endpoint is full. The next transfer complete interrupt picks back up here
+                break;
+            }
+        }
+    }
+}
+
+/// Gets our transmit ring buffer.
+///
+/// # Safety
+/// Must be called with USB interrupts masked or from within a USB interrupt handler.
+unsafe fn tx_queue() -> &'static mut Queue<u8, TX_QUEUE_SIZE> {
+    &mut *ptr::addr_of_mut!(TX_QUEUE)
+}
+
+/// Number of bytes that can currently be queued in `queue`
+fn tx_free(queue: &Queue<u8, TX_QUEUE_SIZE>) -> usize {
+    queue.capacity() - queue.len()
+}
+
+/// Queues `bytes` to be sent out over USB serial, applying `source`'s backpressure policy.
+///
+/// # Returns
+/// `true` if all of `bytes` were queued, `false` if some or all of them were dropped.
+pub fn write_bytes(source: TxSource, bytes: &[u8]) -> bool {
+    let mut remaining = bytes;
+    let mut polls = 0;
+    loop {
+        // Safety: `TX_QUEUE` and `USB_SERIAL` are only touched with USB interrupts masked
+        let (queued, configured) = usb_free(|_| unsafe {
+            let queue = tx_queue();
+            let free = tx_free(queue);
+            if source.policy() == TxPolicy::DropMessage && free < remaining.len() {
+                source.record_dropped(remaining.len());
+                return (0, false);
+            }
+
+            let queued = free.min(remaining.len());
+            for byte in &remaining[..queued] {
+                // can't fail, we checked for room above
+                queue.enqueue(*byte).ok();
+            }
+
+            let serial = USB_SERIAL.as_mut().expect("UsbSerial not initialized");
+            serial.drain_tx();
+            (queued, serial.configured())
+        });
+
+        remaining = &remaining[queued..];
+        if queued > 0 {
+            polls = 0;
+            TX_STALLED.store(false, atomic::Ordering::Release);
+        }
+        if remaining.is_empty() {
+            return true;
+        }
+        let stalled = polls >= TX_BLOCK_MAX_POLLS || TX_STALLED.load(atomic::Ordering::Acquire);
+        if !configured || stalled {
+            if source.policy() == TxPolicy::Block {
+                TX_STALLED.store(configured, atomic::Ordering::Release);
+                usb_free(|_| source.record_dropped(remaining.len()));
+            }
+            return false;
+        }
+
+        // give the USB interrupt a chance to make some room. A busy wait rather than `wfi`, as
+        // a host that stopped reading may not raise any interrupt to wake us.
+        polls += 1;
+        cortex_m::asm::delay(TX_BLOCK_POLL_CYCLES);
+    }
+}
+
+/// Formats `args` and queues the result to be sent out over USB serial, applying `source`'s
+/// backpressure policy. There's no limit on the message length beyond the policy: blocking sources
+/// wait for room chunk by chunk, dropping sources need the whole message to fit in `TX_QUEUE`.
+///
+/// # Returns
+/// `true` if the whole message was queued, `false` if some or all of it was dropped.
+pub fn write_fmt(source: TxSource, args: fmt::Arguments) -> bool {
+    match source.policy() {
+        TxPolicy::Block => fmt::write(&mut TxWriter(source), args).is_ok(),
+        TxPolicy::DropMessage => {
+            // format once just to measure, so we can decide up front if it all fits
+            let mut counter = ByteCounter(0);
+            fmt::write(&mut counter, args).ok();
+
+            // Safety: `TX_QUEUE` and `USB_SERIAL` are only touched with USB interrupts masked
+            usb_free(|_| unsafe {
+                let queue = tx_queue();
+                if tx_free(queue) < counter.0 {
+                    source.record_dropped(counter.0);
+                    return false;
+                }
+
+                fmt::write(&mut QueueWriter(queue), args).ok();
+                USB_SERIAL
+                    .as_mut()
+                    .expect("UsbSerial not initialized")
+                    .drain_tx();
+                true
+            })
+        }
+    }
+}
+
+/// `fmt::Write` adapter that queues everything through [`write_bytes`]
+struct TxWriter(TxSource);
+
+impl fmt::Write for TxWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if write_bytes(self.0, s.as_bytes()) {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
+
+/// `fmt::Write` sink that only counts how many bytes would be written
+struct ByteCounter(usize);
+
+impl fmt::Write for ByteCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+/// `fmt::Write` adapter that enqueues straight into an already borrowed `TX_QUEUE`
+struct QueueWriter<'a>(&'a mut Queue<u8, TX_QUEUE_SIZE>);
+
+impl fmt::Write for QueueWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0.enqueue(byte).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
 }
 
 /// Initializes our global singleton
@@ -101,6 +359,21 @@ pub fn init(nvic: &mut NVIC, usb_allocator: UsbBusAllocator<UsbBus>) {
     UsbSerial::init(nvic, usb_allocator);
 }
 
+/// Waits for the host to configure us, for about a second at most, as writes give up right away
+/// without a configured host to read them.
+///
+/// # Returns
+/// Whether we're configured
+pub fn wait_configured() -> bool {
+    for _ in 0..CONFIGURE_MAX_POLLS {
+        if get(|serial| serial.configured()) {
+            return true;
+        }
+        cortex_m::asm::delay(TX_BLOCK_POLL_CYCLES);
+    }
+    get(|serial| serial.configured())
+}
+
 /// Checks if a user is present at the serial port by checking if we've received any
 /// bytes since boot
 pub fn user_present() -> bool {
@@ -176,30 +449,54 @@ where
     r
 }
 
-/// Writes the given message out over USB serial.
+/// Queues the given formatted message to be written out over USB serial.
 ///
 /// # Arguments
-/// * println args: variable arguments passed along to `ufmt::uwrite!`
+/// * source: the [`TxSource`] the message comes from, which decides the backpressure policy
+/// * format args: variable arguments passed along to `core::format_args!`
 ///
-/// # Warning
-/// as this function deals with a static mut, and it is also accessed in the
-/// USB interrupt handler, we both have unsafe code for unwrapping a static mut
-/// as well as disabling of interrupts while we do so.
-///
-/// # Safety
-/// the only time the static mut is used, we have interrupts disabled so we know
-/// we have sole access
+/// # Returns
+/// `true` if the whole message was queued. See [`write_fmt`].
 #[macro_export]
 macro_rules! serial_write {
-    ($($tt:tt)+) => {{
-        use core::fmt::Write;
-
-        let mut s: heapless::String<64> = heapless::String::new();
-        core::write!(&mut s, $($tt)*).unwrap();
-        $crate::usb_serial::get(|usbserial| { usbserial.write_str(s.as_str()); });
+    ($source:expr, $($tt:tt)+) => {{
+        $crate::usb_serial::write_fmt($source, core::format_args!($($tt)+))
     }};
 }
 
+fn usb_control(
+    _menu: &menu::Menu<cli::Output>,
+    item: &menu::Item<cli::Output>,
+    args: &[&str],
+    context: &mut cli::Output,
+) {
+    use core::fmt::Write;
+
+    if let Ok(Some(_)) = menu::argument_finder(item, args, pt_cli::ARG_STATS) {
+        // Safety: `TX_QUEUE` is only touched with USB interrupts masked
+        let queued = usb_free(|_| unsafe { tx_queue().len() });
+        writeln!(context, "tx queued: {}/{}", queued, TX_QUEUE_SIZE - 1).unwrap();
+        for source in TxSource::ALL {
+            writeln!(context, "{} dropped: {}", source.name(), source.dropped()).unwrap();
+        }
+    } else {
+        writeln!(context, "invalid usage").unwrap();
+    }
+}
+
+/// Method to put our CLI entry in for USB serial control
+pub const USB_CLI_ITEM: cli::Item = cli::Item {
+    item_type: menu::ItemType::Callback {
+        function: usb_control,
+        parameters: &[menu::Parameter::Named {
+            parameter_name: pt_cli::ARG_STATS,
+            help: Some("prints transmit buffer usage and dropped byte counters"),
+        }],
+    },
+    command: pt_cli::CMD_USB,
+    help: Some("Inspects our USB serial connection"),
+};
+
 fn poll_usb() {
     let mut buf = [0_u8; 64];
     // Safety:
@@ -207,12 +504,16 @@ fn poll_usb() {
     // Only interrupt handler that accesses it. thread access is only done
     // while interrupts are disabled.
     //
+    // `TX_QUEUE`:
+    // thread access is only done while interrupts are disabled.
+    //
     // `CLI_INPUT_PRODUCER`:
     // This is the only spot that we mutate it. When we initialize it to `Some()`,
     // interrupts are disabled so this handler cannot run.
     unsafe {
         if let Some(serial) = USB_SERIAL.as_mut() {
             let bytes_read = serial.poll(&mut buf);
+            serial.drain_tx();
             // serial.write(&buf[0..bytes_read]);
             if bytes_read != 0 {
                 USER_PRESENT.store(true, atomic::Ordering::Release);
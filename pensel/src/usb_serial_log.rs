@@ -3,7 +3,7 @@ use log::{Level, LevelFilter, Metadata, Record, SetLoggerError};
 
 use core::sync::atomic;
 
-use crate::{cli, serial_write, usb_serial};
+use crate::{cli, serial_write, usb_serial::TxSource};
 use pensel_types::cli as pt_cli;
 
 struct UsbSerialLogger {
@@ -55,9 +55,9 @@ impl log::Log for UsbSerialLogger {
             let level = record.level();
             if level == Level::Info {
                 // leave INFO prefix off for expected normal output
-                usb_serial::get(|_| serial_write!("{}\n", record.args()));
+                serial_write!(TxSource::Log, "{}\n", record.args());
             } else {
-                usb_serial::get(|_| serial_write!("{}: {}\n", record.level(), record.args()));
+                serial_write!(TxSource::Log, "{}: {}\n", record.level(), record.args());
             }
         }
     }
@@ -86,11 +86,11 @@ pub fn init() -> Result<(), SetLoggerError> {
     })
 }
 
-fn log_control<const N: usize>(
-    _menu: &menu::Menu<cli::Output<N>>,
-    item: &menu::Item<cli::Output<N>>,
+fn log_control(
+    _menu: &menu::Menu<cli::Output>,
+    item: &menu::Item<cli::Output>,
     args: &[&str],
-    context: &mut cli::Output<N>,
+    context: &mut cli::Output,
 ) {
     use core::fmt::Write;
 